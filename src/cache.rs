@@ -0,0 +1,365 @@
+//! Persistent cache of content hashes
+//!
+//! Re-hashing an unchanged tree is the dominant cost of repeated syncs. The
+//! cache remembers each file's hash together with the size and mtime it was
+//! computed for, so a later scan can reuse the hash while both still match.
+//!
+//! ## Trust
+//!
+//! A size+mtime match is only as reliable as the filesystem's timestamps.
+//! On filesystems with coarse or resettable mtimes, set
+//! [`CacheConfig::cache_ttl`] so entries are re-hashed once they get old.
+//! On Unix the inode number and ctime are checked too, which catches a file
+//! replaced by a rename even if its size and mtime were carefully preserved.
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::core::ScanResult;
+use crate::hash::ContentHash;
+use crate::io::write_file_atomic;
+
+/// Configuration for a [`HashCache`]
+#[derive(Debug, Clone, Default)]
+pub struct CacheConfig {
+    /// Maximum age of an entry before it is ignored and the file re-hashed
+    ///
+    /// `None` trusts entries for as long as size and mtime match.
+    pub cache_ttl: Option<Duration>,
+}
+
+/// The file state a hash is valid for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileStamp {
+    /// File size in bytes
+    pub size: u64,
+    /// Last modified time
+    pub mtime: SystemTime,
+    /// Inode number (Unix only)
+    pub inode: Option<u64>,
+    /// Status change time as (seconds, nanoseconds) (Unix only)
+    pub ctime: Option<(i64, i64)>,
+}
+
+impl FileStamp {
+    /// Stamp for a file from its metadata
+    pub fn from_metadata(metadata: &fs::Metadata) -> io::Result<Self> {
+        #[cfg(unix)]
+        let (inode, ctime) = {
+            use std::os::unix::fs::MetadataExt;
+            (Some(metadata.ino()), Some((metadata.ctime(), metadata.ctime_nsec())))
+        };
+        #[cfg(not(unix))]
+        let (inode, ctime) = (None, None);
+
+        Ok(Self {
+            size: metadata.len(),
+            mtime: metadata.modified()?,
+            inode,
+            ctime,
+        })
+    }
+}
+
+/// A cached hash and the file state it was computed for
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct CacheEntry {
+    size: u64,
+    mtime_secs: u64,
+    mtime_nanos: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    inode: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    ctime: Option<(i64, i64)>,
+    hash: ContentHash,
+    /// When the entry was recorded, in seconds since the epoch
+    inserted: u64,
+    /// The mtime is only known to the second (seeded from a saved scan)
+    #[serde(default)]
+    whole_seconds: bool,
+}
+
+/// Content hashes keyed by file path, valid while size and mtime match
+#[derive(Debug, Default)]
+pub struct HashCache {
+    entries: HashMap<PathBuf, CacheEntry>,
+    config: CacheConfig,
+}
+
+impl HashCache {
+    /// Create an empty cache
+    pub fn new(config: CacheConfig) -> Self {
+        Self { entries: HashMap::new(), config }
+    }
+
+    /// Load a cache from disk, starting empty if the file doesn't exist yet
+    pub fn load(path: &Path, config: CacheConfig) -> io::Result<Self> {
+        let entries = match fs::read_to_string(path) {
+            Ok(json) => serde_json::from_str(&json)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => HashMap::new(),
+            Err(e) => return Err(e),
+        };
+        Ok(Self { entries, config })
+    }
+
+    /// Save the cache to disk
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let json = serde_json::to_string(&self.entries)?;
+        write_file_atomic(path, json.as_bytes())
+    }
+
+    /// Look up the hash for a file in the given state
+    ///
+    /// Misses if the file is unknown, its size, mtime, inode or ctime changed,
+    /// or the entry is older than the configured TTL. Inode and ctime are only
+    /// compared when both the entry and `stamp` know them.
+    pub fn lookup(&self, path: &Path, stamp: &FileStamp) -> Option<ContentHash> {
+        let entry = self.entries.get(path)?;
+        let (mtime_secs, mtime_nanos) = split_time(stamp.mtime);
+        if entry.size != stamp.size
+            || entry.mtime_secs != mtime_secs
+            || (!entry.whole_seconds && entry.mtime_nanos != mtime_nanos)
+            || known_and_differ(entry.inode, stamp.inode)
+            || known_and_differ(entry.ctime, stamp.ctime)
+        {
+            return None;
+        }
+
+        if let Some(ttl) = self.config.cache_ttl {
+            let inserted = UNIX_EPOCH + Duration::from_secs(entry.inserted);
+            let age = SystemTime::now().duration_since(inserted).unwrap_or_default();
+            if age > ttl {
+                return None;
+            }
+        }
+
+        Some(entry.hash.clone())
+    }
+
+    /// Record the hash for a file in the given state
+    pub fn insert(&mut self, path: PathBuf, stamp: FileStamp, hash: ContentHash) {
+        self.insert_at(path, stamp, hash, SystemTime::now());
+    }
+
+    /// Seed the cache with every file of a previous scan
+    ///
+    /// Saved scans only keep whole-second mtimes, so these entries match any
+    /// mtime within the same second. They age from the scan's `scan_time`, and
+    /// paths the cache already holds keep their more precise entries. Files
+    /// whose hashing was deferred are left out.
+    pub fn insert_scan(&mut self, scan: &ScanResult) {
+        for file in scan.files.iter().filter(|f| !f.hash_deferred) {
+            let path = scan.root.join(&file.path);
+            if self.entries.contains_key(&path) {
+                continue;
+            }
+            let stamp = FileStamp {
+                size: file.size,
+                mtime: file.mtime,
+                inode: file.inode,
+                ctime: file.ctime,
+            };
+            self.insert_at(path.clone(), stamp, file.hash.clone(), scan.scan_time);
+            if let Some(entry) = self.entries.get_mut(&path) {
+                entry.whole_seconds = true;
+            }
+        }
+    }
+
+    /// Record an entry as if it had been inserted at `inserted`
+    fn insert_at(
+        &mut self,
+        path: PathBuf,
+        stamp: FileStamp,
+        hash: ContentHash,
+        inserted: SystemTime,
+    ) {
+        let (mtime_secs, mtime_nanos) = split_time(stamp.mtime);
+        let entry = CacheEntry {
+            size: stamp.size,
+            mtime_secs,
+            mtime_nanos,
+            inode: stamp.inode,
+            ctime: stamp.ctime,
+            hash,
+            inserted: split_time(inserted).0,
+            whole_seconds: false,
+        };
+        self.entries.insert(path, entry);
+    }
+
+    /// Number of cached entries
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Check if the cache is empty
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+/// Whether two optional values are both known and different
+fn known_and_differ<T: PartialEq>(a: Option<T>, b: Option<T>) -> bool {
+    a.zip(b).is_some_and(|(a, b)| a != b)
+}
+
+/// Split a timestamp into whole seconds and nanoseconds since the epoch
+fn split_time(time: SystemTime) -> (u64, u32) {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    (since_epoch.as_secs(), since_epoch.subsec_nanos())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hash::hash_bytes;
+
+    fn stamp(size: u64, mtime: SystemTime) -> FileStamp {
+        FileStamp { size, mtime, inode: None, ctime: None }
+    }
+
+    #[test]
+    fn test_lookup_matches_size_and_mtime() {
+        let mut cache = HashCache::new(CacheConfig::default());
+        let mtime = UNIX_EPOCH + Duration::from_secs(1_000);
+        let hash = hash_bytes(b"content");
+        cache.insert(PathBuf::from("/data/file"), stamp(7, mtime), hash.clone());
+
+        assert_eq!(cache.lookup(Path::new("/data/file"), &stamp(7, mtime)), Some(hash));
+        assert_eq!(cache.lookup(Path::new("/data/file"), &stamp(8, mtime)), None);
+        assert_eq!(
+            cache.lookup(Path::new("/data/file"), &stamp(7, mtime + Duration::from_secs(1))),
+            None
+        );
+        assert_eq!(cache.lookup(Path::new("/data/other"), &stamp(7, mtime)), None);
+    }
+
+    #[test]
+    fn test_lookup_checks_inode_and_ctime() {
+        let mut cache = HashCache::new(CacheConfig::default());
+        let recorded = FileStamp {
+            inode: Some(42),
+            ctime: Some((1_000, 0)),
+            ..stamp(7, UNIX_EPOCH + Duration::from_secs(1_000))
+        };
+        cache.insert(PathBuf::from("/f"), recorded, hash_bytes(b"f"));
+
+        let path = Path::new("/f");
+        assert!(cache.lookup(path, &recorded).is_some());
+        assert!(cache.lookup(path, &FileStamp { inode: Some(43), ..recorded }).is_none());
+        assert!(cache.lookup(path, &FileStamp { ctime: Some((1_000, 1)), ..recorded }).is_none());
+        // Unknown identity (e.g. non-Unix) falls back to size and mtime
+        let unknown = FileStamp { inode: None, ctime: None, ..recorded };
+        assert!(cache.lookup(path, &unknown).is_some());
+    }
+
+    #[test]
+    fn test_expired_entries_miss() {
+        let mtime = UNIX_EPOCH + Duration::from_secs(1_000);
+        let hash = hash_bytes(b"content");
+        let an_hour_ago = SystemTime::now() - Duration::from_secs(3_600);
+
+        let mut cache = HashCache::new(CacheConfig { cache_ttl: Some(Duration::from_secs(60)) });
+        cache.insert_at(PathBuf::from("/stale"), stamp(7, mtime), hash.clone(), an_hour_ago);
+        cache.insert(PathBuf::from("/fresh"), stamp(7, mtime), hash.clone());
+
+        assert_eq!(cache.lookup(Path::new("/stale"), &stamp(7, mtime)), None);
+        assert_eq!(cache.lookup(Path::new("/fresh"), &stamp(7, mtime)), Some(hash.clone()));
+
+        // Without a TTL the old entry is still trusted
+        cache.config.cache_ttl = None;
+        assert_eq!(cache.lookup(Path::new("/stale"), &stamp(7, mtime)), Some(hash));
+    }
+
+    #[test]
+    fn test_insert_scan_matches_within_second() {
+        let scan = ScanResult {
+            root: PathBuf::from("/src"),
+            files: vec![crate::core::FileMeta {
+                path: PathBuf::from("a.txt"),
+                size: 1,
+                mtime: UNIX_EPOCH + Duration::from_secs(1_000),
+                hash: hash_bytes(b"a"),
+                permissions: None,
+                owner: None,
+                metadata_hash: None,
+                inode: None,
+                ctime: None,
+                atime: None,
+                btime: None,
+                hash_deferred: false,
+            }],
+            scan_time: SystemTime::now(),
+            warnings: Vec::new(),
+            dirs: Vec::new(),
+            traversed_dirs: Vec::new(),
+            fifo_spool: None,
+        };
+        let mut cache = HashCache::new(CacheConfig::default());
+        cache.insert_scan(&scan);
+
+        let path = Path::new("/src/a.txt");
+        let mtime = UNIX_EPOCH + Duration::new(1_000, 500_000_000);
+        assert_eq!(cache.lookup(path, &stamp(1, mtime)), Some(hash_bytes(b"a")));
+        assert_eq!(cache.lookup(path, &stamp(1, mtime + Duration::from_secs(1))), None);
+    }
+
+    #[test]
+    fn test_insert_scan_ages_from_scan_time() {
+        let mtime = UNIX_EPOCH + Duration::new(1_000, 500_000_000);
+        let file = |name: &str| crate::core::FileMeta {
+            path: PathBuf::from(name),
+            size: 1,
+            mtime,
+            hash: hash_bytes(b"manifest"),
+            permissions: None,
+            owner: None,
+            metadata_hash: None,
+            inode: None,
+            ctime: None,
+            atime: None,
+            btime: None,
+            hash_deferred: false,
+        };
+        let scan = ScanResult {
+            root: PathBuf::from("/src"),
+            files: vec![file("cached.txt"), file("old.txt")],
+            scan_time: SystemTime::now() - Duration::from_secs(3_600),
+            warnings: Vec::new(),
+            dirs: Vec::new(),
+            traversed_dirs: Vec::new(),
+            fifo_spool: None,
+        };
+        let mut cache = HashCache::new(CacheConfig { cache_ttl: Some(Duration::from_secs(60)) });
+        cache.insert(PathBuf::from("/src/cached.txt"), stamp(1, mtime), hash_bytes(b"cached"));
+        cache.insert_scan(&scan);
+
+        // The hour-old manifest is past the TTL, and the fresher entry stays
+        assert_eq!(cache.lookup(Path::new("/src/old.txt"), &stamp(1, mtime)), None);
+        let cached = cache.lookup(Path::new("/src/cached.txt"), &stamp(1, mtime));
+        assert_eq!(cached, Some(hash_bytes(b"cached")));
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let cache_path = dir.path().join("hashes.json");
+        let mtime = UNIX_EPOCH + Duration::new(1_000, 123);
+
+        let mut cache = HashCache::new(CacheConfig::default());
+        cache.insert(PathBuf::from("/a"), stamp(1, mtime), hash_bytes(b"a"));
+        cache.save(&cache_path).unwrap();
+
+        let loaded = HashCache::load(&cache_path, CacheConfig::default()).unwrap();
+        assert_eq!(loaded.len(), 1);
+        assert_eq!(loaded.lookup(Path::new("/a"), &stamp(1, mtime)), Some(hash_bytes(b"a")));
+
+        let missing = HashCache::load(&dir.path().join("none.json"), CacheConfig::default());
+        assert!(missing.unwrap().is_empty());
+    }
+}
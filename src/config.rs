@@ -0,0 +1,214 @@
+//! Named sync profiles loaded from a config file
+//!
+//! A config file saves the source, destination and flags of syncs that run
+//! repeatedly, so `jan --profile nightly` replaces a long command line. It is
+//! JSON, with one object per profile under `profiles`:
+//!
+//! ```json
+//! {
+//!   "profiles": {
+//!     "nightly": {
+//!       "source": "/home/me/data",
+//!       "dest": "/backup/data",
+//!       "delete": true,
+//!       "exclude-if-present": ["CACHEDIR.TAG"]
+//!     }
+//!   }
+//! }
+//! ```
+//!
+//! Keys are the CLI's long flag names. The mutually exclusive
+//! `--delete-before`/`--delete-during`/`--delete-after` and
+//! `--largest-first`/`--smallest-first`/`--newest-first` flags are saved as
+//! `"delete-timing": "before"` and `"transfer-order": "largest-first"`.
+//! Unknown keys are rejected rather than ignored, so a misspelled option
+//! can't silently do nothing.
+
+use serde::{Deserialize, Deserializer};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::core::{DeleteTiming, SyncError, TransferOrder};
+use crate::hash::ContentHash;
+
+/// Contents of a config file
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// Profiles by name
+    #[serde(default)]
+    pub profiles: BTreeMap<String, Profile>,
+}
+
+/// Saved settings for one sync, keyed like the CLI flags they stand for
+///
+/// Flags given on the command line take precedence over the profile: an
+/// option or list given on both uses the command line's value, and a switch
+/// given as `--flag` or `--no-flag` overrides the profile's setting. Unset
+/// keys keep the CLI's defaults.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Profile {
+    pub source: Option<PathBuf>,
+    pub dest: Option<PathBuf>,
+    pub delete: Option<bool>,
+    pub delete_timing: Option<DeleteTiming>,
+    pub transfer_order: Option<TransferOrder>,
+    pub limit: Option<usize>,
+    pub yes: Option<bool>,
+    pub quiet: Option<bool>,
+    pub modify_window: Option<u64>,
+    pub checksum: Option<bool>,
+    pub update: Option<bool>,
+    pub clock_skew: Option<u64>,
+    pub no_renames: Option<bool>,
+    pub rename_min_size: Option<u64>,
+    pub include_vcs: Option<bool>,
+    pub include_git_tracked: Option<bool>,
+    pub exclude_if_present: Vec<String>,
+    pub replicate_empty_parents: Option<bool>,
+    pub content_only: Option<bool>,
+    pub strict_metadata: Option<bool>,
+    pub preserve_atime: Option<bool>,
+    pub preserve_btime: Option<bool>,
+    pub batch_small_files: Option<bool>,
+    pub fadvise: Option<bool>,
+    pub dir_hashes: Option<bool>,
+    pub keep: Vec<String>,
+    pub owner: Option<String>,
+    pub group: Option<String>,
+    pub protect: Vec<String>,
+    pub checksum_cache: Option<PathBuf>,
+    pub checksum_cache_ttl: Option<u64>,
+    pub manifest: Option<PathBuf>,
+    pub dest_manifest: Option<PathBuf>,
+    pub verify_dest: Option<bool>,
+    pub dest_sample_rate: Option<f64>,
+    pub compress_manifest_paths: Option<bool>,
+    pub scan_checkpoint: Option<PathBuf>,
+    pub heartbeat: Option<u64>,
+    pub status_file: Option<PathBuf>,
+    #[serde(deserialize_with = "hex_hash")]
+    pub expect_hash: Option<ContentHash>,
+    pub threads: Option<usize>,
+}
+
+/// Read a hash in the hex form `--expect-hash` takes
+fn hex_hash<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<ContentHash>, D::Error> {
+    let hex = String::deserialize(deserializer)?;
+    hex.parse().map(Some).map_err(serde::de::Error::custom)
+}
+
+impl Config {
+    /// Where the config file is looked for when none is given
+    ///
+    /// `$XDG_CONFIG_HOME/janus/config.json`, falling back to
+    /// `~/.config/janus/config.json` (`%APPDATA%\janus\config.json` on
+    /// Windows). `None` if the home directory is unknown.
+    pub fn default_path() -> Option<PathBuf> {
+        let config_dir = if cfg!(windows) {
+            std::env::var_os("APPDATA").map(PathBuf::from)
+        } else {
+            std::env::var_os("XDG_CONFIG_HOME")
+                .filter(|dir| !dir.is_empty())
+                .map(PathBuf::from)
+                .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".config")))
+        };
+        config_dir.map(|dir| dir.join("janus").join("config.json"))
+    }
+
+    /// Load and validate a config file
+    pub fn load(path: &Path) -> Result<Self, SyncError> {
+        let contents = fs::read_to_string(path)
+            .map_err(|e| SyncError::InvalidConfig(format!("can't read {}: {e}", path.display())))?;
+        serde_json::from_str(&contents)
+            .map_err(|e| SyncError::InvalidConfig(format!("{}: {e}", path.display())))
+    }
+
+    /// The profile called `name`
+    pub fn profile(&self, name: &str) -> Result<&Profile, SyncError> {
+        self.profiles.get(name).ok_or_else(|| {
+            let known: Vec<&str> = self.profiles.keys().map(String::as_str).collect();
+            SyncError::InvalidConfig(format!(
+                "no profile named {name} (profiles: {})",
+                if known.is_empty() {
+                    "none".to_string()
+                } else {
+                    known.join(", ")
+                }
+            ))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load_profile() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("config.json");
+        fs::write(
+            &path,
+            r#"{"profiles": {"nightly": {
+                "source": "/data", "dest": "/backup", "delete": true,
+                "modify-window": 2, "exclude-if-present": ["CACHEDIR.TAG"]
+            }}}"#,
+        )
+        .unwrap();
+
+        let config = Config::load(&path).unwrap();
+        let profile = config.profile("nightly").unwrap();
+        assert_eq!(
+            *profile,
+            Profile {
+                source: Some(PathBuf::from("/data")),
+                dest: Some(PathBuf::from("/backup")),
+                delete: Some(true),
+                modify_window: Some(2),
+                exclude_if_present: vec!["CACHEDIR.TAG".to_string()],
+                ..Default::default()
+            }
+        );
+        let missing = config.profile("weekly").unwrap_err().to_string();
+        assert!(missing.contains("no profile named weekly (profiles: nightly)"));
+    }
+
+    #[test]
+    fn test_load_enum_and_hash_keys() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("config.json");
+        let hash = crate::hash::hash_bytes(b"tree");
+        let config = format!(
+            r#"{{"profiles": {{"nightly": {{
+                "delete": false, "delete-timing": "before",
+                "transfer-order": "largest-first", "expect-hash": "{hash}"
+            }}}}}}"#
+        );
+        fs::write(&path, config).unwrap();
+
+        let config = Config::load(&path).unwrap();
+        let profile = config.profile("nightly").unwrap();
+        assert_eq!(profile.delete, Some(false));
+        assert_eq!(profile.delete_timing, Some(DeleteTiming::Before));
+        assert_eq!(profile.transfer_order, Some(TransferOrder::LargestFirst));
+        assert_eq!(profile.expect_hash, Some(hash));
+
+        fs::write(&path, r#"{"profiles": {"nightly": {"expect-hash": "abc"}}}"#).unwrap();
+        let error = Config::load(&path).unwrap_err().to_string();
+        assert!(error.contains("not a 64-digit hex hash: abc"), "{error}");
+    }
+
+    #[test]
+    fn test_unknown_keys_are_rejected() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("config.json");
+        fs::write(&path, r#"{"profiles": {"nightly": {"source": "/data", "delte": true}}}"#)
+            .unwrap();
+
+        let error = Config::load(&path).unwrap_err().to_string();
+        assert!(error.contains("unknown field `delte`"), "{error}");
+    }
+}
@@ -1,16 +1,36 @@
 //! Core synchronization logic for scanning, diffing, and syncing directories.
 
-use crate::hash::{ContentHash, Hasher};
-use crate::io::{copy_file_with_metadata, remove_file_safe};
-use crate::progress::ProgressReporter;
+use crate::cache::{FileStamp, HashCache};
+use crate::hash::{hash_bytes, hash_file, ContentHash, Hasher};
+#[cfg(target_os = "linux")]
+use crate::io::drop_from_page_cache;
+#[cfg(unix)]
+use crate::io::set_file_mode_and_owner;
+use crate::io::{
+    copy_file_streaming, copy_file_tolerating_mtime_errors, drain_fifo, ensure_directory,
+    is_case_insensitive, remove_file_safe, replace_via_temp, replace_via_temp_in, same_filesystem,
+    set_file_times, swap_directories, verify_files_identical, write_file_atomic, StagingArchive,
+};
+use crate::progress::{
+    Heartbeat, ParallelProgress, ProgressReporter, StatusFile, PROGRESS_INTERVAL,
+};
 use anyhow::Result;
 use rayon::prelude::*;
 use serde::{Deserialize, Serialize};
-use std::collections::{HashMap, HashSet};
+use std::borrow::Cow;
+use std::collections::hash_map::RandomState;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
 use std::fs;
+use std::hash::BuildHasher;
+use std::ops::Range;
 use std::path::{Path, PathBuf};
-use std::time::SystemTime;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use thiserror::Error;
+use unicode_normalization::UnicodeNormalization;
 
 /// Errors that can occur during synchronization operations
 #[derive(Error, Debug)]
@@ -18,23 +38,120 @@ pub enum SyncError {
     #[error("Failed to read directory: {0}")]
     DirectoryRead(String),
 
-    #[error("Failed to hash file: {0}")]
-    HashError(String),
+    #[error("Failed to hash {}: {reason}", path.display())]
+    HashError { path: PathBuf, reason: String },
 
-    #[error("Failed to copy file: {0}")]
-    CopyError(String),
+    #[error("Failed to copy {}: {reason}", path.display())]
+    CopyError { path: PathBuf, reason: String },
 
-    #[error("Invalid path: {0}")]
-    InvalidPath(String),
+    #[error("Invalid path {}: {reason}", path.display())]
+    InvalidPath { path: PathBuf, reason: String },
+
+    #[error("Paths collide on case-insensitive destination: {0}")]
+    CaseConflict(String),
+
+    #[error("Invalid glob pattern: {0}")]
+    InvalidGlob(String),
+
+    #[error("Blocklisted content planned for sync: {0}")]
+    Blocked(String),
+
+    #[error("Scans of different roots can't be merged: {0} and {1}")]
+    RootMismatch(String, String),
+
+    #[error("Path appears in more than one merged scan: {}", .0.display())]
+    DuplicatePath(PathBuf),
+
+    #[error("Destination tree hash {actual} does not match the expected {expected}")]
+    TreeHashMismatch {
+        expected: ContentHash,
+        actual: ContentHash,
+    },
+
+    #[error("Invalid config: {0}")]
+    InvalidConfig(String),
 
     #[error("I/O error: {0}")]
     Io(#[from] std::io::Error),
 }
 
+impl SyncError {
+    /// Stable machine-readable name of the error, e.g. `invalid_path`
+    ///
+    /// Unlike the message, this doesn't change between releases, so scripts
+    /// can branch on it.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            SyncError::DirectoryRead(_) => "directory_read",
+            SyncError::HashError { .. } => "hash",
+            SyncError::CopyError { .. } => "copy",
+            SyncError::InvalidPath { .. } => "invalid_path",
+            SyncError::CaseConflict(_) => "case_conflict",
+            SyncError::InvalidGlob(_) => "invalid_glob",
+            SyncError::Blocked(_) => "blocked",
+            SyncError::RootMismatch(..) => "root_mismatch",
+            SyncError::DuplicatePath(_) => "duplicate_path",
+            SyncError::TreeHashMismatch { .. } => "tree_hash_mismatch",
+            SyncError::InvalidConfig(_) => "invalid_config",
+            SyncError::Io(_) => "io",
+        }
+    }
+
+    /// The path the error is about, for errors that name a single path
+    pub fn path(&self) -> Option<&Path> {
+        match self {
+            SyncError::HashError { path, .. }
+            | SyncError::CopyError { path, .. }
+            | SyncError::InvalidPath { path, .. }
+            | SyncError::DuplicatePath(path) => Some(path),
+            _ => None,
+        }
+    }
+}
+
+/// Options that contradict each other or can't take effect, found by `validate`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OptionConflict {
+    /// Names of the fields involved
+    pub options: Vec<&'static str>,
+    /// Why the combination is rejected
+    pub message: String,
+}
+
+impl OptionConflict {
+    fn new(options: &[&'static str], message: impl Into<String>) -> Self {
+        Self {
+            options: options.to_vec(),
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for OptionConflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.options.join(" + "), self.message)
+    }
+}
+
+/// `Ok` when no conflicts were found, otherwise all of them
+fn conflicts_to_result(
+    conflicts: Vec<OptionConflict>,
+) -> std::result::Result<(), Vec<OptionConflict>> {
+    if conflicts.is_empty() {
+        Ok(())
+    } else {
+        Err(conflicts)
+    }
+}
+
 /// Metadata for a single file including content hash
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct FileMeta {
     /// Relative path from scan root
+    ///
+    /// Native separators in memory; manifests store it with `/` so they
+    /// can be shared between Windows and Unix.
+    #[serde(with = "portable_path_serde")]
     pub path: PathBuf,
     /// File size in bytes
     pub size: u64,
@@ -46,6 +163,54 @@ pub struct FileMeta {
     /// Unix permissions (if available)
     #[serde(skip_serializing_if = "Option::is_none")]
     pub permissions: Option<u32>,
+    /// Unix owner as (uid, gid) (if available)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub owner: Option<(u32, u32)>,
+    /// Hash of the permissions and owner, independent of the content hash
+    ///
+    /// Lets metadata-only drift be detected without touching file contents.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metadata_hash: Option<ContentHash>,
+    /// Inode number (Unix only)
+    ///
+    /// With `ctime`, lets quick size+mtime checks notice a file that was
+    /// replaced by a rename with its size and mtime preserved.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub inode: Option<u64>,
+    /// Status change time as (seconds, nanoseconds) (Unix only)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ctime: Option<(i64, i64)>,
+    /// Last access time, read before hashing touches the file
+    ///
+    /// Only meaningful for the scan that read it; not saved in manifests.
+    #[serde(skip)]
+    pub atime: Option<SystemTime>,
+    /// Creation (birth) time, if the platform and filesystem report one
+    #[serde(skip)]
+    pub btime: Option<SystemTime>,
+    /// `hash` is a placeholder because hashing was deferred
+    ///
+    /// Set by [`HashPolicy::OnConflict`] scans until a diff needs the real
+    /// hash. The placeholder is unique to the path and size, so it never
+    /// equals another file's hash.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub hash_deferred: bool,
+}
+
+impl FileMeta {
+    /// Placeholder hash for a file whose hashing was deferred
+    fn deferred_hash(path: &Path, size: u64) -> ContentHash {
+        hash_bytes(format!("janus-deferred\0{size}\0{}", path.display()).as_bytes())
+    }
+}
+
+/// Hash the metadata fields tracked separately from content
+fn metadata_hash(permissions: Option<u32>, owner: Option<(u32, u32)>) -> Option<ContentHash> {
+    if permissions.is_none() && owner.is_none() {
+        return None;
+    }
+    let encoded = format!("mode={permissions:?};owner={owner:?}");
+    Some(hash_bytes(encoded.as_bytes()))
 }
 
 // Helper module for SystemTime serialization
@@ -70,16 +235,235 @@ mod systemtime_serde {
     }
 }
 
+/// Relative paths in manifests, always written with `/` separators
+///
+/// Keeps a manifest made on one OS usable on another: paths are converted
+/// to native separators when loaded, so everything touching the filesystem
+/// sees native paths.
+mod portable_path_serde {
+    use serde::ser::Error as _;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use std::path::{Path, PathBuf};
+
+    pub fn serialize<S>(path: &Path, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        super::portable_path(path)
+            .ok_or_else(|| S::Error::custom("path contains invalid UTF-8 characters"))?
+            .serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<PathBuf, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let portable = String::deserialize(deserializer)?;
+        Ok(super::native_path(&portable))
+    }
+}
+
+/// A relative path as a string with `/` separators, if it is valid UTF-8
+fn portable_path(path: &Path) -> Option<String> {
+    let path = path.to_str()?;
+    Some(if std::path::MAIN_SEPARATOR == '/' {
+        path.to_owned()
+    } else {
+        path.replace(std::path::MAIN_SEPARATOR, "/")
+    })
+}
+
+/// A `/`-separated relative path with the platform's separators
+fn native_path(portable: &str) -> PathBuf {
+    if std::path::MAIN_SEPARATOR == '/' {
+        PathBuf::from(portable)
+    } else {
+        PathBuf::from(portable.replace('/', std::path::MAIN_SEPARATOR_STR))
+    }
+}
+
 /// Result of scanning a directory
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ScanResult {
     /// Root directory that was scanned
+    ///
+    /// Always absolute for scans made by Janus: a relative root is resolved
+    /// against the current directory at scan time (without resolving
+    /// symlinks), so a saved manifest still names the right directory when
+    /// loaded from elsewhere. To apply a manifest to a copy of the tree at a
+    /// different location, see [`ScanResult::rebase_root`].
     pub root: PathBuf,
     /// List of all files found
     pub files: Vec<FileMeta>,
     /// Timestamp when scan was performed
     #[serde(with = "systemtime_serde")]
     pub scan_time: SystemTime,
+    /// Files found but left out of `files` because they couldn't be read
+    ///
+    /// Only meaningful for the scan that produced them; not saved in manifests.
+    #[serde(skip)]
+    pub warnings: Vec<ScanWarning>,
+    /// Hash of every directory holding files, if [`ScanOptions::dir_hashes`] was set
+    ///
+    /// Lets [`diff_scans_with_options`] skip subtrees that hash the same in
+    /// both scans. Describes `files` as scanned, so [`ScanResult::merge`]
+    /// clears it and so must any other code that edits `files`. Not saved in
+    /// manifests.
+    #[serde(skip)]
+    pub dirs: Vec<DirMeta>,
+    /// Every directory the scan walked into, relative to the root, sorted
+    ///
+    /// Includes directories without any scanned files, such as empty ones or
+    /// ones whose files were all filtered out, but not directories the walk
+    /// skipped entirely. Used by [`SyncOptions::replicate_empty_parents`].
+    /// Not saved in manifests.
+    #[serde(skip)]
+    pub traversed_dirs: Vec<PathBuf>,
+    /// Drained contents of FIFOs, if [`ScanOptions::drain_fifos`] found any
+    ///
+    /// Not saved in manifests.
+    #[serde(skip)]
+    pub fifo_spool: Option<FifoSpool>,
+}
+
+/// Private directory holding the drained contents of FIFOs until synced
+///
+/// Created by a scan with [`ScanOptions::drain_fifos`] that finds a FIFO
+/// and shared by the scan, its clones and the diffs made from it. The
+/// directory has an unpredictable name and is only accessible to the
+/// current user; it is removed with everything in it once the last of them
+/// is dropped.
+#[derive(Debug, Clone)]
+pub struct FifoSpool(Arc<tempfile::TempDir>);
+
+impl FifoSpool {
+    fn new() -> std::io::Result<Self> {
+        let mut builder = tempfile::Builder::new();
+        builder.prefix("janus-fifo-");
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            builder.permissions(fs::Permissions::from_mode(0o700));
+        }
+        Ok(Self(Arc::new(builder.tempdir()?)))
+    }
+
+    /// The spool directory
+    pub fn path(&self) -> &Path {
+        self.0.path()
+    }
+
+    /// Where drained contents with this hash are kept
+    fn content_path(&self, hash: &ContentHash) -> PathBuf {
+        self.path().join(hash.to_string())
+    }
+}
+
+/// Hash of one directory's subtree, see [`ScanResult::compute_dir_hashes`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirMeta {
+    /// Path relative to the scan root; empty for the root itself
+    pub path: PathBuf,
+    /// Hash of the names and hashes of the directory's files and subdirectories
+    pub hash: ContentHash,
+}
+
+/// What [`ScanResult::merge`] does with a path found in more than one scan
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DuplicatePathPolicy {
+    /// Fail with [`SyncError::DuplicatePath`]
+    #[default]
+    Error,
+    /// Keep the entry from the scan merged last
+    LastWins,
+}
+
+/// A file the scan found but couldn't include
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScanWarning {
+    /// Path relative to the scan root
+    pub path: PathBuf,
+    /// What went wrong
+    pub kind: ScanWarningKind,
+    /// Human-readable error message
+    pub message: String,
+}
+
+/// Category of a [`ScanWarning`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScanWarningKind {
+    /// Permission to read the file was denied
+    PermissionDenied,
+    /// The file disappeared between walking and hashing
+    Vanished,
+    /// Any other error while reading or hashing the file
+    Io,
+}
+
+impl ScanWarning {
+    /// Describe why the file at `path` could not be scanned
+    fn new(path: PathBuf, error: &anyhow::Error) -> Self {
+        let io_kind = error
+            .chain()
+            .find_map(|cause| cause.downcast_ref::<std::io::Error>())
+            .map(std::io::Error::kind);
+        let kind = match io_kind {
+            Some(std::io::ErrorKind::PermissionDenied) => ScanWarningKind::PermissionDenied,
+            Some(std::io::ErrorKind::NotFound) => ScanWarningKind::Vanished,
+            _ => ScanWarningKind::Io,
+        };
+        Self { path, kind, message: error.to_string() }
+    }
+}
+
+/// Version written by [`ManifestFormat::FrontCoded`]
+///
+/// Plain manifests carry no version field and are treated as version 1.
+pub const MANIFEST_FORMAT_VERSION: u32 = 2;
+
+/// On-disk encoding of a saved [`ScanResult`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ManifestFormat {
+    /// The scan serialized as-is, one full path per file
+    #[default]
+    Plain,
+    /// Files sorted by path, each path stored as the number of bytes shared
+    /// with the previous one plus the remaining suffix
+    ///
+    /// Much smaller for deep trees where most of each path repeats its
+    /// neighbour's directories.
+    FrontCoded,
+}
+
+/// Upper bounds (exclusive) and labels for the size histogram buckets
+const SIZE_BUCKETS: &[(u64, &str)] = &[
+    (1024, "< 1 KiB"),
+    (1024 * 1024, "1 KiB - 1 MiB"),
+    (100 * 1024 * 1024, "1 MiB - 100 MiB"),
+    (u64::MAX, ">= 100 MiB"),
+];
+
+/// Number of extensions listed in [`ScanStats::top_extensions`]
+pub const TOP_EXTENSIONS: usize = 10;
+
+/// File count and total size for one histogram bucket
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BucketStats {
+    /// Bucket label (size range or file extension)
+    pub label: String,
+    /// Number of files in the bucket
+    pub count: usize,
+    /// Total size of the files in the bucket
+    pub total_size: u64,
+}
+
+/// Summary statistics for a scan, see [`ScanResult::histogram`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScanStats {
+    /// Files bucketed by size range, smallest range first
+    pub size_buckets: Vec<BucketStats>,
+    /// Extensions with the largest total size, largest first
+    pub top_extensions: Vec<BucketStats>,
 }
 
 impl ScanResult {
@@ -88,23 +472,308 @@ impl ScanResult {
         self.files.iter().map(|f| f.size).sum()
     }
 
+    /// Compute file count and size histograms for capacity planning
+    ///
+    /// Files are bucketed by size range and by extension (case-insensitive,
+    /// `(none)` for files without one). Only the [`TOP_EXTENSIONS`] largest
+    /// extensions by total size are kept.
+    pub fn histogram(&self) -> ScanStats {
+        let mut size_buckets: Vec<BucketStats> = SIZE_BUCKETS
+            .iter()
+            .map(|(_, label)| BucketStats {
+                label: label.to_string(),
+                count: 0,
+                total_size: 0,
+            })
+            .collect();
+        let mut extensions: HashMap<String, BucketStats> = HashMap::new();
+
+        for file in &self.files {
+            let index = SIZE_BUCKETS.iter().position(|(limit, _)| file.size < *limit);
+            let bucket = &mut size_buckets[index.unwrap_or(SIZE_BUCKETS.len() - 1)];
+            bucket.count += 1;
+            bucket.total_size += file.size;
+
+            let ext = file
+                .path
+                .extension()
+                .map(|e| e.to_string_lossy().to_lowercase())
+                .unwrap_or_else(|| "(none)".to_string());
+            let entry = extensions.entry(ext.clone()).or_insert(BucketStats {
+                label: ext,
+                count: 0,
+                total_size: 0,
+            });
+            entry.count += 1;
+            entry.total_size += file.size;
+        }
+
+        let mut top_extensions: Vec<BucketStats> = extensions.into_values().collect();
+        top_extensions
+            .sort_by(|a, b| b.total_size.cmp(&a.total_size).then_with(|| a.label.cmp(&b.label)));
+        top_extensions.truncate(TOP_EXTENSIONS);
+
+        ScanStats { size_buckets, top_extensions }
+    }
+
+    /// Point the scan at the same tree in a different location
+    ///
+    /// File paths are relative to the root, so only the root changes. Use
+    /// this when a manifest taken on one machine, or of one mount point, is
+    /// applied to a copy of the tree elsewhere.
+    pub fn rebase_root(&mut self, new_root: impl Into<PathBuf>) {
+        self.root = new_root.into();
+    }
+
+    /// Combine partial scans of the same root into one
+    ///
+    /// For trees scanned in shards (e.g. one subdirectory per machine): file
+    /// lists and warnings are concatenated in merge order and the latest
+    /// `scan_time` is kept. Every scan must have the same root.
+    pub fn merge(
+        mut self,
+        others: impl IntoIterator<Item = ScanResult>,
+        duplicates: DuplicatePathPolicy,
+    ) -> Result<ScanResult> {
+        self.dirs.clear();
+        let mut index: HashMap<PathBuf, usize> =
+            self.files.iter().enumerate().map(|(i, file)| (file.path.clone(), i)).collect();
+        for other in others {
+            if other.root != self.root {
+                return Err(SyncError::RootMismatch(
+                    self.root.display().to_string(),
+                    other.root.display().to_string(),
+                )
+                .into());
+            }
+            for file in other.files {
+                match index.get(&file.path) {
+                    None => {
+                        index.insert(file.path.clone(), self.files.len());
+                        self.files.push(file);
+                    },
+                    Some(_) if duplicates == DuplicatePathPolicy::Error => {
+                        return Err(SyncError::DuplicatePath(file.path.clone()).into());
+                    },
+                    Some(&i) => self.files[i] = file,
+                }
+            }
+            self.scan_time = self.scan_time.max(other.scan_time);
+            self.warnings.extend(other.warnings);
+            self.traversed_dirs.extend(other.traversed_dirs);
+            match (&self.fifo_spool, other.fifo_spool) {
+                (_, None) => {},
+                (None, spool) => self.fifo_spool = spool,
+                (Some(spool), Some(other)) => {
+                    for entry in fs::read_dir(other.path())? {
+                        let entry = entry?;
+                        fs::rename(entry.path(), spool.path().join(entry.file_name()))?;
+                    }
+                },
+            }
+        }
+        self.traversed_dirs.sort();
+        self.traversed_dirs.dedup();
+        Ok(self)
+    }
+
+    /// Merkle-style hashes of every directory that holds files, sorted by path
+    ///
+    /// A directory's hash folds in the name and hash of each file and
+    /// subdirectory directly inside it, so two directories hash the same
+    /// exactly when their subtrees hold the same paths with the same content.
+    /// The root is included with an empty path. Directories without files
+    /// don't appear. Empty if any file's hash was deferred.
+    pub fn compute_dir_hashes(&self) -> Vec<DirMeta> {
+        if self.files.iter().any(|file| file.hash_deferred) {
+            return Vec::new();
+        }
+
+        // Name, whether it's a directory, and hash of each directory's children
+        let mut children: HashMap<&Path, Vec<(String, bool, ContentHash)>> = HashMap::new();
+        for file in &self.files {
+            let parent = file.path.parent().unwrap_or(Path::new(""));
+            let name = file.path.file_name().unwrap_or_default().to_string_lossy();
+            children
+                .entry(parent)
+                .or_default()
+                .push((name.into_owned(), false, file.hash.clone()));
+            for ancestor in parent.ancestors().skip(1) {
+                children.entry(ancestor).or_default();
+            }
+        }
+
+        // Deepest first, so each directory's subdirectories are hashed before it
+        let mut dirs: Vec<&Path> = children.keys().copied().collect();
+        dirs.sort_by_key(|dir| std::cmp::Reverse(dir.components().count()));
+        let mut hasher = Hasher::new();
+        let mut hashes = Vec::with_capacity(dirs.len());
+        for dir in dirs {
+            let mut entries = children.remove(dir).unwrap_or_default();
+            entries.sort_by(|a, b| a.0.cmp(&b.0));
+            for (name, is_dir, hash) in &entries {
+                hasher.update(name.as_bytes());
+                hasher.update(&[0, u8::from(*is_dir)]);
+                hasher.update(hash.as_bytes());
+            }
+            let hash = hasher.finalize_reset();
+            if let Some(parent) = dir.parent() {
+                let name = dir.file_name().unwrap_or_default().to_string_lossy().into_owned();
+                children.entry(parent).or_default().push((name, true, hash.clone()));
+            }
+            hashes.push(DirMeta { path: dir.to_path_buf(), hash });
+        }
+        hashes.sort_by(|a, b| a.path.cmp(&b.path));
+        hashes
+    }
+
+    /// Hash of the whole tree: every relative path and content hash
+    ///
+    /// Independent of scan order, timestamps and the platform's path
+    /// separator, so two trees with the same files at the same paths have the
+    /// same tree hash. Fails if any file's hash was deferred.
+    pub fn tree_hash(&self) -> Result<ContentHash> {
+        if let Some(file) = self.files.iter().find(|f| f.hash_deferred) {
+            return Err(SyncError::HashError {
+                path: file.path.clone(),
+                reason: "hash was deferred by the scan".to_string(),
+            }
+            .into());
+        }
+        let mut files: Vec<&FileMeta> = self.files.iter().collect();
+        files.sort_by(|a, b| a.path.cmp(&b.path));
+
+        let mut hasher = Hasher::new();
+        for file in files {
+            match portable_path(&file.path) {
+                Some(path) => hasher.update(path.as_bytes()),
+                None => hasher.update(file.path.as_os_str().as_encoded_bytes()),
+            }
+            hasher.update(&[0]);
+            hasher.update(file.hash.as_bytes());
+        }
+        Ok(hasher.finalize())
+    }
+
     /// Save scan results to a JSON file
+    ///
+    /// The file is replaced atomically, so an interrupted save keeps the
+    /// previous contents.
     pub fn save_to_file(&self, path: &Path) -> Result<()> {
-        let json = serde_json::to_string_pretty(self)?;
-        fs::write(path, json)?;
+        self.save_to_file_as(path, ManifestFormat::Plain)
+    }
+
+    /// Save scan results to a JSON file in the given format
+    pub fn save_to_file_as(&self, path: &Path, format: ManifestFormat) -> Result<()> {
+        let json = match format {
+            ManifestFormat::Plain => serde_json::to_string_pretty(self)?,
+            ManifestFormat::FrontCoded => serde_json::to_string(&self.front_coded()?)?,
+        };
+        write_file_atomic(path, json.as_bytes())?;
         Ok(())
     }
 
-    /// Load scan results from a JSON file
+    /// Load scan results from a JSON file written in any [`ManifestFormat`]
     pub fn load_from_file(path: &Path) -> Result<Self> {
         let json = fs::read_to_string(path)?;
-        let scan = serde_json::from_str(&json)?;
-        Ok(scan)
+        let value: serde_json::Value = serde_json::from_str(&json)?;
+        match value.get("format_version").and_then(|v| v.as_u64()) {
+            None => Ok(serde_json::from_value(value)?),
+            Some(version) if version == u64::from(MANIFEST_FORMAT_VERSION) => {
+                Self::from_front_coded(value)
+            },
+            Some(version) => Err(SyncError::Io(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("unsupported manifest format version {version}"),
+            ))
+            .into()),
+        }
+    }
+
+    /// Encode as a [`ManifestFormat::FrontCoded`] JSON value
+    fn front_coded(&self) -> Result<serde_json::Value> {
+        let mut files = self
+            .files
+            .iter()
+            .map(|file| match portable_path(&file.path) {
+                Some(path) => Ok((file, path)),
+                None => Err(SyncError::InvalidPath {
+                    path: file.path.clone(),
+                    reason: "not valid UTF-8".to_string(),
+                }),
+            })
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        files.sort_by(|a, b| a.0.path.cmp(&b.0.path));
+
+        let mut previous = "";
+        let mut entries = Vec::with_capacity(files.len());
+        for (file, path) in &files {
+            let shared = previous
+                .char_indices()
+                .zip(path.chars())
+                .find(|((_, a), b)| a != b)
+                .map_or_else(|| previous.len().min(path.len()), |((i, _), _)| i);
+
+            let mut entry = serde_json::to_value(file)?;
+            if let Some(fields) = entry.as_object_mut() {
+                fields.remove("path");
+                fields.insert("shared".into(), shared.into());
+                fields.insert("suffix".into(), path[shared..].into());
+            }
+            entries.push(entry);
+            previous = path;
+        }
+
+        Ok(serde_json::json!({
+            "format_version": MANIFEST_FORMAT_VERSION,
+            "root": self.root,
+            "scan_time": self.scan_time.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs(),
+            "files": entries,
+        }))
+    }
+
+    /// Decode a [`ManifestFormat::FrontCoded`] JSON value
+    fn from_front_coded(mut value: serde_json::Value) -> Result<Self> {
+        let invalid = |what: &str| {
+            SyncError::Io(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("malformed front-coded manifest: {what}"),
+            ))
+        };
+
+        let entries = match value.get_mut("files").map(serde_json::Value::take) {
+            Some(serde_json::Value::Array(entries)) => entries,
+            _ => return Err(invalid("missing files").into()),
+        };
+        let mut previous = String::new();
+        let mut files = Vec::with_capacity(entries.len());
+        for mut entry in entries {
+            let fields = entry.as_object_mut().ok_or_else(|| invalid("file entry"))?;
+            let shared = fields.remove("shared").and_then(|v| v.as_u64());
+            let suffix = fields.remove("suffix");
+            let (Some(shared), Some(serde_json::Value::String(suffix))) = (shared, suffix) else {
+                return Err(invalid("path encoding").into());
+            };
+            let prefix = previous
+                .get(..shared as usize)
+                .ok_or_else(|| invalid("shared prefix out of range"))?;
+            let path = format!("{prefix}{suffix}");
+            fields.insert("path".into(), path.clone().into());
+            files.push(serde_json::from_value(entry)?);
+            previous = path;
+        }
+
+        let scan = serde_json::json!({
+            "root": value["root"].take(),
+            "scan_time": value["scan_time"].take(),
+            "files": [],
+        });
+        Ok(Self { files, ..serde_json::from_value(scan)? })
     }
 }
 
 /// Result of comparing two scans
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct DiffResult {
     /// Files present in source but not in destination
     pub added: Vec<FileMeta>,
@@ -113,378 +782,4611 @@ pub struct DiffResult {
     /// Files present in both but with different content
     pub modified: Vec<FileMeta>,
     /// Files that were renamed (old, new)
+    ///
+    /// Renames can chain: the old path may itself receive another file's
+    /// content through `modified` (A→B while C→A). Syncing writes every
+    /// target from the source and only clears an old path nothing refills,
+    /// so no ordering between the links of a chain is needed.
     pub renamed: Vec<(FileMeta, FileMeta)>,
+    /// Destination files that differ from the source but were kept because
+    /// they are newer (only with `DiffOptions::newer_wins`)
+    pub kept_newer: Vec<FileMeta>,
+    /// Files with identical content whose permissions or owner differ
+    /// (only with `DiffOptions::compare_metadata`)
+    pub metadata_changed: Vec<FileMeta>,
+    /// The source scan's drained FIFOs, which copies read from
+    pub fifo_spool: Option<FifoSpool>,
 }
 
-/// Options for sync operations
+/// How files present at the same path in both scans are compared
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CompareMode {
+    /// Files are unchanged only if their content hashes match
+    #[default]
+    Checksum,
+    /// Files with equal size and mtime (within `mtime_tolerance`) are unchanged
+    /// without looking at content; otherwise content hashes decide
+    SizeMtime,
+}
+
+/// Unicode normalization form applied to paths before comparing them
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NfForm {
+    /// Canonical composition (typical on Linux and Windows)
+    Nfc,
+    /// Canonical decomposition (used by macOS filesystems)
+    Nfd,
+}
+
+/// Options for comparing scans
 #[derive(Debug, Clone)]
-pub struct SyncOptions {
-    /// Delete files in destination not present in source
-    pub delete_removed: bool,
-    /// Preserve file timestamps
-    pub preserve_timestamps: bool,
-    /// Verify file hash after copying
-    pub verify_after_copy: bool,
+pub struct DiffOptions {
+    /// How files at the same path are compared
+    pub compare: CompareMode,
+    /// Largest mtime difference still treated as equal in [`CompareMode::SizeMtime`]
+    ///
+    /// This is the equivalent of rsync's `--modify-window`, useful for
+    /// filesystems with coarse timestamps (FAT has 2 second resolution). It is
+    /// ignored when comparing by checksum.
+    pub mtime_tolerance: Duration,
+    /// Keep destination files that are newer than the source version
+    /// instead of overwriting them (rsync's `--update`)
+    pub newer_wins: bool,
+    /// How far the destination clock runs ahead of the source clock
+    ///
+    /// Subtracted from destination mtimes before newer-wins comparisons, so
+    /// that a destination machine with a fast clock doesn't make stale files
+    /// look newer than fresh source edits. See [`detect_clock_skew`].
+    pub clock_skew: Duration,
+    /// Normalize relative paths to this form before matching them
+    ///
+    /// Lets the same name stored as NFC on one side and NFD on the other be
+    /// treated as one file. Files keep the source's original form when synced.
+    pub unicode_normalization: Option<NfForm>,
+    /// Confirm equal hashes with a byte-for-byte comparison before trusting them
+    ///
+    /// Guards against hash collisions: a file whose hash matches but whose bytes
+    /// differ is reported as modified (or not paired as a rename). Requires
+    /// both scan roots to be readable.
+    pub collision_check: bool,
+    /// Report unchanged files whose metadata hash differs as `metadata_changed`
+    pub compare_metadata: bool,
+    /// Pair removed and added files with identical content as renames
+    ///
+    /// When false, a moved file is reported as one removal plus one addition.
+    /// The result then no longer depends on path similarity or scan order,
+    /// which keeps diffs deterministic for CI checks.
+    pub detect_renames: bool,
+    /// Smallest file size considered for rename pairing
+    ///
+    /// Small files such as config stubs and markers often share content by
+    /// coincidence, so pairing them by hash produces misleading renames.
+    /// Files smaller than this are always reported as a removal plus an
+    /// addition. The default of 0 pairs every file; 1 leaves out only empty
+    /// files.
+    pub rename_min_size: u64,
+    /// Compare only which relative paths exist, ignoring content entirely
+    ///
+    /// Paths present on one side only are reported as added or removed;
+    /// files at the same path always count as unchanged, and nothing is
+    /// paired as a rename. No file is hashed or read, so scans made with
+    /// [`HashPolicy::OnConflict`] never need their deferred hashes. Useful
+    /// for checking that a tree has the layout of a template.
+    pub structure_only: bool,
 }
 
-impl Default for SyncOptions {
+impl Default for DiffOptions {
     fn default() -> Self {
         Self {
-            delete_removed: false,
-            preserve_timestamps: true,
-            verify_after_copy: false,
+            compare: CompareMode::default(),
+            mtime_tolerance: Duration::ZERO,
+            newer_wins: false,
+            clock_skew: Duration::ZERO,
+            unicode_normalization: None,
+            collision_check: false,
+            compare_metadata: false,
+            detect_renames: true,
+            rename_min_size: 0,
+            structure_only: false,
         }
     }
 }
 
-/// Scan a directory and compute content hashes for all files
-///
-/// This function walks the directory tree in parallel, computing content hashes
-/// for each file using streaming I/O to minimize memory usage.
-///
-/// # Arguments
-///
-/// * `root` - Root directory to scan
-/// * `progress` - Optional progress reporter
-///
-/// # Performance
-///
-/// - Uses `ignore` crate for parallel directory traversal
-/// - Hashes files in parallel using `rayon`
-/// - Streaming hash computation for constant memory usage
-/// - Respects .gitignore patterns for efficiency
-pub fn scan_directory(root: &Path, progress: Option<&ProgressReporter>) -> Result<ScanResult> {
-    if !root.exists() {
-        return Err(SyncError::InvalidPath(format!(
-            "Directory does not exist: {}",
-            root.display()
-        ))
-        .into());
+impl DiffOptions {
+    /// Check for option combinations that contradict each other
+    pub fn validate(&self) -> std::result::Result<(), Vec<OptionConflict>> {
+        let mut conflicts = Vec::new();
+        if !self.clock_skew.is_zero() && !self.newer_wins {
+            conflicts.push(OptionConflict::new(
+                &["clock_skew", "newer_wins"],
+                "clock skew only affects newer-wins comparisons",
+            ));
+        }
+        if !self.mtime_tolerance.is_zero() && self.compare == CompareMode::Checksum {
+            conflicts.push(OptionConflict::new(
+                &["mtime_tolerance", "compare"],
+                "mtime tolerance is ignored when comparing by checksum",
+            ));
+        }
+        conflicts_to_result(conflicts)
     }
 
-    if progress.is_some() {
-        println!("Scanning: {}", root.display());
+    /// Key used to match paths between scans
+    fn path_key<'a>(&self, path: &'a Path) -> Cow<'a, Path> {
+        let (Some(form), Some(s)) = (self.unicode_normalization, path.to_str()) else {
+            return Cow::Borrowed(path);
+        };
+        let normalized: String = match form {
+            NfForm::Nfc => s.nfc().collect(),
+            NfForm::Nfd => s.nfd().collect(),
+        };
+        if normalized == s {
+            Cow::Borrowed(path)
+        } else {
+            Cow::Owned(PathBuf::from(normalized))
+        }
     }
 
-    // Collect all file paths first
-    let walker = ignore::WalkBuilder::new(root)
-        .hidden(false)
-        .git_ignore(true)
-        .git_exclude(true)
-        .threads(num_cpus::get())
-        .build_parallel();
-
-    let files = std::sync::Mutex::new(Vec::new());
-
-    walker.run(|| {
-        Box::new(|entry_result| {
-            if let Ok(entry) = entry_result {
-                if let Some(file_type) = entry.file_type() {
-                    if file_type.is_file() {
-                        files.lock().unwrap().push(entry.path().to_path_buf());
-                    }
-                }
-            }
-            ignore::WalkState::Continue
-        })
-    });
+    /// Check whether two files at the same path should be treated as unchanged
+    fn unchanged(&self, source: &FileMeta, dest: &FileMeta) -> bool {
+        if self.structure_only {
+            return true;
+        }
+        match self.compare {
+            CompareMode::Checksum => source.hash == dest.hash,
+            CompareMode::SizeMtime => {
+                (source.size == dest.size && self.mtime_matches(source.mtime, dest.mtime))
+                    || source.hash == dest.hash
+            },
+        }
+    }
 
-    let file_paths = files.into_inner().unwrap();
-    let total_files = file_paths.len();
+    /// Confirm that two files with equal hashes really have the same bytes
+    ///
+    /// Always true unless `collision_check` is set. Unreadable files count as
+    /// different so they get resynced rather than silently skipped.
+    fn confirm_identical(
+        &self,
+        source_root: &Path,
+        source: &FileMeta,
+        dest_root: &Path,
+        dest: &FileMeta,
+    ) -> bool {
+        if self.structure_only || !self.collision_check || source.hash != dest.hash {
+            return true;
+        }
+        verify_files_identical(&source_root.join(&source.path), &dest_root.join(&dest.path))
+            .unwrap_or(false)
+    }
 
-    if progress.is_some() {
-        println!("Found {total_files} files, computing hashes...");
+    /// Check whether the destination file is newer, after correcting for clock skew
+    fn dest_is_newer(&self, source: &FileMeta, dest: &FileMeta) -> bool {
+        let dest_mtime = dest.mtime.checked_sub(self.clock_skew).unwrap_or(dest.mtime);
+        dest_mtime > source.mtime
     }
 
-    // Hash files in parallel
-    let file_metas: Vec<Result<FileMeta>> = file_paths
-        .par_iter()
-        .map(|path| {
-            let metadata = fs::metadata(path)?;
-            let size = metadata.len();
-            let mtime = metadata.modified()?;
-
-            // Get permissions on Unix systems
-            #[cfg(unix)]
-            let permissions = {
-                use std::os::unix::fs::PermissionsExt;
-                Some(metadata.permissions().mode())
-            };
-            #[cfg(not(unix))]
-            let permissions = None;
+    /// Check whether two mtimes are equal within the configured tolerance
+    fn mtime_matches(&self, a: SystemTime, b: SystemTime) -> bool {
+        let delta = a.duration_since(b).unwrap_or_else(|e| e.duration());
+        delta <= self.mtime_tolerance
+    }
+}
 
-            // Compute content hash using streaming
-            let mut hasher = Hasher::new();
-            hasher.hash_file(path)?;
-            let hash = hasher.finalize();
-
-            // Make path relative to root
-            let rel_path = path
-                .strip_prefix(root)
-                .map_err(|_| {
-                    SyncError::InvalidPath(format!("Path not under root: {}", path.display()))
-                })?
-                .to_path_buf();
-
-            Ok(FileMeta {
-                path: rel_path,
-                size,
-                mtime,
-                hash,
-                permissions,
-            })
-        })
-        .collect();
+/// How a file that already exists in the destination is replaced
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OverwriteStrategy {
+    /// Truncate and rewrite the existing file
+    #[default]
+    InPlace,
+    /// Remove the existing file first, then write a fresh one
+    ///
+    /// Needed for destinations that cannot overwrite in place.
+    DeleteThenWrite,
+    /// Write to a temporary sibling, then rename it over the existing file
+    TempThenRename,
+}
 
-    // Collect results, logging errors but not failing the entire scan
-    let mut successful_files = Vec::new();
-    let mut error_count = 0;
+/// Settings for [`SyncOptions::small_file_batching`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SmallFileBatchConfig {
+    /// Largest file that is batched, in bytes
+    pub max_file_size: u64,
+    /// Most content bytes packed into one staging archive
+    pub max_batch_bytes: u64,
+}
 
-    for result in file_metas {
-        match result {
-            Ok(meta) => successful_files.push(meta),
-            Err(e) => {
-                error_count += 1;
-                eprintln!("Warning: Failed to process file: {e}");
-            },
+impl Default for SmallFileBatchConfig {
+    fn default() -> Self {
+        Self {
+            max_file_size: 64 * 1024,
+            max_batch_bytes: 8 * 1024 * 1024,
         }
     }
+}
 
-    if error_count > 0 {
-        eprintln!("Warning: {error_count} files could not be processed");
-    }
-
-    Ok(ScanResult {
-        root: root.to_path_buf(),
-        files: successful_files,
-        scan_time: SystemTime::now(),
-    })
+/// What to do when planned destination paths differ only by case and the
+/// destination filesystem is case-insensitive
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CaseConflictPolicy {
+    /// Abort the sync before writing anything
+    #[default]
+    Error,
+    /// Keep the first path (in byte order) and skip the others
+    SkipSecond,
+    /// Write the others under a numbered name, e.g. `foo (1).txt`
+    Rename,
 }
 
-/// Compare two scan results and identify differences
-///
-/// This function performs intelligent diff computation with rename detection:
-/// 1. Build hash maps for fast lookup
-/// 2. Identify added/removed/modified files
-/// 3. Detect renames by matching content hashes
-/// 4. Use path similarity as fallback for ambiguous renames
+/// What to do when a rename's target path already holds a different file
 ///
-/// # Performance
-///
-/// - O(n) hash map construction
-/// - O(1) lookups for most operations
-/// - Rename detection is O(n*m) worst case but typically O(n) with hash matching
-pub fn diff_scans(source: &ScanResult, dest: &ScanResult) -> Result<DiffResult> {
-    // Build hash maps for fast lookup
-    let source_by_path: HashMap<&PathBuf, &FileMeta> =
-        source.files.iter().map(|f| (&f.path, f)).collect();
-    let dest_by_path: HashMap<&PathBuf, &FileMeta> =
-        dest.files.iter().map(|f| (&f.path, f)).collect();
+/// Rename detection only knows the scanned destination, so a file it missed
+/// (ignored, outside the scan filters, or created since the scan) can sit
+/// at the new path.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OccupiedRenamePolicy {
+    /// Leave both files alone and report [`SkipReason::TargetOccupied`]
+    #[default]
+    Skip,
+    /// Move the existing file to `name.janus-backup.ext`, or the first free
+    /// `name.janus-backup-N.ext` if an earlier backup is there, then rename
+    Backup,
+    /// Overwrite the existing file
+    Overwrite,
+}
 
-    // Build hash-to-files maps for rename detection
-    let mut source_by_hash: HashMap<&ContentHash, Vec<&FileMeta>> = HashMap::new();
-    for file in &source.files {
+/// When `delete_removed` deletions run relative to the other sync phases
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DeleteTiming {
+    /// Before any copies, freeing space on a nearly full destination
+    Before,
+    /// After copies, before renames and metadata updates
+    During,
+    /// Once everything else is done, so the destination never lacks a file
+    /// that is still on its way
+    #[default]
+    After,
+}
+
+/// Which planned transfers run first, and survive [`SyncOptions::limit`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum TransferOrder {
+    /// Biggest files first, e.g. to get large media moving overnight
+    LargestFirst,
+    /// Smallest files first, to land as many files as possible
+    SmallestFirst,
+    /// Most recently modified files first
+    NewestFirst,
+    /// The plan's own order: copies, then renames, each in scan path order
+    #[default]
+    PathOrder,
+}
+
+impl TransferOrder {
+    /// Compare two files so the one to transfer first sorts first
+    ///
+    /// Ties compare equal, so a stable sort keeps them in plan order.
+    fn compare(self, a: &FileMeta, b: &FileMeta) -> std::cmp::Ordering {
+        match self {
+            TransferOrder::LargestFirst => b.size.cmp(&a.size),
+            TransferOrder::SmallestFirst => a.size.cmp(&b.size),
+            TransferOrder::NewestFirst => b.mtime.cmp(&a.mtime),
+            TransferOrder::PathOrder => std::cmp::Ordering::Equal,
+        }
+    }
+}
+
+/// Options for sync operations
+#[derive(Debug, Clone)]
+pub struct SyncOptions {
+    /// Delete files in destination not present in source
+    pub delete_removed: bool,
+    /// Preserve file timestamps
+    pub preserve_timestamps: bool,
+    /// Fail a copy whose modification time can't be set
+    ///
+    /// By default timestamps are preserved on a best-effort basis: some
+    /// filesystems, such as certain network mounts, refuse to set them, and
+    /// the copy is kept with the failure listed in [`SyncReport::warnings`].
+    pub strict_metadata: bool,
+    /// Verify file hash after copying
+    ///
+    /// Each copy is re-read and hashed; a copy whose hash differs from the
+    /// source's fails with [`SyncError::CopyError`]. Files scanned with
+    /// [`ScanOptions::hash_skip_ranges`] carry a partial hash and so fail,
+    /// which is why [`ReconcileOptions::validate`] rejects the combination.
+    pub verify_after_copy: bool,
+    /// Drop each copy from the page cache before the verify re-read (Linux only)
+    ///
+    /// Without this the re-read is usually served from the cache the copy
+    /// just filled, which can hide data that never made it to disk. The copy
+    /// is flushed and `posix_fadvise(DONTNEED)` asks the kernel to evict it,
+    /// so the hash reflects what the disk returns. Ignored on other systems.
+    pub verify_bypass_cache: bool,
+    /// Keep copies from filling the page cache (Linux only)
+    ///
+    /// Each source is read with `POSIX_FADV_SEQUENTIAL` readahead, and both
+    /// it and its copy get `POSIX_FADV_DONTNEED` once written, so a large
+    /// sync doesn't evict other processes' cached data. Ignored on other
+    /// systems and for small-file batches.
+    pub fadvise: bool,
+    /// Only sync files whose content hash is in this set (others are skipped)
+    pub content_allowlist: Option<HashSet<ContentHash>>,
+    /// Never write files whose content hash is in this set
+    ///
+    /// Blocked files are skipped as [`SkipReason::Blocked`], and take
+    /// precedence over the allowlist.
+    pub content_blocklist: Option<HashSet<ContentHash>>,
+    /// Abort before writing anything if any planned file is blocked
+    pub fail_on_blocked: bool,
+    /// Maximum bytes to copy in this run
+    ///
+    /// Copies and renames that would exceed the quota are deferred as
+    /// [`SkipReason::QuotaReached`]; every file that is written is complete.
+    pub byte_quota: Option<u64>,
+    /// How existing destination files are replaced
+    pub overwrite_strategy: OverwriteStrategy,
+    /// Directory for the temporary files of [`OverwriteStrategy::TempThenRename`]
+    ///
+    /// Defaults to a sibling of each destination file. A scratch directory
+    /// must be on the destination's filesystem for the final rename to stay
+    /// atomic; if it isn't, the sync warns and falls back to siblings.
+    pub temp_dir: Option<PathBuf>,
+    /// Copy tiny files together through staging archives
+    ///
+    /// Packs files under the size threshold into in-memory archives that are
+    /// unpacked with one durability sync each, instead of paying open, sync
+    /// and metadata round trips per file. Only applies with
+    /// [`OverwriteStrategy::InPlace`]; files of an archive that fails are
+    /// retried one by one.
+    pub small_file_batching: Option<SmallFileBatchConfig>,
+    /// Order in which copies and renames run, see [`TransferOrder`]
+    pub transfer_order: TransferOrder,
+    /// Most copies and renames to run, picked in `transfer_order`
+    ///
+    /// The rest are deferred as [`SkipReason::LimitReached`] for a later run.
+    /// Metadata updates and deletions aren't transfers and aren't limited.
+    pub limit: Option<usize>,
+    /// Record failures in the report and keep going instead of aborting
+    pub continue_on_error: bool,
+    /// Only keep aggregate counts in the report, not the per-operation log
+    ///
+    /// Saves memory on very large syncs.
+    pub aggregate_only: bool,
+    /// Cancellation flag checked before each operation starts
+    ///
+    /// Once set, operations already in progress finish (so no file is left
+    /// half-written) and the remaining ones are skipped as
+    /// [`SkipReason::Cancelled`].
+    pub cancel: Option<Arc<AtomicBool>>,
+    /// Extra rounds for reattempting transiently-failed copies and renames
+    ///
+    /// Files that fail with a transient error (locked, busy, timed out) are
+    /// queued and retried after the main pass, so only files that keep failing
+    /// are reported as failures. `0`, the default, reports them right away.
+    pub retry_rounds: u32,
+    /// Delay before the first retry round, doubled for each later round
+    pub retry_backoff: Duration,
+    /// How to handle planned paths that collide on a case-insensitive destination
+    pub case_conflict_policy: CaseConflictPolicy,
+    /// Whether the destination filesystem ignores case (`None` probes it at runtime)
+    pub dest_case_insensitive: Option<bool>,
+    /// Re-check metadata-only updates against the destination before writing
+    ///
+    /// Files whose permissions and owner already match on disk are skipped as
+    /// [`SkipReason::UpToDate`] without any write syscalls, sparing flash wear
+    /// and filesystem-event noise when the destination scan is stale.
+    pub minimize_writes: bool,
+    /// Skip every deletion if any copy or rename failed
+    ///
+    /// Under `continue_on_error` a failed copy would otherwise still be
+    /// followed by the delete phase, which can remove the only remaining
+    /// destination copy of data that was meant to be replaced. Held-back
+    /// deletes are reported as [`SkipReason::CopiesFailed`].
+    pub delete_only_if_copies_succeed: bool,
+    /// Copy file content and nothing else, for maximum speed
+    ///
+    /// A single switch overriding the individual preserve options: no
+    /// timestamps, permissions or ownership are applied to copies, so new
+    /// files get the current time and default permissions. Metadata-only
+    /// updates are skipped as [`SkipReason::ContentOnly`].
+    pub content_only: bool,
+    /// Globs of destination paths that are never modified or deleted
+    ///
+    /// Guards files local to a shared destination (`.env`, local config)
+    /// regardless of what the source holds: planned writes and deletes of
+    /// matching relative paths are skipped as [`SkipReason::Protected`].
+    /// Unlike source excludes, protected files still appear in the diff.
+    pub protect: Vec<String>,
+    /// Globs of destination-only paths that `delete_removed` leaves in place
+    ///
+    /// Keeps sanctioned local-only files (logs, caches) in an otherwise exact
+    /// mirror. Matching removals are skipped as [`SkipReason::Kept`]; unlike
+    /// `protect`, files the source does have are still updated.
+    pub keep: Vec<String>,
+    /// How to handle a rename whose target path already holds a different file
+    pub occupied_rename_policy: OccupiedRenamePolicy,
+    /// Set each copy's access time to the source's, as recorded by the scan
+    ///
+    /// Setting an access time explicitly works on `noatime` mounts too, but
+    /// there nothing updates it afterwards. Filesystems that can't store it
+    /// leave the destination's own access time.
+    pub preserve_atime: bool,
+    /// Set each copy's creation time to the source's (macOS and Windows)
+    ///
+    /// Linux has no call to set a birth time, so this is a no-op there.
+    pub preserve_btime: bool,
+    /// Where the delete phase runs, see [`DeleteTiming`]
+    pub delete_timing: DeleteTiming,
+    /// JSON file rewritten with the sync's progress, see [`StatusFile`]
+    ///
+    /// Lets a dashboard poll a headless sync independently of stdout.
+    pub status_file: Option<PathBuf>,
+    /// How often `status_file` is rewritten, by default as often as the
+    /// progress spinners redraw
+    pub status_interval: Duration,
+    /// Run copies and renames in ordered batches of this many operations
+    ///
+    /// Each batch finishes before the next one starts, so huge plans don't
+    /// flood the I/O scheduler and latency stays predictable. Files within
+    /// a batch are still copied in parallel. `None` runs each phase as one
+    /// batch.
+    pub copy_batch_size: Option<usize>,
+    /// Called after each copy batch, see [`SyncOptions::with_batch_hook`]
+    pub batch_hook: Option<BatchHook>,
+    /// Create every directory the source scan walked into at the destination
+    ///
+    /// By default only the parents of synced files are created, so a
+    /// directory whose files were all filtered out, or that is empty in the
+    /// source, doesn't appear in the destination. With this set the source's
+    /// directory skeleton is replicated as well, which also preserves empty
+    /// directories. Directories the walk skipped entirely (VCS directories,
+    /// gitignored directories, ones with an
+    /// [`ScanOptions::exclude_if_present`] marker) are still left out, and
+    /// deletion never removes directories. Applied by [`ReconcilePlan::apply`]
+    /// from [`ScanResult::traversed_dirs`]; [`sync_changes`] only has the diff
+    /// and ignores it.
+    pub replicate_empty_parents: bool,
+}
+
+/// Closure run between copy batches, set via [`SyncOptions::with_batch_hook`]
+#[derive(Clone)]
+pub struct BatchHook(Arc<dyn Fn(usize) + Send + Sync>);
+
+impl fmt::Debug for BatchHook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("BatchHook(..)")
+    }
+}
+
+impl Default for SyncOptions {
+    fn default() -> Self {
+        Self {
+            delete_removed: false,
+            preserve_timestamps: true,
+            strict_metadata: false,
+            verify_after_copy: false,
+            verify_bypass_cache: false,
+            fadvise: false,
+            content_allowlist: None,
+            content_blocklist: None,
+            fail_on_blocked: false,
+            byte_quota: None,
+            overwrite_strategy: OverwriteStrategy::InPlace,
+            temp_dir: None,
+            small_file_batching: None,
+            transfer_order: TransferOrder::PathOrder,
+            limit: None,
+            continue_on_error: false,
+            aggregate_only: false,
+            cancel: None,
+            retry_rounds: 0,
+            retry_backoff: Duration::from_millis(100),
+            case_conflict_policy: CaseConflictPolicy::Error,
+            dest_case_insensitive: None,
+            minimize_writes: false,
+            delete_only_if_copies_succeed: false,
+            content_only: false,
+            protect: Vec::new(),
+            keep: Vec::new(),
+            occupied_rename_policy: OccupiedRenamePolicy::Skip,
+            preserve_atime: false,
+            preserve_btime: false,
+            delete_timing: DeleteTiming::After,
+            status_file: None,
+            status_interval: PROGRESS_INTERVAL,
+            copy_batch_size: None,
+            batch_hook: None,
+            replicate_empty_parents: false,
+        }
+    }
+}
+
+impl SyncOptions {
+    /// Check for option combinations that contradict each other or can't take effect
+    pub fn validate(&self) -> std::result::Result<(), Vec<OptionConflict>> {
+        let mut conflicts = Vec::new();
+        if self.fail_on_blocked && self.content_blocklist.is_none() {
+            conflicts.push(OptionConflict::new(
+                &["fail_on_blocked", "content_blocklist"],
+                "there is no blocklist to fail on",
+            ));
+        }
+        if let (Some(allow), Some(block)) = (&self.content_allowlist, &self.content_blocklist) {
+            if !allow.is_disjoint(block) {
+                conflicts.push(OptionConflict::new(
+                    &["content_allowlist", "content_blocklist"],
+                    "a content hash is both allowed and blocked",
+                ));
+            }
+        }
+        if self.delete_only_if_copies_succeed && !self.delete_removed {
+            conflicts.push(OptionConflict::new(
+                &["delete_only_if_copies_succeed", "delete_removed"],
+                "nothing is deleted without delete_removed",
+            ));
+        }
+        if self.content_only && self.minimize_writes {
+            conflicts.push(OptionConflict::new(
+                &["content_only", "minimize_writes"],
+                "metadata updates are skipped entirely under content_only",
+            ));
+        }
+        if self.content_only && (self.preserve_atime || self.preserve_btime) {
+            conflicts.push(OptionConflict::new(
+                &["content_only", "preserve_atime", "preserve_btime"],
+                "content-only copies carry no timestamps",
+            ));
+        }
+        if self.status_file.is_some() && self.status_interval.is_zero() {
+            conflicts.push(OptionConflict::new(
+                &["status_file", "status_interval"],
+                "the status file needs a non-zero rewrite interval",
+            ));
+        }
+        if self.copy_batch_size == Some(0) {
+            conflicts.push(OptionConflict::new(
+                &["copy_batch_size"],
+                "batches must hold at least one operation",
+            ));
+        }
+        if let Err(e) = self.protected_paths() {
+            conflicts.push(OptionConflict::new(&["protect"], e.to_string()));
+        }
+        if let Err(e) = self.kept_paths() {
+            conflicts.push(OptionConflict::new(&["keep"], e.to_string()));
+        }
+        if self.delete_only_if_copies_succeed && self.delete_timing == DeleteTiming::Before {
+            conflicts.push(OptionConflict::new(
+                &["delete_only_if_copies_succeed", "delete_timing"],
+                "deleting before copies can't wait for them to succeed",
+            ));
+        }
+        if self.temp_dir.is_some() && self.overwrite_strategy != OverwriteStrategy::TempThenRename {
+            conflicts.push(OptionConflict::new(
+                &["temp_dir", "overwrite_strategy"],
+                "only the temp-then-rename strategy writes temporary files",
+            ));
+        }
+        if self.verify_bypass_cache && !self.verify_after_copy {
+            conflicts.push(OptionConflict::new(
+                &["verify_bypass_cache", "verify_after_copy"],
+                "there is no verify re-read without verify_after_copy",
+            ));
+        }
+        if self.small_file_batching.is_some()
+            && self.overwrite_strategy != OverwriteStrategy::InPlace
+        {
+            conflicts.push(OptionConflict::new(
+                &["small_file_batching", "overwrite_strategy"],
+                "staging archives are unpacked in place",
+            ));
+        }
+        if !self.keep.is_empty() && !self.delete_removed {
+            conflicts.push(OptionConflict::new(
+                &["keep", "delete_removed"],
+                "nothing is deleted without delete_removed",
+            ));
+        }
+        conflicts_to_result(conflicts)
+    }
+
+    /// Run `hook` each time a copy or rename batch completes
+    ///
+    /// The hook receives the number of operations in the finished batch and
+    /// runs before the next batch starts, making it a natural place for
+    /// progress checkpoints. Retries of transient failures happen after the
+    /// last batch and don't trigger it.
+    pub fn with_batch_hook(mut self, hook: impl Fn(usize) + Send + Sync + 'static) -> Self {
+        self.batch_hook = Some(BatchHook(Arc::new(hook)));
+        self
+    }
+
+    /// Copy a file into the destination using the configured overwrite strategy
+    ///
+    /// Returns the error from setting the modification time when that failed
+    /// without failing the copy, see `strict_metadata`. `progress` advances
+    /// as each buffer is written, so even a single huge file moves the bar.
+    fn copy_into_place(
+        &self,
+        source: &Path,
+        dest: &Path,
+        progress: Option<&ParallelProgress>,
+    ) -> std::io::Result<Option<std::io::Error>> {
+        let on_copied = |bytes| {
+            if let Some(progress) = progress {
+                progress.inc_by(bytes);
+            }
+        };
+        let mut mtime_error = None;
+        let mut copy = |dest: &Path| {
+            if self.content_only {
+                return copy_file_streaming(source, dest, self.fadvise, &on_copied);
+            }
+            let error = copy_file_tolerating_mtime_errors(
+                source,
+                dest,
+                self.preserve_timestamps,
+                self.fadvise,
+                &on_copied,
+            )?;
+            match error {
+                Some(e) if self.strict_metadata => Err(e),
+                error => {
+                    mtime_error = error;
+                    Ok(())
+                },
+            }
+        };
+        match self.overwrite_strategy {
+            OverwriteStrategy::InPlace => copy(dest),
+            OverwriteStrategy::DeleteThenWrite => {
+                remove_file_safe(dest)?;
+                copy(dest)
+            },
+            OverwriteStrategy::TempThenRename => match &self.temp_dir {
+                Some(temp_dir) => replace_via_temp_in(temp_dir, dest, copy),
+                None => replace_via_temp(dest, copy),
+            },
+        }?;
+        Ok(mtime_error)
+    }
+
+    /// Re-hash a fresh copy and compare it with the source, if `verify_after_copy` is set
+    fn verify_copy(&self, source: &Path, dest: &Path, file: &FileMeta) -> Result<()> {
+        if !self.verify_after_copy {
+            return Ok(());
+        }
+        #[cfg(target_os = "linux")]
+        if self.verify_bypass_cache {
+            drop_from_page_cache(dest)?;
+        }
+        let expected = if file.hash_deferred {
+            hash_file(source)?
+        } else {
+            file.hash.clone()
+        };
+        if hash_file(dest)? != expected {
+            return Err(SyncError::CopyError {
+                path: dest.to_path_buf(),
+                reason: "does not match the source after copying".to_string(),
+            }
+            .into());
+        }
+        Ok(())
+    }
+
+    /// Apply the access and creation times `preserve_atime` and `preserve_btime` ask for
+    fn preserve_extended_times(&self, dest: &Path, file: &FileMeta) -> std::io::Result<()> {
+        let atime = file.atime.filter(|_| self.preserve_atime && !self.content_only);
+        let btime = file.btime.filter(|_| self.preserve_btime && !self.content_only);
+        if atime.is_none() && btime.is_none() {
+            return Ok(());
+        }
+        set_file_times(dest, atime, btime)
+    }
+
+    /// Compile the `protect` globs
+    fn protected_paths(&self) -> Result<globset::GlobSet> {
+        glob_set(&self.protect)
+    }
+
+    /// Compile the `keep` globs
+    fn kept_paths(&self) -> Result<globset::GlobSet> {
+        glob_set(&self.keep)
+    }
+
+    /// Check whether paths on the destination are matched case-insensitively
+    fn dest_is_case_insensitive(&self, dest_root: &Path) -> bool {
+        self.dest_case_insensitive
+            .unwrap_or_else(|| is_case_insensitive(dest_root).unwrap_or(false))
+    }
+
+    /// Check whether the sync has been cancelled
+    fn is_cancelled(&self) -> bool {
+        self.cancel.as_ref().is_some_and(|flag| flag.load(Ordering::Relaxed))
+    }
+
+    /// Check whether a file passes the content filters
+    fn allows(&self, file: &FileMeta) -> bool {
+        self.rejection(file).is_none()
+    }
+
+    /// Why a file is filtered out by the content blocklist or allowlist, if it is
+    fn rejection(&self, file: &FileMeta) -> Option<SkipReason> {
+        if self
+            .content_blocklist
+            .as_ref()
+            .is_some_and(|blocklist| blocklist.contains(&file.hash))
+        {
+            return Some(SkipReason::Blocked);
+        }
+        match &self.content_allowlist {
+            Some(allowlist) if !allowlist.contains(&file.hash) => Some(SkipReason::NotAllowlisted),
+            _ => None,
+        }
+    }
+}
+
+/// Why a planned operation was not applied
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SkipReason {
+    /// Content hash is not in `SyncOptions::content_allowlist`
+    NotAllowlisted,
+    /// Content hash is in `SyncOptions::content_blocklist`
+    Blocked,
+    /// The sync was cancelled before this operation started
+    Cancelled,
+    /// Another planned path differing only by case was kept instead
+    CaseConflict,
+    /// The destination already matched, so nothing was written
+    UpToDate,
+    /// Deferred because `SyncOptions::byte_quota` was used up
+    QuotaReached,
+    /// Deferred because `SyncOptions::limit` transfers were already planned
+    LimitReached,
+    /// Deletion held back because a copy failed, see
+    /// `SyncOptions::delete_only_if_copies_succeed`
+    CopiesFailed,
+    /// Metadata-only update ignored under `SyncOptions::content_only`
+    ContentOnly,
+    /// Destination path matches `SyncOptions::protect`
+    Protected,
+    /// Destination-only file matches `SyncOptions::keep`
+    Kept,
+    /// A rename target already held a different file, see
+    /// `SyncOptions::occupied_rename_policy`
+    TargetOccupied,
+}
+
+impl fmt::Display for SkipReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SkipReason::NotAllowlisted => write!(f, "not in content allowlist"),
+            SkipReason::Blocked => write!(f, "BLOCKED: content hash is blocklisted"),
+            SkipReason::Cancelled => write!(f, "cancelled"),
+            SkipReason::CaseConflict => write!(f, "case conflict on destination"),
+            SkipReason::UpToDate => write!(f, "already up to date"),
+            SkipReason::QuotaReached => write!(f, "deferred: byte quota reached"),
+            SkipReason::LimitReached => write!(f, "deferred: transfer limit reached"),
+            SkipReason::CopiesFailed => write!(f, "held back: a copy failed"),
+            SkipReason::ContentOnly => write!(f, "metadata not synced (content only)"),
+            SkipReason::Protected => write!(f, "protected on destination"),
+            SkipReason::Kept => write!(f, "kept on destination"),
+            SkipReason::TargetOccupied => write!(f, "rename target holds a different file"),
+        }
+    }
+}
+
+impl SkipReason {
+    /// Whether the destination state after this skip follows from the plan
+    /// alone, so a post-sync manifest can still describe it
+    pub fn leaves_dest_known(self) -> bool {
+        matches!(
+            self,
+            SkipReason::NotAllowlisted
+                | SkipReason::Blocked
+                | SkipReason::UpToDate
+                | SkipReason::LimitReached
+                | SkipReason::ContentOnly
+                | SkipReason::Protected
+                | SkipReason::Kept
+        )
+    }
+}
+
+/// A file that was deliberately left out of a sync
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SkippedFile {
+    /// Relative path of the file
+    pub path: PathBuf,
+    /// Why the file was skipped
+    pub reason: SkipReason,
+}
+
+/// A file whose operation failed (only recorded under `continue_on_error`)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FailedFile {
+    /// Relative path of the file
+    pub path: PathBuf,
+    /// Error message
+    pub error: String,
+}
+
+/// Kind of a planned sync operation
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OperationKind {
+    /// Copy a new or modified file
+    Copy,
+    /// Move a renamed file to its new path
+    Rename,
+    /// Delete a file missing from the source
+    Delete,
+    /// Update permissions or owner of a file whose content is unchanged
+    Metadata,
+}
+
+/// Outcome of a single planned sync operation
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OperationResult {
+    /// File content was written to the destination
+    Copied,
+    /// File now lives at its new path in the destination
+    Moved,
+    /// File was removed from the destination
+    Deleted,
+    /// File permissions or owner were updated without copying content
+    MetadataUpdated,
+    /// Operation was deliberately not applied
+    Skipped(SkipReason),
+    /// Operation failed with the given error
+    Failed(String),
+}
+
+/// Record of one planned operation and what happened to it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OperationOutcome {
+    /// Relative path the operation targets (the new path for renames)
+    pub path: PathBuf,
+    /// Kind of operation
+    pub kind: OperationKind,
+    /// Size of the file involved
+    pub bytes: u64,
+    /// What happened
+    pub result: OperationResult,
+}
+
+/// Summary of the changes applied by [`sync_changes`]
+#[derive(Debug, Clone, Default)]
+pub struct SyncReport {
+    /// Number of new or modified files copied
+    pub files_copied: usize,
+    /// Number of renames applied
+    pub files_renamed: usize,
+    /// Number of files deleted from the destination
+    pub files_deleted: usize,
+    /// Number of files whose metadata alone was updated
+    pub files_metadata_updated: usize,
+    /// Total bytes written to the destination
+    pub bytes_copied: u64,
+    /// Files that were skipped instead of synced
+    pub skipped: Vec<SkippedFile>,
+    /// Files whose operation failed (only populated under `continue_on_error`)
+    pub failed: Vec<FailedFile>,
+    /// Every planned operation with its outcome, in plan order
+    ///
+    /// Left empty when `SyncOptions::aggregate_only` is set.
+    pub outcomes: Vec<OperationOutcome>,
+    /// The sync was cancelled before all operations ran
+    pub interrupted: bool,
+    /// Problems that didn't stop the sync, such as an unusable `temp_dir`
+    pub warnings: Vec<String>,
+    /// Time spent in each phase
+    ///
+    /// [`sync_changes`] only fills in [`PhaseTimings::sync`]; reports from
+    /// [`ReconcilePlan::apply`] and [`reconcile`] cover every phase.
+    pub timings: PhaseTimings,
+}
+
+/// Wall-clock time spent in each phase of a run
+///
+/// Scanning is dominated by hashing and so is usually CPU-bound, while
+/// syncing is dominated by copying and so is usually I/O-bound. A slow scan
+/// benefits from more threads, a slow sync from faster disks.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PhaseTimings {
+    /// Walking and hashing both directories
+    pub scan: Duration,
+    /// Comparing the two scans
+    pub diff: Duration,
+    /// Applying the changes to the destination
+    pub sync: Duration,
+}
+
+impl PhaseTimings {
+    /// Sum of all phases
+    pub fn total(&self) -> Duration {
+        self.scan + self.diff + self.sync
+    }
+}
+
+impl SyncReport {
+    /// Record a file that was skipped before any work was attempted
+    fn skip(
+        &mut self,
+        options: &SyncOptions,
+        path: &Path,
+        kind: OperationKind,
+        reason: SkipReason,
+    ) {
+        let _ = self.record(options, path, kind, 0, Some(Ok(OperationResult::Skipped(reason))));
+    }
+
+    /// Record the result of an attempted operation
+    ///
+    /// Failures are returned as errors unless `continue_on_error` is set, in
+    /// which case they are recorded and the sync carries on. `None` marks an
+    /// operation that was never started because an earlier one failed.
+    fn record(
+        &mut self,
+        options: &SyncOptions,
+        path: &Path,
+        kind: OperationKind,
+        bytes: u64,
+        result: Option<Result<OperationResult>>,
+    ) -> Result<()> {
+        let result = match result {
+            Some(Ok(result)) => result,
+            Some(Err(e)) if !options.continue_on_error => return Err(e),
+            Some(Err(e)) => {
+                let error = format!("{e:#}");
+                self.failed.push(FailedFile {
+                    path: path.to_path_buf(),
+                    error: error.clone(),
+                });
+                OperationResult::Failed(error)
+            },
+            None => return Ok(()),
+        };
+
+        match result {
+            OperationResult::Copied => {
+                self.files_copied += 1;
+                self.bytes_copied += bytes;
+            },
+            OperationResult::Moved => {
+                self.files_renamed += 1;
+                self.bytes_copied += bytes;
+            },
+            OperationResult::Deleted => self.files_deleted += 1,
+            OperationResult::MetadataUpdated => self.files_metadata_updated += 1,
+            OperationResult::Skipped(reason) => {
+                self.skipped.push(SkippedFile { path: path.to_path_buf(), reason });
+            },
+            OperationResult::Failed(_) => {},
+        }
+        self.push_outcome(options, path, kind, bytes, result);
+
+        Ok(())
+    }
+
+    /// Append to the per-operation log unless running in aggregate-only mode
+    fn push_outcome(
+        &mut self,
+        options: &SyncOptions,
+        path: &Path,
+        kind: OperationKind,
+        bytes: u64,
+        result: OperationResult,
+    ) {
+        if !options.aggregate_only {
+            self.outcomes.push(OperationOutcome {
+                path: path.to_path_buf(),
+                kind,
+                bytes,
+                result,
+            });
+        }
+    }
+
+    /// Fold another report into this one
+    fn absorb(&mut self, other: SyncReport) {
+        self.files_copied += other.files_copied;
+        self.files_renamed += other.files_renamed;
+        self.files_deleted += other.files_deleted;
+        self.files_metadata_updated += other.files_metadata_updated;
+        self.bytes_copied += other.bytes_copied;
+        self.skipped.extend(other.skipped);
+        self.failed.extend(other.failed);
+        self.outcomes.extend(other.outcomes);
+        self.interrupted |= other.interrupted;
+        self.timings.scan += other.timings.scan;
+        self.timings.diff += other.timings.diff;
+        self.timings.sync += other.timings.sync;
+    }
+}
+
+/// A relative path provided with different content by more than one source
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceConflict {
+    /// Relative path of the conflicting file
+    pub path: PathBuf,
+    /// Source roots providing the path, in overlay order (the last one wins)
+    pub sources: Vec<PathBuf>,
+}
+
+/// Result of [`sync_many_to_one`]
+#[derive(Debug, Clone, Default)]
+pub struct MergeReport {
+    /// Changes applied to the destination
+    pub sync: SyncReport,
+    /// Paths where a later source overrode an earlier one
+    pub conflicts: Vec<SourceConflict>,
+}
+
+/// How to settle a path changed differently on both sides of a
+/// [`sync_bidirectional`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Resolution {
+    /// Copy the left version over the right one
+    TakeLeft,
+    /// Copy the right version over the left one
+    TakeRight,
+    /// Keep the left version at the path and the right version beside it
+    ///
+    /// The right version is written to both sides with `suffix` inserted
+    /// before the extension, e.g. `file.conflict-HOST.txt`.
+    KeepBoth {
+        /// Inserted between the file stem and extension
+        suffix: String,
+    },
+    /// Leave both sides as they are
+    Skip,
+}
+
+/// Signature of a conflict callback, receiving the left and right versions
+type ResolveFn = dyn Fn(&FileMeta, &FileMeta) -> Resolution + Send + Sync;
+
+/// Conflict callback, set via [`BidirectionalOptions::with_conflict_resolver`]
+#[derive(Clone)]
+pub struct ConflictResolver(Arc<ResolveFn>);
+
+impl fmt::Debug for ConflictResolver {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("ConflictResolver(..)")
+    }
+}
+
+/// Options for [`sync_bidirectional`]
+#[derive(Debug, Clone, Default)]
+pub struct BidirectionalOptions {
+    /// How both directories are scanned
+    pub scan: ScanOptions,
+    /// How copies are applied in each direction (`delete_removed` is ignored)
+    pub sync: SyncOptions,
+    /// Decides each conflict; the newer file wins when unset
+    pub resolve_conflict: Option<ConflictResolver>,
+    /// How far the right clock runs ahead of the left clock
+    ///
+    /// Subtracted from right mtimes when the default resolver picks the newer
+    /// file. See [`detect_clock_skew`].
+    pub clock_skew: Duration,
+}
+
+impl BidirectionalOptions {
+    /// Decide conflicts with a callback receiving the left and right versions
+    pub fn with_conflict_resolver(
+        mut self,
+        resolve: impl Fn(&FileMeta, &FileMeta) -> Resolution + Send + Sync + 'static,
+    ) -> Self {
+        self.resolve_conflict = Some(ConflictResolver(Arc::new(resolve)));
+        self
+    }
+
+    /// Settle one conflict
+    fn resolve(&self, left: &FileMeta, right: &FileMeta) -> Resolution {
+        match &self.resolve_conflict {
+            Some(ConflictResolver(resolve)) => resolve(left, right),
+            None if right.mtime.checked_sub(self.clock_skew).unwrap_or(right.mtime)
+                > left.mtime =>
+            {
+                Resolution::TakeRight
+            },
+            None => Resolution::TakeLeft,
+        }
+    }
+}
+
+/// A path that differed on both sides and how it was settled
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResolvedConflict {
+    /// Relative path of the conflicting file
+    pub path: PathBuf,
+    /// Resolution applied
+    pub resolution: Resolution,
+}
+
+/// Result of [`sync_bidirectional`]
+#[derive(Debug, Clone, Default)]
+pub struct BidirectionalReport {
+    /// Changes applied to the left directory
+    pub left: SyncReport,
+    /// Changes applied to the right directory
+    pub right: SyncReport,
+    /// Every conflict with its resolution, sorted by path
+    pub conflicts: Vec<ResolvedConflict>,
+}
+
+/// Order in which files are hashed during a scan
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ScanOrder {
+    /// Hash files in parallel in whatever order the walker yields them
+    #[default]
+    Parallel,
+    /// Hash files one at a time in path order
+    PathSorted,
+    /// Hash files one at a time in inode order (path order on non-Unix systems)
+    InodeSorted,
+}
+
+/// Version-control metadata directories skipped by default during scans
+///
+/// These are rarely ignored by the repository's own ignore rules, yet are
+/// usually large and machine-local. Set [`ScanOptions::include_vcs`] to scan
+/// them anyway.
+pub const VCS_DIRECTORIES: &[&str] = &[".git", ".svn", ".hg"];
+
+/// Options for scanning directories
+#[derive(Debug, Clone, Default)]
+pub struct ScanOptions {
+    /// Order in which files are hashed
+    ///
+    /// The sorted orders hash on a single thread so reads follow the on-disk
+    /// layout, trading CPU parallelism for far fewer seeks on spinning disks.
+    pub scan_order: ScanOrder,
+    /// Include [`VCS_DIRECTORIES`] instead of skipping them
+    pub include_vcs: bool,
+    /// Skip every directory that contains a file with one of these names
+    ///
+    /// The convention for opting a directory out of backups, like tar's
+    /// `--exclude-tag`: e.g. `CACHEDIR.TAG` or `.nobackup`. The directory's
+    /// whole subtree is left out, marker included.
+    pub exclude_if_present: Vec<String>,
+    /// Custom walker configuration, see [`ScanOptions::with_walk_builder`]
+    pub walk_builder: Option<WalkBuilderHook>,
+    /// Reuse hashes of files whose size and mtime are unchanged
+    ///
+    /// New hashes are added to the cache as files are scanned; saving it
+    /// afterwards is up to the caller.
+    pub hash_cache: Option<Arc<Mutex<HashCache>>>,
+    /// Print a plain status line to stdout at this interval while scanning
+    ///
+    /// Works regardless of the progress reporter, keeping CI logs alive
+    /// during long scans. See [`Heartbeat`].
+    pub heartbeat_interval: Option<Duration>,
+    /// Drain named pipes and sync their data as regular files (Unix only)
+    ///
+    /// **Dangerous.** Each FIFO is read to EOF during the scan, which blocks
+    /// until a writer opens and closes it and consumes the data for every
+    /// other reader. The drained bytes are spooled to a private directory
+    /// under the system temp directory, hashed like a regular file, and
+    /// copied from the spool when synced. See [`FifoSpool`]. By default FIFOs
+    /// are skipped.
+    pub drain_fifos: bool,
+    /// Byte ranges left out of the content hash, per file glob
+    ///
+    /// The first rule whose glob matches a file's relative path applies.
+    /// Files hashed this way bypass the hash cache.
+    pub hash_skip_ranges: Vec<HashSkipRanges>,
+    /// Only scan this subdirectory of the root
+    ///
+    /// Paths stay relative to the root, so a sync of two subpath scans keeps
+    /// the destination layout. Files outside the subpath are neither seen nor
+    /// touched. A subpath that doesn't exist yet scans as empty.
+    pub subpath: Option<PathBuf>,
+    /// Only include files owned by this user id (Unix only)
+    ///
+    /// Checked from the walker's stat, so other users' files are never hashed.
+    pub owner_uid: Option<u32>,
+    /// Only include files owned by this group id (Unix only)
+    pub owner_gid: Option<u32>,
+    /// When file contents are hashed
+    pub hash_policy: HashPolicy,
+    /// Also include files git tracks even though gitignore excludes them
+    ///
+    /// Files force-added with `git add -f` are part of the repository but
+    /// skipped by a gitignore-respecting walk. With this set, the scan asks
+    /// `git ls-files` for them, so a backup holds exactly what git considers
+    /// part of the repository. Requires `git` on the `PATH`; outside a
+    /// repository nothing extra is included.
+    pub include_git_tracked: bool,
+    /// Periodically save hashed files here so an interrupted scan can resume
+    ///
+    /// A scan that finds this file from an earlier, unfinished run reuses its
+    /// hashes for files whose size, whole-second mtime and inode still match,
+    /// and only hashes the rest. The file is removed once the scan completes.
+    /// Unlike [`ScanOptions::hash_cache`] this is crash recovery, not a cache
+    /// kept between runs.
+    pub scan_checkpoint: Option<PathBuf>,
+    /// How often `scan_checkpoint` is saved (default: every 10 seconds)
+    pub checkpoint_interval: Option<Duration>,
+    /// Keep hashed files from filling the page cache (Linux only)
+    ///
+    /// Files are read ahead sequentially and dropped from the cache after
+    /// hashing, so a scan of a large tree doesn't evict other processes'
+    /// cached data, see [`Hasher::with_fadvise`]. Ignored on other systems.
+    pub fadvise: bool,
+    /// Hash each directory's subtree into [`ScanResult::dirs`]
+    ///
+    /// When both scans of a diff have directory hashes, subtrees that hash
+    /// the same are skipped without comparing their files, which pays off
+    /// for huge trees with localized changes.
+    pub dir_hashes: bool,
+}
+
+/// Default for [`ScanOptions::checkpoint_interval`]
+const DEFAULT_CHECKPOINT_INTERVAL: Duration = Duration::from_secs(10);
+
+/// When a scan computes content hashes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum HashPolicy {
+    /// Hash every file during the scan
+    #[default]
+    Always,
+    /// Only record size and mtime; hash when a diff can't decide without it
+    ///
+    /// [`diff_scans_with_options`] hashes a deferred file only if some file
+    /// in the other scan has the same size, since files of different sizes
+    /// can't have the same content. On trees of mostly unique sizes this
+    /// skips most hashing. Deferred files carry a placeholder hash (see
+    /// [`FileMeta::hash_deferred`]), so content allow- and blocklists and
+    /// hash caches don't apply to them, [`ScanResult::tree_hash`] fails on
+    /// them, and such scans aren't saved as trusted destination manifests.
+    OnConflict,
+}
+
+/// Byte ranges to exclude from the content hash of matching files
+///
+/// For formats that embed timestamps or build IDs at fixed offsets, so
+/// rebuilds with identical meaningful content don't show up as modified.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HashSkipRanges {
+    /// Glob matched against the path relative to the scan root, e.g. `**/*.pyc`
+    pub glob: String,
+    /// Byte offsets excluded from the hash
+    pub ranges: Vec<Range<u64>>,
+}
+
+impl ScanOptions {
+    /// Check for option values that can't take effect
+    pub fn validate(&self) -> std::result::Result<(), Vec<OptionConflict>> {
+        let mut conflicts = Vec::new();
+        if let Some(subpath) = &self.subpath {
+            if let Err(e) = checked_subpath(subpath) {
+                conflicts.push(OptionConflict::new(&["subpath"], e.to_string()));
+            }
+        }
+        for rule in &self.hash_skip_ranges {
+            if let Err(e) = globset::Glob::new(&rule.glob) {
+                conflicts.push(OptionConflict::new(&["hash_skip_ranges"], e.to_string()));
+            }
+            if rule.ranges.iter().any(|range| range.start > range.end) {
+                conflicts.push(OptionConflict::new(
+                    &["hash_skip_ranges"],
+                    format!("{}: range start is past its end", rule.glob),
+                ));
+            }
+        }
+        if cfg!(not(unix)) {
+            if self.drain_fifos {
+                conflicts.push(OptionConflict::new(&["drain_fifos"], "named pipes are Unix only"));
+            }
+            if self.owner_uid.is_some() || self.owner_gid.is_some() {
+                conflicts.push(OptionConflict::new(
+                    &["owner_uid", "owner_gid"],
+                    "ownership filters are Unix only",
+                ));
+            }
+        }
+        conflicts_to_result(conflicts)
+    }
+
+    /// Configure the underlying [`ignore::WalkBuilder`] before each scan
+    ///
+    /// The closure runs after Janus applies its own settings (hidden files,
+    /// gitignore handling, VCS filtering) and before the parallel walk starts,
+    /// so anything the `ignore` crate supports can be tweaked: custom ignore
+    /// filenames, `same_file_system`, `follow_links`, and so on. Settings made
+    /// here override or may conflict with Janus's own; for example replacing
+    /// the entry filter re-enables VCS directories.
+    pub fn with_walk_builder(
+        mut self,
+        configure: impl Fn(&mut ignore::WalkBuilder) + Send + Sync + 'static,
+    ) -> Self {
+        self.walk_builder = Some(WalkBuilderHook(Arc::new(configure)));
+        self
+    }
+}
+
+/// Closure that configures the directory walker, set via [`ScanOptions::with_walk_builder`]
+#[derive(Clone)]
+pub struct WalkBuilderHook(Arc<dyn Fn(&mut ignore::WalkBuilder) + Send + Sync>);
+
+impl fmt::Debug for WalkBuilderHook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("WalkBuilderHook(..)")
+    }
+}
+
+/// A file found by the directory walk, waiting to be hashed
+struct WalkedFile {
+    path: PathBuf,
+    inode: u64,
+    /// Size at walk time, used only for progress reporting
+    size: u64,
+    /// The entry is a named pipe to drain, see [`ScanOptions::drain_fifos`]
+    fifo: bool,
+    /// Index of the first matching rule in [`ScanOptions::hash_skip_ranges`]
+    skip_rule: Option<usize>,
+}
+
+/// Hash walked files in the requested order
+///
+/// `progress` advances by each file's walk-time size as it is hashed and is
+/// finished once every file has been processed. `heartbeat` counts files the
+/// same way.
+fn hash_walked_files(
+    root: &Path,
+    mut walked: Vec<WalkedFile>,
+    options: &ScanOptions,
+    fifo_spool: Option<&FifoSpool>,
+    progress: Option<&ParallelProgress>,
+    heartbeat: Option<&Heartbeat>,
+) -> Vec<std::result::Result<FileMeta, ScanWarning>> {
+    let order = options.scan_order;
+    let cache = options.hash_cache.as_deref();
+    let checkpoint = options.scan_checkpoint.as_ref().map(|path| {
+        let interval = options.checkpoint_interval.unwrap_or(DEFAULT_CHECKPOINT_INTERVAL);
+        ScanCheckpoint::start(path.clone(), root, interval)
+    });
+    let hash_one = |hasher: &mut Hasher, file: &WalkedFile| {
+        let meta = if file.fifo {
+            let spool = fifo_spool.expect("a FIFO was walked, so the scan has a spool");
+            drain_fifo_meta(root, &file.path, hasher, spool)
+        } else {
+            let skip = file.skip_rule.map(|i| &options.hash_skip_ranges[i].ranges[..]);
+            let defer = options.hash_policy == HashPolicy::OnConflict;
+            let resumed = checkpoint
+                .as_ref()
+                .filter(|_| skip.is_none())
+                .and_then(|checkpoint| checkpoint.resume(root, &file.path, hasher));
+            match resumed {
+                Some(meta) => Ok(meta),
+                None => read_file_meta(root, &file.path, hasher, cache, skip, defer),
+            }
+        };
+        if let (Some(checkpoint), Ok(meta)) = (&checkpoint, &meta) {
+            checkpoint.record(meta);
+        }
+        if let Some(progress) = progress {
+            progress.inc_by(file.size);
+        }
+        if let Some(heartbeat) = heartbeat {
+            heartbeat.record(file.size);
+        }
+        meta.map_err(|e| {
+            let rel_path = file.path.strip_prefix(root).unwrap_or(&file.path);
+            ScanWarning::new(rel_path.to_path_buf(), &e)
+        })
+    };
+
+    let file_metas = match order {
+        ScanOrder::Parallel => {
+            // Hash files in parallel, reusing one hasher per rayon work split
+            let new_hasher = || Hasher::new().with_fadvise(options.fadvise);
+            walked.par_iter().map_init(new_hasher, hash_one).collect()
+        },
+        ScanOrder::PathSorted | ScanOrder::InodeSorted => {
+            if order == ScanOrder::InodeSorted {
+                walked.sort_by(|a, b| a.inode.cmp(&b.inode).then_with(|| a.path.cmp(&b.path)));
+            } else {
+                walked.sort_by(|a, b| a.path.cmp(&b.path));
+            }
+
+            let mut hasher = Hasher::new().with_fadvise(options.fadvise);
+            walked.iter().map(|file| hash_one(&mut hasher, file)).collect()
+        },
+    };
+
+    if let Some(progress) = progress {
+        progress.finish();
+    }
+    if let Some(checkpoint) = checkpoint {
+        checkpoint.finish();
+    }
+    file_metas
+}
+
+/// Periodically saved progress of a scan, see [`ScanOptions::scan_checkpoint`]
+struct ScanCheckpoint {
+    path: PathBuf,
+    /// Files hashed by the interrupted run, by relative path
+    previous: HashMap<PathBuf, FileMeta>,
+    /// Files hashed so far by this run
+    done: Arc<Mutex<Vec<FileMeta>>>,
+    stop: Option<std::sync::mpsc::Sender<()>>,
+    handle: Option<thread::JoinHandle<()>>,
+}
+
+impl ScanCheckpoint {
+    /// Load the checkpoint left by an earlier run and start saving this one's
+    fn start(path: PathBuf, root: &Path, interval: Duration) -> Self {
+        let previous = ScanResult::load_from_file(&path)
+            .ok()
+            .filter(|scan| scan.root == root)
+            .map(|scan| scan.files.into_iter().map(|file| (file.path.clone(), file)).collect())
+            .unwrap_or_default();
+        let done = Arc::new(Mutex::new(Vec::new()));
+        let (stop, stopped) = std::sync::mpsc::channel::<()>();
+
+        let handle = {
+            let path = path.clone();
+            let root = root.to_path_buf();
+            let done = Arc::clone(&done);
+            thread::spawn(move || {
+                while let Err(std::sync::mpsc::RecvTimeoutError::Timeout) =
+                    stopped.recv_timeout(interval)
+                {
+                    let scan = ScanResult {
+                        root: root.clone(),
+                        files: done.lock().unwrap().clone(),
+                        scan_time: SystemTime::now(),
+                        warnings: Vec::new(),
+                        dirs: Vec::new(),
+                        traversed_dirs: Vec::new(),
+                        fifo_spool: None,
+                    };
+                    // A failed save only costs re-hashing after a crash
+                    let _ = scan.save_to_file(&path);
+                }
+            })
+        };
+
+        Self {
+            path,
+            previous,
+            done,
+            stop: Some(stop),
+            handle: Some(handle),
+        }
+    }
+
+    /// The file's metadata with its hash from the earlier run, if still unchanged
+    fn resume(&self, root: &Path, path: &Path, hasher: &mut Hasher) -> Option<FileMeta> {
+        let rel_path = path.strip_prefix(root).ok()?;
+        let previous = self.previous.get(rel_path)?;
+        let mut meta = read_file_meta(root, path, hasher, None, None, true).ok()?;
+        let secs = |t: SystemTime| t.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).ok();
+        let unchanged = meta.size == previous.size
+            && secs(meta.mtime) == secs(previous.mtime)
+            && (previous.inode.is_none() || meta.inode == previous.inode);
+        if !unchanged {
+            return None;
+        }
+        meta.hash = previous.hash.clone();
+        meta.hash_deferred = false;
+        Some(meta)
+    }
+
+    /// Add a hashed file to the next save
+    fn record(&self, meta: &FileMeta) {
+        if !meta.hash_deferred {
+            self.done.lock().unwrap().push(meta.clone());
+        }
+    }
+
+    /// Stop saving and remove the checkpoint, since the scan completed
+    fn finish(mut self) {
+        self.stop_saving();
+        let _ = remove_file_safe(&self.path);
+    }
+
+    fn stop_saving(&mut self) {
+        // Dropping the sender wakes the thread immediately
+        self.stop.take();
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+impl Drop for ScanCheckpoint {
+    fn drop(&mut self) {
+        self.stop_saving();
+    }
+}
+
+/// Whether a walked entry is one of the [`VCS_DIRECTORIES`]
+fn is_vcs_directory(entry: &ignore::DirEntry) -> bool {
+    entry.file_type().is_some_and(|t| t.is_dir())
+        && entry.file_name().to_str().is_some_and(|name| VCS_DIRECTORIES.contains(&name))
+}
+
+/// The first of `markers` present in `dir`, see [`ScanOptions::exclude_if_present`]
+fn exclusion_marker<'a>(dir: &Path, markers: &'a [String]) -> Option<&'a str> {
+    markers
+        .iter()
+        .find(|marker| fs::symlink_metadata(dir.join(marker)).is_ok())
+        .map(String::as_str)
+}
+
+/// Scan a directory and compute content hashes for all files
+///
+/// This function walks the directory tree in parallel, computing content hashes
+/// for each file using streaming I/O to minimize memory usage.
+///
+/// # Arguments
+///
+/// * `root` - Root directory to scan
+/// * `progress` - Optional progress reporter
+///
+/// # Performance
+///
+/// - Uses `ignore` crate for parallel directory traversal
+/// - Hashes files in parallel using `rayon`
+/// - Streaming hash computation for constant memory usage
+/// - Respects .gitignore patterns for efficiency
+/// - Skips [`VCS_DIRECTORIES`] such as `.git/`
+pub fn scan_directory(root: &Path, progress: Option<&ProgressReporter>) -> Result<ScanResult> {
+    scan_directory_with_options(root, &ScanOptions::default(), progress)
+}
+
+/// Scan a directory using custom [`ScanOptions`]
+///
+/// See [`scan_directory`] for details.
+pub fn scan_directory_with_options(
+    root: &Path,
+    options: &ScanOptions,
+    progress: Option<&ProgressReporter>,
+) -> Result<ScanResult> {
+    if !root.exists() {
+        return Err(SyncError::InvalidPath {
+            path: root.to_path_buf(),
+            reason: "directory does not exist".to_string(),
+        }
+        .into());
+    }
+    let root = &absolute_path(root)?;
+
+    if progress.is_some() {
+        println!("Scanning: {}", root.display());
+    }
+
+    let heartbeat = options
+        .heartbeat_interval
+        .map(|interval| Heartbeat::start(interval, std::io::stdout()));
+
+    // Collect all file paths first
+    let include_vcs = options.include_vcs;
+    let markers = options.exclude_if_present.clone();
+    let drain_fifos = options.drain_fifos;
+    let (owner_uid, owner_gid) = (options.owner_uid, options.owner_gid);
+    let skip_globs = options
+        .hash_skip_ranges
+        .iter()
+        .map(|rule| {
+            globset::Glob::new(&rule.glob)
+                .map(|glob| glob.compile_matcher())
+                .map_err(|e| SyncError::InvalidGlob(format!("{}: {e}", rule.glob)))
+        })
+        .collect::<std::result::Result<Vec<_>, _>>()?;
+    let walk_root = match &options.subpath {
+        Some(subpath) => root.join(checked_subpath(subpath)?),
+        None => root.clone(),
+    };
+    let mut builder = ignore::WalkBuilder::new(&walk_root);
+    builder
+        .hidden(false)
+        .git_ignore(true)
+        .git_exclude(true)
+        .filter_entry(move |entry| {
+            (include_vcs || !is_vcs_directory(entry))
+                && !(entry.file_type().is_some_and(|t| t.is_dir())
+                    && exclusion_marker(entry.path(), &markers).is_some())
+        })
+        .threads(num_cpus::get());
+    if let Some(WalkBuilderHook(configure)) = &options.walk_builder {
+        configure(&mut builder);
+    }
+    let walker = builder.build_parallel();
+
+    let files = Mutex::new(Vec::new());
+    let traversed_dirs = Mutex::new(Vec::new());
+
+    if walk_root.exists() {
+        walker.run(|| {
+            Box::new(|entry_result| {
+                if let Ok(entry) = entry_result {
+                    if let Some(file_type) = entry.file_type() {
+                        if file_type.is_dir() {
+                            let rel_path = entry.path().strip_prefix(root).unwrap_or(entry.path());
+                            if !rel_path.as_os_str().is_empty() {
+                                traversed_dirs.lock().unwrap().push(rel_path.to_path_buf());
+                            }
+                            return ignore::WalkState::Continue;
+                        }
+                        let fifo = drain_fifos && is_fifo_type(&file_type);
+                        if file_type.is_file() || fifo {
+                            let metadata = entry.metadata().ok();
+                            if !metadata
+                                .as_ref()
+                                .map_or(true, |m| owned_by(m, owner_uid, owner_gid))
+                            {
+                                return ignore::WalkState::Continue;
+                            }
+                            // Size from the walker's stat sizes the hashing progress bar
+                            let size = metadata.map_or(0, |m| m.len());
+                            let rel_path = entry.path().strip_prefix(root).unwrap_or(entry.path());
+                            let skip_rule = skip_globs.iter().position(|g| g.is_match(rel_path));
+                            files.lock().unwrap().push(WalkedFile {
+                                path: entry.path().to_path_buf(),
+                                inode: entry_inode(&entry),
+                                size,
+                                fifo,
+                                skip_rule,
+                            });
+                        }
+                    }
+                }
+                ignore::WalkState::Continue
+            })
+        });
+    }
+
+    let mut walked = files.into_inner().unwrap();
+    if options.include_git_tracked && walk_root.exists() {
+        let seen: HashSet<PathBuf> = walked.iter().map(|file| file.path.clone()).collect();
+        for path in git_tracked_ignored(&walk_root)? {
+            let path = walk_root.join(path);
+            let Ok(metadata) = fs::symlink_metadata(&path) else {
+                continue;
+            };
+            let marked = path
+                .ancestors()
+                .skip(1)
+                .take_while(|dir| dir.starts_with(&walk_root))
+                .any(|dir| exclusion_marker(dir, &options.exclude_if_present).is_some());
+            if !metadata.is_file()
+                || seen.contains(&path)
+                || marked
+                || !owned_by(&metadata, owner_uid, owner_gid)
+            {
+                continue;
+            }
+            let rel_path = path.strip_prefix(root).unwrap_or(&path);
+            walked.push(WalkedFile {
+                skip_rule: skip_globs.iter().position(|g| g.is_match(rel_path)),
+                inode: metadata_inode(&metadata),
+                size: metadata.len(),
+                fifo: false,
+                path,
+            });
+        }
+    }
+    let total_files = walked.len();
+
+    if progress.is_some() {
+        println!("Found {total_files} files, computing hashes...");
+    }
+
+    let hash_progress = progress.map(|reporter| {
+        let total_bytes = walked.iter().map(|file| file.size).sum();
+        ParallelProgress::new(reporter.add_bytes_task("Hashing", total_bytes))
+    });
+    let fifo_spool = match walked.iter().any(|file| file.fifo) {
+        true => Some(FifoSpool::new()?),
+        false => None,
+    };
+    let file_metas = hash_walked_files(
+        root,
+        walked,
+        options,
+        fifo_spool.as_ref(),
+        hash_progress.as_ref(),
+        heartbeat.as_ref(),
+    );
+    drop(heartbeat);
+
+    // Collect results, recording errors as warnings rather than failing the entire scan
+    let mut successful_files = Vec::new();
+    let mut warnings = Vec::new();
+
+    for result in file_metas {
+        match result {
+            Ok(meta) => successful_files.push(meta),
+            Err(warning) => warnings.push(warning),
+        }
+    }
+    warnings.sort_by(|a, b| a.path.cmp(&b.path));
+    let mut traversed_dirs = traversed_dirs.into_inner().unwrap();
+    traversed_dirs.sort();
+
+    let mut scan = ScanResult {
+        root: root.to_path_buf(),
+        files: successful_files,
+        scan_time: SystemTime::now(),
+        warnings,
+        dirs: Vec::new(),
+        traversed_dirs,
+        fifo_spool,
+    };
+    if options.dir_hashes {
+        scan.dirs = scan.compute_dir_hashes();
+    }
+    Ok(scan)
+}
+
+/// Whether a file matches the [`ScanOptions::owner_uid`] and
+/// [`ScanOptions::owner_gid`] filters
+#[cfg(unix)]
+fn owned_by(metadata: &fs::Metadata, uid: Option<u32>, gid: Option<u32>) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    uid.map_or(true, |uid| metadata.uid() == uid) && gid.map_or(true, |gid| metadata.gid() == gid)
+}
+
+#[cfg(not(unix))]
+fn owned_by(_metadata: &fs::Metadata, _uid: Option<u32>, _gid: Option<u32>) -> bool {
+    true
+}
+
+/// Inode number of a walked entry, used for [`ScanOrder::InodeSorted`]
+fn entry_inode(entry: &ignore::DirEntry) -> u64 {
+    #[cfg(unix)]
+    {
+        entry.ino().unwrap_or(0)
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = entry;
+        0
+    }
+}
+
+/// Inode number from file metadata, see [`entry_inode`]
+fn metadata_inode(metadata: &fs::Metadata) -> u64 {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        metadata.ino()
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = metadata;
+        0
+    }
+}
+
+/// Files under `dir` that git tracks although its ignore rules exclude them
+///
+/// Paths are relative to `dir`. Empty when `dir` isn't inside a repository.
+fn git_tracked_ignored(dir: &Path) -> Result<Vec<PathBuf>> {
+    let output = std::process::Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(["ls-files", "-z", "--cached", "--ignored", "--exclude-standard"])
+        .output()
+        .map_err(|e| SyncError::DirectoryRead(format!("failed to run git ls-files: {e}")))?;
+    if !output.status.success() {
+        return Ok(Vec::new());
+    }
+    Ok(output
+        .stdout
+        .split(|&b| b == 0)
+        .filter(|path| !path.is_empty())
+        .map(path_from_bytes)
+        .collect())
+}
+
+/// Which layer of the scan filters decided a path's inclusion
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FilterSource {
+    /// No rule matched, and files are included by default
+    Default,
+    /// A version-control directory, skipped unless `include_vcs` is set
+    VcsDirectory,
+    /// A directory holding this `exclude_if_present` marker file
+    ExclusionMarker(PathBuf),
+    /// A rule in this ignore file (`.ignore`, `.gitignore` or `.git/info/exclude`)
+    IgnoreFile(PathBuf),
+    /// Tracked by git, and kept by `include_git_tracked` despite the ignore rule
+    GitTracked(PathBuf),
+}
+
+/// Why [`explain_filter`] found a path included or excluded
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FilterDecision {
+    /// The explained path, relative to the scan root
+    pub path: PathBuf,
+    /// Whether a scan picks the path up
+    pub included: bool,
+    /// The deciding rule as written (`!` marks a re-include), if any
+    pub rule: Option<String>,
+    /// The path the rule matched: the path itself or an excluded parent directory
+    pub matched: PathBuf,
+    /// Where the deciding rule comes from
+    pub source: FilterSource,
+}
+
+impl fmt::Display for FilterDecision {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let verdict = if self.included {
+            "included"
+        } else {
+            "excluded"
+        };
+        write!(f, "{}: {verdict}", self.path.display())?;
+        let rule = self.rule.as_deref().unwrap_or_default();
+        match &self.source {
+            FilterSource::Default => write!(f, " (no rule matched)"),
+            FilterSource::VcsDirectory => {
+                write!(f, " inside version-control directory {}", self.matched.display())
+            },
+            FilterSource::ExclusionMarker(marker) => {
+                write!(f, " by marker file {}", marker.display())
+            },
+            FilterSource::IgnoreFile(file) => write!(f, " by `{rule}` in {}", file.display()),
+            FilterSource::GitTracked(file) => {
+                write!(f, " as tracked by git, despite `{rule}` in {}", file.display())
+            },
+        }?;
+        let names_match =
+            matches!(self.source, FilterSource::VcsDirectory | FilterSource::ExclusionMarker(_));
+        if self.matched != self.path && !names_match {
+            write!(f, " (matched {})", self.matched.display())?;
+        }
+        Ok(())
+    }
+}
+
+/// Explain which filter rule decides whether a scan of `root` includes `path`
+///
+/// Follows the walker's layering: version-control directories first, then
+/// `exclude_if_present` markers, then `.ignore` files (deepest first), then
+/// `.gitignore` files (deepest first, only inside a git repository), then the
+/// repository's `.git/info/exclude`.
+/// A path under an excluded directory is excluded by that directory's rule.
+/// Rules added through [`ScanOptions::with_walk_builder`] and the owner
+/// filters aren't explained.
+pub fn explain_filter(root: &Path, path: &Path, options: &ScanOptions) -> Result<FilterDecision> {
+    let root = absolute_path(root)?;
+    let path: PathBuf = checked_subpath(path)?
+        .components()
+        .filter(|c| matches!(c, std::path::Component::Normal(_)))
+        .collect();
+    let repo = root.ancestors().find(|dir| dir.join(".git").exists()).map(Path::to_path_buf);
+    // Ignore files above the scan root apply up to the repository root
+    let mut parents: Vec<PathBuf> = match &repo {
+        Some(repo) => root
+            .ancestors()
+            .skip(1)
+            .take_while(|dir| dir.starts_with(repo))
+            .map(Path::to_path_buf)
+            .collect(),
+        None => Vec::new(),
+    };
+    parents.reverse();
+
+    let mut decision = FilterDecision {
+        path: path.clone(),
+        included: true,
+        rule: None,
+        matched: path.clone(),
+        source: FilterSource::Default,
+    };
+    let mut prefix = PathBuf::new();
+    let mut dirs = parents;
+    dirs.push(root.clone());
+    let components: Vec<_> = path.components().collect();
+    for (i, component) in components.iter().enumerate() {
+        prefix.push(component);
+        let is_dir = i + 1 < components.len() || root.join(&prefix).is_dir();
+        let name = component.as_os_str().to_str();
+        if is_dir && !options.include_vcs && name.is_some_and(|n| VCS_DIRECTORIES.contains(&n)) {
+            decision.included = false;
+            decision.rule = name.map(str::to_string);
+            decision.matched = prefix;
+            decision.source = FilterSource::VcsDirectory;
+            return Ok(decision);
+        }
+        let marker = is_dir
+            .then(|| exclusion_marker(&root.join(&prefix), &options.exclude_if_present))
+            .flatten();
+        if let Some(marker) = marker {
+            decision.included = false;
+            decision.rule = Some(marker.to_string());
+            decision.matched = prefix.clone();
+            decision.source = FilterSource::ExclusionMarker(prefix.join(marker));
+            return Ok(decision);
+        }
+
+        if let Some((file, glob, excluded)) =
+            ignore_rule_for(&root, &prefix, is_dir, &dirs, repo.as_deref())?
+        {
+            // Re-includes only matter for the path itself
+            if excluded || i + 1 == components.len() {
+                decision.included = !excluded;
+                decision.rule = Some(glob);
+                decision.matched = prefix.clone();
+                decision.source = FilterSource::IgnoreFile(file);
+            }
+            if excluded {
+                break;
+            }
+        }
+        dirs.push(root.join(&prefix));
+    }
+
+    if let (false, true, FilterSource::IgnoreFile(file)) =
+        (decision.included, options.include_git_tracked, &decision.source)
+    {
+        if git_tracked_ignored(&root)?.contains(&path) {
+            decision.source = FilterSource::GitTracked(file.clone());
+            decision.included = true;
+        }
+    }
+    Ok(decision)
+}
+
+/// The ignore-file rule matching `prefix`, as (ignore file, rule, excludes)
+///
+/// `dirs` are the directories whose ignore files apply, shallowest first.
+fn ignore_rule_for(
+    root: &Path,
+    prefix: &Path,
+    is_dir: bool,
+    dirs: &[PathBuf],
+    repo: Option<&Path>,
+) -> Result<Option<(PathBuf, String, bool)>> {
+    let target = root.join(prefix);
+    let mut candidates: Vec<(PathBuf, PathBuf)> =
+        dirs.iter().rev().map(|dir| (dir.clone(), dir.join(".ignore"))).collect();
+    if let Some(repo) = repo {
+        candidates.extend(dirs.iter().rev().map(|dir| (dir.clone(), dir.join(".gitignore"))));
+        candidates.push((repo.to_path_buf(), repo.join(".git/info/exclude")));
+    }
+
+    for (dir, file) in candidates {
+        if !file.is_file() {
+            continue;
+        }
+        let mut builder = ignore::gitignore::GitignoreBuilder::new(&dir);
+        if let Some(e) = builder.add(&file) {
+            return Err(SyncError::InvalidGlob(format!("{}: {e}", file.display())).into());
+        }
+        let matcher = builder
+            .build()
+            .map_err(|e| SyncError::InvalidGlob(format!("{}: {e}", file.display())))?;
+        match matcher.matched(&target, is_dir) {
+            ignore::Match::None => {},
+            ignore::Match::Ignore(glob) => {
+                return Ok(Some((file, glob.original().to_string(), true)));
+            },
+            ignore::Match::Whitelist(glob) => {
+                return Ok(Some((file, glob.original().to_string(), false)));
+            },
+        }
+    }
+    Ok(None)
+}
+
+/// Path from raw bytes printed by an external tool
+fn path_from_bytes(bytes: &[u8]) -> PathBuf {
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStrExt;
+        PathBuf::from(std::ffi::OsStr::from_bytes(bytes))
+    }
+    #[cfg(not(unix))]
+    {
+        PathBuf::from(String::from_utf8_lossy(bytes).into_owned())
+    }
+}
+
+/// Whether a walked entry is a named pipe
+fn is_fifo_type(file_type: &fs::FileType) -> bool {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::FileTypeExt;
+        file_type.is_fifo()
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = file_type;
+        false
+    }
+}
+
+/// Drain a named pipe into the spool and describe the drained bytes as a file
+fn drain_fifo_meta(
+    root: &Path,
+    path: &Path,
+    hasher: &mut Hasher,
+    spool: &FifoSpool,
+) -> Result<FileMeta> {
+    static NEXT_SPOOL: AtomicU64 = AtomicU64::new(0);
+
+    let partial = spool
+        .path()
+        .join(format!("{}.partial", NEXT_SPOOL.fetch_add(1, Ordering::Relaxed)));
+    hasher.reset();
+    let drained = drain_fifo(path, &partial, |chunk| hasher.update(chunk));
+    let size = match drained {
+        Ok(size) => size,
+        Err(e) => {
+            let _ = remove_file_safe(&partial);
+            return Err(e.into());
+        },
+    };
+    let hash = hasher.finalize_reset();
+    fs::rename(&partial, spool.content_path(&hash))?;
+
+    let rel_path = path
+        .strip_prefix(root)
+        .map_err(|_| SyncError::InvalidPath {
+            path: path.to_path_buf(),
+            reason: "not under the scan root".to_string(),
+        })?
+        .to_path_buf();
+
+    Ok(FileMeta {
+        path: rel_path,
+        size,
+        mtime: SystemTime::now(),
+        hash,
+        permissions: None,
+        owner: None,
+        metadata_hash: None,
+        inode: None,
+        ctime: None,
+        atime: None,
+        btime: None,
+        hash_deferred: false,
+    })
+}
+
+/// Path to copy a source file from, which is the spool for drained FIFOs
+fn copy_source(source_root: &Path, file: &FileMeta, spool: Option<&FifoSpool>) -> PathBuf {
+    #[cfg(unix)]
+    if let Some(spool) = spool {
+        if crate::io::is_fifo(&source_root.join(&file.path)) {
+            return spool.content_path(&file.hash);
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = spool;
+    source_root.join(&file.path)
+}
+
+/// Read metadata and compute the content hash for a single file
+fn read_file_meta(
+    root: &Path,
+    path: &Path,
+    hasher: &mut Hasher,
+    cache: Option<&Mutex<HashCache>>,
+    skip: Option<&[Range<u64>]>,
+    defer: bool,
+) -> Result<FileMeta> {
+    let metadata = fs::metadata(path)?;
+    let stamp = FileStamp::from_metadata(&metadata)?;
+
+    // Get permissions on Unix systems
+    #[cfg(unix)]
+    let (permissions, owner) = {
+        use std::os::unix::fs::{MetadataExt, PermissionsExt};
+        (Some(metadata.permissions().mode()), Some((metadata.uid(), metadata.gid())))
+    };
+    #[cfg(not(unix))]
+    let (permissions, owner) = (None, None);
+
+    // Partial hashes stay out of the cache, which holds full-content hashes
+    let cache = cache.filter(|_| skip.is_none());
+    let cached = cache.and_then(|cache| cache.lock().unwrap().lookup(path, &stamp));
+    let hash = match (cached, skip) {
+        (Some(hash), _) => Some(hash),
+        (None, Some(skip)) => {
+            hasher.reset();
+            hasher.hash_file_skipping(path, skip)?;
+            Some(hasher.finalize_reset())
+        },
+        // Only deferred when unambiguous: a skipping hash can't be redone later
+        (None, None) if defer => None,
+        (None, None) => {
+            // Compute content hash using streaming. Reset first in case the previous
+            // file on this hasher failed mid-read and left partial state behind.
+            hasher.reset();
+            hasher.hash_file(path)?;
+            let hash = hasher.finalize_reset();
+            if let Some(cache) = cache {
+                cache.lock().unwrap().insert(path.to_path_buf(), stamp, hash.clone());
+            }
+            Some(hash)
+        },
+    };
+
+    // Make path relative to root
+    let rel_path = path
+        .strip_prefix(root)
+        .map_err(|_| SyncError::InvalidPath {
+            path: path.to_path_buf(),
+            reason: "not under the scan root".to_string(),
+        })?
+        .to_path_buf();
+
+    Ok(FileMeta {
+        size: stamp.size,
+        mtime: stamp.mtime,
+        hash_deferred: hash.is_none(),
+        hash: hash.unwrap_or_else(|| FileMeta::deferred_hash(&rel_path, stamp.size)),
+        path: rel_path,
+        permissions,
+        owner,
+        metadata_hash: metadata_hash(permissions, owner),
+        inode: stamp.inode,
+        ctime: stamp.ctime,
+        atime: metadata.accessed().ok(),
+        btime: metadata.created().ok(),
+    })
+}
+
+/// Compare two scan results and identify differences
+///
+/// This function performs intelligent diff computation with rename detection:
+/// 1. Build hash maps for fast lookup
+/// 2. Identify added/removed/modified files
+/// 3. Detect renames by matching content hashes
+/// 4. Use path similarity as fallback for ambiguous renames
+///
+/// # Performance
+///
+/// - O(n) hash map construction
+/// - O(1) lookups for most operations
+/// - Rename detection is O(n*m) worst case but typically O(n) with hash matching
+pub fn diff_scans(source: &ScanResult, dest: &ScanResult) -> Result<DiffResult> {
+    diff_scans_with_options(source, dest, &DiffOptions::default())
+}
+
+/// Compare two scan results using custom [`DiffOptions`]
+///
+/// See [`diff_scans`] for the algorithm; the options control how files at the
+/// same path are judged unchanged.
+pub fn diff_scans_with_options(
+    source: &ScanResult,
+    dest: &ScanResult,
+    options: &DiffOptions,
+) -> Result<DiffResult> {
+    let hashed;
+    // Files in subtrees that hash the same on both sides can't have changed
+    let unchanged = unchanged_dirs(source, dest, options);
+    let compared =
+        |file: &&FileMeta| !unchanged.contains(file.path.parent().unwrap_or(Path::new("")));
+
+    let deferred = source.files.iter().chain(&dest.files).any(|f| f.hash_deferred);
+    let (source, dest) = if deferred && !options.structure_only {
+        hashed = (hash_size_collisions(source, dest)?, hash_size_collisions(dest, source)?);
+        (&hashed.0, &hashed.1)
+    } else {
+        (source, dest)
+    };
+
+    // Build hash maps for fast lookup
+    let source_by_path: HashMap<Cow<Path>, &FileMeta> = source
+        .files
+        .iter()
+        .filter(compared)
+        .map(|f| (options.path_key(&f.path), f))
+        .collect();
+    let dest_by_path: HashMap<Cow<Path>, &FileMeta> = dest
+        .files
+        .iter()
+        .filter(compared)
+        .map(|f| (options.path_key(&f.path), f))
+        .collect();
+
+    // Build hash-to-files maps for rename detection
+    let mut source_by_hash: HashMap<&ContentHash, Vec<&FileMeta>> = HashMap::new();
+    for file in source.files.iter().filter(compared) {
         source_by_hash.entry(&file.hash).or_default().push(file);
     }
 
-    let mut dest_by_hash: HashMap<&ContentHash, Vec<&FileMeta>> = HashMap::new();
-    for file in &dest.files {
-        dest_by_hash.entry(&file.hash).or_default().push(file);
+    let mut dest_by_hash: HashMap<&ContentHash, Vec<&FileMeta>> = HashMap::new();
+    for file in dest.files.iter().filter(compared) {
+        dest_by_hash.entry(&file.hash).or_default().push(file);
+    }
+
+    let mut added = Vec::new();
+    let mut removed = Vec::new();
+    let mut modified = Vec::new();
+    let mut renamed = Vec::new();
+    let mut kept_newer = Vec::new();
+    let mut metadata_changed = Vec::new();
+    let mut processed_dest_paths = HashSet::new();
+
+    // Find added and modified files
+    for source_file in source.files.iter().filter(compared) {
+        if let Some(dest_file) = dest_by_path.get(&options.path_key(&source_file.path)) {
+            // File exists in both locations
+            let unchanged = options.unchanged(source_file, dest_file)
+                && options.confirm_identical(&source.root, source_file, &dest.root, dest_file);
+            if !unchanged {
+                if options.newer_wins && options.dest_is_newer(source_file, dest_file) {
+                    // Destination copy is newer, keep it
+                    kept_newer.push((*dest_file).clone());
+                } else {
+                    // Content changed
+                    modified.push(source_file.clone());
+                }
+            } else if options.compare_metadata
+                && !options.structure_only
+                && source_file.metadata_hash != dest_file.metadata_hash
+            {
+                // Same content, only permissions or owner drifted
+                metadata_changed.push(source_file.clone());
+            }
+            processed_dest_paths.insert(&dest_file.path);
+        } else {
+            // File not at same path in destination
+            // Check if it might be a rename (same content, different path)
+            let candidates = dest_by_hash
+                .get(&source_file.hash)
+                .filter(|_| options.detect_renames && !options.structure_only)
+                .filter(|_| source_file.size >= options.rename_min_size);
+            if let Some(dest_files_with_hash) = candidates {
+                // Find best match from files with same hash
+                let mut best_match: Option<&FileMeta> = None;
+                let mut best_score = 0.0;
+
+                for candidate in dest_files_with_hash {
+                    if processed_dest_paths.contains(&candidate.path)
+                        || !options.confirm_identical(
+                            &source.root,
+                            source_file,
+                            &dest.root,
+                            candidate,
+                        )
+                    {
+                        continue;
+                    }
+
+                    let score = path_similarity(&source_file.path, &candidate.path);
+                    if score > best_score {
+                        best_score = score;
+                        best_match = Some(candidate);
+                    }
+                }
+
+                if let Some(matched_dest) = best_match {
+                    // Detected rename
+                    renamed.push(((*matched_dest).clone(), source_file.clone()));
+                    processed_dest_paths.insert(&matched_dest.path);
+                } else {
+                    // Hash matches but all candidates already processed - treat as new file
+                    added.push(source_file.clone());
+                }
+            } else {
+                // New file
+                added.push(source_file.clone());
+            }
+        }
+    }
+
+    // Find removed files (in dest but not in source, and not part of a rename)
+    for dest_file in dest.files.iter().filter(compared) {
+        if !source_by_path.contains_key(&options.path_key(&dest_file.path))
+            && !processed_dest_paths.contains(&dest_file.path)
+        {
+            removed.push(dest_file.clone());
+        }
+    }
+
+    Ok(DiffResult {
+        added,
+        removed,
+        modified,
+        renamed,
+        kept_newer,
+        metadata_changed,
+        fifo_spool: source.fifo_spool.clone(),
+    })
+}
+
+/// Directories whose hash is the same in both scans, if skipping them is sound
+///
+/// A directory that hashes the same has subdirectories that do too, so
+/// skipping the files directly inside each returned directory skips whole
+/// subtrees. Metadata comparison and collision checks look beyond content
+/// hashes, so they need every file compared and get an empty set.
+fn unchanged_dirs<'a>(
+    source: &'a ScanResult,
+    dest: &ScanResult,
+    options: &DiffOptions,
+) -> HashSet<&'a Path> {
+    if source.dirs.is_empty() || dest.dirs.is_empty() {
+        return HashSet::new();
+    }
+    if options.compare_metadata || options.collision_check {
+        return HashSet::new();
+    }
+    let dest_dirs: HashMap<&Path, &ContentHash> =
+        dest.dirs.iter().map(|dir| (dir.path.as_path(), &dir.hash)).collect();
+    source
+        .dirs
+        .iter()
+        .filter(|dir| dest_dirs.get(dir.path.as_path()) == Some(&&dir.hash))
+        .map(|dir| dir.path.as_path())
+        .collect()
+}
+
+/// Most entries [`format_diff_tree`] lists under one directory
+const TREE_MAX_ENTRIES: usize = 25;
+
+/// Directory depth at which [`format_diff_tree`] stops expanding and only
+/// counts the changes below
+const TREE_MAX_DEPTH: usize = 8;
+
+/// Render a diff as an indented directory tree with change markers
+///
+/// Files are marked `+` added, `-` removed, `~` modified and `→` renamed
+/// (listed at their new path). Content-identical metadata changes show as `~`
+/// with a note; files kept because the destination is newer are left out.
+/// Directories that only contain one subdirectory are collapsed into a single
+/// `a/b/c/` line. Each directory lists at most a fixed number of entries and
+/// directories nested too deeply are summarized by their change count.
+pub fn format_diff_tree(diff: &DiffResult) -> String {
+    let mut root = TreeNode::default();
+    for file in &diff.added {
+        root.insert(&file.path, '+', String::new());
+    }
+    for file in &diff.removed {
+        root.insert(&file.path, '-', String::new());
+    }
+    for file in &diff.modified {
+        root.insert(&file.path, '~', String::new());
+    }
+    for (old, new) in &diff.renamed {
+        root.insert(&new.path, '→', format!(" (from {})", old.path.display()));
+    }
+    for file in &diff.metadata_changed {
+        root.insert(&file.path, '~', " (metadata)".to_string());
+    }
+
+    let mut out = String::new();
+    root.render(0, &mut out);
+    out
+}
+
+/// Changes under one directory of a [`format_diff_tree`] rendering
+#[derive(Default)]
+struct TreeNode {
+    dirs: BTreeMap<String, TreeNode>,
+    /// File name, change marker and trailing note
+    files: Vec<(String, char, String)>,
+}
+
+impl TreeNode {
+    fn insert(&mut self, path: &Path, marker: char, note: String) {
+        let mut names: Vec<String> =
+            path.iter().map(|name| name.to_string_lossy().into_owned()).collect();
+        let Some(name) = names.pop() else {
+            return;
+        };
+        let node = names.into_iter().fold(self, |node, dir| node.dirs.entry(dir).or_default());
+        node.files.push((name, marker, note));
+    }
+
+    /// The single entry of a directory that holds nothing but one subdirectory
+    fn only_subdirectory(&self) -> Option<(&String, &TreeNode)> {
+        match (self.files.is_empty(), self.dirs.len()) {
+            (true, 1) => self.dirs.iter().next(),
+            _ => None,
+        }
+    }
+
+    /// Number of changed files at or below this directory
+    fn count(&self) -> usize {
+        self.files.len() + self.dirs.values().map(TreeNode::count).sum::<usize>()
+    }
+
+    fn render(&self, depth: usize, out: &mut String) {
+        use std::fmt::Write;
+
+        let indent = "  ".repeat(depth);
+        let mut shown = 0;
+        let mut hidden = 0;
+        for (name, node) in &self.dirs {
+            // Collapse chains of directories that only hold one subdirectory
+            let mut label = name.clone();
+            let mut node = node;
+            while let Some((child, only)) = node.only_subdirectory() {
+                label = format!("{label}/{child}");
+                node = only;
+            }
+            if shown == TREE_MAX_ENTRIES {
+                hidden += node.count();
+                continue;
+            }
+            shown += 1;
+            if depth + 1 == TREE_MAX_DEPTH {
+                let _ = writeln!(out, "{indent}{label}/ ({} changes)", node.count());
+            } else {
+                let _ = writeln!(out, "{indent}{label}/");
+                node.render(depth + 1, out);
+            }
+        }
+
+        let mut files: Vec<_> = self.files.iter().collect();
+        files.sort_by(|a, b| a.0.cmp(&b.0));
+        for (name, marker, note) in files {
+            if shown == TREE_MAX_ENTRIES {
+                hidden += 1;
+                continue;
+            }
+            shown += 1;
+            let _ = writeln!(out, "{indent}{marker} {name}{note}");
+        }
+        if hidden > 0 {
+            let _ = writeln!(out, "{indent}… {hidden} more changes");
+        }
+    }
+}
+
+/// Copy of `scan` with deferred hashes filled in for files whose size also
+/// occurs in `other`
+fn hash_size_collisions(scan: &ScanResult, other: &ScanResult) -> Result<ScanResult> {
+    let other_sizes: HashSet<u64> = other.files.iter().map(|f| f.size).collect();
+    let mut scan = scan.clone();
+    scan.files
+        .par_iter_mut()
+        .filter(|file| file.hash_deferred && other_sizes.contains(&file.size))
+        .try_for_each(|file| -> Result<()> {
+            file.hash = hash_file(&scan.root.join(&file.path))?;
+            file.hash_deferred = false;
+            Ok(())
+        })?;
+    Ok(scan)
+}
+
+/// Minimum number of same-path file pairs needed to report clock skew
+const SKEW_MIN_SAMPLES: usize = 5;
+
+/// Largest spread between per-file mtime offsets still considered "constant"
+const SKEW_MAX_SPREAD: Duration = Duration::from_secs(2);
+
+/// Detect a constant clock offset between the source and destination machines
+///
+/// Looks at files present at the same path in both scans. Pairs with equal
+/// mtimes were copied with their timestamps and say nothing about the clocks,
+/// so they are ignored. If every other pair (and at least a handful) is newer
+/// in the destination by roughly the same amount, the destination clock is
+/// most likely running ahead, and newer-wins comparisons would wrongly keep
+/// stale destination files. Returns the median offset, suitable for
+/// `DiffOptions::clock_skew`.
+pub fn detect_clock_skew(source: &ScanResult, dest: &ScanResult) -> Option<Duration> {
+    let source_by_path: HashMap<&PathBuf, &FileMeta> =
+        source.files.iter().map(|f| (&f.path, f)).collect();
+
+    let mut offsets = Vec::new();
+    for dest_file in &dest.files {
+        if let Some(source_file) = source_by_path.get(&dest_file.path) {
+            if dest_file.mtime == source_file.mtime {
+                continue;
+            }
+            match dest_file.mtime.duration_since(source_file.mtime) {
+                Ok(offset) if offset > SKEW_MAX_SPREAD => offsets.push(offset),
+                // Any pair that isn't clearly ahead rules out a constant skew
+                _ => return None,
+            }
+        }
+    }
+
+    if offsets.len() < SKEW_MIN_SAMPLES {
+        return None;
+    }
+
+    offsets.sort();
+    let spread = offsets[offsets.len() - 1] - offsets[0];
+    (spread <= SKEW_MAX_SPREAD).then(|| offsets[offsets.len() / 2])
+}
+
+/// Compute path similarity score between two paths (0.0 to 1.0)
+///
+/// Uses a simple token-based approach: compares path components and filenames.
+/// Higher score indicates more similar paths.
+fn path_similarity(path1: &Path, path2: &Path) -> f64 {
+    let name1 = path1.file_name().and_then(|n| n.to_str()).unwrap_or("");
+    let name2 = path2.file_name().and_then(|n| n.to_str()).unwrap_or("");
+
+    // Exact filename match is a strong signal
+    if name1 == name2 {
+        return 0.9;
+    }
+
+    // Compute simple string similarity for filenames
+    let filename_sim = simple_string_similarity(name1, name2);
+
+    // Also consider directory similarity
+    let dir1 = path1.parent().map(|p| p.to_string_lossy());
+    let dir2 = path2.parent().map(|p| p.to_string_lossy());
+
+    let dir_sim = match (dir1, dir2) {
+        (Some(d1), Some(d2)) => simple_string_similarity(&d1, &d2),
+        _ => 0.0,
+    };
+
+    // Weight filename more heavily than directory
+    filename_sim * 0.7 + dir_sim * 0.3
+}
+
+/// Simple string similarity using character overlap (Jaccard-like)
+fn simple_string_similarity(s1: &str, s2: &str) -> f64 {
+    // Handle exact matches first (including empty strings)
+    if s1 == s2 {
+        return 1.0;
+    }
+    // If one is empty but not both (already handled above), they're completely different
+    if s1.is_empty() || s2.is_empty() {
+        return 0.0;
+    }
+
+    let chars1: HashSet<char> = s1.chars().collect();
+    let chars2: HashSet<char> = s2.chars().collect();
+
+    let intersection = chars1.intersection(&chars2).count();
+    let union = chars1.union(&chars2).count();
+
+    if union == 0 {
+        0.0
+    } else {
+        intersection as f64 / union as f64
+    }
+}
+
+/// Synchronize changes from source to destination based on diff results
+///
+/// This function applies the changes identified in a diff:
+/// - Copies new and modified files
+/// - Handles renames (moves files if possible, copies otherwise)
+/// - Optionally deletes removed files
+///
+/// Files rejected by the options (e.g. not in the content allowlist) are left
+/// untouched and listed in the returned [`SyncReport`].
+///
+/// # Resuming
+///
+/// A copy only gets the source's mtime once its content is completely
+/// written. After an interrupted run, a rescan therefore sees finished files
+/// as unchanged, even when comparing by size and mtime, and partial ones as
+/// modified, so simply running again redoes only the remaining work. This
+/// relies on `preserve_timestamps`; under `content_only` a size+mtime
+/// comparison recopies everything.
+///
+/// # Arguments
+///
+/// * `source_root` - Source directory root
+/// * `dest_root` - Destination directory root
+/// * `diff` - Diff results to apply
+/// * `options` - Sync options
+/// * `progress` - Optional progress reporter
+pub fn sync_changes(
+    source_root: &Path,
+    dest_root: &Path,
+    diff: &DiffResult,
+    options: &SyncOptions,
+    progress: Option<&ProgressReporter>,
+) -> Result<SyncReport> {
+    let started = Instant::now();
+    let mut report = SyncReport::default();
+
+    let fallback = sibling_temps_fallback(options, dest_root, same_filesystem);
+    let options = match &fallback {
+        Some((sibling_temps, warning)) => {
+            report.warnings.push(warning.clone());
+            sibling_temps
+        },
+        None => options,
+    };
+
+    let total_ops = diff.added.len()
+        + diff.modified.len()
+        + diff.renamed.len()
+        + diff.metadata_changed.len()
+        + if options.delete_removed {
+            diff.removed.len()
+        } else {
+            0
+        };
+
+    if progress.is_some() {
+        println!("Applying {total_ops} changes...");
+    }
+
+    if options.fail_on_blocked {
+        let blocked: Vec<String> = diff
+            .added
+            .iter()
+            .chain(&diff.modified)
+            .chain(diff.renamed.iter().map(|(_, new)| new))
+            .chain(&diff.metadata_changed)
+            .filter(|file| options.rejection(file) == Some(SkipReason::Blocked))
+            .map(|file| file.path.display().to_string())
+            .collect();
+        if !blocked.is_empty() {
+            return Err(SyncError::Blocked(blocked.join(", ")).into());
+        }
+    }
+
+    let protected = options.protected_paths()?;
+    let kept = options.kept_paths()?;
+    let skip_reason = |file: &FileMeta| {
+        options
+            .rejection(file)
+            .or_else(|| protected.is_match(&file.path).then_some(SkipReason::Protected))
+    };
+
+    // Copy new and modified files
+    let mut files_to_copy: Vec<&FileMeta> = Vec::new();
+    for file in diff.added.iter().chain(diff.modified.iter()) {
+        match skip_reason(file) {
+            None => files_to_copy.push(file),
+            Some(reason) => report.skip(options, &file.path, OperationKind::Copy, reason),
+        }
+    }
+
+    let mut renames: Vec<&(FileMeta, FileMeta)> = Vec::new();
+    for pair in &diff.renamed {
+        match skip_reason(&pair.1) {
+            None => renames.push(pair),
+            Some(reason) => report.skip(options, &pair.1.path, OperationKind::Rename, reason),
+        }
+    }
+
+    // Copies and renames carry the relative path they are written to, which
+    // only differs from the source path when a case conflict is renamed
+    let mut copies: Vec<(&FileMeta, PathBuf)> =
+        files_to_copy.into_iter().map(|file| (file, file.path.clone())).collect();
+    let mut renames: Vec<(&(FileMeta, FileMeta), PathBuf)> =
+        renames.into_iter().map(|pair| (pair, pair.1.path.clone())).collect();
+    resolve_case_conflicts(dest_root, &mut copies, &mut renames, options, &mut report)?;
+
+    // Old rename paths that another change writes to must not be removed,
+    // even if that write is deferred
+    let refilled: HashSet<PathBuf> = copies
+        .iter()
+        .map(|(_, target)| target.clone())
+        .chain(renames.iter().map(|(_, target)| target.clone()))
+        .collect();
+    // A rename target that is another rename's old path holds a file the
+    // scan saw, which is being moved on rather than lost
+    let moved_on: HashSet<&Path> = renames.iter().map(|((old, _), _)| old.path.as_path()).collect();
+
+    copies.sort_by(|a, b| options.transfer_order.compare(a.0, b.0));
+    renames.sort_by(|a, b| options.transfer_order.compare(&a.0 .1, &b.0 .1));
+    let (kept_copies, kept_renames) = transfers_within_limit(
+        options,
+        &copies.iter().map(|(file, _)| *file).collect::<Vec<_>>(),
+        &renames.iter().map(|((_, new), _)| new).collect::<Vec<_>>(),
+    );
+    for (_, target) in copies.drain(kept_copies..) {
+        report.skip(options, &target, OperationKind::Copy, SkipReason::LimitReached);
+    }
+    for (_, target) in renames.drain(kept_renames..) {
+        report.skip(options, &target, OperationKind::Rename, SkipReason::LimitReached);
+    }
+
+    let delete_skip = |path: &Path| {
+        if protected.is_match(path) {
+            Some(SkipReason::Protected)
+        } else if kept.is_match(path) {
+            Some(SkipReason::Kept)
+        } else {
+            None
+        }
+    };
+    let status = options.status_file.clone().map(|path| {
+        let deletes = if options.delete_removed {
+            diff.removed.len()
+        } else {
+            0
+        };
+        let files_total = copies.len() + renames.len() + diff.metadata_changed.len() + deletes;
+        let bytes_total = copies.iter().map(|(file, _)| file.size).sum();
+        StatusFile::start(path, options.status_interval, files_total as u64, bytes_total)
+    });
+    let finished = |result: &Result<OperationResult>, bytes: u64| {
+        if let Some(status) = &status {
+            let copied = matches!(result, Ok(OperationResult::Copied));
+            status.finish_file(if copied { bytes } else { 0 });
+        }
+    };
+
+    if options.delete_timing == DeleteTiming::Before {
+        let status = status.as_ref();
+        delete_removed_files(dest_root, diff, options, delete_skip, false, &mut report, status)?;
+    }
+
+    let budget = ByteBudget::new(options.byte_quota);
+    // Renames are copies too, so one byte count covers both, advanced per
+    // buffer and labelled with the file being written
+    let copy_progress = progress.map(|reporter| {
+        let copied = copies.iter().map(|(file, _)| file.size);
+        let bytes = copied.chain(renames.iter().map(|((_, new), _)| new.size)).sum();
+        ParallelProgress::new(reporter.add_bytes_task("Copying", bytes))
+    });
+    let start_copy = |target: &Path| {
+        if let Some(progress) = &copy_progress {
+            progress.set_message(format!("Copying {}", target.display()));
+        }
+    };
+    // Timestamps that couldn't be set, collected from the parallel copies
+    let mtime_warnings = Mutex::new(Vec::new());
+    let note_mtime_error = |target: &Path, error: Option<std::io::Error>| {
+        if let Some(e) = error {
+            let warning = format!("{}: modification time not preserved: {e}", target.display());
+            mtime_warnings.lock().unwrap().push(warning);
+        }
+    };
+
+    if let Some(status) = &status {
+        status.set_phase("copy");
+    }
+    let landed = match &options.small_file_batching {
+        Some(config) => {
+            let spool = diff.fifo_spool.as_ref();
+            copy_in_staging_archives(
+                source_root,
+                dest_root,
+                &copies,
+                spool,
+                options,
+                config,
+                &budget,
+            )
+        },
+        None => vec![false; copies.len()],
+    };
+    let mut results: Vec<Option<Result<OperationResult>>> = Vec::with_capacity(copies.len());
+    for (landed, (file, _)) in landed.iter().zip(&copies) {
+        if *landed {
+            results.push(Some(Ok(OperationResult::Copied)));
+            if let Some(status) = &status {
+                status.finish_file(file.size);
+            }
+            if let Some(progress) = &copy_progress {
+                progress.inc_by(file.size);
+            }
+        } else {
+            results.push(None);
+        }
+    }
+    let pending: Vec<usize> = (0..copies.len()).filter(|&i| !landed[i]).collect();
+    let pending_results = run_operations(&pending, options, |&i| {
+        let (file, target) = &copies[i];
+        if let Some(status) = &status {
+            status.start_file(target);
+        }
+        let result = budget.spend(file.size, || {
+            let source_path = copy_source(source_root, file, diff.fifo_spool.as_ref());
+            let dest_path = dest_root.join(target);
+
+            // Ensure parent directory exists
+            if let Some(parent) = dest_path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+
+            start_copy(target);
+            let mtime_error =
+                options.copy_into_place(&source_path, &dest_path, copy_progress.as_ref())?;
+            note_mtime_error(target, mtime_error);
+            options.verify_copy(&source_path, &dest_path, file)?;
+            options.preserve_extended_times(&dest_path, file)?;
+            Ok(OperationResult::Copied)
+        });
+        finished(&result, file.size);
+        result
+    });
+    for (i, result) in pending.into_iter().zip(pending_results) {
+        results[i] = result;
+    }
+    for ((file, target), result) in copies.iter().zip(results) {
+        report.record(options, target, OperationKind::Copy, file.size, result)?;
+    }
+
+    if options.delete_timing == DeleteTiming::During {
+        let copies_failed = !report.failed.is_empty();
+        let status = status.as_ref();
+        delete_removed_files(
+            dest_root,
+            diff,
+            options,
+            delete_skip,
+            copies_failed,
+            &mut report,
+            status,
+        )?;
+    }
+
+    // Handle renames - for now, just copy to new location
+    // TODO: Optimize by moving files when possible (requires checking if old location should be deleted)
+    let rename_into_place = |old: &FileMeta, new: &FileMeta, target: &Path| -> Result<_> {
+        let dest_path = dest_root.join(target);
+        let source_path = copy_source(source_root, new, diff.fifo_spool.as_ref());
+        // The target was free when scanned, but may hold a file the scan
+        // didn't see (ignored, filtered, or created since)
+        let occupied = options.occupied_rename_policy != OccupiedRenamePolicy::Overwrite
+            && !moved_on.contains(target)
+            && holds_other_content(&dest_path, &source_path)?;
+        if occupied && options.occupied_rename_policy == OccupiedRenamePolicy::Skip {
+            return Ok(OperationResult::Skipped(SkipReason::TargetOccupied));
+        }
+
+        budget.spend(new.size, || {
+            // Only move the occupant aside once the rename is sure to run, so
+            // a deferred rename leaves it in place
+            if occupied {
+                fs::rename(&dest_path, free_backup_path(&dest_path)?)?;
+            }
+            if let Some(parent) = dest_path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+
+            start_copy(target);
+            let mtime_error =
+                options.copy_into_place(&source_path, &dest_path, copy_progress.as_ref())?;
+            note_mtime_error(target, mtime_error);
+            options.verify_copy(&source_path, &dest_path, new)?;
+            options.preserve_extended_times(&dest_path, new)?;
+
+            // Remove old file in destination, unless it is protected or
+            // is the next link of a rename chain
+            if !protected.is_match(&old.path) && !refilled.contains(old.path.as_path()) {
+                remove_file_safe(&dest_root.join(&old.path))?;
+            }
+
+            Ok(OperationResult::Moved)
+        })
+    };
+    if let Some(status) = &status {
+        status.set_phase("rename");
+    }
+    let results = run_operations(&renames, options, |((old, new), target)| {
+        if let Some(status) = &status {
+            status.start_file(target);
+        }
+        let result = rename_into_place(old, new, target);
+        finished(&result, 0);
+        result
+    });
+    for (((_, new), target), result) in renames.iter().zip(results) {
+        report.record(options, target, OperationKind::Rename, new.size, result)?;
+    }
+    if let Some(progress) = &copy_progress {
+        progress.finish();
+    }
+    let mut mtime_warnings = mtime_warnings.into_inner().unwrap();
+    mtime_warnings.sort();
+    report.warnings.extend(mtime_warnings);
+    let copies_failed = !report.failed.is_empty();
+
+    // Apply metadata-only updates without recopying content
+    if let Some(status) = &status {
+        status.set_phase("metadata");
+    }
+    for file in &diff.metadata_changed {
+        if let Some(status) = &status {
+            status.start_file(&file.path);
+        }
+        let dest_path = dest_root.join(&file.path);
+        let reason = skip_reason(file)
+            .or_else(|| options.is_cancelled().then_some(SkipReason::Cancelled))
+            .or_else(|| options.content_only.then_some(SkipReason::ContentOnly))
+            .or_else(|| {
+                (options.minimize_writes && metadata_matches(&dest_path, file))
+                    .then_some(SkipReason::UpToDate)
+            });
+        match reason {
+            Some(reason) => report.skip(options, &file.path, OperationKind::Metadata, reason),
+            None => {
+                let result = apply_metadata(&dest_path, file)
+                    .map(|()| OperationResult::MetadataUpdated)
+                    .map_err(anyhow::Error::from);
+                report.record(options, &file.path, OperationKind::Metadata, 0, Some(result))?;
+            },
+        }
+        if let Some(status) = &status {
+            status.finish_file(0);
+        }
+    }
+
+    if options.delete_timing == DeleteTiming::After {
+        let status = status.as_ref();
+        delete_removed_files(
+            dest_root,
+            diff,
+            options,
+            delete_skip,
+            copies_failed,
+            &mut report,
+            status,
+        )?;
+    }
+
+    report.interrupted = options.is_cancelled();
+    if let Some(status) = status {
+        status.finish(report.interrupted);
+    }
+    report.timings.sync = started.elapsed();
+
+    Ok(report)
+}
+
+/// Options writing temporary files beside their destinations, with a
+/// warning, if `options.temp_dir` isn't on the destination's filesystem
+///
+/// A temp directory on another filesystem would turn each final rename into
+/// a non-atomic copy. `same_fs` is [`same_filesystem`] outside of tests.
+fn sibling_temps_fallback(
+    options: &SyncOptions,
+    dest_root: &Path,
+    same_fs: impl Fn(&Path, &Path) -> std::io::Result<bool>,
+) -> Option<(SyncOptions, String)> {
+    let temp_dir = options.temp_dir.as_ref()?;
+    if same_fs(temp_dir, dest_root).unwrap_or(false) {
+        return None;
+    }
+    let warning = format!(
+        "temp directory {} is not on the destination's filesystem; writing temporary files \
+         beside their destinations instead",
+        temp_dir.display()
+    );
+    Some((SyncOptions { temp_dir: None, ..options.clone() }, warning))
+}
+
+/// Delete removed files if requested, the delete phase of [`sync_changes`]
+///
+/// `delete_skip` gives the reason a path is held back, if any.
+fn delete_removed_files(
+    dest_root: &Path,
+    diff: &DiffResult,
+    options: &SyncOptions,
+    delete_skip: impl Fn(&Path) -> Option<SkipReason>,
+    copies_failed: bool,
+    report: &mut SyncReport,
+    status: Option<&StatusFile>,
+) -> Result<()> {
+    if !options.delete_removed {
+        return Ok(());
+    }
+    if let Some(status) = status {
+        status.set_phase("delete");
+    }
+    for file in &diff.removed {
+        if let Some(status) = status {
+            status.start_file(&file.path);
+        }
+        let reason = if options.is_cancelled() {
+            Some(SkipReason::Cancelled)
+        } else if options.delete_only_if_copies_succeed && copies_failed {
+            Some(SkipReason::CopiesFailed)
+        } else {
+            delete_skip(&file.path)
+        };
+        match reason {
+            Some(reason) => report.skip(options, &file.path, OperationKind::Delete, reason),
+            None => {
+                let dest_path = dest_root.join(&file.path);
+                let result = remove_file_safe(&dest_path)
+                    .map(|()| OperationResult::Deleted)
+                    .map_err(anyhow::Error::from);
+                report.record(
+                    options,
+                    &file.path,
+                    OperationKind::Delete,
+                    file.size,
+                    Some(result),
+                )?;
+            },
+        }
+        if let Some(status) = status {
+            status.finish_file(0);
+        }
+    }
+    Ok(())
+}
+
+/// Bytes left to transfer under `SyncOptions::byte_quota`, shared across copy threads
+struct ByteBudget {
+    remaining: Option<AtomicU64>,
+}
+
+impl ByteBudget {
+    fn new(quota: Option<u64>) -> Self {
+        Self { remaining: quota.map(AtomicU64::new) }
+    }
+
+    /// Run a transfer of `bytes` if it fits in the remaining budget
+    ///
+    /// Transfers that don't fit are deferred as [`SkipReason::QuotaReached`]
+    /// without starting, so no file is left partially written. Bytes
+    /// reserved for a failed transfer are returned to the budget.
+    fn spend(
+        &self,
+        bytes: u64,
+        transfer: impl FnOnce() -> Result<OperationResult>,
+    ) -> Result<OperationResult> {
+        let Some(remaining) = &self.remaining else {
+            return transfer();
+        };
+
+        let reserved = remaining
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |left| left.checked_sub(bytes))
+            .is_ok();
+        if !reserved {
+            return Ok(OperationResult::Skipped(SkipReason::QuotaReached));
+        }
+
+        let result = transfer();
+        if result.is_err() {
+            remaining.fetch_add(bytes, Ordering::SeqCst);
+        }
+        result
+    }
+}
+
+/// Copy the small files of `copies` through staging archives
+///
+/// Files no larger than `config.max_file_size` are packed into archives of
+/// at most `config.max_batch_bytes`, which are unpacked in parallel. Returns
+/// which copies landed; files of an archive that failed, was cancelled or
+/// didn't fit the byte budget are left to the regular per-file copy.
+fn copy_in_staging_archives(
+    source_root: &Path,
+    dest_root: &Path,
+    copies: &[(&FileMeta, PathBuf)],
+    fifo_spool: Option<&FifoSpool>,
+    options: &SyncOptions,
+    config: &SmallFileBatchConfig,
+    budget: &ByteBudget,
+) -> Vec<bool> {
+    let mut landed = vec![false; copies.len()];
+    // Archives overwrite in place
+    if options.overwrite_strategy != OverwriteStrategy::InPlace {
+        return landed;
+    }
+
+    let mut packs: Vec<Vec<usize>> = Vec::new();
+    let mut pack_bytes = 0;
+    for (i, (file, _)) in copies.iter().enumerate() {
+        if file.size > config.max_file_size {
+            continue;
+        }
+        if packs.is_empty() || pack_bytes + file.size > config.max_batch_bytes {
+            packs.push(Vec::new());
+            pack_bytes = 0;
+        }
+        packs.last_mut().unwrap().push(i);
+        pack_bytes += file.size;
+    }
+
+    let unpacked: Vec<bool> = packs
+        .par_iter()
+        .map(|pack| {
+            if options.is_cancelled() {
+                return false;
+            }
+            let bytes = pack.iter().map(|&i| copies[i].0.size).sum();
+            let result = budget.spend(bytes, || {
+                let mut archive = StagingArchive::default();
+                for &i in pack {
+                    let (file, target) = &copies[i];
+                    archive
+                        .add(&copy_source(source_root, file, fifo_spool), dest_root.join(target))?;
+                }
+                let preserve_timestamps = options.preserve_timestamps && !options.content_only;
+                archive.unpack(preserve_timestamps, !options.content_only)?;
+                for &i in pack {
+                    let (file, target) = &copies[i];
+                    let dest_path = dest_root.join(target);
+                    options.verify_copy(
+                        &copy_source(source_root, file, fifo_spool),
+                        &dest_path,
+                        file,
+                    )?;
+                    options.preserve_extended_times(&dest_path, file)?;
+                }
+                Ok(OperationResult::Copied)
+            });
+            matches!(result, Ok(OperationResult::Copied))
+        })
+        .collect();
+    for (pack, unpacked) in packs.iter().zip(unpacked) {
+        for &i in pack {
+            landed[i] = unpacked;
+        }
+    }
+    landed
+}
+
+/// How many of the planned copies and renames fit in `SyncOptions::limit`
+///
+/// Both lists must already be sorted by `transfer_order`, so the first
+/// `limit` transfers across the two are a prefix of each. Ties go to copies,
+/// which run first.
+fn transfers_within_limit(
+    options: &SyncOptions,
+    copies: &[&FileMeta],
+    renames: &[&FileMeta],
+) -> (usize, usize) {
+    let Some(limit) = options.limit else {
+        return (copies.len(), renames.len());
+    };
+    let (mut kept_copies, mut kept_renames) = (0, 0);
+    while kept_copies + kept_renames < limit {
+        let next_is_copy = match (copies.get(kept_copies), renames.get(kept_renames)) {
+            (Some(copy), Some(rename)) => options.transfer_order.compare(copy, rename).is_le(),
+            (None, None) => break,
+            (copy, _) => copy.is_some(),
+        };
+        if next_is_copy {
+            kept_copies += 1;
+        } else {
+            kept_renames += 1;
+        }
+    }
+    (kept_copies, kept_renames)
+}
+
+/// Apply the case conflict policy to planned copy and rename targets
+///
+/// Only runs when two targets differ solely by case and the destination
+/// ignores case, where the later write would otherwise silently replace the
+/// earlier one. Within a colliding group the first path in byte order wins.
+fn resolve_case_conflicts(
+    dest_root: &Path,
+    copies: &mut Vec<(&FileMeta, PathBuf)>,
+    renames: &mut Vec<(&(FileMeta, FileMeta), PathBuf)>,
+    options: &SyncOptions,
+    report: &mut SyncReport,
+) -> Result<()> {
+    let case_key = |path: &Path| path.to_string_lossy().to_lowercase();
+
+    // Indices below `copies.len()` are copies, the rest are renames
+    let copy_count = copies.len();
+    let mut targets: Vec<(usize, PathBuf)> = copies
+        .iter()
+        .map(|(_, target)| target.clone())
+        .chain(renames.iter().map(|(_, target)| target.clone()))
+        .enumerate()
+        .collect();
+    targets.sort_by(|a, b| a.1.cmp(&b.1));
+
+    let mut taken = HashSet::new();
+    let mut losers = Vec::new();
+    for (index, target) in targets {
+        if !taken.insert(case_key(&target)) {
+            losers.push((index, target));
+        }
+    }
+    if losers.is_empty() || !options.dest_is_case_insensitive(dest_root) {
+        return Ok(());
+    }
+
+    match options.case_conflict_policy {
+        CaseConflictPolicy::Error => {
+            let paths: Vec<String> =
+                losers.iter().map(|(_, path)| path.display().to_string()).collect();
+            return Err(SyncError::CaseConflict(paths.join(", ")).into());
+        },
+        CaseConflictPolicy::SkipSecond => {
+            for (index, path) in &losers {
+                let kind = if *index < copy_count {
+                    OperationKind::Copy
+                } else {
+                    OperationKind::Rename
+                };
+                report.skip(options, path, kind, SkipReason::CaseConflict);
+            }
+            let skipped: HashSet<usize> = losers.into_iter().map(|(index, _)| index).collect();
+            let mut index = 0..;
+            copies.retain(|_| !skipped.contains(&index.next().unwrap_or_default()));
+            renames.retain(|_| !skipped.contains(&index.next().unwrap_or_default()));
+        },
+        CaseConflictPolicy::Rename => {
+            for (index, path) in losers {
+                let renamed = (1..)
+                    .map(|n| numbered_path(&path, n))
+                    .find(|candidate| taken.insert(case_key(candidate)))
+                    .unwrap_or(path);
+                if index < copy_count {
+                    copies[index].1 = renamed;
+                } else {
+                    renames[index - copy_count].1 = renamed;
+                }
+            }
+        },
+    }
+
+    Ok(())
+}
+
+/// Insert a counter before the extension, e.g. `dir/foo.txt` -> `dir/foo (2).txt`
+fn numbered_path(path: &Path, n: usize) -> PathBuf {
+    let stem = path.file_stem().map(|s| s.to_string_lossy()).unwrap_or_default();
+    let name = match path.extension() {
+        Some(ext) => format!("{stem} ({n}).{}", ext.to_string_lossy()),
+        None => format!("{stem} ({n})"),
+    };
+    path.with_file_name(name)
+}
+
+/// Apply a file's recorded permissions and owner to `dest`
+fn apply_metadata(dest: &Path, file: &FileMeta) -> std::io::Result<()> {
+    #[cfg(unix)]
+    {
+        set_file_mode_and_owner(dest, file.permissions, file.owner)
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = (dest, file);
+        Ok(())
+    }
+}
+
+/// Check whether `dest` already has a file's recorded permissions and owner
+///
+/// Only reads metadata. Unreadable files never match, so they are updated.
+fn metadata_matches(dest: &Path, file: &FileMeta) -> bool {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+        let Ok(current) = fs::metadata(dest) else {
+            return false;
+        };
+        file.permissions.map_or(true, |mode| current.permissions().mode() == mode)
+            && file.owner.map_or(true, |owner| (current.uid(), current.gid()) == owner)
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = (dest, file);
+        true
+    }
+}
+
+/// Run one sync operation per item in parallel
+///
+/// Items run in batches of `copy_batch_size` when set. Unless
+/// `continue_on_error` is set, items are no longer started once any
+/// operation has failed; those items yield `None`. Items not yet started when
+/// the sync is cancelled are skipped.
+///
+/// Transient failures are queued rather than treated as failed, and the queue
+/// is reprocessed for up to `retry_rounds` rounds with exponential backoff
+/// once the main pass completes.
+fn run_operations<T, F>(
+    items: &[T],
+    options: &SyncOptions,
+    op: F,
+) -> Vec<Option<Result<OperationResult>>>
+where
+    T: Sync,
+    F: Fn(&T) -> Result<OperationResult> + Sync,
+{
+    let failed = AtomicBool::new(false);
+    let can_retry = options.retry_rounds > 0;
+
+    let batch_size = options.copy_batch_size.unwrap_or(items.len()).max(1);
+    let mut results: Vec<Option<Result<OperationResult>>> = Vec::with_capacity(items.len());
+    for batch in items.chunks(batch_size) {
+        results.par_extend(batch.par_iter().map(|item| {
+            if !options.continue_on_error && failed.load(Ordering::Relaxed) {
+                return None;
+            }
+            if options.is_cancelled() {
+                return Some(Ok(OperationResult::Skipped(SkipReason::Cancelled)));
+            }
+            let result = op(item);
+            if let Err(e) = &result {
+                if !(can_retry && is_transient(e)) {
+                    failed.store(true, Ordering::Relaxed);
+                }
+            }
+            Some(result)
+        }));
+        if let Some(BatchHook(hook)) = &options.batch_hook {
+            hook(batch.len());
+        }
     }
 
-    let mut added = Vec::new();
-    let mut removed = Vec::new();
-    let mut modified = Vec::new();
-    let mut renamed = Vec::new();
-    let mut processed_dest_paths = HashSet::new();
+    let mut backoff = options.retry_backoff;
+    for _ in 0..options.retry_rounds {
+        if failed.load(Ordering::Relaxed) && !options.continue_on_error {
+            break;
+        }
+        let queue: Vec<usize> = results
+            .iter()
+            .enumerate()
+            .filter(|(_, result)| matches!(result, Some(Err(e)) if is_transient(e)))
+            .map(|(i, _)| i)
+            .collect();
+        if queue.is_empty() || options.is_cancelled() {
+            break;
+        }
 
-    // Find added and modified files
-    for source_file in &source.files {
-        if let Some(dest_file) = dest_by_path.get(&source_file.path) {
-            // File exists in both locations
-            if source_file.hash != dest_file.hash {
-                // Content changed
-                modified.push(source_file.clone());
+        thread::sleep(backoff);
+        backoff = backoff.saturating_mul(2);
+
+        let retried: Vec<Result<OperationResult>> =
+            queue.par_iter().map(|&i| op(&items[i])).collect();
+        for (i, result) in queue.into_iter().zip(retried) {
+            results[i] = Some(result);
+        }
+    }
+
+    results
+}
+
+/// Check whether an error is likely to go away if the operation is retried
+fn is_transient(error: &anyhow::Error) -> bool {
+    let Some(io_error) = error.downcast_ref::<std::io::Error>() else {
+        return false;
+    };
+
+    match io_error.kind() {
+        std::io::ErrorKind::WouldBlock
+        | std::io::ErrorKind::TimedOut
+        | std::io::ErrorKind::Interrupted => return true,
+        _ => {},
+    }
+
+    #[cfg(unix)]
+    let transient_codes = [libc::EBUSY, libc::ETXTBSY];
+    // ERROR_SHARING_VIOLATION, ERROR_LOCK_VIOLATION
+    #[cfg(windows)]
+    let transient_codes = [32, 33];
+    #[cfg(not(any(unix, windows)))]
+    let transient_codes: [i32; 0] = [];
+
+    io_error.raw_os_error().is_some_and(|code| transient_codes.contains(&code))
+}
+
+/// Default for [`ReconcileOptions::dest_sample_rate`]
+pub const DEFAULT_DEST_SAMPLE_RATE: f64 = 0.01;
+
+/// Options for [`reconcile`], one set per phase
+#[derive(Debug, Clone)]
+pub struct ReconcileOptions {
+    /// How both directories are scanned
+    pub scan: ScanOptions,
+    /// How the scans are compared
+    pub diff: DiffOptions,
+    /// How the changes are applied
+    pub sync: SyncOptions,
+    /// Manifest of the destination written by the previous run
+    ///
+    /// When it exists and describes the same destination root, it is used
+    /// instead of scanning the destination, which is safe as long as nothing
+    /// but Janus writes there. Neither read nor rewritten when
+    /// [`ScanOptions::subpath`] is set. See [`ReconcilePlan::save_dest_manifest`].
+    pub trusted_dest_manifest: Option<PathBuf>,
+    /// Scan the destination even if a trusted manifest is available
+    pub verify_dest: bool,
+    /// Fraction of trusted manifest entries to stat before trusting it
+    ///
+    /// Catches out-of-band changes to the destination cheaply: a random
+    /// sample of files is compared by size and mtime, and any mismatch is
+    /// handled according to [`ReconcileOptions::drift_policy`]. At least one
+    /// file is checked for any positive rate; `0.0` disables the check.
+    /// Defaults to [`DEFAULT_DEST_SAMPLE_RATE`].
+    pub dest_sample_rate: f64,
+    /// What to do when sampling finds the destination changed out-of-band
+    pub drift_policy: DriftPolicy,
+    /// Encoding used when [`reconcile`] rewrites the trusted manifest
+    pub manifest_format: ManifestFormat,
+    /// Fail unless the destination's [`ScanResult::tree_hash`] equals this
+    /// after syncing, see [`verify_tree_hash`]
+    pub expected_tree_hash: Option<ContentHash>,
+}
+
+impl Default for ReconcileOptions {
+    fn default() -> Self {
+        Self {
+            scan: ScanOptions::default(),
+            diff: DiffOptions::default(),
+            sync: SyncOptions::default(),
+            trusted_dest_manifest: None,
+            verify_dest: false,
+            dest_sample_rate: DEFAULT_DEST_SAMPLE_RATE,
+            drift_policy: DriftPolicy::default(),
+            manifest_format: ManifestFormat::default(),
+            expected_tree_hash: None,
+        }
+    }
+}
+
+impl ReconcileOptions {
+    /// Check every phase's options, and how they combine, for conflicts
+    pub fn validate(&self) -> std::result::Result<(), Vec<OptionConflict>> {
+        let mut conflicts: Vec<OptionConflict> =
+            [self.scan.validate(), self.diff.validate(), self.sync.validate()]
+                .into_iter()
+                .filter_map(std::result::Result::err)
+                .flatten()
+                .collect();
+
+        if self.verify_dest && self.trusted_dest_manifest.is_none() {
+            conflicts.push(OptionConflict::new(
+                &["verify_dest", "trusted_dest_manifest"],
+                "there is no destination manifest to verify",
+            ));
+        }
+        if !(0.0..=1.0).contains(&self.dest_sample_rate) {
+            conflicts.push(OptionConflict::new(
+                &["dest_sample_rate"],
+                format!("{} is not a fraction between 0 and 1", self.dest_sample_rate),
+            ));
+        }
+        if self.trusted_dest_manifest.is_some() && self.scan.subpath.is_some() {
+            conflicts.push(OptionConflict::new(
+                &["trusted_dest_manifest", "subpath"],
+                "a destination manifest isn't used when scanning a subpath",
+            ));
+        }
+        if self.trusted_dest_manifest.is_some() && self.scan.hash_policy == HashPolicy::OnConflict {
+            conflicts.push(OptionConflict::new(
+                &["trusted_dest_manifest", "hash_policy"],
+                "a destination manifest needs every hash, but OnConflict defers them",
+            ));
+        }
+        if self.diff.compare_metadata && self.sync.content_only {
+            conflicts.push(OptionConflict::new(
+                &["compare_metadata", "content_only"],
+                "metadata differences are found but never applied under content_only",
+            ));
+        }
+        if self.sync.verify_after_copy && !self.scan.hash_skip_ranges.is_empty() {
+            conflicts.push(OptionConflict::new(
+                &["verify_after_copy", "hash_skip_ranges"],
+                "a copy is verified against its whole content, but the scan skips ranges",
+            ));
+        }
+        conflicts_to_result(conflicts)
+    }
+}
+
+/// Response to a trusted destination manifest that no longer matches disk
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DriftPolicy {
+    /// Discard the manifest and scan the destination
+    #[default]
+    Rescan,
+    /// Keep using the manifest and report the drifted paths
+    Warn,
+}
+
+/// Scanned and diffed state of two directories, ready to be applied
+#[derive(Debug, Clone)]
+pub struct ReconcilePlan {
+    /// Scan of the source directory
+    pub source: ScanResult,
+    /// Scan of the destination directory
+    pub dest: ScanResult,
+    /// Changes needed to make the destination match the source
+    pub diff: DiffResult,
+    /// The destination scan was loaded from a trusted manifest
+    pub dest_from_manifest: bool,
+    /// Sampled destination files that no longer match the trusted manifest
+    pub dest_drift: Vec<PathBuf>,
+    /// Time spent scanning and diffing; `sync` is zero until applied
+    pub timings: PhaseTimings,
+}
+
+impl ReconcilePlan {
+    /// Check whether applying the plan would change anything
+    ///
+    /// Removed files only count when they would be deleted.
+    pub fn has_changes(&self, delete_removed: bool) -> bool {
+        let diff = &self.diff;
+        !(diff.added.is_empty()
+            && diff.modified.is_empty()
+            && diff.renamed.is_empty()
+            && diff.metadata_changed.is_empty()
+            && (!delete_removed || diff.removed.is_empty()))
+    }
+
+    /// Apply the planned changes to the destination
+    pub fn apply(
+        &self,
+        options: &SyncOptions,
+        progress: Option<&ProgressReporter>,
+    ) -> Result<SyncReport> {
+        let mut report =
+            sync_changes(&self.source.root, &self.dest.root, &self.diff, options, progress)?;
+        if options.replicate_empty_parents {
+            for dir in &self.source.traversed_dirs {
+                fs::create_dir_all(self.dest.root.join(dir))?;
             }
-            processed_dest_paths.insert(&dest_file.path);
-        } else {
-            // File not at same path in destination
-            // Check if it might be a rename (same content, different path)
-            if let Some(dest_files_with_hash) = dest_by_hash.get(&source_file.hash) {
-                // Find best match from files with same hash
-                let mut best_match: Option<&FileMeta> = None;
-                let mut best_score = 0.0;
+        }
+        report.timings.scan = self.timings.scan;
+        report.timings.diff = self.timings.diff;
+        Ok(report)
+    }
 
-                for candidate in dest_files_with_hash {
-                    if processed_dest_paths.contains(&candidate.path) {
-                        continue;
-                    }
+    /// List the operations applying the plan would perform, in order
+    ///
+    /// Files the options filter out (content lists, `protect`, `keep`,
+    /// `content_only` metadata updates) are left out, and deletes are placed
+    /// according to `delete_timing`. Copies and renames carry the bytes they
+    /// transfer, since a rename is carried out as a copy. Works from the
+    /// scans alone, so it needs no access to the destination.
+    pub fn operations(&self, options: &SyncOptions) -> Result<Vec<PlannedOperation>> {
+        let protected = options.protected_paths()?;
+        let kept = options.kept_paths()?;
+        let allowed =
+            |file: &FileMeta| options.rejection(file).is_none() && !protected.is_match(&file.path);
+        let planned = |kind, file: &FileMeta, from: Option<&FileMeta>| PlannedOperation {
+            kind,
+            path: file.path.clone(),
+            from: from.map(|old| old.path.clone()),
+            bytes: if kind == OperationKind::Metadata {
+                0
+            } else {
+                file.size
+            },
+        };
+        let diff = &self.diff;
 
-                    let score = path_similarity(&source_file.path, &candidate.path);
-                    if score > best_score {
-                        best_score = score;
-                        best_match = Some(candidate);
-                    }
-                }
+        let mut copies: Vec<&FileMeta> =
+            diff.added.iter().chain(&diff.modified).filter(|file| allowed(file)).collect();
+        let mut renames: Vec<&(FileMeta, FileMeta)> =
+            diff.renamed.iter().filter(|(_, new)| allowed(new)).collect();
+        copies.sort_by(|a, b| options.transfer_order.compare(a, b));
+        renames.sort_by(|a, b| options.transfer_order.compare(&a.1, &b.1));
+        let new_paths: Vec<&FileMeta> = renames.iter().map(|(_, new)| new).collect();
+        let (kept_copies, kept_renames) = transfers_within_limit(options, &copies, &new_paths);
+        let copies = copies[..kept_copies]
+            .iter()
+            .map(|file| planned(OperationKind::Copy, file, None));
+        let renames = renames[..kept_renames]
+            .iter()
+            .map(|(old, new)| planned(OperationKind::Rename, new, Some(old)));
+        let metadata = diff.metadata_changed.iter().filter(|file| allowed(file));
+        let metadata = metadata
+            .filter(|_| !options.content_only)
+            .map(|file| planned(OperationKind::Metadata, file, None));
+        let deletes: Vec<PlannedOperation> = diff
+            .removed
+            .iter()
+            .filter(|file| {
+                options.delete_removed
+                    && !protected.is_match(&file.path)
+                    && !kept.is_match(&file.path)
+            })
+            .map(|file| planned(OperationKind::Delete, file, None))
+            .collect();
 
-                if let Some(matched_dest) = best_match {
-                    // Detected rename
-                    renamed.push(((*matched_dest).clone(), source_file.clone()));
-                    processed_dest_paths.insert(&matched_dest.path);
+        let mut operations = Vec::new();
+        if options.delete_timing == DeleteTiming::Before {
+            operations.extend(deletes.iter().cloned());
+        }
+        operations.extend(copies);
+        if options.delete_timing == DeleteTiming::During {
+            operations.extend(deletes.iter().cloned());
+        }
+        operations.extend(renames);
+        operations.extend(metadata);
+        if options.delete_timing == DeleteTiming::After {
+            operations.extend(deletes);
+        }
+        Ok(operations)
+    }
+
+    /// Record the destination state after applying the plan
+    ///
+    /// Writes the post-sync destination manifest for the next run to trust.
+    /// Skips the manifest can account for, such as protected and kept paths,
+    /// leave their destination entries as scanned. If the sync was
+    /// interrupted, any operation failed, or one was skipped for a reason that
+    /// depends on the destination at run time (see [`SkipReason::leaves_dest_known`]),
+    /// the resulting state is uncertain, so the manifest is removed instead and
+    /// the next run scans the destination. The same happens when any file's
+    /// hash was deferred, since a placeholder can't be trusted later.
+    pub fn save_dest_manifest(
+        &self,
+        path: &Path,
+        format: ManifestFormat,
+        options: &SyncOptions,
+        report: &SyncReport,
+    ) -> Result<()> {
+        let stale = report.skipped.iter().any(|skip| !skip.reason.leaves_dest_known());
+        if report.interrupted || stale || !report.failed.is_empty() {
+            remove_file_safe(path)?;
+            return Ok(());
+        }
+        let (mut manifest, touched) = apply_diff_to_scan(&self.dest, &self.diff, options)?;
+        if manifest.files.iter().any(|file| file.hash_deferred) {
+            remove_file_safe(path)?;
+            return Ok(());
+        }
+
+        // Written files may not carry the source's size and mtime on disk, so
+        // record what is actually there for later drift sampling
+        for file in &mut manifest.files {
+            if touched.contains(file.path.as_path()) {
+                let metadata = fs::metadata(manifest.root.join(&file.path))?;
+                let stamp = FileStamp::from_metadata(&metadata)?;
+                file.size = stamp.size;
+                file.mtime = stamp.mtime;
+                file.inode = stamp.inode;
+                file.ctime = stamp.ctime;
+            }
+        }
+        manifest.save_to_file_as(path, format)
+    }
+}
+
+/// One operation a sync would perform, listed by [`ReconcilePlan::operations`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlannedOperation {
+    /// Kind of operation
+    pub kind: OperationKind,
+    /// Relative path the operation targets (the new path for renames)
+    pub path: PathBuf,
+    /// Path a rename moves the file from
+    pub from: Option<PathBuf>,
+    /// Bytes copied, or the size of the file deleted
+    pub bytes: u64,
+}
+
+/// Scan and diff two directories without changing anything
+///
+/// The first half of [`reconcile`], for callers that want to inspect or
+/// confirm the changes before applying them with [`ReconcilePlan::apply`].
+pub fn plan_reconcile(
+    source_root: &Path,
+    dest_root: &Path,
+    options: &ReconcileOptions,
+) -> Result<ReconcilePlan> {
+    let started = Instant::now();
+    let source = scan_directory_with_options(source_root, &options.scan, None)?;
+    let mut trusted = trusted_dest_scan(dest_root, options);
+    let mut dest_drift = Vec::new();
+    if let Some(manifest) = &trusted {
+        dest_drift = sample_manifest_drift(manifest, options.dest_sample_rate);
+        if !dest_drift.is_empty() && options.drift_policy == DriftPolicy::Rescan {
+            trusted = None;
+        }
+    }
+    let dest_from_manifest = trusted.is_some();
+    let dest = match trusted {
+        Some(dest) => dest,
+        None => scan_directory_with_options(dest_root, &options.scan, None)?,
+    };
+    let scanned = Instant::now();
+    let diff = diff_scans_with_options(&source, &dest, &options.diff)?;
+    let timings = PhaseTimings {
+        scan: scanned - started,
+        diff: scanned.elapsed(),
+        sync: Duration::ZERO,
+    };
+    Ok(ReconcilePlan {
+        source,
+        dest,
+        diff,
+        dest_from_manifest,
+        dest_drift,
+        timings,
+    })
+}
+
+/// Plan a sync against a destination known only from its manifest
+///
+/// Scans the source and diffs it against `dest` without touching the
+/// destination, e.g. to preview a sync to another machine from a manifest
+/// exported there. The plan is the one [`plan_reconcile`] makes for a live
+/// destination in the same state; list it with [`ReconcilePlan::operations`].
+pub fn plan_against_manifest(
+    source_root: &Path,
+    dest: ScanResult,
+    options: &ReconcileOptions,
+) -> Result<ReconcilePlan> {
+    let started = Instant::now();
+    let source = scan_directory_with_options(source_root, &options.scan, None)?;
+    let scanned = Instant::now();
+    let diff = diff_scans_with_options(&source, &dest, &options.diff)?;
+    let timings = PhaseTimings {
+        scan: scanned - started,
+        diff: scanned.elapsed(),
+        sync: Duration::ZERO,
+    };
+    Ok(ReconcilePlan {
+        source,
+        dest,
+        diff,
+        dest_from_manifest: true,
+        dest_drift: Vec::new(),
+        timings,
+    })
+}
+
+/// Stat a random sample of a manifest's files and return those that changed
+///
+/// `rate` is the fraction of entries to check (at least one when positive).
+/// A file drifted if it is missing, or its size, whole-second mtime, or (when
+/// recorded) inode or ctime differs from the manifest.
+pub fn sample_manifest_drift(manifest: &ScanResult, rate: f64) -> Vec<PathBuf> {
+    if rate <= 0.0 || manifest.files.is_empty() {
+        return Vec::new();
+    }
+    let total = manifest.files.len();
+    let count = ((total as f64 * rate.min(1.0)).ceil() as usize).clamp(1, total);
+
+    // Order by a randomly keyed hash so each run samples different files
+    let state = RandomState::new();
+    let mut sample: Vec<&FileMeta> = manifest.files.iter().collect();
+    sample.sort_by_cached_key(|file| state.hash_one(&file.path));
+
+    sample
+        .into_iter()
+        .take(count)
+        .filter(|file| {
+            let path = manifest.root.join(&file.path);
+            let Ok(stamp) = fs::metadata(path).and_then(|m| FileStamp::from_metadata(&m)) else {
+                return true;
+            };
+            let mtime_secs = |t: SystemTime| {
+                t.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or_default()
+            };
+            stamp.size != file.size
+                || mtime_secs(stamp.mtime) != mtime_secs(file.mtime)
+                || (file.inode.is_some() && stamp.inode != file.inode)
+                || (file.ctime.is_some() && stamp.ctime != file.ctime)
+        })
+        .map(|file| file.path.clone())
+        .collect()
+}
+
+/// Load the trusted destination manifest, if one is configured and usable
+fn trusted_dest_scan(dest_root: &Path, options: &ReconcileOptions) -> Option<ScanResult> {
+    // A manifest covers the whole tree, a subpath scan only part of it
+    if options.verify_dest || options.scan.subpath.is_some() || !dest_root.is_dir() {
+        return None;
+    }
+    let manifest = ScanResult::load_from_file(options.trusted_dest_manifest.as_ref()?).ok()?;
+    (manifest.root == absolute_path(dest_root).ok()?).then_some(manifest)
+}
+
+/// Validate a [`ScanOptions::subpath`], which must stay inside the root
+fn checked_subpath(subpath: &Path) -> Result<&Path> {
+    let inside_root = subpath
+        .components()
+        .all(|c| matches!(c, std::path::Component::Normal(_) | std::path::Component::CurDir));
+    if !inside_root {
+        return Err(SyncError::InvalidPath {
+            path: subpath.to_path_buf(),
+            reason: "subpath must be relative and inside the root".to_string(),
+        }
+        .into());
+    }
+    Ok(subpath)
+}
+
+/// Resolve a relative path against the current directory
+///
+/// Unlike [`fs::canonicalize`] this keeps symlinks as they are, only
+/// dropping `.` components.
+fn absolute_path(path: &Path) -> std::io::Result<PathBuf> {
+    let absolute = if path.is_absolute() {
+        path.to_path_buf()
+    } else {
+        std::env::current_dir()?.join(path)
+    };
+    // Collecting the components drops `.` and trailing separators
+    Ok(absolute.components().collect())
+}
+
+/// Make `dest_root` match `source_root` in one call
+///
+/// Scans both directories, diffs them, and applies the changes from source
+/// to destination, returning what was done. With a trusted destination
+/// manifest configured, the manifest replaces the destination scan and is
+/// rewritten afterwards.
+pub fn reconcile(
+    source_root: &Path,
+    dest_root: &Path,
+    options: &ReconcileOptions,
+) -> Result<SyncReport> {
+    let plan = plan_reconcile(source_root, dest_root, options)?;
+    let report = plan.apply(&options.sync, None)?;
+    // A subpath run only saw part of the destination, so it can't rewrite the manifest
+    if let (Some(path), None) = (&options.trusted_dest_manifest, &options.scan.subpath) {
+        plan.save_dest_manifest(path, options.manifest_format, &options.sync, &report)?;
+    }
+    if let Some(expected) = &options.expected_tree_hash {
+        verify_tree_hash(dest_root, expected, &options.scan)?;
+    }
+    Ok(report)
+}
+
+/// Rescan `root` and check that its tree hash is `expected`
+///
+/// Guards deployments against drift or an incomplete sync; fails with
+/// [`SyncError::TreeHashMismatch`] otherwise. Every file is hashed, whatever
+/// `options.hash_policy` says.
+pub fn verify_tree_hash(root: &Path, expected: &ContentHash, options: &ScanOptions) -> Result<()> {
+    let options = ScanOptions {
+        hash_policy: HashPolicy::Always,
+        ..options.clone()
+    };
+    let actual = scan_directory_with_options(root, &options, None)?.tree_hash()?;
+    if actual != *expected {
+        return Err(SyncError::TreeHashMismatch { expected: expected.clone(), actual }.into());
+    }
+    Ok(())
+}
+
+/// A destination file that doesn't match the source scan, see [`verify_destination`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mismatch {
+    /// Path relative to the roots
+    pub path: PathBuf,
+    /// How the destination file differs
+    pub kind: MismatchKind,
+}
+
+/// How a destination file failed verification
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MismatchKind {
+    /// The file is missing from the destination
+    Missing,
+    /// The file's content hash differs from the source's
+    ContentDiffers {
+        /// Hash recorded in the source scan
+        expected: ContentHash,
+        /// Hash of the destination file
+        actual: ContentHash,
+    },
+    /// The file could not be read
+    Unreadable(String),
+}
+
+/// Re-hash every file of a source scan in the destination and report mismatches
+///
+/// The heavy-duty integrity check after a large migration: unlike
+/// `verify_after_copy`, which checks each copy as it is written, this reads
+/// the whole destination, hashing files in parallel with the same machinery
+/// as a scan. Files only present in the destination aren't reported. Source
+/// scans made with [`ScanOptions::hash_skip_ranges`] compare those files by
+/// their partial hash and so report them as differing.
+pub fn verify_destination(
+    source_scan: &ScanResult,
+    dest_root: &Path,
+    progress: Option<&ProgressReporter>,
+) -> Result<Vec<Mismatch>> {
+    let dest_root = &absolute_path(dest_root)?;
+    let walked: Vec<WalkedFile> = source_scan
+        .files
+        .iter()
+        .map(|file| WalkedFile {
+            path: dest_root.join(&file.path),
+            inode: 0,
+            size: file.size,
+            fifo: false,
+            skip_rule: None,
+        })
+        .collect();
+
+    let hash_progress = progress.map(|reporter| {
+        let total_bytes = walked.iter().map(|file| file.size).sum();
+        ParallelProgress::new(reporter.add_bytes_task("Verifying", total_bytes))
+    });
+    let results = hash_walked_files(
+        dest_root,
+        walked,
+        &ScanOptions::default(),
+        None,
+        hash_progress.as_ref(),
+        None,
+    );
+
+    let mut mismatches = Vec::new();
+    for (source_file, result) in source_scan.files.iter().zip(results) {
+        let kind = match result {
+            Ok(dest_file) => {
+                let expected = if source_file.hash_deferred {
+                    hash_file(&source_scan.root.join(&source_file.path))?
                 } else {
-                    // Hash matches but all candidates already processed - treat as new file
-                    added.push(source_file.clone());
+                    source_file.hash.clone()
+                };
+                if dest_file.hash == expected {
+                    continue;
+                }
+                MismatchKind::ContentDiffers { expected, actual: dest_file.hash }
+            },
+            Err(warning) if warning.kind == ScanWarningKind::Vanished => MismatchKind::Missing,
+            Err(warning) => MismatchKind::Unreadable(warning.message),
+        };
+        mismatches.push(Mismatch { path: source_file.path.clone(), kind });
+    }
+    mismatches.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(mismatches)
+}
+
+/// How a file on disk compares with a manifest, see [`audit`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AuditStatus {
+    /// Size and content match the manifest
+    Ok,
+    /// In the manifest but not on disk
+    Missing,
+    /// On disk with a different size than the manifest records
+    SizeMismatch {
+        /// Size recorded in the manifest
+        expected: u64,
+        /// Size on disk
+        actual: u64,
+    },
+    /// Same size, but the content hash differs from the manifest's
+    HashMismatch {
+        /// Hash recorded in the manifest
+        expected: ContentHash,
+        /// Hash of the file on disk
+        actual: ContentHash,
+    },
+    /// On disk but not in the manifest
+    Extra,
+    /// On disk but couldn't be read
+    Unreadable(String),
+}
+
+impl fmt::Display for AuditStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AuditStatus::Ok => write!(f, "ok"),
+            AuditStatus::Missing => write!(f, "missing"),
+            AuditStatus::SizeMismatch { expected, actual } => {
+                write!(f, "size mismatch: expected {expected} bytes, found {actual}")
+            },
+            AuditStatus::HashMismatch { expected, actual } => {
+                write!(f, "hash mismatch: expected {expected}, found {actual}")
+            },
+            AuditStatus::Extra => write!(f, "extra: not in manifest"),
+            AuditStatus::Unreadable(message) => write!(f, "unreadable: {message}"),
+        }
+    }
+}
+
+/// Check a directory against a manifest, with a status for every file
+///
+/// A stronger check than [`verify_destination`]: one parallel scan of `root`
+/// gives every manifest file a status, and files on disk that the manifest
+/// doesn't list are reported as [`AuditStatus::Extra`]. Pass the
+/// [`ScanOptions`] the manifest was written with: files they exclude are
+/// neither checked nor reported as extra, and skipped hash ranges must match
+/// for hashes to compare equal. Files with deferred hashes in the manifest
+/// are only checked by size. Results are sorted by path.
+pub fn audit(
+    root: &Path,
+    manifest: &ScanResult,
+    options: &ScanOptions,
+) -> Result<Vec<(PathBuf, AuditStatus)>> {
+    let disk = scan_directory_with_options(root, options, None)?;
+    Ok(audit_scan(&disk, manifest))
+}
+
+/// Compare a fresh scan of the audited directory with the manifest
+fn audit_scan(disk: &ScanResult, manifest: &ScanResult) -> Vec<(PathBuf, AuditStatus)> {
+    let mut on_disk: HashMap<&Path, &FileMeta> =
+        disk.files.iter().map(|file| (file.path.as_path(), file)).collect();
+    let unreadable: HashMap<&Path, &ScanWarning> =
+        disk.warnings.iter().map(|warning| (warning.path.as_path(), warning)).collect();
+
+    let mut statuses = Vec::with_capacity(manifest.files.len());
+    for expected in &manifest.files {
+        let status = match on_disk.remove(expected.path.as_path()) {
+            Some(actual) if actual.size != expected.size => AuditStatus::SizeMismatch {
+                expected: expected.size,
+                actual: actual.size,
+            },
+            Some(actual) if !expected.hash_deferred && actual.hash != expected.hash => {
+                AuditStatus::HashMismatch {
+                    expected: expected.hash.clone(),
+                    actual: actual.hash.clone(),
+                }
+            },
+            Some(_) => AuditStatus::Ok,
+            None => match unreadable.get(expected.path.as_path()) {
+                Some(warning) if warning.kind != ScanWarningKind::Vanished => {
+                    AuditStatus::Unreadable(warning.message.clone())
+                },
+                _ => AuditStatus::Missing,
+            },
+        };
+        statuses.push((expected.path.clone(), status));
+    }
+    statuses.extend(on_disk.into_keys().map(|path| (path.to_path_buf(), AuditStatus::Extra)));
+    statuses.sort_by(|a, b| a.0.cmp(&b.0));
+    statuses
+}
+
+/// Largest file [`classify_modifications`] line-diffs when asked to by the CLI
+pub const DEFAULT_TEXT_DIFF_LIMIT: u64 = 256 * 1024;
+
+/// Bytes sampled from the start of a file to tell text from binary
+const TEXT_SAMPLE_SIZE: u64 = 8 * 1024;
+
+/// Lines of unchanged context around each change in a [`TextDiff`]
+const DIFF_CONTEXT: usize = 3;
+
+/// Most added plus removed lines a [`TextDiff`] is computed for
+const MAX_LINE_EDITS: usize = 2000;
+
+/// A modified file classified by [`classify_modifications`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ModifiedFile {
+    /// Relative path of the file
+    pub path: PathBuf,
+    /// Both versions look like text: valid UTF-8 without NUL bytes
+    pub is_text: bool,
+    /// Line diff from the destination version to the source version
+    ///
+    /// `None` unless asked for, and for files over the size limit or whose
+    /// diff would change more than 2000 lines.
+    pub text_diff: Option<TextDiff>,
+}
+
+/// Unified line diff of a modified text file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TextDiff {
+    /// The diff in unified format, with `a/` (destination) and `b/` (source) headers
+    pub unified: String,
+    /// Lines only in the source version
+    pub added: usize,
+    /// Lines only in the destination version
+    pub removed: usize,
+}
+
+/// Tell text from binary changes among a diff's modified files
+///
+/// Each modified file is tagged as text when both its source and destination
+/// versions look like text. With a `text_diff_limit`, text files no larger
+/// than the limit on both sides also get a unified line diff, turning a
+/// [`DiffResult`] into a reviewable tree diff.
+pub fn classify_modifications(
+    diff: &DiffResult,
+    source_root: &Path,
+    dest_root: &Path,
+    text_diff_limit: Option<u64>,
+) -> Result<Vec<ModifiedFile>> {
+    diff.modified
+        .iter()
+        .map(|file| {
+            let source = source_root.join(&file.path);
+            let dest = dest_root.join(&file.path);
+            let is_text = looks_like_text(&source)? && looks_like_text(&dest)?;
+            let small = |path: &Path| {
+                text_diff_limit
+                    .is_some_and(|limit| fs::metadata(path).is_ok_and(|m| m.len() <= limit))
+            };
+            let text_diff = if is_text && small(&source) && small(&dest) {
+                // The sample may look like text while later bytes aren't UTF-8
+                match (fs::read_to_string(&dest), fs::read_to_string(&source)) {
+                    (Ok(old), Ok(new)) => text_diff(&file.path, &old, &new),
+                    _ => None,
                 }
             } else {
-                // New file
-                added.push(source_file.clone());
+                None
+            };
+            Ok(ModifiedFile {
+                path: file.path.clone(),
+                is_text,
+                text_diff,
+            })
+        })
+        .collect()
+}
+
+/// Whether a file's first bytes contain no NUL and are valid UTF-8
+fn looks_like_text(path: &Path) -> Result<bool> {
+    use std::io::Read;
+
+    let mut sample = Vec::new();
+    fs::File::open(path)?.take(TEXT_SAMPLE_SIZE).read_to_end(&mut sample)?;
+    if sample.contains(&0) {
+        return Ok(false);
+    }
+    Ok(match std::str::from_utf8(&sample) {
+        Ok(_) => true,
+        // The sample may end partway through a multi-byte character
+        Err(e) => e.error_len().is_none(),
+    })
+}
+
+/// How a line takes part in a line diff
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum LineEdit {
+    Same,
+    Removed,
+    Added,
+}
+
+/// Unified diff of two versions of a text file
+fn text_diff(path: &Path, old: &str, new: &str) -> Option<TextDiff> {
+    use std::fmt::Write;
+
+    let old_lines: Vec<&str> = old.lines().collect();
+    let new_lines: Vec<&str> = new.lines().collect();
+    let edits = line_edits(&old_lines, &new_lines)?;
+    let count = |edits: &[(LineEdit, &str)], skip: LineEdit| {
+        edits.iter().filter(|(edit, _)| *edit != skip).count()
+    };
+
+    let path = path.display();
+    let mut unified = format!("--- a/{path}\n+++ b/{path}\n");
+    let changes: Vec<usize> = (0..edits.len()).filter(|&i| edits[i].0 != LineEdit::Same).collect();
+    let mut next = 0;
+    while next < changes.len() {
+        // Changes separated by at most twice the context share a hunk
+        let start = changes[next].saturating_sub(DIFF_CONTEXT);
+        let mut last = changes[next];
+        next += 1;
+        while next < changes.len() && changes[next] - last <= 2 * DIFF_CONTEXT + 1 {
+            last = changes[next];
+            next += 1;
+        }
+        let hunk = &edits[start..(last + DIFF_CONTEXT + 1).min(edits.len())];
+
+        // Unified format numbers an empty range by the line before it
+        let range = |before: usize, len: usize| {
+            let first = if len == 0 { before } else { before + 1 };
+            format!("{first},{len}")
+        };
+        let old_range =
+            range(count(&edits[..start], LineEdit::Added), count(hunk, LineEdit::Added));
+        let new_range =
+            range(count(&edits[..start], LineEdit::Removed), count(hunk, LineEdit::Removed));
+        let _ = writeln!(unified, "@@ -{old_range} +{new_range} @@");
+        for (edit, line) in hunk {
+            let marker = match edit {
+                LineEdit::Same => ' ',
+                LineEdit::Removed => '-',
+                LineEdit::Added => '+',
+            };
+            let _ = writeln!(unified, "{marker}{line}");
+        }
+    }
+
+    Some(TextDiff {
+        unified,
+        added: edits.iter().filter(|(edit, _)| *edit == LineEdit::Added).count(),
+        removed: edits.iter().filter(|(edit, _)| *edit == LineEdit::Removed).count(),
+    })
+}
+
+/// Shortest edit script turning `old` into `new`, by Myers' O(ND) algorithm
+///
+/// Common leading and trailing lines are matched up front, so the search
+/// only covers the region that actually changed. Returns `None` once more
+/// than [`MAX_LINE_EDITS`] lines would be added or removed.
+fn line_edits<'a>(old: &[&'a str], new: &[&'a str]) -> Option<Vec<(LineEdit, &'a str)>> {
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let a = &old[prefix..old.len() - suffix];
+    let b = &new[prefix..new.len() - suffix];
+    let (n, m) = (a.len() as isize, b.len() as isize);
+
+    // v[k + limit] is the furthest x reached on diagonal k = x - y, and
+    // trace[d] keeps diagonals -d..=d of v as it was before round d
+    let limit = (a.len() + b.len()).min(MAX_LINE_EDITS) as isize;
+    let mut v = vec![0isize; 2 * limit as usize + 3];
+    let at = |k: isize| (k + limit + 1) as usize;
+    let mut trace: Vec<Vec<isize>> = Vec::new();
+    let mut edit_count = None;
+    'search: for d in 0..=limit {
+        trace.push(v[at(-d)..=at(d)].to_vec());
+        for k in (-d..=d).step_by(2) {
+            let mut x = if k == -d || (k != d && v[at(k - 1)] < v[at(k + 1)]) {
+                v[at(k + 1)]
+            } else {
+                v[at(k - 1)] + 1
+            };
+            let mut y = x - k;
+            while x < n && y < m && a[x as usize] == b[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            v[at(k)] = x;
+            if x >= n && y >= m {
+                edit_count = Some(d);
+                break 'search;
             }
         }
     }
 
-    // Find removed files (in dest but not in source, and not part of a rename)
-    for dest_file in &dest.files {
-        if !source_by_path.contains_key(&dest_file.path)
-            && !processed_dest_paths.contains(&dest_file.path)
-        {
-            removed.push(dest_file.clone());
+    // Walk back from the end, one edit per round
+    let (mut x, mut y) = (n, m);
+    let mut changed = Vec::new();
+    for d in (1..=edit_count?).rev() {
+        let previous = &trace[d as usize];
+        let reached = |k: isize| previous[(k + d) as usize];
+        let k = x - y;
+        let down = k == -d || (k != d && reached(k - 1) < reached(k + 1));
+        let from_k = if down { k + 1 } else { k - 1 };
+        let (from_x, from_y) = (reached(from_k), reached(from_k) - from_k);
+        while x > from_x && y > from_y {
+            x -= 1;
+            y -= 1;
+            changed.push((LineEdit::Same, a[x as usize]));
+        }
+        if down {
+            y -= 1;
+            changed.push((LineEdit::Added, b[y as usize]));
+        } else {
+            x -= 1;
+            changed.push((LineEdit::Removed, a[x as usize]));
         }
     }
+    changed.extend(a[..x as usize].iter().rev().map(|line| (LineEdit::Same, *line)));
 
-    Ok(DiffResult { added, removed, modified, renamed })
+    let mut edits: Vec<(LineEdit, &str)> =
+        old[..prefix].iter().map(|line| (LineEdit::Same, *line)).collect();
+    edits.extend(changed.into_iter().rev());
+    edits.extend(old[old.len() - suffix..].iter().map(|line| (LineEdit::Same, *line)));
+    Some(edits)
 }
 
-/// Compute path similarity score between two paths (0.0 to 1.0)
+/// Compute the destination state a sync would produce, without touching disk
 ///
-/// Uses a simple token-based approach: compares path components and filenames.
-/// Higher score indicates more similar paths.
-fn path_similarity(path1: &Path, path2: &Path) -> f64 {
-    let name1 = path1.file_name().and_then(|n| n.to_str()).unwrap_or("");
-    let name2 = path2.file_name().and_then(|n| n.to_str()).unwrap_or("");
-
-    // Exact filename match is a strong signal
-    if name1 == name2 {
-        return 0.9;
-    }
-
-    // Compute simple string similarity for filenames
-    let filename_sim = simple_string_similarity(name1, name2);
+/// Diffs the two scans and applies the resulting adds, modifications, renames,
+/// and (with `delete_removed`) deletions to the destination scan in memory.
+/// Like [`sync_changes`], it honors the content allow and blocklists,
+/// `protect`, `keep`, `limit` (in `transfer_order`), and `content_only`.
+/// Skips that depend on the destination at run time, such as `byte_quota`,
+/// case conflicts, and occupied rename targets, are not modeled. The result
+/// can be diffed against an expected layout or saved as the would-be
+/// manifest. Files are returned sorted by path.
+pub fn simulate_sync(
+    source: &ScanResult,
+    dest: &ScanResult,
+    options: &SyncOptions,
+) -> Result<ScanResult> {
+    let diff = diff_scans(source, dest)?;
+    Ok(apply_diff_to_scan(dest, &diff, options)?.0)
+}
 
-    // Also consider directory similarity
-    let dir1 = path1.parent().map(|p| p.to_string_lossy());
-    let dir2 = path2.parent().map(|p| p.to_string_lossy());
+/// Apply a diff to a destination scan in memory
+///
+/// Returns the resulting scan and the paths that were written. Files that
+/// [`sync_changes`] would skip, including transfers past `limit` and metadata
+/// updates under `content_only`, keep their destination entries.
+fn apply_diff_to_scan(
+    dest: &ScanResult,
+    diff: &DiffResult,
+    options: &SyncOptions,
+) -> Result<(ScanResult, HashSet<PathBuf>)> {
+    let protected = options.protected_paths()?;
+    let kept = options.kept_paths()?;
+    let written = |file: &&FileMeta| options.allows(file) && !protected.is_match(&file.path);
 
-    let dir_sim = match (dir1, dir2) {
-        (Some(d1), Some(d2)) => simple_string_similarity(&d1, &d2),
-        _ => 0.0,
+    let mut copies: Vec<&FileMeta> =
+        diff.added.iter().chain(&diff.modified).filter(written).collect();
+    let mut renames: Vec<&(FileMeta, FileMeta)> =
+        diff.renamed.iter().filter(|(_, new)| written(&new)).collect();
+    let refilled: HashSet<&Path> = copies
+        .iter()
+        .map(|file| file.path.as_path())
+        .chain(renames.iter().map(|(_, new)| new.path.as_path()))
+        .collect();
+    copies.sort_by(|a, b| options.transfer_order.compare(a, b));
+    renames.sort_by(|a, b| options.transfer_order.compare(&a.1, &b.1));
+    let (kept_copies, kept_renames) = transfers_within_limit(
+        options,
+        &copies,
+        &renames.iter().map(|(_, new)| new).collect::<Vec<_>>(),
+    );
+    copies.truncate(kept_copies);
+    renames.truncate(kept_renames);
+    let metadata: Vec<&FileMeta> = if options.content_only {
+        Vec::new()
+    } else {
+        diff.metadata_changed.iter().filter(written).collect()
     };
 
-    // Weight filename more heavily than directory
-    filename_sim * 0.7 + dir_sim * 0.3
-}
-
-/// Simple string similarity using character overlap (Jaccard-like)
-fn simple_string_similarity(s1: &str, s2: &str) -> f64 {
-    // Handle exact matches first (including empty strings)
-    if s1 == s2 {
-        return 1.0;
+    let mut files: HashMap<PathBuf, FileMeta> =
+        dest.files.iter().map(|f| (f.path.clone(), f.clone())).collect();
+    for (old, _) in &renames {
+        if !protected.is_match(&old.path) && !refilled.contains(old.path.as_path()) {
+            files.remove(&old.path);
+        }
     }
-    // If one is empty but not both (already handled above), they're completely different
-    if s1.is_empty() || s2.is_empty() {
-        return 0.0;
+    if options.delete_removed {
+        for file in &diff.removed {
+            if !protected.is_match(&file.path) && !kept.is_match(&file.path) {
+                files.remove(&file.path);
+            }
+        }
     }
 
-    let chars1: HashSet<char> = s1.chars().collect();
-    let chars2: HashSet<char> = s2.chars().collect();
+    let mut touched = HashSet::new();
+    let renamed = renames.iter().map(|(_, new)| new);
+    for file in copies.into_iter().chain(renamed).chain(metadata) {
+        touched.insert(file.path.clone());
+        files.insert(file.path.clone(), file.clone());
+    }
 
-    let intersection = chars1.intersection(&chars2).count();
-    let union = chars1.union(&chars2).count();
+    let mut files: Vec<FileMeta> = files.into_values().collect();
+    files.sort_by(|a, b| a.path.cmp(&b.path));
 
-    if union == 0 {
-        0.0
-    } else {
-        intersection as f64 / union as f64
-    }
+    let scan = ScanResult {
+        root: dest.root.clone(),
+        files,
+        scan_time: SystemTime::now(),
+        warnings: Vec::new(),
+        dirs: Vec::new(),
+        traversed_dirs: Vec::new(),
+        fifo_spool: None,
+    };
+    Ok((scan, touched))
 }
 
-/// Synchronize changes from source to destination based on diff results
-///
-/// This function applies the changes identified in a diff:
-/// - Copies new and modified files
-/// - Handles renames (moves files if possible, copies otherwise)
-/// - Optionally deletes removed files
+/// Publish `source_root` to `live_path` by syncing into a staging directory and
+/// swapping it into place
 ///
-/// # Arguments
+/// The source is mirrored into `staging_path` (created if missing, with
+/// `delete_removed` forced on so it matches the source exactly), then staging
+/// and live are swapped with [`swap_directories`]. Readers of `live_path` see
+/// either the old tree or the new one, never a half-updated mix. Afterwards
+/// `staging_path` holds the previous live version as a backup, and the next
+/// publish only has to sync the differences into it.
 ///
-/// * `source_root` - Source directory root
-/// * `dest_root` - Destination directory root
-/// * `diff` - Diff results to apply
-/// * `options` - Sync options
-/// * `progress` - Optional progress reporter
-pub fn sync_changes(
+/// On platforms without an atomic directory exchange the swap falls back to
+/// renames through a temporary name, leaving a brief window where `live_path`
+/// does not exist.
+pub fn publish_via_swap(
     source_root: &Path,
+    live_path: &Path,
+    staging_path: &Path,
+    options: &SyncOptions,
+) -> Result<SyncReport> {
+    ensure_directory(staging_path)?;
+
+    let source = scan_directory(source_root, None)?;
+    let staging = scan_directory(staging_path, None)?;
+    let diff = diff_scans(&source, &staging)?;
+
+    let options = SyncOptions { delete_removed: true, ..options.clone() };
+    let report = sync_changes(source_root, staging_path, &diff, &options, None)?;
+
+    swap_directories(live_path, staging_path)?;
+
+    Ok(report)
+}
+
+/// Merge several source directories into one destination
+///
+/// Each source is scanned and overlaid in order, so when more than one source
+/// provides the same relative path the later source wins. The merged view is
+/// then diffed against the destination and synced, with every file copied
+/// from the source that provided it. Paths provided with different content by
+/// several sources are listed in [`MergeReport::conflicts`].
+pub fn sync_many_to_one(
+    sources: &[PathBuf],
     dest_root: &Path,
-    diff: &DiffResult,
     options: &SyncOptions,
-    progress: Option<&ProgressReporter>,
-) -> Result<()> {
-    let total_ops = diff.added.len()
-        + diff.modified.len()
-        + diff.renamed.len()
-        + if options.delete_removed {
-            diff.removed.len()
-        } else {
-            0
-        };
+) -> Result<MergeReport> {
+    let scans = sources
+        .iter()
+        .map(|root| scan_directory(root, None))
+        .collect::<Result<Vec<_>>>()?;
 
-    if progress.is_some() {
-        println!("Applying {total_ops} changes...");
+    // Overlay sources in order, remembering which source provides each path
+    let mut merged: HashMap<&PathBuf, (usize, &FileMeta)> = HashMap::new();
+    let mut providers: HashMap<&PathBuf, Vec<(usize, &ContentHash)>> = HashMap::new();
+    for (index, scan) in scans.iter().enumerate() {
+        for file in &scan.files {
+            merged.insert(&file.path, (index, file));
+            providers.entry(&file.path).or_default().push((index, &file.hash));
+        }
     }
 
-    // Copy new and modified files
-    let files_to_copy: Vec<&FileMeta> = diff.added.iter().chain(diff.modified.iter()).collect();
+    let mut conflicts: Vec<SourceConflict> = providers
+        .into_iter()
+        .filter(|(_, provided)| provided.iter().any(|(_, hash)| *hash != provided[0].1))
+        .map(|(path, provided)| SourceConflict {
+            path: path.clone(),
+            sources: provided.iter().map(|(index, _)| sources[*index].clone()).collect(),
+        })
+        .collect();
+    conflicts.sort_by(|a, b| a.path.cmp(&b.path));
 
-    files_to_copy.par_iter().try_for_each(|file| {
-        let source_path = source_root.join(&file.path);
-        let dest_path = dest_root.join(&file.path);
+    let origin: HashMap<&PathBuf, usize> =
+        merged.iter().map(|(path, (index, _))| (*path, *index)).collect();
+    let merged_scan = ScanResult {
+        root: dest_root.to_path_buf(),
+        files: merged.values().map(|(_, file)| (*file).clone()).collect(),
+        scan_time: SystemTime::now(),
+        warnings: Vec::new(),
+        dirs: Vec::new(),
+        traversed_dirs: Vec::new(),
+        fifo_spool: None,
+    };
 
-        // Ensure parent directory exists
-        if let Some(parent) = dest_path.parent() {
-            fs::create_dir_all(parent)?;
-        }
+    let dest_scan = scan_directory(dest_root, None)?;
+    let diff = diff_scans(&merged_scan, &dest_scan)?;
 
-        copy_file_with_metadata(&source_path, &dest_path, options.preserve_timestamps)?;
-        Ok::<_, anyhow::Error>(())
-    })?;
+    // Split the plan by the source each file comes from and apply each part
+    let mut parts = vec![DiffResult::default(); sources.len()];
+    for file in diff.added {
+        parts[origin[&file.path]].added.push(file);
+    }
+    for file in diff.modified {
+        parts[origin[&file.path]].modified.push(file);
+    }
+    for file in diff.metadata_changed {
+        parts[origin[&file.path]].metadata_changed.push(file);
+    }
+    for (old, new) in diff.renamed {
+        parts[origin[&new.path]].renamed.push((old, new));
+    }
+    if let Some(last) = parts.last_mut() {
+        last.removed = diff.removed;
+    }
 
-    // Handle renames - for now, just copy to new location
-    // TODO: Optimize by moving files when possible (requires checking if old location should be deleted)
-    diff.renamed.par_iter().try_for_each(|(old, new)| {
-        let source_path = source_root.join(&new.path);
-        let dest_path = dest_root.join(&new.path);
+    let mut report = MergeReport { sync: SyncReport::default(), conflicts };
+    for (root, part) in sources.iter().zip(&parts) {
+        report.sync.absorb(sync_changes(root, dest_root, part, options, None)?);
+    }
+
+    Ok(report)
+}
+
+/// Make two directories hold the union of their files
+///
+/// Files present on only one side are copied to the other. Files present on
+/// both sides with different content are conflicts, settled by
+/// [`BidirectionalOptions::resolve_conflict`]. No state is kept between runs,
+/// so a file deleted on one side is indistinguishable from one added on the
+/// other and is copied back; nothing is ever deleted.
+pub fn sync_bidirectional(
+    left_root: &Path,
+    right_root: &Path,
+    options: &BidirectionalOptions,
+) -> Result<BidirectionalReport> {
+    let left = scan_directory_with_options(left_root, &options.scan, None)?;
+    let right = scan_directory_with_options(right_root, &options.scan, None)?;
+    // Deferred placeholders of same-size files can't tell their contents apart
+    let (left, right) = if left.files.iter().chain(&right.files).any(|f| f.hash_deferred) {
+        (hash_size_collisions(&left, &right)?, hash_size_collisions(&right, &left)?)
+    } else {
+        (left, right)
+    };
+    let right_files: HashMap<&PathBuf, &FileMeta> =
+        right.files.iter().map(|f| (&f.path, f)).collect();
+    let left_paths: HashSet<&PathBuf> = left.files.iter().map(|f| &f.path).collect();
 
-        if let Some(parent) = dest_path.parent() {
-            fs::create_dir_all(parent)?;
+    let mut to_right = DiffResult {
+        fifo_spool: left.fifo_spool.clone(),
+        ..DiffResult::default()
+    };
+    let mut to_left = DiffResult {
+        fifo_spool: right.fifo_spool.clone(),
+        ..DiffResult::default()
+    };
+    let mut conflicts = Vec::new();
+    let mut warnings = Vec::new();
+    for file in &left.files {
+        let Some(other) = right_files.get(&file.path) else {
+            to_right.added.push(file.clone());
+            continue;
+        };
+        if file.hash == other.hash {
+            continue;
         }
 
-        copy_file_with_metadata(&source_path, &dest_path, options.preserve_timestamps)?;
+        let resolution = options.resolve(file, other);
+        match &resolution {
+            Resolution::TakeLeft => to_right.modified.push(file.clone()),
+            Resolution::TakeRight => to_left.modified.push((*other).clone()),
+            Resolution::KeepBoth { suffix } => {
+                let kept = suffixed_path(&file.path, suffix);
+                let right_version = right_root.join(&file.path);
+                for root in [left_root, right_root] {
+                    let dest = root.join(&kept);
+                    if let Some(e) = options.sync.copy_into_place(&right_version, &dest, None)? {
+                        warnings.push(format!(
+                            "{}: modification time not preserved: {e}",
+                            dest.display()
+                        ));
+                    }
+                }
+                to_right.modified.push(file.clone());
+            },
+            Resolution::Skip => {},
+        }
+        conflicts.push(ResolvedConflict { path: file.path.clone(), resolution });
+    }
+    to_left.added = right.files.iter().filter(|f| !left_paths.contains(&f.path)).cloned().collect();
+    conflicts.sort_by(|a, b| a.path.cmp(&b.path));
 
-        // Remove old file in destination
-        let old_dest_path = dest_root.join(&old.path);
-        remove_file_safe(&old_dest_path)?;
+    let sync = SyncOptions {
+        delete_removed: false,
+        ..options.sync.clone()
+    };
+    let mut right = sync_changes(left_root, right_root, &to_right, &sync, None)?;
+    right.warnings.extend(warnings);
+    Ok(BidirectionalReport {
+        right,
+        left: sync_changes(right_root, left_root, &to_left, &sync, None)?,
+        conflicts,
+    })
+}
 
-        Ok::<_, anyhow::Error>(())
-    })?;
+/// Compile glob patterns into one matcher
+fn glob_set(patterns: &[String]) -> Result<globset::GlobSet> {
+    let mut builder = globset::GlobSetBuilder::new();
+    for pattern in patterns {
+        let glob = globset::Glob::new(pattern)
+            .map_err(|e| SyncError::InvalidGlob(format!("{pattern}: {e}")))?;
+        builder.add(glob);
+    }
+    Ok(builder.build().map_err(|e| SyncError::InvalidGlob(e.to_string()))?)
+}
 
-    // Delete removed files if requested
-    if options.delete_removed {
-        for file in &diff.removed {
-            let dest_path = dest_root.join(&file.path);
-            remove_file_safe(&dest_path)?;
+/// Whether `path` is an existing file whose content differs from `source`'s
+///
+/// Both files are hashed in full rather than trusting the scanned hash,
+/// which may be deferred or leave out [`ScanOptions::hash_skip_ranges`].
+fn holds_other_content(path: &Path, source: &Path) -> Result<bool> {
+    match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.is_file() => {
+            Ok(metadata.len() != fs::metadata(source)?.len()
+                || hash_file(path)? != hash_file(source)?)
+        },
+        Ok(_) => Ok(true),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(false),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// First of `name.janus-backup.ext`, `name.janus-backup-2.ext`, ... that is free
+fn free_backup_path(path: &Path) -> Result<PathBuf> {
+    for n in 1.. {
+        let suffix = match n {
+            1 => "janus-backup".to_string(),
+            n => format!("janus-backup-{n}"),
+        };
+        let candidate = suffixed_path(path, &suffix);
+        match fs::symlink_metadata(&candidate) {
+            Ok(_) => continue,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(candidate),
+            Err(e) => return Err(e.into()),
         }
     }
+    unreachable!("backup names are unbounded")
+}
 
-    Ok(())
+/// Insert `suffix` between the file stem and extension of `path`
+fn suffixed_path(path: &Path, suffix: &str) -> PathBuf {
+    let stem = path.file_stem().map(|s| s.to_string_lossy()).unwrap_or_default();
+    let name = match path.extension() {
+        Some(ext) => format!("{stem}.{suffix}.{}", ext.to_string_lossy()),
+        None => format!("{stem}.{suffix}"),
+    };
+    path.with_file_name(name)
 }
 
 #[cfg(test)]
@@ -505,6 +5407,577 @@ mod tests {
         assert!(path_similarity(p1, p2) < 0.7); // But still not very similar
     }
 
+    #[test]
+    fn test_run_operations_retries_transient_failures() {
+        use std::sync::atomic::AtomicUsize;
+
+        let options = SyncOptions {
+            retry_rounds: 1,
+            retry_backoff: Duration::ZERO,
+            ..Default::default()
+        };
+        let attempts: Vec<AtomicUsize> = (0..3).map(|_| AtomicUsize::new(0)).collect();
+        let items = [0usize, 1, 2];
+
+        // Item 1 is locked on the first attempt only
+        let results = run_operations(&items, &options, |&i| {
+            let attempt = attempts[i].fetch_add(1, Ordering::SeqCst);
+            if i == 1 && attempt == 0 {
+                return Err(std::io::Error::from(std::io::ErrorKind::WouldBlock).into());
+            }
+            Ok(OperationResult::Copied)
+        });
+
+        assert!(results.iter().all(|r| matches!(r, Some(Ok(OperationResult::Copied)))));
+        assert_eq!(attempts[0].load(Ordering::SeqCst), 1);
+        assert_eq!(attempts[1].load(Ordering::SeqCst), 2);
+        assert_eq!(attempts[2].load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_run_operations_reports_persistent_failures() {
+        let options = SyncOptions {
+            retry_rounds: 2,
+            retry_backoff: Duration::ZERO,
+            continue_on_error: true,
+            ..Default::default()
+        };
+        let attempts = std::sync::atomic::AtomicUsize::new(0);
+
+        let results = run_operations(&[()], &options, |_| {
+            attempts.fetch_add(1, Ordering::SeqCst);
+            Err(std::io::Error::from(std::io::ErrorKind::TimedOut).into())
+        });
+
+        assert!(matches!(results[0], Some(Err(_))));
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+
+        // Without retry rounds, the default, the first failure is reported
+        let results = run_operations(&[()], &SyncOptions::default(), |_| {
+            attempts.fetch_add(1, Ordering::SeqCst);
+            Err(std::io::Error::from(std::io::ErrorKind::TimedOut).into())
+        });
+        assert!(matches!(results[0], Some(Err(_))));
+        assert_eq!(attempts.load(Ordering::SeqCst), 4);
+    }
+
+    #[test]
+    fn test_hash_walked_files_progress() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let mut walked = Vec::new();
+        for (i, content) in [&b"alpha"[..], b"beta", b"gamma delta"].iter().enumerate() {
+            let path = temp_dir.path().join(format!("file{i}.txt"));
+            fs::write(&path, content).unwrap();
+            walked.push(WalkedFile {
+                path,
+                inode: 0,
+                size: content.len() as u64,
+                fifo: false,
+                skip_rule: None,
+            });
+        }
+        let total_bytes: u64 = walked.iter().map(|f| f.size).sum();
+
+        // Without a progress bar hashing works as before
+        let metas = hash_walked_files(
+            temp_dir.path(),
+            walked.iter().map(|f| WalkedFile { path: f.path.clone(), ..*f }).collect(),
+            &ScanOptions::default(),
+            None,
+            None,
+            None,
+        );
+        assert!(metas.iter().all(|m| m.is_ok()));
+
+        for order in [ScanOrder::Parallel, ScanOrder::PathSorted] {
+            let pb = ProgressReporter::new().add_bytes_task("Hashing", total_bytes);
+            let progress = ParallelProgress::new(pb.clone());
+            let files = walked.iter().map(|f| WalkedFile { path: f.path.clone(), ..*f }).collect();
+            let options = ScanOptions { scan_order: order, ..Default::default() };
+            let metas =
+                hash_walked_files(temp_dir.path(), files, &options, None, Some(&progress), None);
+
+            assert_eq!(metas.len(), 3);
+            assert_eq!(pb.position(), total_bytes);
+            assert!(pb.is_finished());
+        }
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_copy_progress_advances_within_a_file() {
+        use std::ffi::CString;
+        use std::io::Write;
+        use std::os::unix::ffi::OsStrExt;
+
+        // A pipe stands in for a huge file: the copy can't finish until the
+        // test has written all of it
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let source = temp_dir.path().join("disk.img");
+        let c_path = CString::new(source.as_os_str().as_bytes()).unwrap();
+        assert_eq!(unsafe { libc::mkfifo(c_path.as_ptr(), 0o600) }, 0);
+        let dest = temp_dir.path().join("copy.img");
+        let total = 4 * 1024 * 1024;
+
+        let pb = ProgressReporter::new().add_bytes_task("Copying", total as u64);
+        let progress = ParallelProgress::new(pb.clone());
+        let copier = {
+            let (source, dest) = (source.clone(), dest.clone());
+            std::thread::spawn(move || {
+                SyncOptions::default().copy_into_place(&source, &dest, Some(&progress)).unwrap()
+            })
+        };
+
+        let mut writer = fs::OpenOptions::new().write(true).open(&source).unwrap();
+        writer.write_all(&vec![7u8; total / 4]).unwrap();
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while pb.position() == 0 {
+            assert!(Instant::now() < deadline, "progress never advanced");
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        assert!(pb.position() <= (total / 4) as u64);
+
+        writer.write_all(&vec![7u8; total - total / 4]).unwrap();
+        drop(writer);
+        assert!(copier.join().unwrap().is_none());
+        assert_eq!(pb.position(), total as u64);
+        assert_eq!(fs::metadata(&dest).unwrap().len(), total as u64);
+    }
+
+    #[test]
+    fn test_hash_walked_files_warns_on_vanished_file() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let walked = vec![WalkedFile {
+            path: temp_dir.path().join("gone.txt"),
+            inode: 0,
+            size: 0,
+            fifo: false,
+            skip_rule: None,
+        }];
+
+        let metas =
+            hash_walked_files(temp_dir.path(), walked, &ScanOptions::default(), None, None, None);
+        let warning = metas.into_iter().next().unwrap().unwrap_err();
+        assert_eq!(warning.path, PathBuf::from("gone.txt"));
+        assert_eq!(warning.kind, ScanWarningKind::Vanished);
+    }
+
+    #[test]
+    fn test_sibling_temps_fallback() {
+        let options = SyncOptions {
+            overwrite_strategy: OverwriteStrategy::TempThenRename,
+            temp_dir: Some(PathBuf::from("/scratch")),
+            ..Default::default()
+        };
+        let dest = Path::new("/dest");
+        assert!(sibling_temps_fallback(&options, dest, |_, _| Ok(true)).is_none());
+        assert!(sibling_temps_fallback(&SyncOptions::default(), dest, |_, _| Ok(false)).is_none());
+
+        // Another device, or one that can't be checked, falls back to siblings
+        let cross_device = |_: &Path, _: &Path| Ok(false);
+        let unknown = |_: &Path, _: &Path| Err(std::io::Error::from(std::io::ErrorKind::NotFound));
+        for (fallback, warning) in [
+            sibling_temps_fallback(&options, dest, cross_device).unwrap(),
+            sibling_temps_fallback(&options, dest, unknown).unwrap(),
+        ] {
+            assert_eq!(fallback.temp_dir, None);
+            assert_eq!(fallback.overwrite_strategy, OverwriteStrategy::TempThenRename);
+            assert!(warning.starts_with("temp directory /scratch is not on the destination's"));
+        }
+    }
+
+    #[test]
+    fn test_scan_warning_kinds() {
+        let warning = |kind: std::io::ErrorKind| {
+            let error = anyhow::Error::from(std::io::Error::from(kind)).context("hashing a.txt");
+            ScanWarning::new(PathBuf::from("a.txt"), &error)
+        };
+
+        let denied = warning(std::io::ErrorKind::PermissionDenied);
+        assert_eq!(denied.kind, ScanWarningKind::PermissionDenied);
+        assert_eq!(denied.path, Path::new("a.txt"));
+        assert_eq!(denied.message, "hashing a.txt");
+        assert_eq!(warning(std::io::ErrorKind::NotFound).kind, ScanWarningKind::Vanished);
+        assert_eq!(warning(std::io::ErrorKind::InvalidData).kind, ScanWarningKind::Io);
+    }
+
+    #[test]
+    fn test_audit_reports_each_status() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let root = temp_dir.path();
+        for (name, content) in [
+            ("ok.txt", "same"),
+            ("edited.txt", "before"),
+            ("grown.txt", "short"),
+            ("deleted.txt", "gone"),
+            ("locked.txt", "secret"),
+        ] {
+            fs::write(root.join(name), content).unwrap();
+        }
+        let manifest = scan_directory(root, None).unwrap();
+
+        fs::write(root.join("edited.txt"), "after!").unwrap();
+        fs::write(root.join("grown.txt"), "much longer").unwrap();
+        fs::remove_file(root.join("deleted.txt")).unwrap();
+        fs::write(root.join("new.txt"), "extra").unwrap();
+        let statuses = audit(root, &manifest, &ScanOptions::default()).unwrap();
+        let status = |name: &str| {
+            statuses.iter().find(|(path, _)| path == Path::new(name)).unwrap().1.clone()
+        };
+
+        assert_eq!(status("ok.txt"), AuditStatus::Ok);
+        assert_eq!(
+            status("edited.txt"),
+            AuditStatus::HashMismatch {
+                expected: hash_bytes(b"before"),
+                actual: hash_bytes(b"after!"),
+            }
+        );
+        assert_eq!(status("grown.txt"), AuditStatus::SizeMismatch { expected: 5, actual: 11 });
+        assert_eq!(status("deleted.txt"), AuditStatus::Missing);
+        assert_eq!(status("new.txt"), AuditStatus::Extra);
+        assert_eq!(statuses.len(), 6);
+
+        // Running as root reads any file, so deny access in the scan itself
+        let mut disk = scan_directory(root, None).unwrap();
+        disk.files.retain(|file| file.path != Path::new("locked.txt"));
+        disk.warnings.push(ScanWarning {
+            path: PathBuf::from("locked.txt"),
+            kind: ScanWarningKind::PermissionDenied,
+            message: "Permission denied".to_string(),
+        });
+        let statuses = audit_scan(&disk, &manifest);
+        let locked = statuses.iter().find(|(path, _)| path == Path::new("locked.txt")).unwrap();
+        assert_eq!(locked.1, AuditStatus::Unreadable("Permission denied".to_string()));
+        assert_eq!(locked.1.to_string(), "unreadable: Permission denied");
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_owned_by() {
+        use std::os::unix::fs::MetadataExt;
+
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let metadata = file.as_file().metadata().unwrap();
+        let (uid, gid) = (metadata.uid(), metadata.gid());
+
+        assert!(owned_by(&metadata, None, None));
+        assert!(owned_by(&metadata, Some(uid), None));
+        assert!(owned_by(&metadata, Some(uid), Some(gid)));
+        assert!(!owned_by(&metadata, Some(uid.wrapping_add(1)), None));
+        assert!(!owned_by(&metadata, Some(uid), Some(gid.wrapping_add(1))));
+    }
+
+    #[test]
+    fn test_validate_accepts_defaults() {
+        assert_eq!(ScanOptions::default().validate(), Ok(()));
+        assert_eq!(DiffOptions::default().validate(), Ok(()));
+        assert_eq!(SyncOptions::default().validate(), Ok(()));
+        assert_eq!(ReconcileOptions::default().validate(), Ok(()));
+    }
+
+    #[test]
+    fn test_validate_reports_conflicts() {
+        let hash = hash_bytes(b"x");
+        let sync = SyncOptions {
+            fail_on_blocked: true,
+            content_allowlist: Some(HashSet::from([hash.clone()])),
+            delete_only_if_copies_succeed: true,
+            copy_batch_size: Some(0),
+            ..Default::default()
+        };
+        let conflicts = sync.validate().unwrap_err();
+        let names: Vec<&str> = conflicts.iter().map(|c| c.options[0]).collect();
+        assert_eq!(names, ["fail_on_blocked", "delete_only_if_copies_succeed", "copy_batch_size"]);
+
+        let both = SyncOptions {
+            content_allowlist: Some(HashSet::from([hash.clone()])),
+            content_blocklist: Some(HashSet::from([hash])),
+            ..Default::default()
+        };
+        assert_eq!(
+            both.validate().unwrap_err()[0].options,
+            ["content_allowlist", "content_blocklist"]
+        );
+
+        let diff = DiffOptions {
+            clock_skew: Duration::from_secs(5),
+            ..Default::default()
+        };
+        assert_eq!(diff.validate().unwrap_err()[0].options, ["clock_skew", "newer_wins"]);
+
+        let scan = ScanOptions {
+            subpath: Some(PathBuf::from("../up")),
+            ..Default::default()
+        };
+        assert_eq!(scan.validate().unwrap_err()[0].options, ["subpath"]);
+    }
+
+    #[test]
+    fn test_validate_reports_each_sync_conflict() {
+        let cases = [
+            (
+                SyncOptions {
+                    content_only: true,
+                    preserve_atime: true,
+                    ..Default::default()
+                },
+                vec!["content_only", "preserve_atime", "preserve_btime"],
+            ),
+            (
+                SyncOptions {
+                    verify_bypass_cache: true,
+                    ..Default::default()
+                },
+                vec!["verify_bypass_cache", "verify_after_copy"],
+            ),
+            (
+                SyncOptions {
+                    temp_dir: Some(PathBuf::from("/scratch")),
+                    ..Default::default()
+                },
+                vec!["temp_dir", "overwrite_strategy"],
+            ),
+            (
+                SyncOptions {
+                    small_file_batching: Some(SmallFileBatchConfig::default()),
+                    overwrite_strategy: OverwriteStrategy::TempThenRename,
+                    ..Default::default()
+                },
+                vec!["small_file_batching", "overwrite_strategy"],
+            ),
+            (
+                SyncOptions {
+                    status_file: Some(PathBuf::from("status.json")),
+                    status_interval: Duration::ZERO,
+                    ..Default::default()
+                },
+                vec!["status_file", "status_interval"],
+            ),
+            (
+                SyncOptions {
+                    delete_removed: true,
+                    delete_only_if_copies_succeed: true,
+                    delete_timing: DeleteTiming::Before,
+                    ..Default::default()
+                },
+                vec!["delete_only_if_copies_succeed", "delete_timing"],
+            ),
+            (
+                SyncOptions {
+                    keep: vec!["*.log".to_string()],
+                    ..Default::default()
+                },
+                vec!["keep", "delete_removed"],
+            ),
+            (
+                SyncOptions {
+                    protect: vec!["[".to_string()],
+                    ..Default::default()
+                },
+                vec!["protect"],
+            ),
+        ];
+        for (options, expected) in cases {
+            let conflicts = options.validate().unwrap_err();
+            let names: Vec<Vec<&str>> = conflicts.iter().map(|c| c.options.clone()).collect();
+            assert_eq!(names, [expected]);
+        }
+
+        let diff = DiffOptions {
+            mtime_tolerance: Duration::from_secs(2),
+            ..Default::default()
+        };
+        assert_eq!(diff.validate().unwrap_err()[0].options, ["mtime_tolerance", "compare"]);
+    }
+
+    #[test]
+    fn test_validate_reconcile_combines_phases() {
+        let options = ReconcileOptions {
+            diff: DiffOptions {
+                compare_metadata: true,
+                ..Default::default()
+            },
+            scan: ScanOptions {
+                hash_skip_ranges: vec![HashSkipRanges {
+                    glob: "*.pyc".to_string(),
+                    ranges: vec![4..8, 12..16],
+                }],
+                ..Default::default()
+            },
+            sync: SyncOptions {
+                content_only: true,
+                minimize_writes: true,
+                verify_after_copy: true,
+                ..Default::default()
+            },
+            verify_dest: true,
+            dest_sample_rate: 1.5,
+            ..Default::default()
+        };
+        let conflicts = options.validate().unwrap_err();
+        let names: Vec<Vec<&str>> = conflicts.iter().map(|c| c.options.clone()).collect();
+        assert_eq!(
+            names,
+            [
+                vec!["content_only", "minimize_writes"],
+                vec!["verify_dest", "trusted_dest_manifest"],
+                vec!["dest_sample_rate"],
+                vec!["compare_metadata", "content_only"],
+                vec!["verify_after_copy", "hash_skip_ranges"],
+            ]
+        );
+        assert_eq!(
+            conflicts[2].to_string(),
+            "dest_sample_rate: 1.5 is not a fraction between 0 and 1"
+        );
+
+        let deferred = ReconcileOptions {
+            scan: ScanOptions {
+                hash_policy: HashPolicy::OnConflict,
+                ..Default::default()
+            },
+            trusted_dest_manifest: Some(PathBuf::from("dest.json")),
+            ..Default::default()
+        };
+        let conflicts = deferred.validate().unwrap_err();
+        assert_eq!(conflicts[0].options, ["trusted_dest_manifest", "hash_policy"]);
+    }
+
+    #[test]
+    fn test_text_diff_hunks() {
+        let old = "a\nb\nc\nd\ne\nf\ng\nh\ni\nj\nk\nl\n";
+        let new = "a\nB\nc\nd\ne\nf\ng\nh\ni\nj\nk\nl\nm\n";
+        let diff = text_diff(Path::new("notes.txt"), old, new).unwrap();
+
+        // Changes more than twice the context apart get separate hunks
+        let expected = "--- a/notes.txt\n+++ b/notes.txt\n\
+                        @@ -1,5 +1,5 @@\n a\n-b\n+B\n c\n d\n e\n\
+                        @@ -10,3 +10,4 @@\n j\n k\n l\n+m\n";
+        assert_eq!(diff.unified, expected);
+        assert_eq!((diff.added, diff.removed), (2, 1));
+
+        let from_empty = text_diff(Path::new("new.txt"), "", "x\n").unwrap();
+        assert!(from_empty.unified.ends_with("@@ -0,0 +1,1 @@\n+x\n"));
+    }
+
+    #[test]
+    fn test_line_edits_minimal_and_bounded() {
+        let old = ["a", "b", "c", "a", "b", "b", "a"];
+        let new = ["c", "b", "a", "b", "a", "c"];
+        let edits = line_edits(&old, &new).unwrap();
+        let kept: Vec<&str> =
+            edits.iter().filter(|e| e.0 != LineEdit::Removed).map(|e| e.1).collect();
+        let had: Vec<&str> = edits.iter().filter(|e| e.0 != LineEdit::Added).map(|e| e.1).collect();
+        assert_eq!((kept, had), (new.to_vec(), old.to_vec()));
+        assert_eq!(edits.iter().filter(|e| e.0 != LineEdit::Same).count(), 5);
+
+        // Unrelated files too different to review get no diff, without a quadratic table
+        let old: Vec<String> = (0..50_000).map(|i| format!("old {i}")).collect();
+        let new: Vec<String> = (0..50_000).map(|i| format!("new {i}")).collect();
+        let old: Vec<&str> = old.iter().map(String::as_str).collect();
+        let new: Vec<&str> = new.iter().map(String::as_str).collect();
+        assert!(line_edits(&old, &new).is_none());
+        let edited: Vec<&str> =
+            old.iter().map(|line| if *line == "old 7" { "edited" } else { line }).collect();
+        assert_eq!(line_edits(&old, &edited).unwrap().len(), 50_001);
+    }
+
+    #[test]
+    fn test_diff_tree_depth_limit() {
+        let mut root = TreeNode::default();
+        for level in 1..=TREE_MAX_DEPTH + 2 {
+            let dirs: Vec<String> = (0..level).map(|i| format!("d{i}")).collect();
+            let path = PathBuf::from(dirs.join("/")).join("f.txt");
+            root.insert(&path, '+', String::new());
+        }
+        let mut out = String::new();
+        root.render(0, &mut out);
+
+        // Branching stops at the depth limit and counts what lies below
+        let summary = format!("{}d{}/ (3 changes)", "  ".repeat(7), TREE_MAX_DEPTH - 1);
+        assert!(out.lines().any(|line| line == summary), "{out}");
+        assert!(!out.contains(&format!("d{TREE_MAX_DEPTH}")));
+    }
+
+    #[test]
+    fn test_small_file_batching() {
+        let source = tempfile::TempDir::new().unwrap();
+        for i in 0..300 {
+            let path = source.path().join(format!("dir{}/file{i}.txt", i % 10));
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(&path, format!("tiny file {i}")).unwrap();
+        }
+        fs::write(source.path().join("large.bin"), vec![7u8; 100 * 1024]).unwrap();
+        let source_scan = scan_directory(source.path(), None).unwrap();
+
+        // A single worker keeps every copy on one thread, so the thread-local
+        // counter sees all of them
+        let pool = rayon::ThreadPoolBuilder::new().num_threads(1).build().unwrap();
+        let sync_counting = |batching: Option<SmallFileBatchConfig>| {
+            let dest = tempfile::TempDir::new().unwrap();
+            let dest_scan = scan_directory(dest.path(), None).unwrap();
+            let diff = diff_scans(&source_scan, &dest_scan).unwrap();
+            let options = SyncOptions {
+                small_file_batching: batching,
+                ..Default::default()
+            };
+            let (report, calls) = pool.install(|| {
+                crate::io::FS_CALLS.with(|calls| calls.set(0));
+                let report = sync_changes(source.path(), dest.path(), &diff, &options, None);
+                (report.unwrap(), crate::io::FS_CALLS.with(|calls| calls.get()))
+            });
+            assert_eq!(report.files_copied, 301);
+            let synced = scan_directory(dest.path(), None).unwrap();
+            assert!(diff_scans(&source_scan, &synced).unwrap().modified.is_empty());
+            for file in &source_scan.files {
+                let copy = fs::metadata(dest.path().join(&file.path)).unwrap();
+                assert_eq!(
+                    copy.modified().unwrap(),
+                    fs::metadata(source.path().join(&file.path)).unwrap().modified().unwrap()
+                );
+            }
+            calls
+        };
+
+        let per_file = sync_counting(None);
+        let batched = sync_counting(Some(SmallFileBatchConfig::default()));
+        assert!(batched < per_file, "{batched} batched vs {per_file} per-file filesystem calls");
+    }
+
+    #[test]
+    fn test_unsettable_mtime_keeps_copy() {
+        let source = tempfile::TempDir::new().unwrap();
+        fs::write(source.path().join("file.txt"), b"content").unwrap();
+        let source_scan = scan_directory(source.path(), None).unwrap();
+
+        // A single worker runs every copy on the thread that fails set_file_mtime
+        let pool = rayon::ThreadPoolBuilder::new().num_threads(1).build().unwrap();
+        let sync_failing_mtime = |strict_metadata: bool| {
+            let dest = tempfile::TempDir::new().unwrap();
+            let diff = diff_scans(&source_scan, &scan_directory(dest.path(), None).unwrap());
+            let options = SyncOptions { strict_metadata, ..Default::default() };
+            let report = pool.install(|| {
+                crate::io::FAIL_SET_MTIME.with(|fail| fail.set(true));
+                let report =
+                    sync_changes(source.path(), dest.path(), &diff.unwrap(), &options, None);
+                crate::io::FAIL_SET_MTIME.with(|fail| fail.set(false));
+                report
+            });
+            (report, fs::read(dest.path().join("file.txt")).ok())
+        };
+
+        let (report, copy) = sync_failing_mtime(false);
+        let report = report.unwrap();
+        assert_eq!(report.files_copied, 1);
+        assert_eq!(copy.as_deref(), Some(&b"content"[..]));
+        assert_eq!(report.warnings.len(), 1);
+        assert!(report.warnings[0].starts_with("file.txt: modification time not preserved"));
+
+        let (report, _) = sync_failing_mtime(true);
+        assert!(report.is_err());
+    }
+
     #[test]
     fn test_string_similarity() {
         assert_eq!(simple_string_similarity("hello", "hello"), 1.0);
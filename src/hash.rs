@@ -20,7 +20,9 @@ use serde::{Deserialize, Serialize};
 use std::fmt;
 use std::fs::File;
 use std::io::{self, Read};
+use std::ops::Range;
 use std::path::Path;
+use std::str::FromStr;
 
 /// Size of buffer for streaming hash computation (64KB)
 ///
@@ -53,6 +55,25 @@ impl ContentHash {
         }
     }
 
+    /// Parse a hash from its hex form, as printed by `Display`
+    ///
+    /// The hash is tagged with the default algorithm.
+    pub fn from_hex(hex: &str) -> Option<Self> {
+        if hex.len() != 64 || !hex.is_ascii() {
+            return None;
+        }
+        let mut bytes = [0u8; 32];
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&hex[2 * i..2 * i + 2], 16).ok()?;
+        }
+        match hash_bytes(b"") {
+            #[cfg(feature = "blake3")]
+            ContentHash::Blake3(_) => Some(ContentHash::Blake3(bytes)),
+            #[cfg(feature = "sha256")]
+            ContentHash::Sha256(_) => Some(ContentHash::Sha256(bytes)),
+        }
+    }
+
     /// Get hash algorithm name
     pub fn algorithm(&self) -> &'static str {
         match self {
@@ -85,6 +106,14 @@ impl fmt::Display for ContentHash {
     }
 }
 
+impl FromStr for ContentHash {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::from_hex(s).ok_or_else(|| format!("not a 64-digit hex hash: {s}"))
+    }
+}
+
 /// A hasher that can compute content hashes using streaming I/O
 ///
 /// The hasher uses the default algorithm based on feature flags:
@@ -107,6 +136,7 @@ impl fmt::Display for ContentHash {
 /// ```
 pub struct Hasher {
     inner: HasherImpl,
+    fadvise: bool,
 }
 
 /// Internal hasher implementation
@@ -126,6 +156,7 @@ impl Hasher {
         {
             Self {
                 inner: HasherImpl::Blake3(Box::new(blake3::Hasher::new())),
+                fadvise: false,
             }
         }
 
@@ -134,6 +165,7 @@ impl Hasher {
             use sha2::Digest;
             Self {
                 inner: HasherImpl::Sha256(sha2::Sha256::new()),
+                fadvise: false,
             }
         }
 
@@ -143,6 +175,17 @@ impl Hasher {
         }
     }
 
+    /// Advise the kernel about the files this hasher reads (Linux only)
+    ///
+    /// Each file is read with `POSIX_FADV_SEQUENTIAL` readahead and dropped
+    /// from the page cache with `POSIX_FADV_DONTNEED` once hashed, so hashing
+    /// a large tree doesn't evict other processes' cached data. Ignored on
+    /// other systems.
+    pub fn with_fadvise(mut self, fadvise: bool) -> Self {
+        self.fadvise = fadvise;
+        self
+    }
+
     /// Update hasher with data from a byte slice
     pub fn update(&mut self, data: &[u8]) {
         match &mut self.inner {
@@ -168,8 +211,11 @@ impl Hasher {
     /// - Uses buffered I/O with 64KB chunks
     /// - Minimal allocations (single reusable buffer)
     /// - Efficient for files of any size (KB to TB)
+    ///
+    /// On Windows the file is opened with permissive sharing, so other
+    /// processes can keep writing, renaming or deleting it while it is hashed.
     pub fn hash_file(&mut self, path: &Path) -> io::Result<()> {
-        let mut file = File::open(path)?;
+        let mut file = self.open(path)?;
         let mut buffer = vec![0u8; HASH_BUFFER_SIZE];
 
         loop {
@@ -180,9 +226,116 @@ impl Hasher {
             self.update(&buffer[..bytes_read]);
         }
 
+        self.done_reading(&file);
+        Ok(())
+    }
+
+    /// Hash the contents of a file, leaving out the given byte ranges
+    ///
+    /// Bytes inside any of `skip` (offsets from the start of the file) are
+    /// not fed to the hasher, so files differing only there hash the same.
+    /// Useful for formats that embed build timestamps at known offsets.
+    pub fn hash_file_skipping(&mut self, path: &Path, skip: &[Range<u64>]) -> io::Result<()> {
+        let mut file = self.open(path)?;
+        let mut buffer = vec![0u8; HASH_BUFFER_SIZE];
+        let mut offset = 0u64;
+
+        loop {
+            let bytes_read = file.read(&mut buffer)?;
+            if bytes_read == 0 {
+                break;
+            }
+            let chunk_end = offset + bytes_read as u64;
+
+            // Feed the parts of the chunk that no skip range covers
+            let mut pos = offset;
+            while pos < chunk_end {
+                match skip.iter().filter(|r| r.contains(&pos)).map(|r| r.end).max() {
+                    Some(end) => pos = end.min(chunk_end),
+                    None => {
+                        let next_skip = skip
+                            .iter()
+                            .filter(|r| r.start > pos && r.start < r.end)
+                            .map(|r| r.start)
+                            .min()
+                            .unwrap_or(chunk_end)
+                            .min(chunk_end);
+                        self.update(
+                            &buffer[(pos - offset) as usize..(next_skip - offset) as usize],
+                        );
+                        pos = next_skip;
+                    },
+                }
+            }
+            offset = chunk_end;
+        }
+
+        self.done_reading(&file);
         Ok(())
     }
 
+    /// Open a file to hash, advising sequential reads if `fadvise` is set
+    fn open(&self, path: &Path) -> io::Result<File> {
+        let file = open_for_hashing(path)?;
+        // Advice is only a hint, so failing to give it doesn't fail the hash
+        #[cfg(target_os = "linux")]
+        if self.fadvise {
+            let _ = crate::io::advise_sequential(&file);
+        }
+        Ok(file)
+    }
+
+    /// Drop a fully hashed file from the page cache if `fadvise` is set
+    fn done_reading(&self, file: &File) {
+        #[cfg(target_os = "linux")]
+        if self.fadvise {
+            let _ = crate::io::advise_dont_need(file);
+        }
+        #[cfg(not(target_os = "linux"))]
+        let _ = file;
+    }
+
+    /// Reset the hasher to its initial state so it can be reused
+    ///
+    /// Reusing a hasher avoids re-allocating and re-initializing hasher state
+    /// when hashing many small files on the same thread.
+    pub fn reset(&mut self) {
+        match &mut self.inner {
+            #[cfg(feature = "blake3")]
+            HasherImpl::Blake3(hasher) => {
+                hasher.reset();
+            },
+            #[cfg(feature = "sha256")]
+            HasherImpl::Sha256(hasher) => {
+                use sha2::Digest;
+                hasher.reset();
+            },
+        }
+    }
+
+    /// Finalize the hash and reset the hasher for reuse
+    ///
+    /// Equivalent to [`Hasher::finalize`] followed by [`Hasher::reset`], without
+    /// consuming the hasher.
+    pub fn finalize_reset(&mut self) -> ContentHash {
+        match &mut self.inner {
+            #[cfg(feature = "blake3")]
+            HasherImpl::Blake3(hasher) => {
+                let hash = hasher.finalize();
+                hasher.reset();
+                ContentHash::Blake3(*hash.as_bytes())
+            },
+            #[cfg(feature = "sha256")]
+            HasherImpl::Sha256(hasher) => {
+                use sha2::Digest;
+                let hash = hasher.finalize_reset();
+                let mut bytes = [0u8; 32];
+                bytes.copy_from_slice(&hash);
+                ContentHash::Sha256(bytes)
+            },
+        }
+    }
+
     /// Finalize the hash and return the result
     ///
     /// This consumes the hasher and returns the computed hash.
@@ -233,6 +386,27 @@ pub fn hash_file(path: &Path) -> io::Result<ContentHash> {
     Ok(hasher.finalize())
 }
 
+/// Open a file for reading without locking out other handles
+#[cfg(windows)]
+fn open_for_hashing(path: &Path) -> io::Result<File> {
+    use std::os::windows::fs::OpenOptionsExt;
+
+    const FILE_SHARE_READ: u32 = 0x1;
+    const FILE_SHARE_WRITE: u32 = 0x2;
+    const FILE_SHARE_DELETE: u32 = 0x4;
+
+    std::fs::OpenOptions::new()
+        .read(true)
+        .share_mode(FILE_SHARE_READ | FILE_SHARE_WRITE | FILE_SHARE_DELETE)
+        .open(path)
+}
+
+/// Open a file for reading (Unix opens never lock out other handles)
+#[cfg(not(windows))]
+fn open_for_hashing(path: &Path) -> io::Result<File> {
+    File::open(path)
+}
+
 /// Hash bytes and return the content hash
 ///
 /// Convenience function for hashing in-memory data.
@@ -289,6 +463,23 @@ mod tests {
         Ok(())
     }
 
+    #[cfg(windows)]
+    #[test]
+    fn test_hashing_file_can_be_opened_for_writing() -> io::Result<()> {
+        let mut temp_file = NamedTempFile::new()?;
+        temp_file.write_all(b"shared")?;
+        temp_file.flush()?;
+
+        // While a hashing handle is open, another handle can still write
+        let _hashing = open_for_hashing(temp_file.path())?;
+        let mut writer = std::fs::OpenOptions::new().write(true).open(temp_file.path())?;
+        writer.write_all(b"SHARED")?;
+        drop(writer);
+
+        assert_eq!(hash_file(temp_file.path())?, hash_bytes(b"SHARED"));
+        Ok(())
+    }
+
     #[test]
     fn test_hash_display() {
         let hash = hash_bytes(b"test");
@@ -297,6 +488,42 @@ mod tests {
         assert!(hash_str.chars().all(|c| c.is_ascii_hexdigit()));
     }
 
+    #[test]
+    fn test_hash_file_skipping() -> io::Result<()> {
+        let mut data = vec![7u8; HASH_BUFFER_SIZE + 100];
+        let mut temp_file = NamedTempFile::new()?;
+        temp_file.write_all(&data)?;
+        temp_file.flush()?;
+
+        // The second range straddles a buffer boundary
+        let boundary = HASH_BUFFER_SIZE as u64;
+        let skip = [2..4, boundary - 10..boundary + 10];
+        let mut hasher = Hasher::new();
+        hasher.hash_file_skipping(temp_file.path(), &skip)?;
+
+        let mut kept = data[..2].to_vec();
+        kept.extend_from_slice(&data[4..HASH_BUFFER_SIZE - 10]);
+        kept.extend_from_slice(&data[HASH_BUFFER_SIZE + 10..]);
+        assert_eq!(hasher.finalize(), hash_bytes(&kept));
+
+        // Changing a skipped byte doesn't change the hash
+        data[HASH_BUFFER_SIZE] = 0;
+        std::fs::write(temp_file.path(), &data)?;
+        let mut hasher = Hasher::new();
+        hasher.hash_file_skipping(temp_file.path(), &skip)?;
+        assert_eq!(hasher.finalize(), hash_bytes(&kept));
+        Ok(())
+    }
+
+    #[test]
+    fn test_hash_from_hex_round_trip() {
+        let hash = hash_bytes(b"test");
+        assert_eq!(ContentHash::from_hex(&hash.to_string()), Some(hash.clone()));
+        assert_eq!(hash.to_string().parse::<ContentHash>(), Ok(hash));
+        assert_eq!(ContentHash::from_hex("abc"), None);
+        assert_eq!(ContentHash::from_hex(&"zz".repeat(32)), None);
+    }
+
     #[test]
     fn test_incremental_hashing() {
         let mut hasher1 = Hasher::new();
@@ -11,9 +11,11 @@
 //! - Graceful error handling with retry logic
 //! - Minimal allocations
 
-use std::fs::{self, File, Metadata};
+use std::fs::{self, File, Metadata, OpenOptions};
 use std::io::{self, Read, Write};
-use std::path::Path;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::time::SystemTime;
 use thiserror::Error;
 
@@ -84,15 +86,39 @@ pub fn copy_file_with_metadata(
     dest: &Path,
     preserve_timestamps: bool,
 ) -> io::Result<()> {
+    match copy_file_tolerating_mtime_errors(source, dest, preserve_timestamps, false, &|_| {})? {
+        Some(e) => Err(e),
+        None => Ok(()),
+    }
+}
+
+/// Like [`copy_file_with_metadata`], but failing to set the modification time doesn't fail the copy
+///
+/// Some filesystems, such as certain network mounts, refuse to set
+/// timestamps. The copy and its permissions are kept either way, and the
+/// error from setting the modification time is returned for the caller to
+/// report.
+///
+/// With `fadvise` set, the kernel is advised to read ahead and to drop both
+/// files from the page cache afterwards (Linux only). `on_copied` is called
+/// with the size of each buffer as it is written, for progress reporting.
+pub fn copy_file_tolerating_mtime_errors(
+    source: &Path,
+    dest: &Path,
+    preserve_timestamps: bool,
+    fadvise: bool,
+    on_copied: &dyn Fn(u64),
+) -> io::Result<Option<io::Error>> {
     // Get metadata before copying
-    let metadata = fs::metadata(source)?;
+    let metadata = counted(fs::metadata(source))?;
 
     // Perform the streaming copy
-    copy_file_streaming(source, dest)?;
+    copy_file_streaming(source, dest, fadvise, on_copied)?;
 
     // Preserve metadata if requested
+    let mut mtime_error = None;
     if preserve_timestamps {
-        set_file_mtime(dest, metadata.modified()?)?;
+        mtime_error = set_file_mtime(dest, metadata.modified()?).err();
     }
 
     // Preserve permissions on Unix systems
@@ -101,55 +127,405 @@ pub fn copy_file_with_metadata(
         set_file_permissions(dest, &metadata)?;
     }
 
-    Ok(())
+    Ok(mtime_error)
+}
+
+/// Copy a file via a temporary sibling that is renamed over the destination
+///
+/// The destination path never holds a partially written file: readers see
+/// either the old content or the complete new content. The temporary file is
+/// removed if the copy fails.
+pub fn copy_file_atomic(source: &Path, dest: &Path, preserve_timestamps: bool) -> io::Result<()> {
+    replace_via_temp(dest, |temp_path| {
+        copy_file_with_metadata(source, temp_path, preserve_timestamps)
+    })
+}
+
+/// Copy file contents only, leaving the destination's metadata to the OS
+///
+/// No timestamps, permissions or ownership are applied, so a new file gets
+/// the current time and default permissions. The cheapest way to copy.
+pub fn copy_file_contents(source: &Path, dest: &Path) -> io::Result<()> {
+    copy_file_streaming(source, dest, false, &|_| {})
+}
+
+/// Produce `dest` by having `write` fill a temporary sibling, then renaming it
+///
+/// The temporary file is removed if `write` or the rename fails.
+pub fn replace_via_temp(
+    dest: &Path,
+    write: impl FnOnce(&Path) -> io::Result<()>,
+) -> io::Result<()> {
+    replace_with_temp(&temp_path_for(dest), dest, write)
+}
+
+/// Like [`replace_via_temp`], but with the temporary file in `temp_dir`
+///
+/// `temp_dir` must be on the same filesystem as `dest` (see
+/// [`same_filesystem`]), otherwise the final rename fails with a
+/// cross-device error rather than quietly turning into a non-atomic copy.
+pub fn replace_via_temp_in(
+    temp_dir: &Path,
+    dest: &Path,
+    write: impl FnOnce(&Path) -> io::Result<()>,
+) -> io::Result<()> {
+    // Temps for files from different directories share `temp_dir`, so the
+    // name alone isn't unique
+    static NEXT_TEMP: AtomicU64 = AtomicU64::new(0);
+    let name = dest.file_name().map(|n| n.to_string_lossy()).unwrap_or_default();
+    let id = NEXT_TEMP.fetch_add(1, Ordering::Relaxed);
+    let temp_path = temp_dir.join(format!(".{name}.{}-{id}.janus-tmp", std::process::id()));
+    replace_with_temp(&temp_path, dest, write)
+}
+
+/// Fill `temp_path` with `write` and rename it to `dest`, cleaning up on failure
+fn replace_with_temp(
+    temp_path: &Path,
+    dest: &Path,
+    write: impl FnOnce(&Path) -> io::Result<()>,
+) -> io::Result<()> {
+    let result = write(temp_path).and_then(|()| fs::rename(temp_path, dest));
+    if result.is_err() {
+        let _ = remove_file_safe(temp_path);
+    }
+
+    result
+}
+
+/// Check whether two existing paths live on the same filesystem
+///
+/// Compares device ids on Unix. Elsewhere the path prefixes (drive letters or
+/// network shares) are compared, which misses volumes mounted in folders.
+pub fn same_filesystem(a: &Path, b: &Path) -> io::Result<bool> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        Ok(fs::metadata(a)?.dev() == fs::metadata(b)?.dev())
+    }
+    #[cfg(not(unix))]
+    {
+        let (a, b) = (a.canonicalize()?, b.canonicalize()?);
+        Ok(a.components().next() == b.components().next())
+    }
+}
+
+/// Write `contents` to `path` via a temporary sibling and a rename
+///
+/// An interrupted write leaves the previous file intact.
+pub fn write_file_atomic(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let temp_path = temp_path_for(path);
+
+    let result = fs::write(&temp_path, contents).and_then(|()| fs::rename(&temp_path, path));
+    if result.is_err() {
+        let _ = remove_file_safe(&temp_path);
+    }
+
+    result
+}
+
+/// Temporary file path used while writing `dest` atomically
+///
+/// The temp file lives in the same directory so the final rename never
+/// crosses a filesystem boundary.
+pub(crate) fn temp_path_for(dest: &Path) -> PathBuf {
+    let name = dest.file_name().map(|n| n.to_string_lossy()).unwrap_or_default();
+    dest.with_file_name(format!(".{name}.janus-tmp"))
 }
 
 /// Copy file contents using streaming I/O
 ///
 /// This is the core copy implementation that uses buffered reads and writes
-/// for maximum efficiency across file sizes.
-fn copy_file_streaming(source: &Path, dest: &Path) -> io::Result<()> {
-    let mut source_file = File::open(source)?;
-    let mut dest_file = File::create(dest)?;
+/// for maximum efficiency across file sizes. `fadvise` and `on_copied` are as
+/// for [`copy_file_tolerating_mtime_errors`].
+pub(crate) fn copy_file_streaming(
+    source: &Path,
+    dest: &Path,
+    fadvise: bool,
+    on_copied: &dyn Fn(u64),
+) -> io::Result<()> {
+    let mut source_file = counted(File::open(source))?;
+    let mut dest_file = counted(File::create(dest))?;
+    // Advice is only a hint, so failing to give it doesn't fail the copy
+    #[cfg(target_os = "linux")]
+    if fadvise {
+        let _ = advise_sequential(&source_file);
+    }
 
     // Allocate buffer once and reuse
     let mut buffer = vec![0u8; COPY_BUFFER_SIZE];
     let mut _total_bytes = 0u64;
 
     loop {
-        let bytes_read = source_file.read(&mut buffer)?;
+        let bytes_read = counted(source_file.read(&mut buffer))?;
         if bytes_read == 0 {
             break;
         }
 
-        dest_file.write_all(&buffer[..bytes_read])?;
+        counted(dest_file.write_all(&buffer[..bytes_read]))?;
+        on_copied(bytes_read as u64);
         _total_bytes += bytes_read as u64;
     }
 
     // Ensure all data is written to disk
-    dest_file.sync_all()?;
+    counted(dest_file.sync_all())?;
+
+    #[cfg(target_os = "linux")]
+    if fadvise {
+        let _ = advise_dont_need(&source_file);
+        let _ = advise_dont_need(&dest_file);
+    }
+    #[cfg(not(target_os = "linux"))]
+    let _ = fadvise;
 
     Ok(())
 }
 
+/// Tiny files read into one buffer so they can be written out together
+///
+/// Copying many small files one by one is dominated by per-file overhead:
+/// a streaming buffer, reopening the copy to set its mtime, and an `fsync`
+/// each. An archive reads each source with a single call, writes each copy
+/// through one handle that also applies the metadata, and makes each copy
+/// durable with `sync_data`, which skips flushing metadata the way a full
+/// `fsync` would.
+#[derive(Debug, Default)]
+pub struct StagingArchive {
+    data: Vec<u8>,
+    entries: Vec<StagedFile>,
+}
+
+/// Where a file packed into a [`StagingArchive`] is unpacked to
+#[derive(Debug)]
+struct StagedFile {
+    dest: PathBuf,
+    range: Range<usize>,
+    metadata: Metadata,
+}
+
+impl StagingArchive {
+    /// Read `source` into the archive, to be unpacked to `dest`
+    pub fn add(&mut self, source: &Path, dest: PathBuf) -> io::Result<()> {
+        let mut file = counted(File::open(source))?;
+        let metadata = counted(file.metadata())?;
+        let start = self.data.len();
+        counted(file.read_to_end(&mut self.data))?;
+        self.entries.push(StagedFile {
+            dest,
+            range: start..self.data.len(),
+            metadata,
+        });
+        Ok(())
+    }
+
+    /// Number of packed files
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Check if no files are packed
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Total bytes of file content packed
+    pub fn size(&self) -> u64 {
+        self.data.len() as u64
+    }
+
+    /// Write every packed file to its destination, creating parent directories
+    ///
+    /// Existing destinations are overwritten in place. Permissions are
+    /// applied on Unix when `preserve_permissions` is set.
+    pub fn unpack(&self, preserve_timestamps: bool, preserve_permissions: bool) -> io::Result<()> {
+        let mut created_dirs = std::collections::HashSet::new();
+        for entry in &self.entries {
+            if let Some(parent) = entry.dest.parent() {
+                if created_dirs.insert(parent) {
+                    counted(fs::create_dir_all(parent))?;
+                }
+            }
+            let mut file = counted(File::create(&entry.dest))?;
+            counted(file.write_all(&self.data[entry.range.clone()]))?;
+            if preserve_timestamps {
+                counted(file.set_modified(entry.metadata.modified()?))?;
+            }
+            #[cfg(unix)]
+            if preserve_permissions {
+                counted(file.set_permissions(entry.metadata.permissions()))?;
+            }
+            counted(file.sync_data())?;
+        }
+        #[cfg(not(unix))]
+        let _ = preserve_permissions;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+thread_local! {
+    /// Filesystem operations made through [`counted`] on this thread
+    ///
+    /// Lets tests compare how much work different copy paths do.
+    pub(crate) static FS_CALLS: std::cell::Cell<u64> = const { std::cell::Cell::new(0) };
+
+    /// Make [`set_file_mtime`] fail on this thread
+    ///
+    /// Stands in for filesystems that can't set modification times.
+    pub(crate) static FAIL_SET_MTIME: std::cell::Cell<bool> = const { std::cell::Cell::new(false) };
+}
+
+/// Pass through the result of one filesystem operation, counting it in
+/// [`FS_CALLS`] in test builds
+#[inline]
+fn counted<T>(result: io::Result<T>) -> io::Result<T> {
+    #[cfg(test)]
+    FS_CALLS.with(|calls| calls.set(calls.get() + 1));
+    result
+}
+
+/// Evict a file from the page cache so the next read comes from disk (Linux only)
+///
+/// The file is flushed first, since only clean pages can be dropped. This is
+/// advice: pages mapped or in use by another process may stay cached.
+#[cfg(target_os = "linux")]
+pub fn drop_from_page_cache(path: &Path) -> io::Result<()> {
+    let file = File::open(path)?;
+    file.sync_data()?;
+    advise_dont_need(&file)
+}
+
+/// Tell the kernel a file will be read from start to end (Linux only)
+///
+/// The kernel then reads further ahead, which speeds up large sequential
+/// reads.
+#[cfg(target_os = "linux")]
+pub fn advise_sequential(file: &File) -> io::Result<()> {
+    fadvise(file, libc::POSIX_FADV_SEQUENTIAL)
+}
+
+/// Tell the kernel a file's cached pages won't be needed again (Linux only)
+///
+/// Clean pages are dropped from the page cache, so a large backup doesn't
+/// evict the working set of other processes. Dirty pages stay until written.
+#[cfg(target_os = "linux")]
+pub fn advise_dont_need(file: &File) -> io::Result<()> {
+    fadvise(file, libc::POSIX_FADV_DONTNEED)
+}
+
+/// Give `posix_fadvise` advice about a whole file
+#[cfg(target_os = "linux")]
+fn fadvise(file: &File, advice: libc::c_int) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    // SAFETY: the descriptor belongs to `file`, which outlives the call
+    match unsafe { libc::posix_fadvise(file.as_raw_fd(), 0, 0, advice) } {
+        0 => Ok(()),
+        errno => Err(io::Error::from_raw_os_error(errno)),
+    }
+}
+
 /// Set file modification time
 ///
 /// Sets the last modified timestamp of a file to the specified time.
 pub fn set_file_mtime(path: &Path, mtime: SystemTime) -> io::Result<()> {
+    #[cfg(test)]
+    if FAIL_SET_MTIME.with(|fail| fail.get()) {
+        return Err(io::Error::new(io::ErrorKind::Unsupported, "setting mtime is unsupported"));
+    }
     // Note: File::set_modified requires Rust 1.75.0+
-    let file = File::open(path)?;
-    file.set_modified(mtime)?;
+    let file = counted(File::open(path))?;
+    counted(file.set_modified(mtime))?;
     Ok(())
 }
 
+/// Set a file's access and creation times, leaving its other times alone
+///
+/// The creation time can only be set on macOS and Windows and is ignored
+/// elsewhere. A filesystem that doesn't support setting a timestamp leaves
+/// it unchanged rather than failing the copy.
+pub fn set_file_times(
+    path: &Path,
+    atime: Option<SystemTime>,
+    btime: Option<SystemTime>,
+) -> io::Result<()> {
+    let mut times = fs::FileTimes::new();
+    if let Some(atime) = atime {
+        times = times.set_accessed(atime);
+    }
+    #[cfg(any(target_os = "macos", windows))]
+    if let Some(btime) = btime {
+        #[cfg(target_os = "macos")]
+        use std::os::macos::fs::FileTimesExt;
+        #[cfg(windows)]
+        use std::os::windows::fs::FileTimesExt;
+        times = times.set_created(btime);
+    }
+    #[cfg(not(any(target_os = "macos", windows)))]
+    let _ = btime;
+
+    // Windows only sets times through a handle opened for writing, while a
+    // read-only file's owner can still set them through a read handle on Unix
+    let file = match OpenOptions::new().write(true).open(path) {
+        Err(e) if e.kind() == io::ErrorKind::PermissionDenied => File::open(path)?,
+        file => file?,
+    };
+    match file.set_times(times) {
+        Err(e) if e.kind() == io::ErrorKind::Unsupported => Ok(()),
+        result => result,
+    }
+}
+
 /// Set file permissions (Unix only)
 #[cfg(unix)]
 pub fn set_file_permissions(path: &Path, metadata: &Metadata) -> io::Result<()> {
     let permissions = metadata.permissions();
-    fs::set_permissions(path, permissions)?;
+    counted(fs::set_permissions(path, permissions))?;
     Ok(())
 }
 
+/// Apply recorded permissions and ownership to a file (Unix only)
+///
+/// Ownership is only changed when it differs from the current owner, so
+/// unprivileged syncs of files they already own don't need `CAP_CHOWN`.
+#[cfg(unix)]
+pub fn set_file_mode_and_owner(
+    path: &Path,
+    mode: Option<u32>,
+    owner: Option<(u32, u32)>,
+) -> io::Result<()> {
+    use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+    if let Some(mode) = mode {
+        fs::set_permissions(path, fs::Permissions::from_mode(mode))?;
+    }
+    if let Some((uid, gid)) = owner {
+        let current = fs::metadata(path)?;
+        if (current.uid(), current.gid()) != (uid, gid) {
+            std::os::unix::fs::chown(path, Some(uid), Some(gid))?;
+        }
+    }
+    Ok(())
+}
+
+/// Check whether the filesystem holding `dir` matches names case-insensitively
+///
+/// Creates a short-lived probe file in `dir` (or its nearest existing
+/// ancestor) and looks it up under a different case.
+pub fn is_case_insensitive(dir: &Path) -> io::Result<bool> {
+    let existing = dir
+        .ancestors()
+        .find(|p| p.is_dir())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no existing ancestor"))?;
+
+    let probe = existing.join(format!(".janus-case-probe-{}", std::process::id()));
+    File::create(&probe)?;
+    let upper = existing.join(format!(".JANUS-CASE-PROBE-{}", std::process::id()));
+    let insensitive = fs::symlink_metadata(&upper).is_ok();
+    remove_file_safe(&probe)?;
+
+    Ok(insensitive)
+}
+
 /// Safely remove a file with error handling
 ///
 /// This function attempts to remove a file, handling common error cases:
@@ -183,6 +559,37 @@ pub fn remove_file_safe(path: &Path) -> io::Result<()> {
     }
 }
 
+/// Check whether a path is a named pipe (Unix only)
+#[cfg(unix)]
+pub fn is_fifo(path: &Path) -> bool {
+    use std::os::unix::fs::FileTypeExt;
+    fs::symlink_metadata(path).is_ok_and(|m| m.file_type().is_fifo())
+}
+
+/// Drain a named pipe to EOF into a regular file, feeding each chunk to `observe`
+///
+/// Blocks until a writer opens the pipe, and returns once it closes it.
+/// `dest` must not exist yet. Returns the number of bytes drained.
+pub fn drain_fifo(fifo: &Path, dest: &Path, mut observe: impl FnMut(&[u8])) -> io::Result<u64> {
+    let mut source = File::open(fifo)?;
+    let mut dest = OpenOptions::new().write(true).create_new(true).open(dest)?;
+    let mut buffer = vec![0u8; COPY_BUFFER_SIZE];
+    let mut total = 0u64;
+
+    loop {
+        let bytes_read = source.read(&mut buffer)?;
+        if bytes_read == 0 {
+            break;
+        }
+        observe(&buffer[..bytes_read]);
+        dest.write_all(&buffer[..bytes_read])?;
+        total += bytes_read as u64;
+    }
+
+    dest.sync_all()?;
+    Ok(total)
+}
+
 /// Remove a directory and all its contents recursively
 ///
 /// This function is similar to `fs::remove_dir_all` but with enhanced
@@ -195,6 +602,117 @@ pub fn remove_dir_recursive(path: &Path) -> io::Result<()> {
     fs::remove_dir_all(path)
 }
 
+/// Remove a directory tree unless it holds more than `max_entries` entries
+///
+/// Guards destructive cleanups against a misconfigured path: a tree larger
+/// than expected is left alone with an error. Pass a larger limit, or use
+/// [`remove_dir_recursive`], to delete it anyway. Symlinks are never
+/// followed, neither when counting nor when deleting, so nothing outside the
+/// tree is touched; a symlink at `path` itself is removed as a link.
+pub fn remove_dir_recursive_guarded(path: &Path, max_entries: usize) -> io::Result<()> {
+    let metadata = match fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    if !metadata.is_dir() {
+        return fs::remove_file(path);
+    }
+
+    if count_entries(path, max_entries)? > max_entries {
+        return Err(io::Error::other(format!(
+            "Refusing to delete {}: more than {max_entries} entries",
+            path.display()
+        )));
+    }
+    fs::remove_dir_all(path)
+}
+
+/// Count entries below `dir` without following symlinks, stopping past `limit`
+fn count_entries(dir: &Path, limit: usize) -> io::Result<usize> {
+    let mut count = 0;
+    let mut pending = vec![dir.to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            count += 1;
+            if count > limit {
+                return Ok(count);
+            }
+            if entry.file_type()?.is_dir() {
+                pending.push(entry.path());
+            }
+        }
+    }
+    Ok(count)
+}
+
+/// Swap two directories so each path ends up holding the other's contents
+///
+/// On Linux this uses `renameat2(RENAME_EXCHANGE)`, which swaps both paths in a
+/// single atomic step. Where that is unavailable (other platforms, or
+/// filesystems that don't support it) the swap falls back to three renames
+/// through a temporary name, during which `a` briefly does not exist.
+///
+/// If `a` does not exist, `b` is simply renamed to `a`.
+pub fn swap_directories(a: &Path, b: &Path) -> io::Result<()> {
+    if !a.exists() {
+        return fs::rename(b, a);
+    }
+
+    #[cfg(target_os = "linux")]
+    {
+        match exchange_paths(a, b) {
+            Ok(()) => return Ok(()),
+            Err(e) if matches!(e.raw_os_error(), Some(libc::EINVAL | libc::ENOSYS)) => {
+                // Not supported here; fall back to plain renames
+            },
+            Err(e) => return Err(e),
+        }
+    }
+
+    let name = a.file_name().map(|n| n.to_string_lossy()).unwrap_or_default();
+    let temp = a.with_file_name(format!(".{name}.janus-swap"));
+
+    fs::rename(a, &temp)?;
+    if let Err(e) = fs::rename(b, a) {
+        // Put the original back so `a` is not left missing
+        let _ = fs::rename(&temp, a);
+        return Err(e);
+    }
+    fs::rename(&temp, b)
+}
+
+/// Atomically exchange two paths with `renameat2(RENAME_EXCHANGE)`
+#[cfg(target_os = "linux")]
+fn exchange_paths(a: &Path, b: &Path) -> io::Result<()> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let to_cstring = |path: &Path| {
+        CString::new(path.as_os_str().as_bytes())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+    };
+    let a = to_cstring(a)?;
+    let b = to_cstring(b)?;
+
+    // SAFETY: both pointers come from valid NUL-terminated CStrings that outlive the call
+    let ret = unsafe {
+        libc::renameat2(
+            libc::AT_FDCWD,
+            a.as_ptr(),
+            libc::AT_FDCWD,
+            b.as_ptr(),
+            libc::RENAME_EXCHANGE,
+        )
+    };
+    if ret == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
 /// Verify that two files have identical content
 ///
 /// This function compares two files byte-by-byte using streaming I/O.
@@ -362,6 +880,28 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_fadvise() -> io::Result<()> {
+        let mut source = NamedTempFile::new()?;
+        source.write_all(&vec![0x42u8; 3 * COPY_BUFFER_SIZE])?;
+        source.flush()?;
+
+        advise_sequential(source.as_file())?;
+        advise_dont_need(source.as_file())?;
+
+        let dest_dir = tempdir()?;
+        let dest_path = dest_dir.path().join("dest.bin");
+        copy_file_tolerating_mtime_errors(source.path(), &dest_path, true, true, &|_| {})?;
+        assert_eq!(fs::read(&dest_path)?, fs::read(source.path())?);
+
+        let mut hasher = crate::hash::Hasher::new().with_fadvise(true);
+        hasher.hash_file(&dest_path)?;
+        assert_eq!(hasher.finalize(), crate::hash::hash_file(source.path())?);
+
+        Ok(())
+    }
+
     #[test]
     fn test_remove_file_safe() -> io::Result<()> {
         let mut temp = NamedTempFile::new()?;
@@ -432,6 +972,68 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_swap_directories() -> io::Result<()> {
+        let temp_dir = tempdir()?;
+        let a = temp_dir.path().join("a");
+        let b = temp_dir.path().join("b");
+        fs::create_dir(&a)?;
+        fs::create_dir(&b)?;
+        fs::write(a.join("file.txt"), b"from a")?;
+        fs::write(b.join("file.txt"), b"from b")?;
+
+        swap_directories(&a, &b)?;
+
+        assert_eq!(fs::read(a.join("file.txt"))?, b"from b");
+        assert_eq!(fs::read(b.join("file.txt"))?, b"from a");
+
+        // Swapping onto a missing path is a plain rename
+        let c = temp_dir.path().join("c");
+        swap_directories(&c, &b)?;
+        assert_eq!(fs::read(c.join("file.txt"))?, b"from a");
+        assert!(!b.exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_remove_dir_recursive_guarded() -> io::Result<()> {
+        let temp_dir = tempdir()?;
+        let tree = temp_dir.path().join("tree");
+        fs::create_dir_all(tree.join("nested"))?;
+        for i in 0..3 {
+            fs::write(tree.join("nested").join(format!("file{i}")), b"x")?;
+        }
+
+        // 1 directory + 3 files
+        let err = remove_dir_recursive_guarded(&tree, 3).unwrap_err();
+        assert!(err.to_string().contains("Refusing to delete"));
+        assert!(tree.join("nested/file2").exists());
+
+        remove_dir_recursive_guarded(&tree, 4)?;
+        assert!(!tree.exists());
+        remove_dir_recursive_guarded(&tree, 0)?;
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_remove_dir_recursive_guarded_keeps_symlink_targets() -> io::Result<()> {
+        let temp_dir = tempdir()?;
+        let outside = temp_dir.path().join("outside");
+        fs::create_dir(&outside)?;
+        fs::write(outside.join("precious"), b"keep")?;
+
+        let tree = temp_dir.path().join("tree");
+        fs::create_dir(&tree)?;
+        std::os::unix::fs::symlink(&outside, tree.join("link"))?;
+
+        remove_dir_recursive_guarded(&tree, 1)?;
+        assert!(!tree.exists());
+        assert!(outside.join("precious").exists());
+        Ok(())
+    }
+
     #[test]
     #[cfg(not(target_os = "windows"))]
     fn test_directory_size() -> io::Result<()> {
@@ -1,13 +1,36 @@
 //! Beautifully fast, simple & reliable file syncing.
 
+pub mod cache;
+pub mod config;
 pub mod core;
 pub mod hash;
 pub mod io;
 pub mod progress;
+pub mod store;
+pub mod transport;
 
+pub use cache::{CacheConfig, FileStamp, HashCache};
+pub use config::{Config, Profile};
 pub use core::{
-    diff_scans, scan_directory, sync_changes, DiffResult, FileMeta, ScanResult, SyncOptions,
+    audit, classify_modifications, detect_clock_skew, diff_scans, diff_scans_with_options,
+    explain_filter, format_diff_tree, plan_against_manifest, plan_reconcile, publish_via_swap,
+    reconcile, sample_manifest_drift, scan_directory, scan_directory_with_options, simulate_sync,
+    sync_bidirectional, sync_changes, sync_many_to_one, verify_destination, verify_tree_hash,
+    AuditStatus, BatchHook, BidirectionalOptions, BidirectionalReport, BucketStats,
+    CaseConflictPolicy, CompareMode, ConflictResolver, DeleteTiming, DiffOptions, DiffResult,
+    DirMeta, DriftPolicy, DuplicatePathPolicy, FailedFile, FifoSpool, FileMeta, FilterDecision,
+    FilterSource, HashPolicy, HashSkipRanges, ManifestFormat, MergeReport, Mismatch, MismatchKind,
+    ModifiedFile, NfForm, OccupiedRenamePolicy, OperationKind, OperationOutcome, OperationResult,
+    OptionConflict, OverwriteStrategy, PhaseTimings, PlannedOperation, ReconcileOptions,
+    ReconcilePlan, Resolution, ResolvedConflict, ScanOptions, ScanOrder, ScanResult, ScanStats,
+    ScanWarning, ScanWarningKind, SkipReason, SkippedFile, SmallFileBatchConfig, SourceConflict,
+    SyncError, SyncOptions, SyncReport, TextDiff, TransferOrder, WalkBuilderHook,
+    DEFAULT_DEST_SAMPLE_RATE, DEFAULT_TEXT_DIFF_LIMIT, MANIFEST_FORMAT_VERSION, VCS_DIRECTORIES,
 };
 pub use hash::{hash_bytes, hash_file, ContentHash, Hasher};
+pub use store::{restore_scan, store_scan, ChunkStore, FsChunkStore, StoreReport};
+pub use transport::{
+    push_file, serve, sync_to_transport, PipeTransport, PrivilegeHelper, RemoteStat, Transport,
+};
 
 pub const VERSION: &str = env!("CARGO_PKG_VERSION");
@@ -1,22 +1,57 @@
 use anyhow::Result;
-use clap::Parser;
-use std::path::PathBuf;
+use clap::{Args, Parser, Subcommand};
+use indicatif::HumanBytes;
+use std::path::{Path, PathBuf};
 use std::process;
+use std::sync::atomic::AtomicBool;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, UNIX_EPOCH};
 
-use janus::{diff_scans, scan_directory, sync_changes, SyncOptions};
+use janus::io::remove_file_safe;
+use janus::{
+    audit, classify_modifications, detect_clock_skew, explain_filter, format_diff_tree,
+    plan_against_manifest, plan_reconcile, scan_directory_with_options, verify_tree_hash,
+    AuditStatus, BucketStats, CacheConfig, CompareMode, Config, ContentHash, DeleteTiming,
+    DiffOptions, DriftPolicy, HashCache, ManifestFormat, ModifiedFile, OperationKind, PhaseTimings,
+    PlannedOperation, Profile, ReconcileOptions, ReconcilePlan, ScanOptions, ScanResult,
+    SmallFileBatchConfig, SyncError, SyncOptions, SyncReport, TransferOrder,
+    DEFAULT_DEST_SAMPLE_RATE, DEFAULT_TEXT_DIFF_LIMIT,
+};
+
+/// Exit code when a sync is interrupted with Ctrl-C (128 + SIGINT)
+const EXIT_INTERRUPTED: i32 = 130;
 
 #[derive(Parser)]
 #[command(
     name = "jan",
     version,
-    about = "Beautifully fast, simple & reliable file syncing"
+    about = "Beautifully fast, simple & reliable file syncing",
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
 )]
 struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
     /// Source directory
-    source: PathBuf,
+    #[arg(required_unless_present_any = ["profile", "server"])]
+    source: Option<PathBuf>,
 
     /// Destination directory
-    dest: PathBuf,
+    #[arg(required_unless_present_any = ["profile", "server"])]
+    dest: Option<PathBuf>,
+
+    /// Use the settings saved as profile NAME in the config file
+    #[arg(long, value_name = "NAME")]
+    profile: Option<String>,
+
+    /// Config file to read --profile from (default: ~/.config/janus/config.json)
+    #[arg(long, value_name = "FILE", requires = "profile")]
+    config: Option<PathBuf>,
+
+    /// Serve file operations on ROOT over stdin/stdout for a remote or privileged sync
+    #[arg(long, value_name = "ROOT", exclusive = true)]
+    server: Option<PathBuf>,
 
     /// Dry run (show changes without applying)
     #[arg(short = 'n', long)]
@@ -26,6 +61,34 @@ struct Cli {
     #[arg(short, long)]
     delete: bool,
 
+    /// With --delete, delete before copying (frees space on a full destination)
+    #[arg(long, requires = "delete", conflicts_with_all = ["delete_during", "delete_after"])]
+    delete_before: bool,
+
+    /// With --delete, delete after copies but before renames
+    #[arg(long, requires = "delete", conflicts_with = "delete_after")]
+    delete_during: bool,
+
+    /// With --delete, delete once everything else is synced (default)
+    #[arg(long, requires = "delete")]
+    delete_after: bool,
+
+    /// Copy the largest files first
+    #[arg(long, conflicts_with_all = ["smallest_first", "newest_first"])]
+    largest_first: bool,
+
+    /// Copy the smallest files first
+    #[arg(long, conflicts_with = "newest_first")]
+    smallest_first: bool,
+
+    /// Copy the most recently modified files first
+    #[arg(long)]
+    newest_first: bool,
+
+    /// Copy or rename at most N files, deferring the rest to a later run
+    #[arg(long, value_name = "N")]
+    limit: Option<usize>,
+
     /// Skip confirmation prompt
     #[arg(short = 'y')]
     yes: bool,
@@ -38,39 +101,615 @@ struct Cli {
     #[arg(short, long)]
     verbose: bool,
 
+    /// Treat mtimes within N seconds as equal (compares by size and mtime)
+    #[arg(long, value_name = "SECONDS")]
+    modify_window: Option<u64>,
+
+    /// Always compare file contents by hash (overrides --modify-window)
+    #[arg(short = 'c', long)]
+    checksum: bool,
+
+    /// Skip files that are newer in the destination
+    #[arg(short = 'u', long)]
+    update: bool,
+
+    /// Seconds the destination clock runs ahead of the source (used by --update)
+    #[arg(long, value_name = "SECONDS")]
+    clock_skew: Option<u64>,
+
+    /// Report moved files as a removal plus an addition instead of a rename
+    #[arg(long)]
+    no_renames: bool,
+
+    /// Only pair files of at least this many bytes as renames
+    #[arg(long, value_name = "BYTES", conflicts_with = "no_renames")]
+    rename_min_size: Option<u64>,
+
+    /// Also sync version-control directories (.git, .svn, .hg)
+    #[arg(long)]
+    include_vcs: bool,
+
+    /// Also sync files git tracks despite gitignore (needs git)
+    #[arg(long)]
+    include_git_tracked: bool,
+
+    /// Skip directories containing a file named NAME, e.g. CACHEDIR.TAG (repeatable)
+    #[arg(long, value_name = "NAME")]
+    exclude_if_present: Vec<String>,
+
+    /// Create every source directory the scan walked into, even without synced files
+    #[arg(long)]
+    replicate_empty_parents: bool,
+
+    /// Copy file content only; skip timestamps, permissions and ownership
+    #[arg(long)]
+    content_only: bool,
+
+    /// Fail copies whose modification time can't be set instead of warning
+    #[arg(long, conflicts_with = "content_only")]
+    strict_metadata: bool,
+
+    /// Preserve access times
+    #[arg(long, conflicts_with = "content_only")]
+    preserve_atime: bool,
+
+    /// Preserve creation times (macOS and Windows)
+    #[arg(long, conflicts_with = "content_only")]
+    preserve_btime: bool,
+
+    /// Copy files under 64 KiB together in batches to cut per-file overhead
+    #[arg(long)]
+    batch_small_files: bool,
+
+    /// Keep scans and copies from filling the page cache (Linux only)
+    #[arg(long)]
+    fadvise: bool,
+
+    /// Hash each directory so the diff skips subtrees that match the destination
+    #[arg(long)]
+    dir_hashes: bool,
+
+    /// With --delete, keep destination-only paths matching GLOB (repeatable)
+    #[arg(long, value_name = "GLOB", requires = "delete")]
+    keep: Vec<String>,
+
+    /// Only sync files owned by this user (name or numeric uid; Unix only)
+    #[arg(long, value_name = "USER")]
+    owner: Option<String>,
+
+    /// Only sync files owned by this group (name or numeric gid; Unix only)
+    #[arg(long, value_name = "GROUP")]
+    group: Option<String>,
+
+    /// Never modify or delete destination paths matching GLOB (repeatable)
+    #[arg(long, value_name = "GLOB")]
+    protect: Vec<String>,
+
+    /// Reuse hashes of unchanged files from this cache file (created if missing)
+    #[arg(long, value_name = "FILE")]
+    checksum_cache: Option<PathBuf>,
+
+    /// Re-hash files whose cached hash is older than N seconds
+    #[arg(long, value_name = "SECONDS", requires = "checksum_cache")]
+    checksum_cache_ttl: Option<u64>,
+
+    /// Reuse source hashes from this manifest and rewrite it after each successful run
+    #[arg(long, value_name = "FILE")]
+    manifest: Option<PathBuf>,
+
+    /// Trust this manifest of the destination instead of scanning it, and rewrite it after each successful run
+    #[arg(long, value_name = "FILE")]
+    dest_manifest: Option<PathBuf>,
+
+    /// Scan the destination even when --dest-manifest is available
+    #[arg(long, requires = "dest_manifest")]
+    verify_dest: bool,
+
+    /// Fraction of --dest-manifest entries to stat for out-of-band changes (default: 0.01, 0 disables)
+    #[arg(long, value_name = "RATE")]
+    dest_sample_rate: Option<f64>,
+
+    /// Write --manifest and --dest-manifest with front-coded paths (smaller for deep trees)
+    #[arg(long)]
+    compress_manifest_paths: bool,
+
+    /// Print how long the scan, diff and sync phases took
+    #[arg(long)]
+    stats: bool,
+
+    /// Save scan progress to FILE so an interrupted scan resumes where it stopped
+    #[arg(long, value_name = "FILE")]
+    scan_checkpoint: Option<PathBuf>,
+
+    /// Print a status line every N seconds while scanning (even with -q)
+    #[arg(long, value_name = "SECONDS")]
+    heartbeat: Option<u64>,
+
+    /// With --dry-run, show line diffs of modified text files
+    #[arg(long, requires = "dry_run")]
+    text_diff: bool,
+
+    /// Explain which filter rule includes or excludes PATH (relative to SOURCE), then exit
+    #[arg(long, value_name = "PATH")]
+    debug_filter: Option<PathBuf>,
+
+    /// Rewrite FILE with JSON sync progress for dashboards as the progress display updates
+    #[arg(long, value_name = "FILE")]
+    status_file: Option<PathBuf>,
+
+    /// Fail unless the destination's tree hash equals HASH after syncing
+    #[arg(long, value_name = "HASH")]
+    expect_hash: Option<ContentHash>,
+
     /// Number of threads (default: CPU count)
     #[arg(short = 'j', long)]
     threads: Option<usize>,
+
+    // `--no-*` forms of the switches a profile can turn on, to turn them
+    // back off for one run
+    #[arg(long, hide = true, overrides_with = "delete")]
+    no_delete: bool,
+    #[arg(long, hide = true, overrides_with = "yes")]
+    no_yes: bool,
+    #[arg(long, hide = true, overrides_with = "quiet")]
+    no_quiet: bool,
+    #[arg(long, hide = true, overrides_with = "checksum")]
+    no_checksum: bool,
+    #[arg(long, hide = true, overrides_with = "update")]
+    no_update: bool,
+    #[arg(long, hide = true, overrides_with = "no_renames")]
+    renames: bool,
+    #[arg(long, hide = true, overrides_with = "include_vcs")]
+    no_include_vcs: bool,
+    #[arg(long, hide = true, overrides_with = "include_git_tracked")]
+    no_include_git_tracked: bool,
+    #[arg(long, hide = true, overrides_with = "replicate_empty_parents")]
+    no_replicate_empty_parents: bool,
+    #[arg(long, hide = true, overrides_with = "content_only")]
+    no_content_only: bool,
+    #[arg(long, hide = true, overrides_with = "strict_metadata")]
+    no_strict_metadata: bool,
+    #[arg(long, hide = true, overrides_with = "preserve_atime")]
+    no_preserve_atime: bool,
+    #[arg(long, hide = true, overrides_with = "preserve_btime")]
+    no_preserve_btime: bool,
+    #[arg(long, hide = true, overrides_with = "batch_small_files")]
+    no_batch_small_files: bool,
+    #[arg(long, hide = true, overrides_with = "fadvise")]
+    no_fadvise: bool,
+    #[arg(long, hide = true, overrides_with = "dir_hashes")]
+    no_dir_hashes: bool,
+    #[arg(long, hide = true, overrides_with = "verify_dest")]
+    no_verify_dest: bool,
+    #[arg(long, hide = true, overrides_with = "compress_manifest_paths")]
+    no_compress_manifest_paths: bool,
+
+    /// Print fatal errors as a JSON object on stderr
+    #[arg(long, global = true)]
+    json: bool,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Scan a directory without syncing
+    Scan(ScanArgs),
+    /// Check a directory against a manifest and report every difference
+    Audit(AuditArgs),
+}
+
+#[derive(Args)]
+struct ScanArgs {
+    /// Directory to scan
+    dir: PathBuf,
+
+    /// Print file count and size histograms
+    #[arg(long)]
+    stats: bool,
+
+    /// List every included file with its size and mtime
+    #[arg(short, long, visible_alias = "list-only")]
+    list: bool,
+
+    /// Include content hashes in the listing
+    #[arg(long, requires = "list")]
+    hash: bool,
+
+    /// Also scan version-control directories (.git, .svn, .hg)
+    #[arg(long)]
+    include_vcs: bool,
+
+    /// Print the tree hash of the directory (for use with --expect-hash)
+    #[arg(long)]
+    tree_hash: bool,
+}
+
+#[derive(Args)]
+struct AuditArgs {
+    /// Directory to check
+    dir: PathBuf,
+
+    /// Manifest to check against (e.g. one written by --manifest)
+    manifest: PathBuf,
+
+    /// Also list files that match the manifest
+    #[arg(long)]
+    all: bool,
+
+    /// Also check version-control directories (.git, .svn, .hg)
+    #[arg(long)]
+    include_vcs: bool,
+
+    /// Also check files git tracks despite gitignore (needs git)
+    #[arg(long)]
+    include_git_tracked: bool,
+
+    /// Skip directories containing a file named NAME, e.g. CACHEDIR.TAG (repeatable)
+    #[arg(long, value_name = "NAME")]
+    exclude_if_present: Vec<String>,
 }
 
 fn main() {
-    if let Err(e) = run() {
-        eprintln!("Error: {e:#}");
+    let mut cli = Cli::parse();
+    if let Err(e) = load_profile(&mut cli).and_then(|()| run(&cli)) {
+        if cli.json {
+            eprintln!("{}", error_json(&e));
+        } else {
+            eprintln!("Error: {e:#}");
+        }
         process::exit(1);
     }
 }
 
-fn run() -> Result<()> {
-    let cli = Cli::parse();
+/// A fatal error as `{"error": {"kind", "message", "path"}}` for `--json`
+///
+/// `kind` comes from [`SyncError::kind`]; errors from elsewhere are `io` or
+/// `other`.
+fn error_json(error: &anyhow::Error) -> serde_json::Value {
+    let sync_error = error.chain().find_map(|e| e.downcast_ref::<SyncError>());
+    let kind = match sync_error {
+        Some(e) => e.kind(),
+        None if error.chain().any(|e| e.is::<std::io::Error>()) => "io",
+        None => "other",
+    };
+    serde_json::json!({
+        "error": {
+            "kind": kind,
+            "message": format!("{error:#}"),
+            "path": sync_error.and_then(SyncError::path),
+        }
+    })
+}
 
+/// Fill in settings from `--profile` that the command line leaves unset
+fn load_profile(cli: &mut Cli) -> Result<()> {
+    let Some(name) = &cli.profile else {
+        return Ok(());
+    };
+    let path = match &cli.config {
+        Some(path) => path.clone(),
+        None => Config::default_path()
+            .ok_or_else(|| anyhow::anyhow!("can't locate the config file; pass --config"))?,
+    };
+    let config = Config::load(&path)?;
+    apply_profile(cli, config.profile(name)?);
+    Ok(())
+}
+
+/// Merge a profile into the command line, which wins where both set a value
+fn apply_profile(cli: &mut Cli, profile: &Profile) {
+    fn fill<T: Clone>(flag: &mut Option<T>, saved: &Option<T>) {
+        if flag.is_none() {
+            flag.clone_from(saved);
+        }
+    }
+    fn fill_list(flag: &mut Vec<String>, saved: &[String]) {
+        if flag.is_empty() {
+            *flag = saved.to_vec();
+        }
+    }
+    // `off` is the switch's `--no-*` form; neither given leaves it to the profile
+    fn switch(flag: &mut bool, off: bool, saved: Option<bool>) {
+        if !*flag && !off {
+            *flag = saved.unwrap_or(false);
+        }
+    }
+
+    fill(&mut cli.source, &profile.source);
+    fill(&mut cli.dest, &profile.dest);
+    switch(&mut cli.delete, cli.no_delete, profile.delete);
+    if !(cli.delete_before || cli.delete_during || cli.delete_after) {
+        match profile.delete_timing {
+            Some(DeleteTiming::Before) => cli.delete_before = true,
+            Some(DeleteTiming::During) => cli.delete_during = true,
+            Some(DeleteTiming::After) | None => {},
+        }
+    }
+    if !(cli.largest_first || cli.smallest_first || cli.newest_first) {
+        match profile.transfer_order {
+            Some(TransferOrder::LargestFirst) => cli.largest_first = true,
+            Some(TransferOrder::SmallestFirst) => cli.smallest_first = true,
+            Some(TransferOrder::NewestFirst) => cli.newest_first = true,
+            Some(TransferOrder::PathOrder) | None => {},
+        }
+    }
+    fill(&mut cli.limit, &profile.limit);
+    switch(&mut cli.yes, cli.no_yes, profile.yes);
+    switch(&mut cli.quiet, cli.no_quiet, profile.quiet);
+    fill(&mut cli.modify_window, &profile.modify_window);
+    switch(&mut cli.checksum, cli.no_checksum, profile.checksum);
+    switch(&mut cli.update, cli.no_update, profile.update);
+    fill(&mut cli.clock_skew, &profile.clock_skew);
+    switch(&mut cli.no_renames, cli.renames, profile.no_renames);
+    fill(&mut cli.rename_min_size, &profile.rename_min_size);
+    switch(&mut cli.include_vcs, cli.no_include_vcs, profile.include_vcs);
+    switch(
+        &mut cli.include_git_tracked,
+        cli.no_include_git_tracked,
+        profile.include_git_tracked,
+    );
+    fill_list(&mut cli.exclude_if_present, &profile.exclude_if_present);
+    switch(
+        &mut cli.replicate_empty_parents,
+        cli.no_replicate_empty_parents,
+        profile.replicate_empty_parents,
+    );
+    switch(&mut cli.content_only, cli.no_content_only, profile.content_only);
+    switch(&mut cli.strict_metadata, cli.no_strict_metadata, profile.strict_metadata);
+    switch(&mut cli.preserve_atime, cli.no_preserve_atime, profile.preserve_atime);
+    switch(&mut cli.preserve_btime, cli.no_preserve_btime, profile.preserve_btime);
+    switch(&mut cli.batch_small_files, cli.no_batch_small_files, profile.batch_small_files);
+    switch(&mut cli.fadvise, cli.no_fadvise, profile.fadvise);
+    switch(&mut cli.dir_hashes, cli.no_dir_hashes, profile.dir_hashes);
+    fill_list(&mut cli.keep, &profile.keep);
+    fill(&mut cli.owner, &profile.owner);
+    fill(&mut cli.group, &profile.group);
+    fill_list(&mut cli.protect, &profile.protect);
+    fill(&mut cli.checksum_cache, &profile.checksum_cache);
+    fill(&mut cli.checksum_cache_ttl, &profile.checksum_cache_ttl);
+    fill(&mut cli.manifest, &profile.manifest);
+    fill(&mut cli.dest_manifest, &profile.dest_manifest);
+    switch(&mut cli.verify_dest, cli.no_verify_dest, profile.verify_dest);
+    fill(&mut cli.dest_sample_rate, &profile.dest_sample_rate);
+    switch(
+        &mut cli.compress_manifest_paths,
+        cli.no_compress_manifest_paths,
+        profile.compress_manifest_paths,
+    );
+    fill(&mut cli.scan_checkpoint, &profile.scan_checkpoint);
+    fill(&mut cli.heartbeat, &profile.heartbeat);
+    fill(&mut cli.status_file, &profile.status_file);
+    fill(&mut cli.expect_hash, &profile.expect_hash);
+    fill(&mut cli.threads, &profile.threads);
+}
+
+fn run(cli: &Cli) -> Result<()> {
     if let Some(t) = cli.threads {
         rayon::ThreadPoolBuilder::new().num_threads(t).build_global()?;
     }
 
+    if let Some(root) = &cli.server {
+        let output = std::io::BufWriter::new(std::io::stdout().lock());
+        return Ok(janus::transport::serve(root, std::io::stdin().lock(), output)?);
+    }
+
+    match &cli.command {
+        Some(Command::Scan(args)) => run_scan(args),
+        Some(Command::Audit(args)) => run_audit(args),
+        None => {
+            let (Some(source), Some(dest)) = (&cli.source, &cli.dest) else {
+                anyhow::bail!(
+                    "profile {} doesn't set a source and dest",
+                    cli.profile.as_deref().unwrap_or_default()
+                );
+            };
+            run_sync(cli, source, dest)
+        },
+    }
+}
+
+fn run_scan(args: &ScanArgs) -> Result<()> {
+    let options = ScanOptions {
+        include_vcs: args.include_vcs,
+        ..Default::default()
+    };
+    let mut scan = scan_directory_with_options(&args.dir, &options, None)?;
+    print_scan_warnings(&scan);
+
+    if args.list {
+        scan.files.sort_by(|a, b| a.path.cmp(&b.path));
+        for file in &scan.files {
+            let mtime = file.mtime.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+            if args.hash {
+                println!("{:>12} {mtime:>12} {} {}", file.size, file.hash, file.path.display());
+            } else {
+                println!("{:>12} {mtime:>12} {}", file.size, file.path.display());
+            }
+        }
+    }
+
+    println!("{} files, {}", scan.files.len(), HumanBytes(scan.total_size()));
+    if args.tree_hash {
+        println!("Tree hash: {}", scan.tree_hash()?);
+    }
+
+    if args.stats {
+        let stats = scan.histogram();
+        println!();
+        print_buckets("Size", &stats.size_buckets);
+        println!();
+        print_buckets("Extension", &stats.top_extensions);
+    }
+
+    Ok(())
+}
+
+fn run_audit(args: &AuditArgs) -> Result<()> {
+    let manifest = ScanResult::load_from_file(&args.manifest)?;
+    let options = ScanOptions {
+        include_vcs: args.include_vcs,
+        include_git_tracked: args.include_git_tracked,
+        exclude_if_present: args.exclude_if_present.clone(),
+        ..Default::default()
+    };
+    let statuses = audit(&args.dir, &manifest, &options)?;
+
+    let problems = statuses.iter().filter(|(_, status)| *status != AuditStatus::Ok).count();
+    for (path, status) in &statuses {
+        if args.all || *status != AuditStatus::Ok {
+            println!("{}: {status}", path.display());
+        }
+    }
+    println!("{} files ok, {problems} problems", statuses.len() - problems);
+    if problems > 0 {
+        anyhow::bail!("{} does not match {}", args.dir.display(), args.manifest.display());
+    }
+    Ok(())
+}
+
+fn print_buckets(heading: &str, buckets: &[BucketStats]) {
+    println!("{heading:<18} {:>10} {:>12}", "Files", "Total");
+    for bucket in buckets {
+        println!(
+            "{:<18} {:>10} {:>12}",
+            bucket.label,
+            bucket.count,
+            HumanBytes(bucket.total_size).to_string()
+        );
+    }
+}
+
+fn run_sync(cli: &Cli, source: &Path, dest: &Path) -> Result<()> {
+    if is_same_directory(source, dest) {
+        if cli.delete {
+            anyhow::bail!(
+                "source and destination are the same directory ({}); refusing to run with --delete",
+                source.display()
+            );
+        }
+        if !cli.quiet {
+            println!("Source and destination are identical; nothing to do");
+        }
+        return Ok(());
+    }
+
     if cli.verbose && !cli.quiet {
-        println!("Scanning: {}", cli.source.display());
+        println!("Scanning: {}", source.display());
     }
 
-    let src = scan_directory(&cli.source, None)?;
-    let dst = scan_directory(&cli.dest, None)?;
-    let diff = diff_scans(&src, &dst)?;
+    let config = CacheConfig {
+        cache_ttl: cli.checksum_cache_ttl.map(Duration::from_secs),
+    };
+    let mut hash_cache = match &cli.checksum_cache {
+        Some(path) => Some(HashCache::load(path, config)?),
+        None if cli.manifest.is_some() => Some(HashCache::new(config)),
+        None => None,
+    };
+    if let (Some(cache), Some(path)) = (&mut hash_cache, &cli.manifest) {
+        if path.exists() {
+            cache.insert_scan(&ScanResult::load_from_file(path)?);
+        }
+    }
+    let hash_cache = hash_cache.map(|cache| Arc::new(Mutex::new(cache)));
+    let cancel = Arc::new(AtomicBool::new(false));
+    let options = ReconcileOptions {
+        scan: ScanOptions {
+            include_vcs: cli.include_vcs,
+            include_git_tracked: cli.include_git_tracked,
+            exclude_if_present: cli.exclude_if_present.clone(),
+            hash_cache: hash_cache.clone(),
+            heartbeat_interval: cli.heartbeat.map(Duration::from_secs),
+            scan_checkpoint: cli.scan_checkpoint.clone(),
+            fadvise: cli.fadvise,
+            dir_hashes: cli.dir_hashes,
+            owner_uid: cli.owner.as_deref().map(user_id).transpose()?,
+            owner_gid: cli.group.as_deref().map(group_id).transpose()?,
+            ..Default::default()
+        },
+        diff: diff_options(cli),
+        sync: SyncOptions {
+            delete_removed: cli.delete,
+            preserve_timestamps: true,
+            content_only: cli.content_only,
+            strict_metadata: cli.strict_metadata,
+            preserve_atime: cli.preserve_atime,
+            preserve_btime: cli.preserve_btime,
+            small_file_batching: cli.batch_small_files.then(SmallFileBatchConfig::default),
+            fadvise: cli.fadvise,
+            replicate_empty_parents: cli.replicate_empty_parents,
+            protect: cli.protect.clone(),
+            keep: cli.keep.clone(),
+            delete_timing: delete_timing(cli),
+            transfer_order: transfer_order(cli),
+            limit: cli.limit,
+            status_file: cli.status_file.clone(),
+            verify_after_copy: false,
+            cancel: Some(Arc::clone(&cancel)),
+            ..Default::default()
+        },
+        trusted_dest_manifest: cli.dest_manifest.clone(),
+        verify_dest: cli.verify_dest,
+        dest_sample_rate: cli.dest_sample_rate.unwrap_or(DEFAULT_DEST_SAMPLE_RATE),
+        manifest_format: manifest_format(cli),
+        ..Default::default()
+    };
+    if let Err(conflicts) = options.validate() {
+        let conflicts: Vec<String> = conflicts.iter().map(ToString::to_string).collect();
+        anyhow::bail!("conflicting options: {}", conflicts.join("; "));
+    }
+    if let Some(path) = &cli.debug_filter {
+        println!("{}", explain_filter(source, path, &options.scan)?);
+        return Ok(());
+    }
+
+    // A destination that isn't reachable from here can still be previewed from its manifest
+    let offline = cli.dry_run && cli.dest_manifest.is_some() && !dest.is_dir();
+    let plan = match &cli.dest_manifest {
+        Some(manifest) if offline => {
+            plan_against_manifest(source, ScanResult::load_from_file(manifest)?, &options)?
+        },
+        _ => plan_reconcile(source, dest, &options)?,
+    };
+    print_scan_warnings(&plan.source);
+    print_scan_warnings(&plan.dest);
+    if !plan.dest_drift.is_empty() {
+        let response = match options.drift_policy {
+            DriftPolicy::Rescan => format!("rescanned {}", dest.display()),
+            DriftPolicy::Warn => "using the manifest anyway".to_string(),
+        };
+        eprintln!(
+            "Warning: {} sampled destination file(s) changed since the last run; {response}",
+            plan.dest_drift.len()
+        );
+    }
+    if plan.dest_from_manifest && cli.verbose && !cli.quiet {
+        println!("Using destination manifest instead of scanning {}", dest.display());
+    }
+    if let (Some(path), Some(cache)) = (&cli.checksum_cache, &hash_cache) {
+        cache.lock().unwrap().save(path)?;
+    }
+    if cli.update && cli.clock_skew.is_none() {
+        if let Some(skew) = detect_clock_skew(&plan.source, &plan.dest) {
+            eprintln!(
+                "Warning: destination clock appears to be {}s ahead of the source; consider \
+                 --clock-skew {}",
+                skew.as_secs(),
+                skew.as_secs()
+            );
+        }
+    }
 
-    let changes = diff.added.len() + diff.modified.len() + diff.renamed.len();
-    if changes == 0 && (!cli.delete || diff.removed.is_empty()) {
+    let diff = &plan.diff;
+    if !plan.has_changes(cli.delete) {
         if !cli.quiet {
             println!("In sync");
         }
-        return Ok(());
+        print_timings(cli, &plan.timings);
+        if offline {
+            return Ok(());
+        }
+        save_dest_manifest(cli, &plan, &options.sync, &SyncReport::default())?;
+        save_manifest(cli, &plan.source)?;
+        return check_tree_hash(cli, dest, &options);
     }
 
     if !cli.quiet {
@@ -84,9 +723,19 @@ fn run() -> Result<()> {
                 String::new()
             }
         );
+        if cli.verbose {
+            print!("{}", format_diff_tree(diff));
+        }
     }
 
     if cli.dry_run {
+        if !cli.quiet {
+            print_operations(&plan.operations(&options.sync)?);
+        }
+        if cli.text_diff && !offline {
+            let limit = Some(DEFAULT_TEXT_DIFF_LIMIT);
+            print_text_changes(&classify_modifications(diff, source, dest, limit)?);
+        }
         return Ok(());
     }
 
@@ -100,21 +749,291 @@ fn run() -> Result<()> {
         }
     }
 
-    sync_changes(
-        &cli.source,
-        &cli.dest,
-        &diff,
-        &SyncOptions {
-            delete_removed: cli.delete,
-            preserve_timestamps: true,
-            verify_after_copy: false,
-        },
-        None,
-    )?;
+    let total_ops = diff.added.len()
+        + diff.modified.len()
+        + diff.renamed.len()
+        + if cli.delete { diff.removed.len() } else { 0 };
+    install_interrupt_handler(cancel);
+
+    // Until the apply succeeds the destination no longer matches its manifest
+    if let Some(path) = &cli.dest_manifest {
+        remove_file_safe(path)?;
+    }
+    let report = plan.apply(&options.sync, None)?;
+    save_dest_manifest(cli, &plan, &options.sync, &report)?;
+    for warning in &report.warnings {
+        eprintln!("Warning: {warning}");
+    }
+
+    if report.interrupted {
+        let completed = report.files_copied + report.files_renamed + report.files_deleted;
+        eprintln!("Interrupted; {completed} of {total_ops} operations completed");
+        process::exit(EXIT_INTERRUPTED);
+    }
 
     if !cli.quiet {
         println!("Done");
     }
+    print_timings(cli, &report.timings);
 
+    save_manifest(cli, &plan.source)?;
+    check_tree_hash(cli, dest, &options)
+}
+
+/// Print each planned operation and the bytes they would transfer
+fn print_operations(operations: &[PlannedOperation]) {
+    let mut transfer = 0;
+    for op in operations {
+        let path = op.path.display();
+        let from = op.from.as_deref().unwrap_or(Path::new("")).display();
+        match op.kind {
+            OperationKind::Copy => println!("copy     {path} ({})", HumanBytes(op.bytes)),
+            OperationKind::Rename => {
+                println!("rename   {from} -> {path} ({})", HumanBytes(op.bytes))
+            },
+            OperationKind::Metadata => println!("metadata {path}"),
+            OperationKind::Delete => println!("delete   {path}"),
+        }
+        if matches!(op.kind, OperationKind::Copy | OperationKind::Rename) {
+            transfer += op.bytes;
+        }
+    }
+    println!("Would transfer {}", HumanBytes(transfer));
+}
+
+/// Print line diffs of modified text files and name the binary ones
+fn print_text_changes(changes: &[ModifiedFile]) {
+    for change in changes {
+        match (&change.text_diff, change.is_text) {
+            (Some(diff), _) => print!("{}", diff.unified),
+            (None, true) => println!("text     {} (too large to diff)", change.path.display()),
+            (None, false) => println!("binary   {}", change.path.display()),
+        }
+    }
+}
+
+/// Verify the destination against `--expect-hash`, if given
+fn check_tree_hash(cli: &Cli, dest: &Path, options: &ReconcileOptions) -> Result<()> {
+    let Some(expected) = &cli.expect_hash else {
+        return Ok(());
+    };
+    verify_tree_hash(dest, expected, &options.scan)?;
+    if cli.verbose && !cli.quiet {
+        println!("Tree hash verified: {expected}");
+    }
     Ok(())
 }
+
+/// Print the phase durations for `--stats`
+fn print_timings(cli: &Cli, timings: &PhaseTimings) {
+    if cli.stats {
+        println!(
+            "Time: scan {:.2?}, diff {:.2?}, sync {:.2?} (total {:.2?})",
+            timings.scan,
+            timings.diff,
+            timings.sync,
+            timings.total()
+        );
+    }
+}
+
+/// Report files a scan had to leave out on stderr
+fn print_scan_warnings(scan: &ScanResult) {
+    for warning in &scan.warnings {
+        eprintln!("Warning: Failed to process {}: {}", warning.path.display(), warning.message);
+    }
+    if !scan.warnings.is_empty() {
+        eprintln!("Warning: {} files could not be processed", scan.warnings.len());
+    }
+}
+
+/// Write the source scan to `--manifest`, if given, to warm the next run
+fn save_manifest(cli: &Cli, source: &ScanResult) -> Result<()> {
+    match &cli.manifest {
+        Some(path) if !cli.dry_run => source.save_to_file_as(path, manifest_format(cli)),
+        _ => Ok(()),
+    }
+}
+
+/// Record the post-sync destination in `--dest-manifest`, if given
+fn save_dest_manifest(
+    cli: &Cli,
+    plan: &ReconcilePlan,
+    options: &SyncOptions,
+    report: &SyncReport,
+) -> Result<()> {
+    match &cli.dest_manifest {
+        Some(path) if !cli.dry_run => {
+            plan.save_dest_manifest(path, manifest_format(cli), options, report)
+        },
+        _ => Ok(()),
+    }
+}
+
+/// Encoding for manifests written by this run
+fn manifest_format(cli: &Cli) -> ManifestFormat {
+    if cli.compress_manifest_paths {
+        ManifestFormat::FrontCoded
+    } else {
+        ManifestFormat::Plain
+    }
+}
+
+/// Resolve a user name or numeric uid
+#[cfg(unix)]
+fn user_id(user: &str) -> Result<u32> {
+    if let Ok(uid) = user.parse() {
+        return Ok(uid);
+    }
+    let name = std::ffi::CString::new(user)?;
+    // SAFETY: getpwnam is called from the main thread before any other lookups
+    let entry = unsafe { libc::getpwnam(name.as_ptr()) };
+    if entry.is_null() {
+        anyhow::bail!("Unknown user: {user}");
+    }
+    // SAFETY: checked non-null above
+    Ok(unsafe { (*entry).pw_uid })
+}
+
+/// Resolve a group name or numeric gid
+#[cfg(unix)]
+fn group_id(group: &str) -> Result<u32> {
+    if let Ok(gid) = group.parse() {
+        return Ok(gid);
+    }
+    let name = std::ffi::CString::new(group)?;
+    // SAFETY: getgrnam is called from the main thread before any other lookups
+    let entry = unsafe { libc::getgrnam(name.as_ptr()) };
+    if entry.is_null() {
+        anyhow::bail!("Unknown group: {group}");
+    }
+    // SAFETY: checked non-null above
+    Ok(unsafe { (*entry).gr_gid })
+}
+
+#[cfg(not(unix))]
+fn user_id(_user: &str) -> Result<u32> {
+    anyhow::bail!("--owner is only supported on Unix")
+}
+
+#[cfg(not(unix))]
+fn group_id(_group: &str) -> Result<u32> {
+    anyhow::bail!("--group is only supported on Unix")
+}
+
+/// Flag set by the SIGINT handler, shared with the running sync
+static INTERRUPT: OnceLock<Arc<AtomicBool>> = OnceLock::new();
+
+/// Make Ctrl-C cancel the sync gracefully instead of killing the process
+///
+/// The first SIGINT sets `flag` so in-flight files finish and the rest are
+/// skipped; a second SIGINT terminates immediately.
+#[cfg(unix)]
+fn install_interrupt_handler(flag: Arc<AtomicBool>) {
+    extern "C" fn on_sigint(_: libc::c_int) {
+        if let Some(flag) = INTERRUPT.get() {
+            flag.store(true, std::sync::atomic::Ordering::SeqCst);
+        }
+        // SAFETY: signal() is async-signal-safe; restores the default action
+        unsafe {
+            libc::signal(libc::SIGINT, libc::SIG_DFL);
+        }
+    }
+
+    let _ = INTERRUPT.set(flag);
+    // SAFETY: the handler only touches an atomic flag and calls signal()
+    unsafe {
+        libc::signal(libc::SIGINT, on_sigint as extern "C" fn(libc::c_int) as libc::sighandler_t);
+    }
+}
+
+#[cfg(not(unix))]
+fn install_interrupt_handler(flag: Arc<AtomicBool>) {
+    let _ = INTERRUPT.set(flag);
+}
+
+/// Build diff options from the comparison flags
+fn diff_options(cli: &Cli) -> DiffOptions {
+    let mut options = DiffOptions {
+        newer_wins: cli.update,
+        clock_skew: Duration::from_secs(cli.clock_skew.unwrap_or(0)),
+        detect_renames: !cli.no_renames,
+        rename_min_size: cli.rename_min_size.unwrap_or(0),
+        ..Default::default()
+    };
+    if let (Some(secs), false) = (cli.modify_window, cli.checksum) {
+        options.compare = CompareMode::SizeMtime;
+        options.mtime_tolerance = Duration::from_secs(secs);
+    }
+    options
+}
+
+/// When deletions run, from --delete-before/--delete-during/--delete-after
+fn delete_timing(cli: &Cli) -> DeleteTiming {
+    if cli.delete_before {
+        DeleteTiming::Before
+    } else if cli.delete_during {
+        DeleteTiming::During
+    } else {
+        DeleteTiming::After
+    }
+}
+
+/// Which files go first, from --largest-first/--smallest-first/--newest-first
+fn transfer_order(cli: &Cli) -> TransferOrder {
+    if cli.largest_first {
+        TransferOrder::LargestFirst
+    } else if cli.smallest_first {
+        TransferOrder::SmallestFirst
+    } else if cli.newest_first {
+        TransferOrder::NewestFirst
+    } else {
+        TransferOrder::PathOrder
+    }
+}
+
+/// Check whether two paths resolve to the same directory
+fn is_same_directory(a: &Path, b: &Path) -> bool {
+    match (a.canonicalize(), b.canonicalize()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_profile_with_cli_overrides() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let config = dir.path().join("config.json");
+        std::fs::write(
+            &config,
+            r#"{"profiles": {"nightly": {
+                "source": "/data", "dest": "/backup", "delete": true, "modify-window": 2,
+                "protect": ["*.lock"], "exclude-if-present": ["CACHEDIR.TAG"]
+            }}}"#,
+        )
+        .unwrap();
+
+        let args = ["jan", "--profile", "nightly", "--config", config.to_str().unwrap()];
+        let overrides = ["--modify-window", "5", "--protect", "keep/**", "-u"];
+        let mut cli = Cli::try_parse_from(args.iter().chain(&overrides)).unwrap();
+        load_profile(&mut cli).unwrap();
+
+        assert_eq!(cli.source, Some(PathBuf::from("/data")));
+        assert_eq!(cli.dest, Some(PathBuf::from("/backup")));
+        assert!(cli.delete);
+        assert_eq!(cli.exclude_if_present, ["CACHEDIR.TAG"]);
+        assert_eq!(cli.protect, ["keep/**"]);
+        let diff = diff_options(&cli);
+        assert_eq!(diff.compare, CompareMode::SizeMtime);
+        assert_eq!(diff.mtime_tolerance, Duration::from_secs(5));
+        assert!(diff.newer_wins);
+
+        let args = ["jan", "--profile", "weekly", "--config", config.to_str().unwrap()];
+        let mut cli = Cli::try_parse_from(args).unwrap();
+        assert!(load_profile(&mut cli).is_err());
+    }
+}
@@ -0,0 +1,287 @@
+//! Content-addressed storage of file contents
+//!
+//! A [`ChunkStore`] keeps blobs keyed by their content hash, so content
+//! shared by many files, or by many backups of the same tree, is stored once.
+//! [`store_scan`] backs a scanned tree up into a store, and [`restore_scan`]
+//! rebuilds the tree from the scan's manifest.
+//!
+//! ## Chunks
+//!
+//! A chunk is the whole content of one file, keyed by the same hash a scan
+//! records, so a saved manifest is all that's needed to restore. Chunks are
+//! passed around in memory, which suits the small-to-medium files that
+//! dominate dedup workloads.
+
+use rayon::prelude::*;
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+use crate::core::{ScanResult, SyncError};
+use crate::hash::{hash_bytes, ContentHash};
+use crate::io::{set_file_mtime, write_file_atomic};
+
+/// Storage for content chunks keyed by their hash
+///
+/// Implementations must be safe to call from several threads at once.
+pub trait ChunkStore: Send + Sync {
+    /// Whether a chunk with this hash is stored
+    fn has(&self, hash: &ContentHash) -> io::Result<bool>;
+
+    /// Store `bytes` under `hash`
+    ///
+    /// Storing a chunk that is already present must succeed without
+    /// changing it. Callers are responsible for `hash` matching `bytes`.
+    fn put(&self, hash: &ContentHash, bytes: &[u8]) -> io::Result<()>;
+
+    /// The chunk stored under `hash`, or a `NotFound` error
+    fn get(&self, hash: &ContentHash) -> io::Result<Vec<u8>>;
+}
+
+/// A [`ChunkStore`] in a local directory
+///
+/// Each chunk is a file named by its hex hash, fanned out into
+/// subdirectories by algorithm and the first two hex digits so no directory
+/// grows too large. Chunks are written atomically, so a crash never leaves a
+/// truncated chunk behind.
+#[derive(Debug, Clone)]
+pub struct FsChunkStore {
+    root: PathBuf,
+}
+
+impl FsChunkStore {
+    /// Use `root` as the store directory, creating it if needed
+    pub fn open(root: impl Into<PathBuf>) -> io::Result<Self> {
+        let root = root.into();
+        fs::create_dir_all(&root)?;
+        Ok(Self { root })
+    }
+
+    /// Directory holding the chunks
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// File a chunk is stored in
+    fn chunk_path(&self, hash: &ContentHash) -> PathBuf {
+        let hex = hash.to_string();
+        self.root.join(hash.algorithm().to_lowercase()).join(&hex[..2]).join(hex)
+    }
+}
+
+impl ChunkStore for FsChunkStore {
+    fn has(&self, hash: &ContentHash) -> io::Result<bool> {
+        match fs::metadata(self.chunk_path(hash)) {
+            Ok(_) => Ok(true),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn put(&self, hash: &ContentHash, bytes: &[u8]) -> io::Result<()> {
+        if self.has(hash)? {
+            return Ok(());
+        }
+        let path = self.chunk_path(hash);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        write_file_atomic(&path, bytes)
+    }
+
+    fn get(&self, hash: &ContentHash) -> io::Result<Vec<u8>> {
+        fs::read(self.chunk_path(hash))
+    }
+}
+
+/// What [`store_scan`] did
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StoreReport {
+    /// Files whose content was added to the store
+    pub chunks_stored: usize,
+    /// Files whose content the store already held
+    pub chunks_reused: usize,
+    /// Bytes of new chunks written
+    pub bytes_stored: u64,
+}
+
+/// Store the content of every file in `scan` that `store` doesn't hold yet
+///
+/// Files are read from the scan's root and checked against their recorded
+/// hash, so a file modified since the scan fails instead of being stored
+/// under a stale key. Content appearing in several files is stored once.
+/// Scans with deferred hashes (see [`crate::core::HashPolicy::OnConflict`])
+/// can't be stored.
+pub fn store_scan(scan: &ScanResult, store: &dyn ChunkStore) -> anyhow::Result<StoreReport> {
+    if let Some(file) = scan.files.iter().find(|f| f.hash_deferred) {
+        return Err(SyncError::HashError {
+            path: file.path.clone(),
+            reason: "hash was deferred by the scan".to_string(),
+        }
+        .into());
+    }
+
+    // One file per distinct content is enough
+    let mut unique: Vec<_> = scan.files.iter().collect();
+    unique.sort_by(|a, b| a.hash.as_bytes().cmp(b.hash.as_bytes()));
+    unique.dedup_by(|a, b| a.hash == b.hash);
+
+    let stored = unique
+        .par_iter()
+        .map(|file| -> anyhow::Result<Option<u64>> {
+            if store.has(&file.hash)? {
+                return Ok(None);
+            }
+            let bytes = fs::read(scan.root.join(&file.path))?;
+            if hash_bytes(&bytes) != file.hash {
+                return Err(SyncError::HashError {
+                    path: file.path.clone(),
+                    reason: "changed since it was scanned".to_string(),
+                }
+                .into());
+            }
+            store.put(&file.hash, &bytes)?;
+            Ok(Some(bytes.len() as u64))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    let mut report = StoreReport {
+        chunks_reused: scan.files.len() - unique.len(),
+        ..Default::default()
+    };
+    for bytes in stored {
+        match bytes {
+            Some(bytes) => {
+                report.chunks_stored += 1;
+                report.bytes_stored += bytes;
+            },
+            None => report.chunks_reused += 1,
+        }
+    }
+    Ok(report)
+}
+
+/// Recreate the files of `scan` under `dest_root` from chunks in `store`
+///
+/// Each chunk is checked against its hash before it is written, and files
+/// get the modification time the scan recorded. Existing files at the same
+/// paths are replaced; other files under `dest_root` are left alone.
+///
+/// Fails before writing anything if a path in the scan is absolute or
+/// contains `..`, since a manifest may come from anywhere.
+pub fn restore_scan(
+    scan: &ScanResult,
+    store: &dyn ChunkStore,
+    dest_root: &Path,
+) -> anyhow::Result<()> {
+    let escaping = scan
+        .files
+        .iter()
+        .find(|file| !file.path.components().all(|c| matches!(c, Component::Normal(_))));
+    if let Some(file) = escaping {
+        return Err(SyncError::InvalidPath {
+            path: file.path.clone(),
+            reason: "manifest path escapes the restore root".to_string(),
+        }
+        .into());
+    }
+
+    scan.files.par_iter().try_for_each(|file| -> anyhow::Result<()> {
+        let bytes = store.get(&file.hash)?;
+        if hash_bytes(&bytes) != file.hash {
+            return Err(SyncError::HashError {
+                path: file.path.clone(),
+                reason: format!("chunk {} is corrupt", file.hash),
+            }
+            .into());
+        }
+        let dest = dest_root.join(&file.path);
+        if let Some(parent) = dest.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        write_file_atomic(&dest, &bytes)?;
+        set_file_mtime(&dest, file.mtime)?;
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fs_chunk_store_has_put_get() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let store = FsChunkStore::open(dir.path().join("chunks")).unwrap();
+        let hash = hash_bytes(b"chunk");
+
+        assert!(!store.has(&hash).unwrap());
+        assert_eq!(store.get(&hash).unwrap_err().kind(), io::ErrorKind::NotFound);
+
+        store.put(&hash, b"chunk").unwrap();
+        assert!(store.has(&hash).unwrap());
+        assert_eq!(store.get(&hash).unwrap(), b"chunk");
+        assert!(!store.has(&hash_bytes(b"other")).unwrap());
+    }
+
+    #[test]
+    fn test_fs_chunk_store_put_is_idempotent() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let store = FsChunkStore::open(dir.path()).unwrap();
+        let hash = hash_bytes(b"chunk");
+
+        store.put(&hash, b"chunk").unwrap();
+        let path = store.chunk_path(&hash);
+        let written = fs::metadata(&path).unwrap().modified().unwrap();
+        store.put(&hash, b"chunk").unwrap();
+
+        assert_eq!(store.get(&hash).unwrap(), b"chunk");
+        assert_eq!(fs::metadata(&path).unwrap().modified().unwrap(), written);
+        let chunks = fs::read_dir(path.parent().unwrap()).unwrap().count();
+        assert_eq!(chunks, 1);
+    }
+
+    #[test]
+    fn test_store_and_restore_scan() {
+        let source = tempfile::TempDir::new().unwrap();
+        fs::write(source.path().join("a.txt"), b"shared").unwrap();
+        fs::create_dir(source.path().join("sub")).unwrap();
+        fs::write(source.path().join("sub/b.txt"), b"shared").unwrap();
+        fs::write(source.path().join("c.txt"), b"unique").unwrap();
+        let scan = crate::core::scan_directory(source.path(), None).unwrap();
+
+        let dir = tempfile::TempDir::new().unwrap();
+        let store = FsChunkStore::open(dir.path().join("chunks")).unwrap();
+        let report = store_scan(&scan, &store).unwrap();
+        assert_eq!((report.chunks_stored, report.chunks_reused, report.bytes_stored), (2, 1, 12));
+
+        // A second backup of the same tree stores nothing new
+        let report = store_scan(&scan, &store).unwrap();
+        assert_eq!((report.chunks_stored, report.chunks_reused), (0, 3));
+
+        let restored = dir.path().join("restored");
+        restore_scan(&scan, &store, &restored).unwrap();
+        assert_eq!(fs::read(restored.join("sub/b.txt")).unwrap(), b"shared");
+        assert_eq!(fs::read(restored.join("c.txt")).unwrap(), b"unique");
+    }
+
+    #[test]
+    fn test_restore_scan_rejects_escaping_paths() {
+        let source = tempfile::TempDir::new().unwrap();
+        fs::write(source.path().join("a.txt"), b"payload").unwrap();
+        let mut scan = crate::core::scan_directory(source.path(), None).unwrap();
+
+        let dir = tempfile::TempDir::new().unwrap();
+        let store = FsChunkStore::open(dir.path().join("chunks")).unwrap();
+        store_scan(&scan, &store).unwrap();
+
+        let restored = dir.path().join("restored");
+        for path in ["../escape", "sub/../../escape", "/tmp/escape"] {
+            scan.files[0].path = PathBuf::from(path);
+            let err = restore_scan(&scan, &store, &restored).unwrap_err();
+            assert!(err.to_string().contains("escapes the restore root"), "{path}: {err}");
+        }
+        assert!(!dir.path().join("escape").exists());
+        assert!(!restored.exists());
+    }
+}
@@ -0,0 +1,1048 @@
+//! File operations on a destination reached through a helper process
+//!
+//! A [`Transport`] performs the few filesystem operations a sync needs
+//! (stat, ranged reads and writes, rename, remove, list) on a tree named by
+//! paths relative to its root. [`PipeTransport`] sends them over a pair of
+//! pipes to a `jan --server ROOT` helper, e.g. one started through `sudo` so
+//! only the writes run with elevated privileges while the scan, diff and
+//! hashing stay unprivileged (see [`PipeTransport::spawn_privileged`]).
+//! [`serve`] is the helper's side, and [`sync_to_transport`] brings the
+//! tree behind a transport in line with a scan.
+//!
+//! ## Protocol
+//!
+//! Every message is a 4-byte big-endian length followed by that many bytes
+//! of JSON. The client sends a [`Request`] and the server answers each with
+//! exactly one [`Response`]. A `write_range` request and a `data` response
+//! are followed by `len` raw bytes of file content, so content is never
+//! JSON-encoded. Failed operations are answered with an `error` response
+//! naming the [`io::ErrorKind`], which the client turns back into an error
+//! of the same kind.
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+#[cfg(unix)]
+use std::ffi::{CStr, CString, OsStr};
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+#[cfg(unix)]
+use std::os::fd::{AsRawFd, FromRawFd, IntoRawFd, OwnedFd};
+#[cfg(unix)]
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Component, Path, PathBuf};
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::core::{explain_filter, ScanOptions, ScanResult, SyncReport};
+use crate::io::temp_path_for;
+
+/// Largest JSON message either side accepts
+pub const MAX_MESSAGE_SIZE: usize = 16 * 1024 * 1024;
+
+/// Largest content payload either side accepts in one message
+pub const MAX_PAYLOAD_SIZE: u64 = 16 * 1024 * 1024;
+
+/// Bytes sent per `write_range` request by [`push_file`]
+const PUSH_CHUNK_SIZE: usize = 1024 * 1024;
+
+/// An operation sent to the server
+///
+/// Paths are relative to the server's root.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum Request {
+    /// Size, modification time and type of a path
+    Stat { path: PathBuf },
+    /// Up to `len` bytes of a file starting at `offset`
+    ///
+    /// Servers send at most [`MAX_PAYLOAD_SIZE`] bytes per request.
+    ReadRange { path: PathBuf, offset: u64, len: u64 },
+    /// Write the `len` bytes following the message at `offset`
+    ///
+    /// Parent directories are created as needed. A write at offset 0
+    /// creates or truncates the file.
+    WriteRange { path: PathBuf, offset: u64, len: u64 },
+    /// Set a file's modification time, in nanoseconds since the Unix epoch
+    SetMtime { path: PathBuf, mtime_ns: u64 },
+    /// Rename a file, replacing any file at `to`
+    Rename { from: PathBuf, to: PathBuf },
+    /// Remove a file
+    Remove { path: PathBuf },
+    /// Every file under the root
+    List,
+}
+
+/// The server's answer to a [`Request`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum Response {
+    /// The operation succeeded and has nothing to return
+    Ok,
+    /// Answer to `stat`; `None` if nothing exists at the path
+    Stat { stat: Option<RemoteStat> },
+    /// Answer to `read_range`, followed by `len` bytes of content
+    Data { len: u64 },
+    /// Answer to `list`
+    List { paths: Vec<PathBuf> },
+    /// The operation failed
+    Error { kind: String, message: String },
+}
+
+impl Response {
+    /// Describe a failed operation
+    pub fn error(error: &io::Error) -> Self {
+        Response::Error {
+            kind: error_kind_name(error.kind()).to_string(),
+            message: error.to_string(),
+        }
+    }
+}
+
+/// What [`Transport::stat`] reports about a path
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RemoteStat {
+    /// Size in bytes
+    pub size: u64,
+    /// Modification time in nanoseconds since the Unix epoch
+    pub mtime_ns: u64,
+    /// The path is a directory
+    pub is_dir: bool,
+}
+
+impl RemoteStat {
+    /// The modification time
+    pub fn mtime(&self) -> SystemTime {
+        UNIX_EPOCH + Duration::from_nanos(self.mtime_ns)
+    }
+}
+
+/// Nanoseconds since the Unix epoch, as sent on the wire
+pub fn mtime_ns(time: SystemTime) -> u64 {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    u64::try_from(since_epoch.as_nanos()).unwrap_or(u64::MAX)
+}
+
+/// File operations on a tree, addressed by paths relative to its root
+///
+/// Calls take `&mut self` because a transport talks to one peer over one
+/// channel, one request at a time.
+pub trait Transport {
+    /// Size, modification time and type of `path`, or `None` if it doesn't exist
+    fn stat(&mut self, path: &Path) -> io::Result<Option<RemoteStat>>;
+
+    /// Up to `len` bytes of `path` starting at `offset`; fewer at the end of the file
+    fn read_range(&mut self, path: &Path, offset: u64, len: u64) -> io::Result<Vec<u8>>;
+
+    /// Write `bytes` to `path` at `offset`, see [`Request::WriteRange`]
+    fn write_range(&mut self, path: &Path, offset: u64, bytes: &[u8]) -> io::Result<()>;
+
+    /// Set the modification time of `path`
+    fn set_mtime(&mut self, path: &Path, mtime: SystemTime) -> io::Result<()>;
+
+    /// Rename `from` to `to`, replacing any file at `to`
+    fn rename(&mut self, from: &Path, to: &Path) -> io::Result<()>;
+
+    /// Remove the file at `path`
+    fn remove(&mut self, path: &Path) -> io::Result<()>;
+
+    /// Every file under the root, sorted
+    fn list(&mut self) -> io::Result<Vec<PathBuf>>;
+}
+
+/// Copy the local file `source` to `dest` through a transport
+///
+/// The content is streamed into a temporary sibling of `dest` and renamed
+/// into place, so the destination never holds a partial file. With `mtime`
+/// the copy gets that modification time. Returns the bytes sent.
+pub fn push_file(
+    transport: &mut dyn Transport,
+    source: &Path,
+    dest: &Path,
+    mtime: Option<SystemTime>,
+) -> io::Result<u64> {
+    let temp = temp_path_for(dest);
+    let result = (|| {
+        let mut file = File::open(source)?;
+        let mut buffer = vec![0u8; PUSH_CHUNK_SIZE];
+        let mut offset = 0u64;
+        loop {
+            let read = file.read(&mut buffer)?;
+            // An empty file still needs its write at offset 0 to create it
+            if read == 0 && offset > 0 {
+                break;
+            }
+            transport.write_range(&temp, offset, &buffer[..read])?;
+            offset += read as u64;
+            if read == 0 {
+                break;
+            }
+        }
+        if let Some(mtime) = mtime {
+            transport.set_mtime(&temp, mtime)?;
+        }
+        transport.rename(&temp, dest)?;
+        Ok(offset)
+    })();
+    if result.is_err() {
+        let _ = transport.remove(&temp);
+    }
+    result
+}
+
+/// Program used to run the server with elevated privileges
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PrivilegeHelper {
+    /// `sudo`, which asks for a password on the terminal if needed
+    Sudo,
+    /// `pkexec` (polkit), which asks through the desktop session
+    Pkexec,
+}
+
+impl PrivilegeHelper {
+    /// The command that starts `program --server root` with elevated privileges
+    fn command(self, program: &Path, root: &Path) -> Command {
+        let mut command = match self {
+            PrivilegeHelper::Sudo => {
+                let mut command = Command::new("sudo");
+                command.arg("--");
+                command
+            },
+            PrivilegeHelper::Pkexec => Command::new("pkexec"),
+        };
+        command.arg(program).arg("--server").arg(root);
+        command
+    }
+}
+
+/// A [`Transport`] speaking the protocol to a server over a pair of streams
+///
+/// Usually the streams are the stdin and stdout of a `jan --server`
+/// process, see [`PipeTransport::spawn`], but any reader and writer work,
+/// such as the two ends of a socket.
+pub struct PipeTransport<R: Read, W: Write> {
+    reader: R,
+    /// `None` once closed, which tells the server to exit
+    writer: Option<W>,
+    child: Option<Child>,
+}
+
+impl<R: Read, W: Write> PipeTransport<R, W> {
+    /// Talk to a server that reads requests from `writer` and answers on `reader`
+    pub fn new(reader: R, writer: W) -> Self {
+        Self {
+            reader,
+            writer: Some(writer),
+            child: None,
+        }
+    }
+
+    /// Send a request and wait for the server's response
+    ///
+    /// Error responses are returned as errors. A `data` response's payload
+    /// is returned along with it.
+    fn call(&mut self, request: &Request, payload: &[u8]) -> io::Result<(Response, Vec<u8>)> {
+        let writer = self
+            .writer
+            .as_mut()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::BrokenPipe, "transport is closed"))?;
+        write_message(writer, request, payload)?;
+        let response: Response = read_message(&mut self.reader)?.ok_or_else(|| {
+            io::Error::new(io::ErrorKind::UnexpectedEof, "server closed the connection")
+        })?;
+        match response {
+            Response::Error { kind, message } => Err(io::Error::new(error_kind(&kind), message)),
+            Response::Data { len } => {
+                let data = read_payload(&mut self.reader, len)?;
+                Ok((response, data))
+            },
+            response => Ok((response, Vec::new())),
+        }
+    }
+
+    /// Send a request whose only answer is `ok`
+    fn call_ok(&mut self, request: &Request) -> io::Result<()> {
+        match self.call(request, &[])?.0 {
+            Response::Ok => Ok(()),
+            other => Err(unexpected(&other)),
+        }
+    }
+}
+
+impl PipeTransport<ChildStdout, ChildStdin> {
+    /// Start a server process and talk to it over its stdin and stdout
+    ///
+    /// The process's stderr is inherited, so password prompts and errors
+    /// reach the terminal.
+    pub fn spawn(mut command: Command) -> io::Result<Self> {
+        let mut child = command.stdin(Stdio::piped()).stdout(Stdio::piped()).spawn()?;
+        let reader = child.stdout.take().expect("stdout is piped");
+        let writer = child.stdin.take().expect("stdin is piped");
+        Ok(Self {
+            reader,
+            writer: Some(writer),
+            child: Some(child),
+        })
+    }
+
+    /// Start `jan --server root` through `helper` and talk to it
+    ///
+    /// Runs the same `jan` executable as the current process, so only this
+    /// helper holds elevated privileges.
+    pub fn spawn_privileged(helper: PrivilegeHelper, root: &Path) -> io::Result<Self> {
+        Self::spawn(helper.command(&std::env::current_exe()?, root))
+    }
+}
+
+impl<R: Read, W: Write> Drop for PipeTransport<R, W> {
+    fn drop(&mut self) {
+        // Closing the server's input ends its loop
+        self.writer = None;
+        if let Some(child) = &mut self.child {
+            let _ = child.wait();
+        }
+    }
+}
+
+impl<R: Read, W: Write> Transport for PipeTransport<R, W> {
+    fn stat(&mut self, path: &Path) -> io::Result<Option<RemoteStat>> {
+        match self.call(&Request::Stat { path: path.to_path_buf() }, &[])?.0 {
+            Response::Stat { stat } => Ok(stat),
+            other => Err(unexpected(&other)),
+        }
+    }
+
+    fn read_range(&mut self, path: &Path, offset: u64, len: u64) -> io::Result<Vec<u8>> {
+        let request = Request::ReadRange { path: path.to_path_buf(), offset, len };
+        match self.call(&request, &[])? {
+            (Response::Data { .. }, data) => Ok(data),
+            (other, _) => Err(unexpected(&other)),
+        }
+    }
+
+    fn write_range(&mut self, path: &Path, offset: u64, bytes: &[u8]) -> io::Result<()> {
+        let request = Request::WriteRange {
+            path: path.to_path_buf(),
+            offset,
+            len: bytes.len() as u64,
+        };
+        match self.call(&request, bytes)?.0 {
+            Response::Ok => Ok(()),
+            other => Err(unexpected(&other)),
+        }
+    }
+
+    fn set_mtime(&mut self, path: &Path, mtime: SystemTime) -> io::Result<()> {
+        self.call_ok(&Request::SetMtime {
+            path: path.to_path_buf(),
+            mtime_ns: mtime_ns(mtime),
+        })
+    }
+
+    fn rename(&mut self, from: &Path, to: &Path) -> io::Result<()> {
+        self.call_ok(&Request::Rename {
+            from: from.to_path_buf(),
+            to: to.to_path_buf(),
+        })
+    }
+
+    fn remove(&mut self, path: &Path) -> io::Result<()> {
+        self.call_ok(&Request::Remove { path: path.to_path_buf() })
+    }
+
+    fn list(&mut self) -> io::Result<Vec<PathBuf>> {
+        match self.call(&Request::List, &[])?.0 {
+            Response::List { paths } => Ok(paths),
+            other => Err(unexpected(&other)),
+        }
+    }
+}
+
+/// Make the tree behind `transport` match a scan of the source
+///
+/// Files missing from the destination or differing in size or whole-second
+/// modification time are sent with [`push_file`] and get the source's
+/// modification time. With `delete_removed`, destination files the scan
+/// doesn't have are removed. Comparing by size and time keeps the exchange
+/// to one `stat` per file, so content is never read back.
+///
+/// `options` are the ones the source was scanned with. A destination file
+/// they filter out at the source, such as anything under `.git`, matched by
+/// an ignore file or outside the subpath, is outside the sync and never
+/// removed.
+pub fn sync_to_transport(
+    source: &ScanResult,
+    options: &ScanOptions,
+    transport: &mut dyn Transport,
+    delete_removed: bool,
+) -> io::Result<SyncReport> {
+    let mut report = SyncReport::default();
+    for file in &source.files {
+        let current = transport.stat(&file.path)?;
+        let unchanged = current.is_some_and(|stat| {
+            !stat.is_dir
+                && stat.size == file.size
+                && stat.mtime_ns / 1_000_000_000 == mtime_ns(file.mtime) / 1_000_000_000
+        });
+        if unchanged {
+            continue;
+        }
+        let source_path = source.root.join(&file.path);
+        report.bytes_copied += push_file(transport, &source_path, &file.path, Some(file.mtime))?;
+        report.files_copied += 1;
+    }
+
+    if delete_removed {
+        let wanted: HashSet<&Path> = source.files.iter().map(|file| file.path.as_path()).collect();
+        for path in transport.list()? {
+            let outside_subpath =
+                options.subpath.as_ref().is_some_and(|sub| !path.starts_with(sub));
+            if wanted.contains(path.as_path()) || outside_subpath {
+                continue;
+            }
+            let decision = explain_filter(&source.root, &path, options)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+            if decision.included {
+                transport.remove(&path)?;
+                report.files_deleted += 1;
+            }
+        }
+    }
+    Ok(report)
+}
+
+/// Answer requests from `reader` on `writer` until the input ends
+///
+/// The server side of the protocol, run by `jan --server ROOT`, operating
+/// on the tree at `root`. A failed operation is reported to the client
+/// rather than ending the loop.
+///
+/// `root` is a jail. Every operation resolves its path one component at a
+/// time from a handle on the root directory, never following a symlink, and
+/// then acts on the handle it resolved, so swapping a directory for a
+/// symlink mid-request can't redirect it outside the root. Absolute paths,
+/// `..` components and paths through symlinks are refused with a
+/// `permission_denied` error. A symlink in the last component can still be
+/// removed, or replaced by a rename, without touching its target. Needs a
+/// Unix system.
+pub fn serve(root: &Path, mut reader: impl Read, mut writer: impl Write) -> io::Result<()> {
+    if !root.is_dir() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("{} is not a directory", root.display()),
+        ));
+    }
+    #[cfg(unix)]
+    {
+        let jail = Jail::open(root)?;
+        while let Some(request) = read_message::<Request>(&mut reader)? {
+            // A write's content follows it whether or not the write succeeds
+            let payload = match &request {
+                Request::WriteRange { len, .. } => read_payload(&mut reader, *len)?,
+                _ => Vec::new(),
+            };
+            let (response, data) = match handle_request(&jail, request, &payload) {
+                Ok(answer) => answer,
+                Err(e) => (Response::error(&e), Vec::new()),
+            };
+            write_message(&mut writer, &response, &data)?;
+        }
+        Ok(())
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = (&mut reader, &mut writer);
+        Err(io::Error::new(io::ErrorKind::Unsupported, "server mode needs a Unix system"))
+    }
+}
+
+/// Carry out one request, returning the response and any content to send
+#[cfg(unix)]
+fn handle_request(
+    jail: &Jail,
+    request: Request,
+    payload: &[u8],
+) -> io::Result<(Response, Vec<u8>)> {
+    let response = match request {
+        Request::Stat { path } => Response::Stat { stat: jail.stat(&path)? },
+        Request::ReadRange { path, offset, len } => {
+            let mut file = jail.open_file(&path, libc::O_RDONLY)?;
+            file.seek(SeekFrom::Start(offset))?;
+            let mut data = Vec::new();
+            file.take(len.min(MAX_PAYLOAD_SIZE)).read_to_end(&mut data)?;
+            return Ok((Response::Data { len: data.len() as u64 }, data));
+        },
+        Request::WriteRange { path, offset, .. } => {
+            let truncate = if offset == 0 { libc::O_TRUNC } else { 0 };
+            let mut file = jail.open_file(&path, libc::O_WRONLY | libc::O_CREAT | truncate)?;
+            file.seek(SeekFrom::Start(offset))?;
+            file.write_all(payload)?;
+            Response::Ok
+        },
+        Request::SetMtime { path, mtime_ns } => {
+            let file = jail.open_file(&path, libc::O_RDONLY)?;
+            file.set_modified(UNIX_EPOCH + Duration::from_nanos(mtime_ns))?;
+            Response::Ok
+        },
+        Request::Rename { from, to } => {
+            jail.rename(&from, &to)?;
+            Response::Ok
+        },
+        Request::Remove { path } => {
+            jail.remove(&path)?;
+            Response::Ok
+        },
+        Request::List => {
+            let mut paths = Vec::new();
+            jail.list(&jail.root, Path::new(""), &mut paths)?;
+            paths.sort();
+            Response::List { paths }
+        },
+    };
+    Ok((response, Vec::new()))
+}
+
+/// Handle on the server's root that every request path is resolved from
+///
+/// Paths are walked with `openat(O_NOFOLLOW)` one directory at a time and
+/// the final operation uses the `*at` call on the last directory's handle,
+/// so there is no window between checking a path and using it.
+#[cfg(unix)]
+struct Jail {
+    root: OwnedFd,
+}
+
+#[cfg(unix)]
+impl Jail {
+    fn open(root: &Path) -> io::Result<Self> {
+        let root = CString::new(root.as_os_str().as_bytes())?;
+        let flags = libc::O_RDONLY | libc::O_DIRECTORY | libc::O_CLOEXEC;
+        // SAFETY: `root` is a valid C string
+        let fd = unsafe { libc::open(root.as_ptr(), flags) };
+        Ok(Self { root: owned_fd(fd)? })
+    }
+
+    /// Handle on the directory holding the last component of `path`, and that component
+    ///
+    /// With `create`, missing directories on the way are created.
+    fn parent(&self, path: &Path, create: bool) -> io::Result<(OwnedFd, CString)> {
+        let mut names = Vec::new();
+        for component in path.components() {
+            match component {
+                Component::Normal(name) => names.push(CString::new(name.as_bytes())?),
+                Component::CurDir => {},
+                _ => return Err(outside_root(path)),
+            }
+        }
+        let Some(last) = names.pop() else {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "path names the root"));
+        };
+
+        let mut dir = self.root.try_clone()?;
+        for name in &names {
+            let flags = libc::O_RDONLY | libc::O_DIRECTORY | libc::O_NOFOLLOW | libc::O_CLOEXEC;
+            // SAFETY: `dir` is an open directory and `name` a valid C string
+            let mut fd = unsafe { libc::openat(dir.as_raw_fd(), name.as_ptr(), flags) };
+            if fd < 0 && create && io::Error::last_os_error().raw_os_error() == Some(libc::ENOENT) {
+                // SAFETY: as above
+                if unsafe { libc::mkdirat(dir.as_raw_fd(), name.as_ptr(), 0o777) } < 0 {
+                    let error = io::Error::last_os_error();
+                    if error.raw_os_error() != Some(libc::EEXIST) {
+                        return Err(error);
+                    }
+                }
+                // SAFETY: as above
+                fd = unsafe { libc::openat(dir.as_raw_fd(), name.as_ptr(), flags) };
+            }
+            dir = owned_fd(fd).map_err(|e| refuse_symlink(&dir, name, path, e))?;
+        }
+        Ok((dir, last))
+    }
+
+    /// Size, modification time and type of `path`, without following a final symlink
+    fn stat(&self, path: &Path) -> io::Result<Option<RemoteStat>> {
+        let (dir, name) = match self.parent(path, false) {
+            Ok(parent) => parent,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        // SAFETY: `stat` is plain data that `fstatat` fills in
+        let mut stat: libc::stat = unsafe { std::mem::zeroed() };
+        // SAFETY: `dir` is an open directory and `name` a valid C string
+        let result = unsafe {
+            libc::fstatat(dir.as_raw_fd(), name.as_ptr(), &mut stat, libc::AT_SYMLINK_NOFOLLOW)
+        };
+        if result < 0 {
+            let error = io::Error::last_os_error();
+            return match error.kind() {
+                io::ErrorKind::NotFound => Ok(None),
+                _ => Err(error),
+            };
+        }
+        let mtime_ns = (stat.st_mtime as u64)
+            .saturating_mul(1_000_000_000)
+            .saturating_add(stat.st_mtime_nsec as u64);
+        Ok(Some(RemoteStat {
+            size: stat.st_size as u64,
+            mtime_ns,
+            is_dir: stat.st_mode & libc::S_IFMT == libc::S_IFDIR,
+        }))
+    }
+
+    /// Open the file at `path`, refusing a symlink in the last component
+    ///
+    /// With `O_CREAT` in `flags`, missing parent directories are created.
+    fn open_file(&self, path: &Path, flags: libc::c_int) -> io::Result<File> {
+        let (dir, name) = self.parent(path, flags & libc::O_CREAT != 0)?;
+        let flags = flags | libc::O_NOFOLLOW | libc::O_CLOEXEC;
+        // SAFETY: `dir` is an open directory and `name` a valid C string
+        let fd = unsafe { libc::openat(dir.as_raw_fd(), name.as_ptr(), flags, 0o666) };
+        let fd = owned_fd(fd).map_err(|e| refuse_symlink(&dir, &name, path, e))?;
+        Ok(File::from(fd))
+    }
+
+    /// Rename `from` to `to`, creating `to`'s parent directories
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        let (from_dir, from_name) = self.parent(from, false)?;
+        let (to_dir, to_name) = self.parent(to, true)?;
+        // SAFETY: both handles are open directories and both names valid C strings
+        let result = unsafe {
+            libc::renameat(
+                from_dir.as_raw_fd(),
+                from_name.as_ptr(),
+                to_dir.as_raw_fd(),
+                to_name.as_ptr(),
+            )
+        };
+        if result < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    /// Remove the file or symlink at `path`; a missing one is not an error
+    fn remove(&self, path: &Path) -> io::Result<()> {
+        let (dir, name) = match self.parent(path, false) {
+            Ok(parent) => parent,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        // SAFETY: `dir` is an open directory and `name` a valid C string
+        if unsafe { libc::unlinkat(dir.as_raw_fd(), name.as_ptr(), 0) } < 0 {
+            let error = io::Error::last_os_error();
+            if error.kind() != io::ErrorKind::NotFound {
+                return Err(error);
+            }
+        }
+        Ok(())
+    }
+
+    /// Add the files under the directory `dir` to `paths`, prefixed with `prefix`
+    ///
+    /// Symlinks are neither followed nor listed.
+    fn list(&self, dir: &OwnedFd, prefix: &Path, paths: &mut Vec<PathBuf>) -> io::Result<()> {
+        // `closedir` closes the handle it was given, so read from a duplicate
+        let handle = dir.try_clone()?.into_raw_fd();
+        // SAFETY: `handle` is an open directory that the stream takes over
+        let stream = unsafe { libc::fdopendir(handle) };
+        if stream.is_null() {
+            let error = io::Error::last_os_error();
+            // SAFETY: `fdopendir` failed, so `handle` is still ours to close
+            unsafe { libc::close(handle) };
+            return Err(error);
+        }
+        let mut subdirs = Vec::new();
+        loop {
+            // SAFETY: `stream` is an open directory stream
+            let entry = unsafe { libc::readdir(stream) };
+            if entry.is_null() {
+                break;
+            }
+            // SAFETY: `readdir` returned a valid entry, which stays valid until the next call
+            let name = unsafe { CStr::from_ptr((*entry).d_name.as_ptr()) }.to_owned();
+            if name.as_bytes() == b"." || name.as_bytes() == b".." {
+                continue;
+            }
+            // SAFETY: `stat` is plain data that `fstatat` fills in
+            let mut stat: libc::stat = unsafe { std::mem::zeroed() };
+            // SAFETY: `dir` is an open directory and `name` a valid C string
+            let result = unsafe {
+                libc::fstatat(dir.as_raw_fd(), name.as_ptr(), &mut stat, libc::AT_SYMLINK_NOFOLLOW)
+            };
+            if result < 0 {
+                continue;
+            }
+            match stat.st_mode & libc::S_IFMT {
+                libc::S_IFREG => paths.push(prefix.join(OsStr::from_bytes(name.as_bytes()))),
+                libc::S_IFDIR => subdirs.push(name),
+                _ => {},
+            }
+        }
+        // SAFETY: `stream` is open and not used afterwards
+        unsafe { libc::closedir(stream) };
+
+        for name in subdirs {
+            let flags = libc::O_RDONLY | libc::O_DIRECTORY | libc::O_NOFOLLOW | libc::O_CLOEXEC;
+            // SAFETY: `dir` is an open directory and `name` a valid C string
+            let fd = unsafe { libc::openat(dir.as_raw_fd(), name.as_ptr(), flags) };
+            // Replaced by something else since it was listed
+            let Ok(subdir) = owned_fd(fd) else {
+                continue;
+            };
+            let prefix = prefix.join(OsStr::from_bytes(name.as_bytes()));
+            self.list(&subdir, &prefix, paths)?;
+        }
+        Ok(())
+    }
+}
+
+/// Take ownership of a descriptor returned by a libc call, or its error
+#[cfg(unix)]
+fn owned_fd(fd: libc::c_int) -> io::Result<OwnedFd> {
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: a non-negative result is a fresh descriptor nothing else owns
+    Ok(unsafe { OwnedFd::from_raw_fd(fd) })
+}
+
+/// Turn the error of an `O_NOFOLLOW` open into a jail violation if it hit a symlink
+#[cfg(unix)]
+fn refuse_symlink(dir: &OwnedFd, name: &CStr, path: &Path, error: io::Error) -> io::Error {
+    if !matches!(error.raw_os_error(), Some(libc::ELOOP | libc::ENOTDIR | libc::EMLINK)) {
+        return error;
+    }
+    // SAFETY: `stat` is plain data that `fstatat` fills in
+    let mut stat: libc::stat = unsafe { std::mem::zeroed() };
+    // SAFETY: `dir` is an open directory and `name` a valid C string
+    let result = unsafe {
+        libc::fstatat(dir.as_raw_fd(), name.as_ptr(), &mut stat, libc::AT_SYMLINK_NOFOLLOW)
+    };
+    if result == 0 && stat.st_mode & libc::S_IFMT == libc::S_IFLNK {
+        outside_root(path)
+    } else {
+        error
+    }
+}
+
+/// Error for a request path that leaves the server's root
+fn outside_root(path: &Path) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::PermissionDenied,
+        format!("{}: outside the server root", path.display()),
+    )
+}
+
+/// Error for a response that doesn't answer the request
+fn unexpected(response: &Response) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("unexpected response: {response:?}"))
+}
+
+/// Send one message, followed by `payload`
+pub fn write_message<T: Serialize>(
+    writer: &mut impl Write,
+    message: &T,
+    payload: &[u8],
+) -> io::Result<()> {
+    let json = serde_json::to_vec(message)?;
+    if json.len() > MAX_MESSAGE_SIZE {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "message too large"));
+    }
+    writer.write_all(&(json.len() as u32).to_be_bytes())?;
+    writer.write_all(&json)?;
+    writer.write_all(payload)?;
+    writer.flush()
+}
+
+/// Receive one message, or `None` if the stream ended before it began
+pub fn read_message<T: DeserializeOwned>(reader: &mut impl Read) -> io::Result<Option<T>> {
+    let mut length = [0u8; 4];
+    match reader.read_exact(&mut length) {
+        Ok(()) => {},
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+    let length = u32::from_be_bytes(length) as usize;
+    if length > MAX_MESSAGE_SIZE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("message of {length} bytes exceeds the limit"),
+        ));
+    }
+    let mut json = vec![0u8; length];
+    reader.read_exact(&mut json)?;
+    Ok(Some(serde_json::from_slice(&json)?))
+}
+
+/// Receive the `len` bytes of content following a message
+pub fn read_payload(reader: &mut impl Read, len: u64) -> io::Result<Vec<u8>> {
+    if len > MAX_PAYLOAD_SIZE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("payload of {len} bytes exceeds the limit"),
+        ));
+    }
+    let mut payload = vec![0u8; len as usize];
+    reader.read_exact(&mut payload)?;
+    Ok(payload)
+}
+
+/// Name an error kind in an `error` response
+fn error_kind_name(kind: io::ErrorKind) -> &'static str {
+    match kind {
+        io::ErrorKind::NotFound => "not_found",
+        io::ErrorKind::PermissionDenied => "permission_denied",
+        io::ErrorKind::AlreadyExists => "already_exists",
+        io::ErrorKind::InvalidInput => "invalid_input",
+        io::ErrorKind::InvalidData => "invalid_data",
+        io::ErrorKind::UnexpectedEof => "unexpected_eof",
+        _ => "other",
+    }
+}
+
+/// The error kind an `error` response names
+fn error_kind(name: &str) -> io::ErrorKind {
+    match name {
+        "not_found" => io::ErrorKind::NotFound,
+        "permission_denied" => io::ErrorKind::PermissionDenied,
+        "already_exists" => io::ErrorKind::AlreadyExists,
+        "invalid_input" => io::ErrorKind::InvalidInput,
+        "invalid_data" => io::ErrorKind::InvalidData,
+        "unexpected_eof" => io::ErrorKind::UnexpectedEof,
+        _ => io::ErrorKind::Other,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+    use std::fs;
+
+    /// Content and mtime of each file the mock endpoint holds
+    type MockFiles = BTreeMap<PathBuf, (Vec<u8>, u64)>;
+
+    /// Stands in for a privileged helper: keeps files in memory and logs
+    /// every operation it performs
+    fn mock_endpoint(mut reader: impl Read, mut writer: impl Write) -> (MockFiles, Vec<String>) {
+        let mut files = MockFiles::new();
+        let mut log = Vec::new();
+        let not_found = |path: &Path| {
+            Response::error(&io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("{}: no such file", path.display()),
+            ))
+        };
+        while let Some(request) = read_message::<Request>(&mut reader).unwrap() {
+            log.push(format!("{request:?}").split_whitespace().next().unwrap().to_string());
+            let mut payload = Vec::new();
+            let response = match request {
+                Request::Stat { path } => Response::Stat {
+                    stat: files.get(&path).map(|(content, mtime_ns)| RemoteStat {
+                        size: content.len() as u64,
+                        mtime_ns: *mtime_ns,
+                        is_dir: false,
+                    }),
+                },
+                Request::ReadRange { path, offset, len } => match files.get(&path) {
+                    Some((content, _)) => {
+                        let start = (offset as usize).min(content.len());
+                        let end = (start + len as usize).min(content.len());
+                        payload = content[start..end].to_vec();
+                        Response::Data { len: payload.len() as u64 }
+                    },
+                    None => not_found(&path),
+                },
+                Request::WriteRange { path, offset, len } => {
+                    let bytes = read_payload(&mut reader, len).unwrap();
+                    let (content, _) = files.entry(path).or_default();
+                    content.truncate(offset as usize);
+                    content.extend_from_slice(&bytes);
+                    Response::Ok
+                },
+                Request::SetMtime { path, mtime_ns } => match files.get_mut(&path) {
+                    Some(file) => {
+                        file.1 = mtime_ns;
+                        Response::Ok
+                    },
+                    None => not_found(&path),
+                },
+                Request::Rename { from, to } => match files.remove(&from) {
+                    Some(file) => {
+                        files.insert(to, file);
+                        Response::Ok
+                    },
+                    None => not_found(&from),
+                },
+                Request::Remove { path } => match files.remove(&path) {
+                    Some(_) => Response::Ok,
+                    None => not_found(&path),
+                },
+                Request::List => Response::List { paths: files.keys().cloned().collect() },
+            };
+            write_message(&mut writer, &response, &payload).unwrap();
+        }
+        (files, log)
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_pipe_transport_with_mock_endpoint() {
+        use std::os::unix::net::UnixStream;
+
+        let (client, server) = UnixStream::pair().unwrap();
+        let endpoint =
+            std::thread::spawn(move || mock_endpoint(server.try_clone().unwrap(), server));
+
+        let dir = tempfile::TempDir::new().unwrap();
+        let source = dir.path().join("big.bin");
+        let content: Vec<u8> = (0..PUSH_CHUNK_SIZE * 2 + 10).map(|i| i as u8).collect();
+        std::fs::write(&source, &content).unwrap();
+        let empty = dir.path().join("empty");
+        std::fs::write(&empty, b"").unwrap();
+        let mtime = UNIX_EPOCH + Duration::from_nanos(1_700_000_000_123_456_789);
+
+        let mut transport = PipeTransport::new(client.try_clone().unwrap(), client);
+        let dest = Path::new("backup/big.bin");
+        assert_eq!(
+            push_file(&mut transport, &source, dest, Some(mtime)).unwrap(),
+            content.len() as u64
+        );
+        assert_eq!(push_file(&mut transport, &empty, Path::new("empty"), None).unwrap(), 0);
+
+        let stat = transport.stat(dest).unwrap().unwrap();
+        assert_eq!((stat.size, stat.mtime(), stat.is_dir), (content.len() as u64, mtime, false));
+        assert_eq!(transport.stat(Path::new("missing")).unwrap(), None);
+        assert_eq!(transport.read_range(dest, 5, 3).unwrap(), content[5..8]);
+        assert_eq!(
+            transport.list().unwrap(),
+            [PathBuf::from("backup/big.bin"), PathBuf::from("empty")]
+        );
+
+        // Errors keep their kind across the pipe
+        let error = transport.remove(Path::new("missing")).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::NotFound);
+        assert!(error.to_string().contains("missing: no such file"));
+        transport.remove(Path::new("empty")).unwrap();
+
+        drop(transport);
+        let (files, log) = endpoint.join().unwrap();
+        assert_eq!(files.keys().collect::<Vec<_>>(), [Path::new("backup/big.bin")]);
+        assert_eq!(files[dest].0, content);
+        // Three chunks into the temp file, then its mtime, then the rename
+        assert_eq!(log[..5], ["WriteRange", "WriteRange", "WriteRange", "SetMtime", "Rename"]);
+    }
+
+    /// Send `requests` to a server for `root` and collect its responses
+    fn call_server(root: &Path, requests: &[(Request, &[u8])]) -> Vec<Response> {
+        let mut input = Vec::new();
+        for (request, payload) in requests {
+            write_message(&mut input, request, payload).unwrap();
+        }
+        let mut output = Vec::new();
+        serve(root, io::Cursor::new(input), &mut output).unwrap();
+
+        let mut output = io::Cursor::new(output);
+        let mut responses = Vec::new();
+        while let Some(response) = read_message::<Response>(&mut output).unwrap() {
+            if let Response::Data { len } = response {
+                read_payload(&mut output, len).unwrap();
+            }
+            responses.push(response);
+        }
+        responses
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_server_rejects_paths_outside_root() {
+        use std::os::unix::fs::symlink;
+
+        let dir = tempfile::TempDir::new().unwrap();
+        let root = dir.path().join("root");
+        let outside = dir.path().join("outside");
+        fs::create_dir_all(root.join("sub")).unwrap();
+        fs::create_dir(&outside).unwrap();
+        fs::write(outside.join("secret.txt"), b"secret").unwrap();
+        fs::write(root.join("sub/file.txt"), b"inside").unwrap();
+        symlink(&outside, root.join("escape")).unwrap();
+        symlink(outside.join("secret.txt"), root.join("leak.txt")).unwrap();
+        symlink(outside.join("planted.txt"), root.join("dangling.txt")).unwrap();
+        symlink(root.join("sub"), root.join("alias")).unwrap();
+
+        let read = |path: &str| Request::ReadRange {
+            path: PathBuf::from(path),
+            offset: 0,
+            len: 64,
+        };
+        let write = |path: &str| Request::WriteRange {
+            path: PathBuf::from(path),
+            offset: 0,
+            len: 4,
+        };
+        let attempts = [
+            (write("../outside/planted.txt"), &b"evil"[..]),
+            (read("sub/../../outside/secret.txt"), &[][..]),
+            (read(outside.join("secret.txt").to_str().unwrap()), &[][..]),
+            (write("/tmp/planted.txt"), &b"evil"[..]),
+            (read("escape/secret.txt"), &[][..]),
+            (write("escape/planted.txt"), &b"evil"[..]),
+            (read("leak.txt"), &[][..]),
+            (write("dangling.txt"), &b"evil"[..]),
+            (
+                Request::Rename {
+                    from: PathBuf::from("sub/file.txt"),
+                    to: PathBuf::from("escape/moved.txt"),
+                },
+                &[][..],
+            ),
+            (Request::Remove { path: PathBuf::from("escape/secret.txt") }, &[][..]),
+            (Request::Stat { path: PathBuf::from("..") }, &[][..]),
+        ];
+        let responses = call_server(&root, &attempts);
+
+        assert_eq!(responses.len(), attempts.len());
+        for ((request, _), response) in attempts.iter().zip(&responses) {
+            assert!(
+                matches!(response, Response::Error { kind, .. } if kind == "permission_denied"),
+                "{request:?} answered with {response:?}"
+            );
+        }
+        assert_eq!(fs::read_dir(&outside).unwrap().count(), 1, "nothing was written outside");
+        assert_eq!(fs::read(outside.join("secret.txt")).unwrap(), b"secret");
+        assert!(root.join("sub/file.txt").exists());
+
+        // Symlinks aren't followed even when they stay inside the root, but
+        // one in the last component can be removed without touching its target
+        let remove = |path: &str| Request::Remove { path: PathBuf::from(path) };
+        let responses = call_server(
+            &root,
+            &[
+                (read("sub/file.txt"), &[][..]),
+                (read("alias/file.txt"), &[][..]),
+                (remove("dangling.txt"), &[][..]),
+                (remove("leak.txt"), &[][..]),
+            ],
+        );
+        assert_eq!(responses[0], Response::Data { len: 6 });
+        assert!(
+            matches!(&responses[1], Response::Error { kind, .. } if kind == "permission_denied")
+        );
+        assert_eq!(responses[2..], [Response::Ok, Response::Ok]);
+        assert!(fs::symlink_metadata(root.join("dangling.txt")).is_err());
+        assert!(fs::symlink_metadata(root.join("leak.txt")).is_err());
+        assert_eq!(fs::read(outside.join("secret.txt")).unwrap(), b"secret");
+    }
+
+    #[test]
+    fn test_privileged_helper_command() {
+        let command = PrivilegeHelper::Sudo.command(Path::new("/usr/bin/jan"), Path::new("/srv"));
+        assert_eq!(command.get_program(), "sudo");
+        let args: Vec<_> = command.get_args().collect();
+        assert_eq!(args, ["--", "/usr/bin/jan", "--server", "/srv"]);
+
+        let command = PrivilegeHelper::Pkexec.command(Path::new("/usr/bin/jan"), Path::new("/srv"));
+        assert_eq!(command.get_program(), "pkexec");
+        assert_eq!(command.get_args().collect::<Vec<_>>(), ["/usr/bin/jan", "--server", "/srv"]);
+    }
+}
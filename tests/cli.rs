@@ -0,0 +1,481 @@
+//! End-to-end tests for the `jan` command-line interface
+
+use std::fs;
+use std::path::Path;
+use std::process::{Command, Output};
+use std::time::Duration;
+
+use janus::io::set_file_mtime;
+use janus::{hash_bytes, ScanResult};
+use tempfile::TempDir;
+
+/// Run the `jan` binary with the given arguments
+fn jan(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_jan"))
+        .args(args)
+        .output()
+        .expect("failed to run jan")
+}
+
+/// Helper to create a test file with content
+fn create_file(dir: &Path, rel_path: &str, content: &[u8]) {
+    let path = dir.join(rel_path);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).unwrap();
+    }
+    fs::write(path, content).unwrap();
+}
+
+#[test]
+fn test_same_source_and_dest() {
+    let dir = TempDir::new().unwrap();
+    create_file(dir.path(), "file.txt", b"content");
+    let before = fs::metadata(dir.path().join("file.txt")).unwrap().modified().unwrap();
+
+    let path = dir.path().to_str().unwrap();
+    let nested = dir.path().join(".").join("");
+    let output = jan(&[path, nested.to_str().unwrap(), "-y"]);
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).contains("identical"));
+
+    let output = jan(&[path, path, "-y", "--delete"]);
+    assert!(!output.status.success(), "--delete on the same directory should fail");
+    assert!(String::from_utf8_lossy(&output.stderr).contains("same directory"));
+
+    // Nothing was touched either way
+    assert_eq!(fs::read(dir.path().join("file.txt")).unwrap(), b"content");
+    let after = fs::metadata(dir.path().join("file.txt")).unwrap().modified().unwrap();
+    assert_eq!(before, after);
+}
+
+#[test]
+fn test_scan_stats() {
+    let dir = TempDir::new().unwrap();
+    create_file(dir.path(), "small.txt", b"tiny");
+    create_file(dir.path(), "nested/big.bin", &vec![0u8; 4096]);
+
+    let output = jan(&["scan", dir.path().to_str().unwrap(), "--stats"]);
+    assert!(output.status.success());
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.starts_with("2 files"));
+    assert!(stdout.contains("< 1 KiB"));
+    assert!(stdout.contains("bin"));
+    assert!(stdout.contains("txt"));
+}
+
+#[test]
+fn test_modify_window() {
+    let source = TempDir::new().unwrap();
+    let dest = TempDir::new().unwrap();
+    create_file(source.path(), "file.txt", b"aaaa");
+    create_file(dest.path(), "file.txt", b"bbbb");
+
+    // Same size, mtimes one second apart
+    let mtime = fs::metadata(source.path().join("file.txt")).unwrap().modified().unwrap();
+    set_file_mtime(&dest.path().join("file.txt"), mtime + Duration::from_secs(1)).unwrap();
+
+    let src = source.path().to_str().unwrap();
+    let dst = dest.path().to_str().unwrap();
+
+    let output = jan(&[src, dst, "-n", "--modify-window", "2"]);
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).contains("In sync"));
+
+    // Outside the window the content hashes decide
+    let output = jan(&[src, dst, "-n", "--modify-window", "0"]);
+    assert!(String::from_utf8_lossy(&output.stdout).contains("1 copy"));
+
+    // --checksum ignores the window entirely
+    let output = jan(&[src, dst, "-n", "--modify-window", "2", "--checksum"]);
+    assert!(String::from_utf8_lossy(&output.stdout).contains("1 copy"));
+}
+
+#[test]
+#[cfg(unix)]
+fn test_sigint_finishes_current_file() {
+    use std::process::Stdio;
+    use std::thread;
+    use std::time::Instant;
+
+    let source = TempDir::new().unwrap();
+    let dest = TempDir::new().unwrap();
+    let content = vec![0x5au8; 256 * 1024];
+    for i in 0..400 {
+        create_file(source.path(), &format!("file{i:03}.bin"), &content);
+    }
+
+    let child = Command::new(env!("CARGO_BIN_EXE_jan"))
+        .args([source.path().to_str().unwrap(), dest.path().to_str().unwrap(), "-y", "-j", "1"])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    // Wait for the sync phase to start writing, then interrupt it
+    let deadline = Instant::now() + Duration::from_secs(60);
+    while fs::read_dir(dest.path()).unwrap().next().is_none() && Instant::now() < deadline {
+        thread::sleep(Duration::from_millis(1));
+    }
+    // SAFETY: sending a signal to our own child process
+    unsafe {
+        libc::kill(child.id() as libc::pid_t, libc::SIGINT);
+    }
+    let output = child.wait_with_output().unwrap();
+
+    // The run may legitimately finish before the signal lands
+    if output.status.code() == Some(130) {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains("Interrupted;"), "unexpected stderr: {stderr}");
+        assert!(stderr.contains("of 400 operations completed"));
+    } else {
+        assert!(output.status.success());
+    }
+
+    // Whatever landed is complete, never truncated mid-write
+    for entry in fs::read_dir(dest.path()).unwrap() {
+        assert_eq!(fs::read(entry.unwrap().path()).unwrap(), content);
+    }
+}
+
+#[test]
+fn test_scan_list() {
+    let dir = TempDir::new().unwrap();
+    create_file(dir.path(), "src/lib.rs", b"pub fn lib() {}");
+    create_file(dir.path(), ".git/HEAD", b"ref: refs/heads/main");
+    create_file(dir.path(), ".gitignore", b"*.log\n");
+    create_file(dir.path(), "debug.log", b"noise");
+
+    let path = dir.path().to_str().unwrap();
+    let output = jan(&["scan", path, "--list-only"]);
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("src/lib.rs") || stdout.contains("src\\lib.rs"));
+    assert!(stdout.contains(".gitignore"));
+    assert!(!stdout.contains("HEAD"), "VCS directories are excluded by default");
+    assert!(!stdout.contains("debug.log"), "gitignored files are excluded");
+
+    let output = jan(&["scan", path, "--list", "--hash", "--include-vcs"]);
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("HEAD"));
+    assert!(stdout.contains(&janus::hash_bytes(b"pub fn lib() {}").to_string()));
+}
+
+#[test]
+fn test_checksum_cache() {
+    let source = TempDir::new().unwrap();
+    let dest = TempDir::new().unwrap();
+    let cache_dir = TempDir::new().unwrap();
+    create_file(source.path(), "file.txt", b"content");
+    let cache = cache_dir.path().join("hashes.json");
+
+    let src = source.path().to_str().unwrap();
+    let dst = dest.path().to_str().unwrap();
+    let cache_arg = cache.to_str().unwrap();
+
+    let output =
+        jan(&[src, dst, "-y", "--checksum-cache", cache_arg, "--checksum-cache-ttl", "60"]);
+    assert!(output.status.success());
+    assert!(cache.exists(), "cache is written after scanning");
+    assert_eq!(fs::read(dest.path().join("file.txt")).unwrap(), b"content");
+
+    let output = jan(&[src, dst, "-y", "--checksum-cache", cache_arg]);
+    assert!(String::from_utf8_lossy(&output.stdout).contains("In sync"));
+
+    let output = jan(&[src, dst, "--checksum-cache-ttl", "60"]);
+    assert!(!output.status.success(), "--checksum-cache-ttl requires --checksum-cache");
+}
+
+#[test]
+fn test_manifest_reuses_hashes() {
+    let source = TempDir::new().unwrap();
+    let dest = TempDir::new().unwrap();
+    let state = TempDir::new().unwrap();
+    create_file(source.path(), "file.txt", b"version 1");
+    let manifest = state.path().join("manifest.json");
+
+    let src = source.path().to_str().unwrap();
+    let dst = dest.path().to_str().unwrap();
+    let manifest_arg = manifest.to_str().unwrap();
+
+    let output = jan(&[src, dst, "-y", "--manifest", manifest_arg]);
+    assert!(output.status.success());
+    assert!(manifest.exists(), "manifest is written after a successful run");
+
+    // Tamper with the recorded hash: the file itself is untouched, so only a
+    // run that trusts the manifest sees a difference
+    let mut scan = ScanResult::load_from_file(&manifest).unwrap();
+    scan.files[0].hash = hash_bytes(b"version 2");
+    scan.save_to_file(&manifest).unwrap();
+
+    let output = jan(&[src, dst, "-n", "--manifest", manifest_arg]);
+    assert!(
+        String::from_utf8_lossy(&output.stdout).contains("1 copy"),
+        "second run reuses the cached hash from the manifest"
+    );
+
+    let output = jan(&[src, dst, "-n"]);
+    assert!(String::from_utf8_lossy(&output.stdout).contains("In sync"));
+}
+
+#[test]
+#[cfg(unix)]
+fn test_owner_filter() {
+    use std::os::unix::fs::MetadataExt;
+
+    let source = TempDir::new().unwrap();
+    let dest = TempDir::new().unwrap();
+    create_file(source.path(), "mine.txt", b"mine");
+    let uid = fs::metadata(source.path().join("mine.txt")).unwrap().uid();
+    let (src, dst) = (source.path().to_str().unwrap(), dest.path().to_str().unwrap());
+
+    let other = (uid + 1).to_string();
+    let output = jan(&[src, dst, "-y", "--owner", &other]);
+    assert!(output.status.success());
+    assert!(!dest.path().join("mine.txt").exists());
+
+    let output = jan(&[src, dst, "-y", "--owner", &uid.to_string()]);
+    assert!(output.status.success());
+    assert_eq!(fs::read(dest.path().join("mine.txt")).unwrap(), b"mine");
+
+    let output = jan(&[src, dst, "-y", "--group", "no-such-group-janus"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Unknown group"));
+}
+
+#[test]
+fn test_dir_hashes() {
+    let source = TempDir::new().unwrap();
+    let dest = TempDir::new().unwrap();
+    create_file(source.path(), "same/file.txt", b"unchanged");
+    create_file(source.path(), "edited/file.txt", b"before");
+    let (src, dst) = (source.path().to_str().unwrap(), dest.path().to_str().unwrap());
+    assert!(jan(&[src, dst, "-qy", "--dir-hashes"]).status.success());
+
+    create_file(source.path(), "edited/file.txt", b"after!");
+    create_file(source.path(), "edited/new.txt", b"new");
+    let output = jan(&[src, dst, "-y", "--dir-hashes"]);
+    assert!(output.status.success());
+    assert_eq!(fs::read(dest.path().join("edited/file.txt")).unwrap(), b"after!");
+    assert_eq!(fs::read(dest.path().join("edited/new.txt")).unwrap(), b"new");
+    assert_eq!(fs::read(dest.path().join("same/file.txt")).unwrap(), b"unchanged");
+}
+
+#[test]
+fn test_conflicting_options_fail_early() {
+    let source = TempDir::new().unwrap();
+    let dest = TempDir::new().unwrap();
+    create_file(source.path(), "file.txt", b"content");
+
+    let (src, dst) = (source.path().to_str().unwrap(), dest.path().to_str().unwrap());
+    let output = jan(&[src, dst, "-y", "--clock-skew", "30"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("conflicting options"));
+    assert!(!dest.path().join("file.txt").exists());
+}
+
+#[test]
+fn test_dry_run_from_remote_manifest() {
+    let source = TempDir::new().unwrap();
+    let remote = TempDir::new().unwrap();
+    let state = TempDir::new().unwrap();
+    create_file(source.path(), "report.txt", b"0123456789");
+    create_file(remote.path(), "old.txt", b"old");
+
+    let manifest = state.path().join("remote.json");
+    janus::scan_directory(remote.path(), None)
+        .unwrap()
+        .save_to_file(&manifest)
+        .unwrap();
+    let src = source.path().to_str().unwrap();
+    let unreachable = state.path().join("not-mounted");
+
+    let output = jan(&[
+        src,
+        unreachable.to_str().unwrap(),
+        "-n",
+        "-d",
+        "--dest-manifest",
+        manifest.to_str().unwrap(),
+    ]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("copy     report.txt (10 B)"), "{stdout}");
+    assert!(stdout.contains("delete   old.txt"), "{stdout}");
+    assert!(stdout.contains("Would transfer 10 B"), "{stdout}");
+    assert!(!unreachable.exists());
+}
+
+#[test]
+fn test_failed_sync_drops_dest_manifest() {
+    let source = TempDir::new().unwrap();
+    let dest = TempDir::new().unwrap();
+    let state = TempDir::new().unwrap();
+    create_file(source.path(), "file.txt", b"content");
+    fs::create_dir(source.path().join("empty")).unwrap();
+    create_file(dest.path(), "empty", b"a file where the directory goes");
+    let (src, dst) = (source.path().to_str().unwrap(), dest.path().to_str().unwrap());
+    let manifest = state.path().join("dest.json");
+    let manifest_arg = manifest.to_str().unwrap();
+
+    let output = jan(&[src, dst, "-qy", "--dest-manifest", manifest_arg]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(manifest.exists());
+
+    // Replicating the empty directory fails after the file was copied
+    create_file(source.path(), "file.txt", b"changed");
+    let output =
+        jan(&[src, dst, "-qy", "--dest-manifest", manifest_arg, "--replicate-empty-parents"]);
+    assert!(!output.status.success());
+    assert!(!manifest.exists(), "a failed sync must not leave a trusted manifest");
+}
+
+#[test]
+fn test_kept_paths_keep_dest_manifest() {
+    let source = TempDir::new().unwrap();
+    let dest = TempDir::new().unwrap();
+    let state = TempDir::new().unwrap();
+    create_file(source.path(), "file.txt", b"content");
+    create_file(dest.path(), "logs/run.log", b"dest only");
+    let (src, dst) = (source.path().to_str().unwrap(), dest.path().to_str().unwrap());
+    let manifest = state.path().join("dest.json");
+    let manifest_arg = manifest.to_str().unwrap();
+
+    let args = [
+        src,
+        dst,
+        "-qy",
+        "--delete",
+        "--keep",
+        "logs/**",
+        "--dest-manifest",
+        manifest_arg,
+    ];
+    let output = jan(&args);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(manifest.exists(), "kept paths must not drop the manifest");
+    let saved = janus::ScanResult::load_from_file(&manifest).unwrap();
+    let paths: Vec<_> = saved.files.iter().map(|f| f.path.to_str().unwrap()).collect();
+    assert_eq!(paths, ["file.txt", "logs/run.log"]);
+}
+
+#[test]
+fn test_json_error_output() {
+    let source = TempDir::new().unwrap();
+    let dest = TempDir::new().unwrap();
+    create_file(source.path(), "file.txt", b"content");
+    let (src, dst) = (source.path().to_str().unwrap(), dest.path().to_str().unwrap());
+
+    let wrong = hash_bytes(b"some other tree").to_string();
+    let output = jan(&[src, dst, "-qy", "--expect-hash", &wrong, "--json"]);
+    assert!(!output.status.success());
+    let error: serde_json::Value = serde_json::from_slice(&output.stderr).unwrap();
+    assert_eq!(error["error"]["kind"], "tree_hash_mismatch");
+    assert!(error["error"]["message"].as_str().unwrap().contains(&wrong));
+    assert!(error["error"]["path"].is_null());
+
+    let missing = source.path().join("missing");
+    let output = jan(&[missing.to_str().unwrap(), dst, "-n", "--json"]);
+    let error: serde_json::Value = serde_json::from_slice(&output.stderr).unwrap();
+    assert_eq!(error["error"]["kind"], "invalid_path");
+    assert_eq!(error["error"]["path"], missing.to_str().unwrap());
+
+    // Without --json errors stay human-readable
+    let output = jan(&[missing.to_str().unwrap(), dst, "-n"]);
+    assert!(String::from_utf8_lossy(&output.stderr).starts_with("Error: "));
+}
+
+#[test]
+fn test_profile() {
+    let source = TempDir::new().unwrap();
+    let dest = TempDir::new().unwrap();
+    create_file(source.path(), "file.txt", b"content");
+    create_file(dest.path(), "stale.txt", b"old");
+    let config_dir = TempDir::new().unwrap();
+    let config = config_dir.path().join("config.json");
+    let profile = serde_json::json!({
+        "profiles": {"mirror": {"source": source.path(), "dest": dest.path(), "delete": true}}
+    });
+    std::fs::write(&config, profile.to_string()).unwrap();
+    let config = config.to_str().unwrap();
+
+    let output = jan(&["--profile", "mirror", "--config", config, "-qy"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(dest.path().join("file.txt").exists());
+    assert!(!dest.path().join("stale.txt").exists());
+
+    // A switch's --no- form turns the profile's setting off for one run
+    create_file(dest.path(), "stale.txt", b"old");
+    let output = jan(&["--profile", "mirror", "--config", config, "-qy", "--no-delete"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(dest.path().join("stale.txt").exists());
+
+    let output = jan(&["--profile", "backup", "--config", config, "--json"]);
+    let error: serde_json::Value = serde_json::from_slice(&output.stderr).unwrap();
+    assert_eq!(error["error"]["kind"], "invalid_config");
+}
+
+#[test]
+fn test_audit() {
+    let dir = TempDir::new().unwrap();
+    let state = TempDir::new().unwrap();
+    create_file(dir.path(), "kept.txt", b"kept");
+    create_file(dir.path(), "edited.txt", b"before");
+    let manifest = state.path().join("manifest.json");
+    janus::scan_directory(dir.path(), None)
+        .unwrap()
+        .save_to_file(&manifest)
+        .unwrap();
+
+    let path = dir.path().to_str().unwrap();
+    let manifest_arg = manifest.to_str().unwrap();
+    let output = jan(&["audit", path, manifest_arg]);
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).contains("2 files ok, 0 problems"));
+
+    create_file(dir.path(), "edited.txt", b"after");
+    create_file(dir.path(), "new.txt", b"new");
+    let output = jan(&["audit", path, manifest_arg]);
+    assert!(!output.status.success(), "a mismatch fails the audit");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("edited.txt: size mismatch: expected 6 bytes, found 5"));
+    assert!(stdout.contains("new.txt: extra: not in manifest"));
+    assert!(!stdout.contains("kept.txt"), "matching files are only listed with --all");
+    assert!(stdout.contains("1 files ok, 2 problems"));
+
+    // The scan flags the manifest was written with apply to the audit too
+    create_file(dir.path(), ".git/HEAD", b"ref: refs/heads/main");
+    let options = janus::ScanOptions { include_vcs: true, ..Default::default() };
+    janus::scan_directory_with_options(dir.path(), &options, None)
+        .unwrap()
+        .save_to_file(&manifest)
+        .unwrap();
+    let output = jan(&["audit", path, manifest_arg]);
+    assert!(String::from_utf8_lossy(&output.stdout).contains(".git/HEAD: missing"));
+    let output = jan(&["audit", path, manifest_arg, "--include-vcs"]);
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).contains("4 files ok, 0 problems"));
+}
+
+#[test]
+#[cfg(unix)]
+fn test_server_mode() {
+    use janus::transport::{PipeTransport, Transport};
+
+    let source = TempDir::new().unwrap();
+    let dest = TempDir::new().unwrap();
+    create_file(source.path(), "file.txt", b"content");
+
+    let mut command = Command::new(env!("CARGO_BIN_EXE_jan"));
+    command.arg("--server").arg(dest.path());
+    let mut transport = PipeTransport::spawn(command).unwrap();
+    let scan = janus::scan_directory(source.path(), None).unwrap();
+    let report =
+        janus::sync_to_transport(&scan, &Default::default(), &mut transport, false).unwrap();
+    assert_eq!(report.files_copied, 1);
+    assert_eq!(transport.list().unwrap(), [Path::new("file.txt")]);
+    drop(transport);
+
+    assert_eq!(fs::read(dest.path().join("file.txt")).unwrap(), b"content");
+}
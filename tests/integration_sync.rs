@@ -1,10 +1,26 @@
 //! Integration tests for end-to-end sync operations
 
-use janus::core::{diff_scans, scan_directory, sync_changes, SyncOptions};
+use janus::cache::{CacheConfig, HashCache};
+use janus::core::{
+    classify_modifications, diff_scans, diff_scans_with_options, explain_filter, format_diff_tree,
+    plan_against_manifest, plan_reconcile, publish_via_swap, reconcile, sample_manifest_drift,
+    scan_directory, scan_directory_with_options, simulate_sync, sync_bidirectional, sync_changes,
+    sync_many_to_one, verify_destination, BidirectionalOptions, CaseConflictPolicy, CompareMode,
+    DeleteTiming, DiffOptions, DiffResult, DriftPolicy, FilterSource, HashPolicy, HashSkipRanges,
+    MismatchKind, OccupiedRenamePolicy, OperationKind, OperationResult, OverwriteStrategy,
+    ReconcileOptions, Resolution, ScanOptions, ScanOrder, ScanResult, ScanWarning, ScanWarningKind,
+    SkipReason, SyncError, SyncOptions, TransferOrder,
+};
+use janus::hash::hash_bytes;
+use janus::io::{same_filesystem, set_file_mtime};
+use janus::progress::{ProgressReporter, SyncStatus};
+use std::collections::HashSet;
 use std::fs;
 use std::path::{Path, PathBuf};
+use std::sync::atomic::AtomicBool;
+use std::sync::{Arc, Mutex};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use tempfile::TempDir;
 
 /// Helper to create a test file with content
@@ -23,6 +39,19 @@ fn assert_file_content(path: &Path, expected: &[u8]) {
     assert_eq!(actual, expected, "File content mismatch at {}", path.display());
 }
 
+/// Helper to set up one added, modified, renamed, removed, and unchanged file
+fn create_complex_scenario(source: &Path, dest: &Path) {
+    create_file(source, "added.txt", b"new file");
+    create_file(source, "modified.txt", b"updated content");
+    create_file(source, "renamed_new.txt", b"renamed content");
+    create_file(source, "unchanged.txt", b"same content");
+
+    create_file(dest, "modified.txt", b"old content");
+    create_file(dest, "renamed_old.txt", b"renamed content");
+    create_file(dest, "removed.txt", b"will be deleted");
+    create_file(dest, "unchanged.txt", b"same content");
+}
+
 #[test]
 fn test_basic_scan() {
     let temp_dir = TempDir::new().unwrap();
@@ -232,16 +261,7 @@ fn test_complex_sync_scenario() {
     let source = TempDir::new().unwrap();
     let dest = TempDir::new().unwrap();
 
-    // Setup complex scenario
-    create_file(source.path(), "added.txt", b"new file");
-    create_file(source.path(), "modified.txt", b"updated content");
-    create_file(source.path(), "renamed_new.txt", b"renamed content");
-    create_file(source.path(), "unchanged.txt", b"same content");
-
-    create_file(dest.path(), "modified.txt", b"old content");
-    create_file(dest.path(), "renamed_old.txt", b"renamed content");
-    create_file(dest.path(), "removed.txt", b"will be deleted");
-    create_file(dest.path(), "unchanged.txt", b"same content");
+    create_complex_scenario(source.path(), dest.path());
 
     let source_scan = scan_directory(source.path(), None).unwrap();
     let dest_scan = scan_directory(dest.path(), None).unwrap();
@@ -272,6 +292,43 @@ fn test_complex_sync_scenario() {
     assert_file_content(&dest.path().join("renamed_new.txt"), b"renamed content");
 }
 
+#[test]
+fn test_format_diff_tree() {
+    let source = TempDir::new().unwrap();
+    let dest = TempDir::new().unwrap();
+
+    create_complex_scenario(source.path(), dest.path());
+    create_file(source.path(), "docs/guide/intro.md", b"intro");
+    create_file(source.path(), "deep/a/b/c/d/e/f/g/h/leaf.txt", b"leaf");
+    for i in 0..30 {
+        create_file(source.path(), &format!("bulk/file{i:02}.txt"), format!("{i}").as_bytes());
+    }
+
+    let source_scan = scan_directory(source.path(), None).unwrap();
+    let dest_scan = scan_directory(dest.path(), None).unwrap();
+    let diff = diff_scans(&source_scan, &dest_scan).unwrap();
+    let tree = format_diff_tree(&diff);
+    let lines: Vec<&str> = tree.lines().collect();
+
+    assert!(lines.contains(&"+ added.txt"));
+    assert!(lines.contains(&"- removed.txt"));
+    assert!(lines.contains(&"~ modified.txt"));
+    assert!(lines.contains(&"→ renamed_new.txt (from renamed_old.txt)"));
+    assert!(!tree.contains("unchanged.txt"));
+
+    // Single-child directory chains collapse onto one line
+    let guide = lines.iter().position(|l| *l == "docs/guide/").unwrap();
+    assert_eq!(lines[guide + 1], "  + intro.md");
+    assert!(lines.contains(&"deep/a/b/c/d/e/f/g/h/"));
+    assert!(lines.contains(&"  + leaf.txt"));
+
+    // Large directories are truncated
+    assert!(lines.contains(&"bulk/"));
+    assert!(lines.contains(&"  + file24.txt"));
+    assert!(!lines.contains(&"  + file25.txt"));
+    assert!(lines.contains(&"  … 5 more changes"));
+}
+
 #[test]
 #[cfg(not(target_os = "windows"))]
 fn test_preserve_timestamps() {
@@ -393,3 +450,2145 @@ fn test_no_changes_sync() {
     // File should still exist and be unchanged
     assert_file_content(&dest.path().join("file.txt"), b"content");
 }
+
+#[test]
+#[cfg(not(target_os = "windows"))]
+fn test_sync_content_allowlist() {
+    let source = TempDir::new().unwrap();
+    let dest = TempDir::new().unwrap();
+
+    create_file(source.path(), "approved.bin", b"approved artifact");
+    create_file(source.path(), "unapproved.bin", b"unapproved artifact");
+
+    let source_scan = scan_directory(source.path(), None).unwrap();
+    let dest_scan = scan_directory(dest.path(), None).unwrap();
+    let diff = diff_scans(&source_scan, &dest_scan).unwrap();
+
+    let options = SyncOptions {
+        content_allowlist: Some([hash_bytes(b"approved artifact")].into_iter().collect()),
+        ..Default::default()
+    };
+    let report = sync_changes(source.path(), dest.path(), &diff, &options, None).unwrap();
+
+    assert_file_content(&dest.path().join("approved.bin"), b"approved artifact");
+    assert!(!dest.path().join("unapproved.bin").exists());
+
+    assert_eq!(report.files_copied, 1);
+    assert_eq!(report.skipped.len(), 1);
+    assert_eq!(report.skipped[0].path, PathBuf::from("unapproved.bin"));
+    assert_eq!(report.skipped[0].reason, SkipReason::NotAllowlisted);
+}
+
+#[test]
+fn test_sync_content_blocklist() {
+    let source = TempDir::new().unwrap();
+    let dest = TempDir::new().unwrap();
+
+    create_file(source.path(), "notes.txt", b"harmless notes");
+    create_file(source.path(), "secrets.env", b"API_KEY=hunter2");
+
+    let source_scan = scan_directory(source.path(), None).unwrap();
+    let dest_scan = scan_directory(dest.path(), None).unwrap();
+    let diff = diff_scans(&source_scan, &dest_scan).unwrap();
+
+    let blocklist: HashSet<_> = [hash_bytes(b"API_KEY=hunter2")].into_iter().collect();
+    let options = SyncOptions {
+        content_blocklist: Some(blocklist.clone()),
+        ..Default::default()
+    };
+    let report = sync_changes(source.path(), dest.path(), &diff, &options, None).unwrap();
+
+    assert_file_content(&dest.path().join("notes.txt"), b"harmless notes");
+    assert!(!dest.path().join("secrets.env").exists());
+    assert_eq!(report.files_copied, 1);
+    assert_eq!(report.skipped.len(), 1);
+    assert_eq!(report.skipped[0].path, PathBuf::from("secrets.env"));
+    assert_eq!(report.skipped[0].reason, SkipReason::Blocked);
+
+    // With fail_on_blocked nothing is written at all
+    let dest = TempDir::new().unwrap();
+    let options = SyncOptions {
+        content_blocklist: Some(blocklist),
+        fail_on_blocked: true,
+        ..Default::default()
+    };
+    let err = sync_changes(source.path(), dest.path(), &diff, &options, None).unwrap_err();
+    assert!(err.to_string().contains("secrets.env"));
+    assert!(!dest.path().join("notes.txt").exists());
+}
+
+/// Sync a modified file with the given strategy, returning the content seen
+/// through a hard link taken to the destination file before the sync
+#[cfg(unix)]
+fn content_via_hard_link(strategy: OverwriteStrategy) -> Vec<u8> {
+    let source = TempDir::new().unwrap();
+    let dest = TempDir::new().unwrap();
+
+    create_file(source.path(), "file.txt", b"new content");
+    let dest_file = create_file(dest.path(), "file.txt", b"old content");
+    let link = dest.path().join("link.txt");
+    fs::hard_link(&dest_file, &link).unwrap();
+
+    let source_scan = scan_directory(source.path(), None).unwrap();
+    let dest_scan = scan_directory(dest.path(), None).unwrap();
+    let diff = diff_scans(&source_scan, &dest_scan).unwrap();
+    assert_eq!(diff.modified.len(), 1);
+
+    let options = SyncOptions {
+        overwrite_strategy: strategy,
+        ..Default::default()
+    };
+    sync_changes(source.path(), dest.path(), &diff, &options, None).unwrap();
+
+    assert_file_content(&dest_file, b"new content");
+    fs::read(&link).unwrap()
+}
+
+#[test]
+#[cfg(unix)]
+fn test_overwrite_strategies() {
+    // In-place rewrites the existing inode, so the link sees the new content
+    assert_eq!(content_via_hard_link(OverwriteStrategy::InPlace), b"new content");
+
+    // The other strategies unlink the old inode before the new content lands
+    assert_eq!(content_via_hard_link(OverwriteStrategy::DeleteThenWrite), b"old content");
+    assert_eq!(content_via_hard_link(OverwriteStrategy::TempThenRename), b"old content");
+}
+
+#[test]
+#[cfg(unix)]
+fn test_temp_dir_on_other_filesystem_falls_back() {
+    let source = TempDir::new().unwrap();
+    let dest = TempDir::new().unwrap();
+    let scratch = TempDir::new().unwrap();
+    create_file(source.path(), "file.txt", b"new content");
+
+    let sync_with = |temp_dir: &Path| {
+        create_file(dest.path(), "file.txt", b"old content");
+        let source_scan = scan_directory(source.path(), None).unwrap();
+        let dest_scan = scan_directory(dest.path(), None).unwrap();
+        let diff = diff_scans(&source_scan, &dest_scan).unwrap();
+        let options = SyncOptions {
+            overwrite_strategy: OverwriteStrategy::TempThenRename,
+            temp_dir: Some(temp_dir.to_path_buf()),
+            ..Default::default()
+        };
+        let report = sync_changes(source.path(), dest.path(), &diff, &options, None).unwrap();
+        assert_file_content(&dest.path().join("file.txt"), b"new content");
+        assert_eq!(fs::read_dir(temp_dir).unwrap().count(), 0, "Temp file left behind");
+        report
+    };
+
+    assert!(sync_with(scratch.path()).warnings.is_empty());
+
+    // /dev/shm is a separate tmpfs on most Linux systems; the fallback
+    // itself is unit tested with an injected filesystem check
+    let Ok(other_fs) = tempfile::tempdir_in("/dev/shm") else {
+        eprintln!("skipping the cross-device case: /dev/shm is not available");
+        return;
+    };
+    if same_filesystem(other_fs.path(), dest.path()).unwrap() {
+        eprintln!("skipping the cross-device case: /dev/shm is on the destination's filesystem");
+        return;
+    }
+    let report = sync_with(other_fs.path());
+    assert_eq!(report.warnings.len(), 1);
+    assert!(report.warnings[0].contains("not on the destination's filesystem"));
+}
+
+#[test]
+#[cfg(not(target_os = "windows"))]
+fn test_publish_via_swap() {
+    let source = TempDir::new().unwrap();
+    let site = TempDir::new().unwrap();
+    let live = site.path().join("live");
+    let staging = site.path().join("staging");
+
+    create_file(&live, "index.html", b"old index");
+    create_file(&live, "stale.html", b"stale page");
+
+    create_file(source.path(), "index.html", b"new index");
+    create_file(source.path(), "about.html", b"about page");
+
+    publish_via_swap(source.path(), &live, &staging, &SyncOptions::default()).unwrap();
+
+    // Live holds exactly the new version
+    let live_scan = scan_directory(&live, None).unwrap();
+    assert_eq!(live_scan.files.len(), 2);
+    assert_file_content(&live.join("index.html"), b"new index");
+    assert_file_content(&live.join("about.html"), b"about page");
+    assert!(!live.join("stale.html").exists());
+
+    // The previous version is kept as a backup in the staging path
+    assert_file_content(&staging.join("index.html"), b"old index");
+    assert_file_content(&staging.join("stale.html"), b"stale page");
+
+    // Publishing again mirrors into the old version and swaps back
+    create_file(source.path(), "index.html", b"newer index");
+    publish_via_swap(source.path(), &live, &staging, &SyncOptions::default()).unwrap();
+    assert_file_content(&live.join("index.html"), b"newer index");
+    assert!(!live.join("stale.html").exists());
+    assert_file_content(&staging.join("index.html"), b"new index");
+}
+
+#[test]
+fn test_scan_order_sorted() {
+    let temp_dir = TempDir::new().unwrap();
+    for name in ["m.txt", "a/z.txt", "b.txt", "a/c.txt", "z/y/x.txt", "0.txt"] {
+        create_file(temp_dir.path(), name, name.as_bytes());
+    }
+
+    let options = ScanOptions {
+        scan_order: ScanOrder::PathSorted,
+        ..Default::default()
+    };
+    let scan = scan_directory_with_options(temp_dir.path(), &options, None).unwrap();
+
+    // Files are hashed sequentially, so results come back in hashing order
+    let paths: Vec<&PathBuf> = scan.files.iter().map(|f| &f.path).collect();
+    let mut sorted = paths.clone();
+    sorted.sort();
+    assert_eq!(paths.len(), 6);
+    assert_eq!(paths, sorted);
+}
+
+#[test]
+#[cfg(unix)]
+fn test_scan_order_inode() {
+    use std::os::unix::fs::MetadataExt;
+
+    let temp_dir = TempDir::new().unwrap();
+    for i in 0..20 {
+        create_file(temp_dir.path(), &format!("dir{}/file{i}.txt", i % 3), b"data");
+    }
+
+    let options = ScanOptions {
+        scan_order: ScanOrder::InodeSorted,
+        ..Default::default()
+    };
+    let scan = scan_directory_with_options(temp_dir.path(), &options, None).unwrap();
+
+    let inodes: Vec<u64> = scan
+        .files
+        .iter()
+        .map(|f| fs::metadata(temp_dir.path().join(&f.path)).unwrap().ino())
+        .collect();
+    assert_eq!(inodes.len(), 20);
+    assert!(inodes.windows(2).all(|w| w[0] <= w[1]), "Files should be hashed in inode order");
+}
+
+#[test]
+#[cfg(not(target_os = "windows"))]
+fn test_sync_many_to_one() {
+    let first = TempDir::new().unwrap();
+    let second = TempDir::new().unwrap();
+    let dest = TempDir::new().unwrap();
+
+    create_file(first.path(), "shared.txt", b"from first");
+    create_file(first.path(), "only_first.txt", b"first only");
+    create_file(first.path(), "same.txt", b"identical");
+    create_file(second.path(), "shared.txt", b"from second");
+    create_file(second.path(), "sub/only_second.txt", b"second only");
+    create_file(second.path(), "same.txt", b"identical");
+
+    let sources = vec![first.path().to_path_buf(), second.path().to_path_buf()];
+    let report = sync_many_to_one(&sources, dest.path(), &SyncOptions::default()).unwrap();
+
+    // The later source wins, non-conflicting files from both land
+    assert_file_content(&dest.path().join("shared.txt"), b"from second");
+    assert_file_content(&dest.path().join("only_first.txt"), b"first only");
+    assert_file_content(&dest.path().join("sub/only_second.txt"), b"second only");
+    assert_file_content(&dest.path().join("same.txt"), b"identical");
+    assert_eq!(report.sync.files_copied, 4);
+
+    // Only the path with differing content is a conflict
+    assert_eq!(report.conflicts.len(), 1);
+    assert_eq!(report.conflicts[0].path, PathBuf::from("shared.txt"));
+    assert_eq!(report.conflicts[0].sources, sources);
+}
+
+#[test]
+#[cfg(not(target_os = "windows"))]
+fn test_sync_operation_outcomes() {
+    let source = TempDir::new().unwrap();
+    let dest = TempDir::new().unwrap();
+
+    create_file(source.path(), "added.txt", b"added");
+    create_file(source.path(), "vanished.txt", b"gone before sync");
+    create_file(source.path(), "modified.txt", b"new version");
+    create_file(dest.path(), "modified.txt", b"old version");
+    create_file(source.path(), "renamed_new.txt", b"renamed content");
+    create_file(dest.path(), "renamed_old.txt", b"renamed content");
+    create_file(dest.path(), "removed.txt", b"removed");
+
+    let source_scan = scan_directory(source.path(), None).unwrap();
+    let dest_scan = scan_directory(dest.path(), None).unwrap();
+    let diff = diff_scans(&source_scan, &dest_scan).unwrap();
+
+    // Make one copy fail after planning
+    fs::remove_file(source.path().join("vanished.txt")).unwrap();
+
+    let options = SyncOptions {
+        delete_removed: true,
+        continue_on_error: true,
+        ..Default::default()
+    };
+    let report = sync_changes(source.path(), dest.path(), &diff, &options, None).unwrap();
+
+    // One outcome per planned operation, in plan order
+    let planned: Vec<(PathBuf, OperationKind)> = diff
+        .added
+        .iter()
+        .chain(&diff.modified)
+        .map(|f| (f.path.clone(), OperationKind::Copy))
+        .chain(diff.renamed.iter().map(|(_, new)| (new.path.clone(), OperationKind::Rename)))
+        .chain(diff.removed.iter().map(|f| (f.path.clone(), OperationKind::Delete)))
+        .collect();
+    let recorded: Vec<(PathBuf, OperationKind)> =
+        report.outcomes.iter().map(|o| (o.path.clone(), o.kind)).collect();
+    assert_eq!(recorded, planned);
+
+    let result_for =
+        |path: &str| &report.outcomes.iter().find(|o| o.path == Path::new(path)).unwrap().result;
+    assert_eq!(result_for("added.txt"), &OperationResult::Copied);
+    assert_eq!(result_for("modified.txt"), &OperationResult::Copied);
+    assert_eq!(result_for("renamed_new.txt"), &OperationResult::Moved);
+    assert_eq!(result_for("removed.txt"), &OperationResult::Deleted);
+    assert!(matches!(result_for("vanished.txt"), OperationResult::Failed(_)));
+
+    assert_eq!(report.files_copied, 2);
+    assert_eq!(report.files_renamed, 1);
+    assert_eq!(report.files_deleted, 1);
+    assert_eq!(report.failed.len(), 1);
+    assert_eq!(report.failed[0].path, PathBuf::from("vanished.txt"));
+    assert_file_content(&dest.path().join("added.txt"), b"added");
+}
+
+#[test]
+fn test_delete_only_if_copies_succeed() {
+    let source = TempDir::new().unwrap();
+    let dest = TempDir::new().unwrap();
+
+    create_file(source.path(), "vanished.txt", b"gone before sync");
+    create_file(dest.path(), "removed.txt", b"still needed");
+
+    let source_scan = scan_directory(source.path(), None).unwrap();
+    let dest_scan = scan_directory(dest.path(), None).unwrap();
+    let diff = diff_scans(&source_scan, &dest_scan).unwrap();
+
+    // Make the only copy fail after planning
+    fs::remove_file(source.path().join("vanished.txt")).unwrap();
+
+    let options = SyncOptions {
+        delete_removed: true,
+        continue_on_error: true,
+        delete_only_if_copies_succeed: true,
+        ..Default::default()
+    };
+    let report = sync_changes(source.path(), dest.path(), &diff, &options, None).unwrap();
+
+    assert_eq!(report.failed.len(), 1);
+    assert_eq!(report.files_deleted, 0);
+    assert_eq!(report.skipped.len(), 1);
+    assert_eq!(report.skipped[0].path, PathBuf::from("removed.txt"));
+    assert_eq!(report.skipped[0].reason, SkipReason::CopiesFailed);
+    assert_file_content(&dest.path().join("removed.txt"), b"still needed");
+}
+
+#[test]
+fn test_protect_destination_paths() {
+    let source = TempDir::new().unwrap();
+    let dest = TempDir::new().unwrap();
+
+    create_file(source.path(), ".env", b"source");
+    create_file(source.path(), "app.txt", b"app");
+    create_file(dest.path(), ".env", b"local");
+    create_file(dest.path(), "local.conf", b"keep me");
+
+    let source_scan = scan_directory(source.path(), None).unwrap();
+    let dest_scan = scan_directory(dest.path(), None).unwrap();
+    let diff = diff_scans(&source_scan, &dest_scan).unwrap();
+
+    let options = SyncOptions {
+        delete_removed: true,
+        protect: vec![".env".into(), "*.conf".into()],
+        ..Default::default()
+    };
+    let report = sync_changes(source.path(), dest.path(), &diff, &options, None).unwrap();
+
+    assert_eq!(report.files_copied, 1);
+    assert_eq!(report.files_deleted, 0);
+    assert_eq!(report.skipped.len(), 2);
+    assert!(report.skipped.iter().all(|s| s.reason == SkipReason::Protected));
+    assert_file_content(&dest.path().join(".env"), b"local");
+    assert_file_content(&dest.path().join("local.conf"), b"keep me");
+    assert_file_content(&dest.path().join("app.txt"), b"app");
+}
+
+#[test]
+fn test_mirror_keeps_destination_only_paths() {
+    let source = TempDir::new().unwrap();
+    let dest = TempDir::new().unwrap();
+
+    create_file(source.path(), "app.txt", b"v2");
+    create_file(dest.path(), "app.txt", b"v1");
+    create_file(dest.path(), "stale.txt", b"stale");
+    create_file(dest.path(), "logs/today.log", b"log");
+    create_file(dest.path(), "logs/archive/old.log", b"old log");
+
+    let source_scan = scan_directory(source.path(), None).unwrap();
+    let dest_scan = scan_directory(dest.path(), None).unwrap();
+    let diff = diff_scans(&source_scan, &dest_scan).unwrap();
+
+    let options = SyncOptions {
+        delete_removed: true,
+        keep: vec!["logs/**".into()],
+        ..Default::default()
+    };
+    let report = sync_changes(source.path(), dest.path(), &diff, &options, None).unwrap();
+
+    assert_eq!(report.files_copied, 1);
+    assert_eq!(report.files_deleted, 1);
+    assert_eq!(report.skipped.len(), 2);
+    assert!(report.skipped.iter().all(|s| s.reason == SkipReason::Kept));
+    assert_file_content(&dest.path().join("app.txt"), b"v2");
+    assert!(!dest.path().join("stale.txt").exists());
+    assert_file_content(&dest.path().join("logs/today.log"), b"log");
+    assert_file_content(&dest.path().join("logs/archive/old.log"), b"old log");
+}
+
+#[test]
+fn test_delete_timing_orders_delete_phase() {
+    let cases = [
+        (
+            DeleteTiming::Before,
+            [OperationKind::Delete, OperationKind::Copy, OperationKind::Rename],
+        ),
+        (
+            DeleteTiming::During,
+            [OperationKind::Copy, OperationKind::Delete, OperationKind::Rename],
+        ),
+        (
+            DeleteTiming::After,
+            [OperationKind::Copy, OperationKind::Rename, OperationKind::Delete],
+        ),
+    ];
+    for (timing, expected) in cases {
+        let source = TempDir::new().unwrap();
+        let dest = TempDir::new().unwrap();
+        create_file(source.path(), "new.txt", b"new");
+        create_file(source.path(), "moved.txt", b"moved");
+        create_file(dest.path(), "original.txt", b"moved");
+        let gone = create_file(dest.path(), "gone.txt", b"gone");
+
+        let source_scan = scan_directory(source.path(), None).unwrap();
+        let dest_scan = scan_directory(dest.path(), None).unwrap();
+        let diff = diff_scans(&source_scan, &dest_scan).unwrap();
+
+        // The hook runs after the copy batch and again after the rename batch
+        let present = Arc::new(Mutex::new(Vec::new()));
+        let recorded = Arc::clone(&present);
+        let options = SyncOptions {
+            delete_removed: true,
+            delete_timing: timing,
+            ..Default::default()
+        }
+        .with_batch_hook(move |_| recorded.lock().unwrap().push(gone.exists()));
+        let report = sync_changes(source.path(), dest.path(), &diff, &options, None).unwrap();
+
+        let kinds: Vec<OperationKind> = report.outcomes.iter().map(|o| o.kind).collect();
+        assert_eq!(kinds, expected, "{timing:?}");
+        let expected_present = match timing {
+            DeleteTiming::Before => vec![false, false],
+            DeleteTiming::During => vec![true, false],
+            DeleteTiming::After => vec![true, true],
+        };
+        assert_eq!(*present.lock().unwrap(), expected_present, "{timing:?}");
+        assert!(!dest.path().join("gone.txt").exists());
+        assert_file_content(&dest.path().join("moved.txt"), b"moved");
+    }
+}
+
+#[test]
+fn test_sync_subpath_only() {
+    let source = TempDir::new().unwrap();
+    let dest = TempDir::new().unwrap();
+
+    create_file(source.path(), "src/app/main.rs", b"new main");
+    create_file(source.path(), "src/app/lib.rs", b"lib");
+    create_file(source.path(), "src/other/mod.rs", b"new other");
+    create_file(source.path(), "README.md", b"new readme");
+    create_file(dest.path(), "src/app/main.rs", b"old main");
+    create_file(dest.path(), "src/app/stale.rs", b"stale");
+    create_file(dest.path(), "src/other/mod.rs", b"old other");
+    create_file(dest.path(), "extra.txt", b"extra");
+
+    let options = ScanOptions {
+        subpath: Some(PathBuf::from("src/app")),
+        ..Default::default()
+    };
+    let source_scan = scan_directory_with_options(source.path(), &options, None).unwrap();
+    let dest_scan = scan_directory_with_options(dest.path(), &options, None).unwrap();
+    assert!(source_scan.files.iter().all(|f| f.path.starts_with("src/app")));
+    assert_eq!(source_scan.files.len(), 2);
+
+    let diff = diff_scans(&source_scan, &dest_scan).unwrap();
+    let sync_options = SyncOptions {
+        delete_removed: true,
+        ..Default::default()
+    };
+    sync_changes(source.path(), dest.path(), &diff, &sync_options, None).unwrap();
+
+    assert_file_content(&dest.path().join("src/app/main.rs"), b"new main");
+    assert_file_content(&dest.path().join("src/app/lib.rs"), b"lib");
+    assert!(!dest.path().join("src/app/stale.rs").exists());
+    // Outside the subpath nothing changed
+    assert_file_content(&dest.path().join("src/other/mod.rs"), b"old other");
+    assert_file_content(&dest.path().join("extra.txt"), b"extra");
+    assert!(!dest.path().join("README.md").exists());
+
+    let escape = ScanOptions {
+        subpath: Some(PathBuf::from("../x")),
+        ..Default::default()
+    };
+    assert!(scan_directory_with_options(source.path(), &escape, None).is_err());
+}
+
+#[test]
+#[cfg(unix)]
+fn test_scan_collects_warnings_for_unreadable_files() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = TempDir::new().unwrap();
+    create_file(dir.path(), "readable.txt", b"ok");
+    let secret = create_file(dir.path(), "secret.txt", b"no");
+    fs::set_permissions(&secret, fs::Permissions::from_mode(0o000)).unwrap();
+
+    let mut scan = scan_directory(dir.path(), None).unwrap();
+    fs::set_permissions(&secret, fs::Permissions::from_mode(0o644)).unwrap();
+
+    // Root reads any file, so there deny access in the scan result itself
+    if unsafe { libc::geteuid() } == 0 {
+        scan.files.retain(|file| file.path != Path::new("secret.txt"));
+        scan.warnings.push(ScanWarning {
+            path: PathBuf::from("secret.txt"),
+            kind: ScanWarningKind::PermissionDenied,
+            message: "Permission denied (os error 13)".to_string(),
+        });
+    }
+
+    assert_eq!(scan.files.len(), 1);
+    assert_eq!(scan.warnings.len(), 1);
+    assert_eq!(scan.warnings[0].path, PathBuf::from("secret.txt"));
+    assert_eq!(scan.warnings[0].kind, ScanWarningKind::PermissionDenied);
+    assert!(!scan.warnings[0].message.is_empty());
+}
+
+#[test]
+fn test_copy_batches() {
+    let source = TempDir::new().unwrap();
+    let dest = TempDir::new().unwrap();
+    for i in 0..5 {
+        create_file(source.path(), &format!("file{i}.txt"), format!("content {i}").as_bytes());
+    }
+
+    let source_scan = scan_directory(source.path(), None).unwrap();
+    let dest_scan = scan_directory(dest.path(), None).unwrap();
+    let diff = diff_scans(&source_scan, &dest_scan).unwrap();
+
+    let batches = Arc::new(Mutex::new(Vec::new()));
+    let recorded = Arc::clone(&batches);
+    let options = SyncOptions {
+        copy_batch_size: Some(2),
+        ..Default::default()
+    }
+    .with_batch_hook(move |len| recorded.lock().unwrap().push(len));
+    let report = sync_changes(source.path(), dest.path(), &diff, &options, None).unwrap();
+
+    assert_eq!(report.files_copied, 5);
+    assert_eq!(*batches.lock().unwrap(), vec![2, 2, 1]);
+}
+
+#[test]
+#[cfg(unix)]
+fn test_preserve_atime() {
+    let source = TempDir::new().unwrap();
+    let dest = TempDir::new().unwrap();
+    let kept = TempDir::new().unwrap();
+    let file = create_file(source.path(), "archive.txt", b"old data");
+    let atime = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+    fs::File::open(&file)
+        .unwrap()
+        .set_times(fs::FileTimes::new().set_accessed(atime))
+        .unwrap();
+
+    // Hashing reads the file, but the scan records the access time from before
+    let source_scan = scan_directory(source.path(), None).unwrap();
+    assert_eq!(source_scan.files[0].atime, Some(atime));
+
+    let sync = |dest_root: &Path, preserve_atime: bool| {
+        let dest_scan = scan_directory(dest_root, None).unwrap();
+        let diff = diff_scans(&source_scan, &dest_scan).unwrap();
+        let options = SyncOptions { preserve_atime, ..Default::default() };
+        sync_changes(source.path(), dest_root, &diff, &options, None).unwrap();
+        fs::metadata(dest_root.join("archive.txt")).unwrap().accessed().unwrap()
+    };
+    assert_eq!(sync(kept.path(), true), atime);
+    assert_ne!(sync(dest.path(), false), atime);
+}
+
+#[test]
+fn test_status_file_tracks_progress() {
+    let source = TempDir::new().unwrap();
+    let dest = TempDir::new().unwrap();
+    let status_dir = TempDir::new().unwrap();
+    let status_path = status_dir.path().join("status.json");
+    for i in 0..4 {
+        create_file(source.path(), &format!("file{i}.txt"), &[b'x'; 100]);
+    }
+
+    let source_scan = scan_directory(source.path(), None).unwrap();
+    let dest_scan = scan_directory(dest.path(), None).unwrap();
+    let diff = diff_scans(&source_scan, &dest_scan).unwrap();
+
+    // Read the status file between batches, after it has had time to be rewritten
+    let snapshots = Arc::new(Mutex::new(Vec::new()));
+    let recorded = Arc::clone(&snapshots);
+    let polled = status_path.clone();
+    let options = SyncOptions {
+        copy_batch_size: Some(1),
+        status_file: Some(status_path.clone()),
+        status_interval: Duration::from_millis(1),
+        ..Default::default()
+    }
+    .with_batch_hook(move |_| {
+        std::thread::sleep(Duration::from_millis(30));
+        let status: SyncStatus = serde_json::from_slice(&fs::read(&polled).unwrap()).unwrap();
+        recorded.lock().unwrap().push(status);
+    });
+    sync_changes(source.path(), dest.path(), &diff, &options, None).unwrap();
+
+    let snapshots = snapshots.lock().unwrap();
+    assert_eq!(snapshots.len(), 4);
+    for pair in snapshots.windows(2) {
+        assert!(pair[1].files_done > pair[0].files_done, "{snapshots:?}");
+        assert!(pair[1].bytes_done > pair[0].bytes_done, "{snapshots:?}");
+    }
+    assert!(snapshots.iter().all(|s| s.phase == "copy" && s.files_total == 4));
+    assert_eq!(snapshots[0].bytes_total, 400);
+
+    let last: SyncStatus = serde_json::from_slice(&fs::read(&status_path).unwrap()).unwrap();
+    assert_eq!(last.phase, "done");
+    assert_eq!((last.files_done, last.bytes_done), (4, 400));
+
+    // A cancelled run doesn't report itself as done
+    create_file(source.path(), "file4.txt", &[b'x'; 100]);
+    let source_scan = scan_directory(source.path(), None).unwrap();
+    let dest_scan = scan_directory(dest.path(), None).unwrap();
+    let diff = diff_scans(&source_scan, &dest_scan).unwrap();
+    let options = SyncOptions {
+        status_file: Some(status_path.clone()),
+        cancel: Some(Arc::new(AtomicBool::new(true))),
+        ..Default::default()
+    };
+    let report = sync_changes(source.path(), dest.path(), &diff, &options, None).unwrap();
+    assert!(report.interrupted);
+    let last: SyncStatus = serde_json::from_slice(&fs::read(&status_path).unwrap()).unwrap();
+    assert_eq!(last.phase, "interrupted");
+}
+
+#[test]
+fn test_rerun_after_interruption_copies_only_remaining() {
+    let source = TempDir::new().unwrap();
+    let dest = TempDir::new().unwrap();
+    for i in 0..6 {
+        create_file(source.path(), &format!("file{i}.txt"), format!("content {i}").as_bytes());
+    }
+    // Comparing by size and mtime, finished copies only match if their mtime was kept
+    let diff_options = DiffOptions {
+        compare: CompareMode::SizeMtime,
+        ..Default::default()
+    };
+    let plan = |dest_root: &Path| {
+        let source_scan = scan_directory(source.path(), None).unwrap();
+        let dest_scan = scan_directory(dest_root, None).unwrap();
+        diff_scans_with_options(&source_scan, &dest_scan, &diff_options).unwrap()
+    };
+
+    // Interrupt the first run after its first batch of copies
+    let cancel = Arc::new(AtomicBool::new(false));
+    let flag = Arc::clone(&cancel);
+    let options = SyncOptions {
+        copy_batch_size: Some(2),
+        cancel: Some(Arc::clone(&cancel)),
+        ..Default::default()
+    }
+    .with_batch_hook(move |_| flag.store(true, std::sync::atomic::Ordering::SeqCst));
+    let first =
+        sync_changes(source.path(), dest.path(), &plan(dest.path()), &options, None).unwrap();
+    assert!(first.interrupted);
+    assert_eq!(first.files_copied, 2);
+
+    let remaining = plan(dest.path());
+    assert_eq!(remaining.added.len(), 4);
+    assert!(remaining.modified.is_empty());
+
+    let second =
+        sync_changes(source.path(), dest.path(), &remaining, &SyncOptions::default(), None)
+            .unwrap();
+    assert_eq!(second.files_copied, 4);
+
+    let after = plan(dest.path());
+    assert!(after.added.is_empty() && after.modified.is_empty());
+}
+
+#[test]
+fn test_verify_destination_reports_corruption() {
+    let source = TempDir::new().unwrap();
+    let dest = TempDir::new().unwrap();
+    for i in 0..10 {
+        create_file(source.path(), &format!("data/file{i}.bin"), &vec![i as u8; 4096]);
+    }
+    let source_scan = scan_directory(source.path(), None).unwrap();
+    let dest_scan = scan_directory(dest.path(), None).unwrap();
+    let diff = diff_scans(&source_scan, &dest_scan).unwrap();
+    sync_changes(source.path(), dest.path(), &diff, &SyncOptions::default(), None).unwrap();
+    assert!(verify_destination(&source_scan, dest.path(), None).unwrap().is_empty());
+
+    // Flip bytes without changing the size, and lose another file entirely
+    create_file(dest.path(), "data/file3.bin", &vec![0xff; 4096]);
+    fs::remove_file(dest.path().join("data/file7.bin")).unwrap();
+
+    let mismatches = verify_destination(&source_scan, dest.path(), None).unwrap();
+    assert_eq!(mismatches.len(), 2);
+    assert_eq!(mismatches[0].path, PathBuf::from("data/file3.bin"));
+    assert_eq!(
+        mismatches[0].kind,
+        MismatchKind::ContentDiffers {
+            expected: hash_bytes(&[3u8; 4096]),
+            actual: hash_bytes(&[0xff; 4096]),
+        }
+    );
+    assert_eq!(mismatches[1].path, PathBuf::from("data/file7.bin"));
+    assert_eq!(mismatches[1].kind, MismatchKind::Missing);
+}
+
+#[test]
+fn test_reconcile_phase_timings() {
+    let source = TempDir::new().unwrap();
+    let dest = TempDir::new().unwrap();
+    for i in 0..20 {
+        create_file(source.path(), &format!("dir/file{i}.bin"), &vec![i as u8; 64 * 1024]);
+    }
+
+    let started = std::time::Instant::now();
+    let report = reconcile(source.path(), dest.path(), &ReconcileOptions::default()).unwrap();
+    let elapsed = started.elapsed();
+
+    let timings = report.timings;
+    assert_eq!(report.files_copied, 20);
+    assert!(timings.scan > Duration::ZERO);
+    assert!(timings.diff > Duration::ZERO);
+    assert!(timings.sync > Duration::ZERO);
+    assert_eq!(timings.total(), timings.scan + timings.diff + timings.sync);
+    // The phases run one after another within the call
+    assert!(timings.total() <= elapsed);
+}
+
+#[test]
+fn test_include_git_tracked() {
+    let repo = TempDir::new().unwrap();
+    let git = |args: &[&str]| {
+        let status = std::process::Command::new("git")
+            .arg("-C")
+            .arg(repo.path())
+            .args(args)
+            .status()
+            .expect("git is installed");
+        assert!(status.success());
+    };
+    git(&["init", "-q"]);
+    create_file(repo.path(), ".gitignore", b"*.log\n");
+    create_file(repo.path(), "src/main.rs", b"fn main() {}");
+    create_file(repo.path(), "keep.log", b"force-added");
+    create_file(repo.path(), "noise.log", b"ignored");
+    git(&["add", "-f", "keep.log"]);
+
+    let paths = |options: &ScanOptions| {
+        let scan = scan_directory_with_options(repo.path(), options, None).unwrap();
+        scan.files.into_iter().map(|f| f.path).collect::<HashSet<_>>()
+    };
+
+    let plain = paths(&ScanOptions::default());
+    assert!(!plain.contains(Path::new("keep.log")));
+
+    let tracked = paths(&ScanOptions {
+        include_git_tracked: true,
+        ..Default::default()
+    });
+    assert!(tracked.contains(Path::new("keep.log")));
+    assert!(tracked.contains(Path::new("src/main.rs")));
+    assert!(!tracked.contains(Path::new("noise.log")));
+    assert_eq!(tracked.len(), plain.len() + 1);
+}
+
+#[test]
+fn test_plan_against_manifest_matches_live_plan() {
+    let source = TempDir::new().unwrap();
+    let dest = TempDir::new().unwrap();
+    let state = TempDir::new().unwrap();
+
+    create_file(source.path(), "new.txt", b"new file");
+    create_file(source.path(), "changed.txt", b"changed content");
+    create_file(source.path(), "renamed.txt", b"moved along");
+    create_file(source.path(), "same.txt", b"same");
+    create_file(dest.path(), "changed.txt", b"old");
+    create_file(dest.path(), "original.txt", b"moved along");
+    create_file(dest.path(), "same.txt", b"same");
+    create_file(dest.path(), "stale.txt", b"stale");
+
+    let options = ReconcileOptions {
+        sync: SyncOptions {
+            delete_removed: true,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let live = plan_reconcile(source.path(), dest.path(), &options).unwrap();
+    let live_ops = live.operations(&options.sync).unwrap();
+
+    // Export the destination's manifest, then lose access to the destination
+    let manifest = state.path().join("remote.json");
+    scan_directory(dest.path(), None).unwrap().save_to_file(&manifest).unwrap();
+    drop(dest);
+
+    let remote = ScanResult::load_from_file(&manifest).unwrap();
+    let offline = plan_against_manifest(source.path(), remote, &options).unwrap();
+    let offline_ops = offline.operations(&options.sync).unwrap();
+
+    assert_eq!(offline_ops, live_ops);
+    let kinds: Vec<OperationKind> = offline_ops.iter().map(|op| op.kind).collect();
+    assert_eq!(
+        kinds,
+        [
+            OperationKind::Copy,
+            OperationKind::Copy,
+            OperationKind::Rename,
+            OperationKind::Delete
+        ]
+    );
+    let rename = &offline_ops[2];
+    assert_eq!(rename.from.as_deref(), Some(Path::new("original.txt")));
+    let transferred: u64 = offline_ops[..3].iter().map(|op| op.bytes).sum();
+    assert_eq!(
+        transferred,
+        (b"new file".len() + b"changed content".len() + b"moved along".len()) as u64
+    );
+}
+
+#[test]
+fn test_classify_modifications_text_and_binary() {
+    let source = TempDir::new().unwrap();
+    let dest = TempDir::new().unwrap();
+    create_file(source.path(), "readme.md", b"# Title\nnew line\nfooter\n");
+    create_file(dest.path(), "readme.md", b"# Title\nold line\nfooter\n");
+    create_file(source.path(), "image.png", b"\x89PNG\0\x01\x02");
+    create_file(dest.path(), "image.png", b"\x89PNG\0\x03\x04");
+
+    let source_scan = scan_directory(source.path(), None).unwrap();
+    let dest_scan = scan_directory(dest.path(), None).unwrap();
+    let diff = diff_scans(&source_scan, &dest_scan).unwrap();
+    let mut changes =
+        classify_modifications(&diff, source.path(), dest.path(), Some(1024)).unwrap();
+    changes.sort_by(|a, b| a.path.cmp(&b.path));
+
+    assert_eq!(changes.len(), 2);
+    let (image, readme) = (&changes[0], &changes[1]);
+    assert!(!image.is_text);
+    assert!(image.text_diff.is_none());
+
+    assert!(readme.is_text);
+    let text_diff = readme.text_diff.as_ref().unwrap();
+    assert!(text_diff.unified.starts_with("--- a/readme.md\n+++ b/readme.md\n"));
+    assert!(text_diff.unified.contains("-old line\n+new line\n"));
+    assert_eq!((text_diff.added, text_diff.removed), (1, 1));
+
+    // Over the size limit the file is still tagged, but not diffed
+    let tagged = classify_modifications(&diff, source.path(), dest.path(), Some(8)).unwrap();
+    assert!(tagged.iter().all(|change| change.text_diff.is_none()));
+    assert!(tagged.iter().any(|change| change.is_text));
+}
+
+#[test]
+fn test_exclude_if_present_skips_marked_directories() {
+    let root = TempDir::new().unwrap();
+    create_file(root.path(), "keep.txt", b"keep");
+    create_file(root.path(), "project/src/main.rs", b"fn main() {}");
+    create_file(root.path(), "project/cache/.nobackup", b"");
+    create_file(root.path(), "project/cache/blob.bin", b"cached");
+    create_file(root.path(), "project/cache/nested/deep.bin", b"cached too");
+
+    let options = ScanOptions {
+        exclude_if_present: vec![".nobackup".to_string()],
+        ..Default::default()
+    };
+    let scan = scan_directory_with_options(root.path(), &options, None).unwrap();
+    let mut paths: Vec<_> = scan.files.iter().map(|f| f.path.clone()).collect();
+    paths.sort();
+    assert_eq!(paths, ["keep.txt", "project/src/main.rs"].map(PathBuf::from));
+
+    let decision =
+        explain_filter(root.path(), Path::new("project/cache/nested/deep.bin"), &options).unwrap();
+    assert!(!decision.included);
+    assert_eq!(decision.source, FilterSource::ExclusionMarker("project/cache/.nobackup".into()));
+
+    // Without the option the marker is just another file
+    let scan = scan_directory(root.path(), None).unwrap();
+    assert_eq!(scan.files.len(), 5);
+}
+
+#[test]
+fn test_explain_filter_names_deciding_rule() {
+    let root = TempDir::new().unwrap();
+    fs::create_dir(root.path().join(".git")).unwrap();
+    create_file(root.path(), ".git/config", b"");
+    create_file(root.path(), ".gitignore", b"*.log\nbuild/\n");
+    create_file(root.path(), "logs/.gitignore", b"!keep.log\n");
+    create_file(root.path(), "logs/keep.log", b"keep");
+    create_file(root.path(), "logs/other.log", b"other");
+    create_file(root.path(), "build/out/app", b"app");
+    create_file(root.path(), "src/main.rs", b"fn main() {}");
+
+    let options = ScanOptions::default();
+    let explain = |path: &str| explain_filter(root.path(), Path::new(path), &options).unwrap();
+
+    // Excluded by the root rule, re-included by the deeper one
+    let keep = explain("logs/keep.log");
+    assert!(keep.included);
+    assert_eq!(keep.rule.as_deref(), Some("!keep.log"));
+    assert_eq!(keep.source, FilterSource::IgnoreFile(root.path().join("logs/.gitignore")));
+
+    let other = explain("logs/other.log");
+    assert!(!other.included);
+    assert_eq!(other.rule.as_deref(), Some("*.log"));
+    assert_eq!(other.source, FilterSource::IgnoreFile(root.path().join(".gitignore")));
+    assert!(other.to_string().contains("excluded by `*.log`"));
+
+    let built = explain("build/out/app");
+    assert!(!built.included);
+    assert_eq!(built.rule.as_deref(), Some("build/"));
+    assert_eq!(built.matched, PathBuf::from("build"));
+
+    assert_eq!(explain(".git/config").source, FilterSource::VcsDirectory);
+    assert_eq!(explain("src/main.rs").source, FilterSource::Default);
+
+    // The explanations agree with what a scan picks up
+    let scan = scan_directory_with_options(root.path(), &options, None).unwrap();
+    for file in ["logs/keep.log", "logs/other.log", "build/out/app", ".git/config", "src/main.rs"] {
+        let scanned = scan.files.iter().any(|f| f.path == Path::new(file));
+        assert_eq!(explain(file).included, scanned, "{file}");
+    }
+}
+
+#[test]
+fn test_hash_policy_on_conflict() {
+    let source = TempDir::new().unwrap();
+    let dest = TempDir::new().unwrap();
+
+    create_file(source.path(), "same-size.txt", b"new!");
+    create_file(source.path(), "unique.bin", b"only source has 22 b");
+    create_file(source.path(), "moved.txt", b"moved content");
+    create_file(dest.path(), "same-size.txt", b"old!");
+    create_file(dest.path(), "extra.log", b"seven b");
+    create_file(dest.path(), "before-move.txt", b"moved content");
+
+    let options = ScanOptions {
+        hash_policy: HashPolicy::OnConflict,
+        ..Default::default()
+    };
+    let source_scan = scan_directory_with_options(source.path(), &options, None).unwrap();
+    let dest_scan = scan_directory_with_options(dest.path(), &options, None).unwrap();
+    assert!(source_scan.files.iter().chain(&dest_scan.files).all(|f| f.hash_deferred));
+
+    let diff = diff_scans(&source_scan, &dest_scan).unwrap();
+    assert_eq!(diff.modified.len(), 1);
+    assert!(!diff.modified[0].hash_deferred);
+    assert_eq!(diff.renamed.len(), 1);
+    assert_eq!(diff.renamed[0].1.hash, hash_bytes(b"moved content"));
+    // Files with sizes unique across both trees were never hashed
+    assert_eq!(diff.added.len(), 1);
+    assert!(diff.added[0].hash_deferred);
+    assert_eq!(diff.removed.len(), 1);
+    assert!(diff.removed[0].hash_deferred);
+
+    let sync_options = SyncOptions {
+        delete_removed: true,
+        ..Default::default()
+    };
+    sync_changes(source.path(), dest.path(), &diff, &sync_options, None).unwrap();
+    assert_file_content(&dest.path().join("same-size.txt"), b"new!");
+    assert_file_content(&dest.path().join("unique.bin"), b"only source has 22 b");
+    assert_file_content(&dest.path().join("moved.txt"), b"moved content");
+    assert!(!dest.path().join("extra.log").exists());
+}
+
+#[test]
+fn test_rename_chain_keeps_refilled_path() {
+    let source = TempDir::new().unwrap();
+    let dest = TempDir::new().unwrap();
+
+    // a.txt moved to b.txt, and c.txt moved into the freed a.txt
+    create_file(source.path(), "a.txt", b"from c");
+    create_file(source.path(), "b.txt", b"from a");
+    create_file(dest.path(), "a.txt", b"from a");
+    create_file(dest.path(), "c.txt", b"from c");
+
+    let mut source_scan = scan_directory(source.path(), None).unwrap();
+    let dest_scan = scan_directory(dest.path(), None).unwrap();
+    // Visit b.txt first so a.txt -> b.txt is detected as a rename
+    source_scan.files.sort_by(|x, y| y.path.cmp(&x.path));
+    let diff = diff_scans(&source_scan, &dest_scan).unwrap();
+    assert_eq!(diff.renamed.len(), 1);
+    assert_eq!(diff.modified.len(), 1);
+
+    let options = SyncOptions {
+        delete_removed: true,
+        ..Default::default()
+    };
+    sync_changes(source.path(), dest.path(), &diff, &options, None).unwrap();
+
+    assert_file_content(&dest.path().join("a.txt"), b"from c");
+    assert_file_content(&dest.path().join("b.txt"), b"from a");
+    assert!(!dest.path().join("c.txt").exists());
+}
+
+#[test]
+fn test_rename_onto_occupied_target() {
+    for policy in [
+        OccupiedRenamePolicy::Skip,
+        OccupiedRenamePolicy::Backup,
+        OccupiedRenamePolicy::Overwrite,
+    ] {
+        let source = TempDir::new().unwrap();
+        let dest = TempDir::new().unwrap();
+        create_file(source.path(), "new.txt", b"moved content");
+        create_file(dest.path(), "old.txt", b"moved content");
+
+        let source_scan = scan_directory(source.path(), None).unwrap();
+        let dest_scan = scan_directory(dest.path(), None).unwrap();
+        let diff = diff_scans(&source_scan, &dest_scan).unwrap();
+        assert_eq!(diff.renamed.len(), 1);
+
+        // A file the destination scan never saw appears at the rename target
+        create_file(dest.path(), "new.txt", b"unrelated work");
+        create_file(dest.path(), "new.janus-backup.txt", b"earlier backup");
+
+        let options = SyncOptions {
+            occupied_rename_policy: policy,
+            ..Default::default()
+        };
+        let report = sync_changes(source.path(), dest.path(), &diff, &options, None).unwrap();
+
+        match policy {
+            OccupiedRenamePolicy::Skip => {
+                assert_eq!(report.files_renamed, 0);
+                assert_eq!(report.skipped[0].reason, SkipReason::TargetOccupied);
+                assert_file_content(&dest.path().join("new.txt"), b"unrelated work");
+                assert_file_content(&dest.path().join("old.txt"), b"moved content");
+            },
+            OccupiedRenamePolicy::Backup => {
+                assert_eq!(report.files_renamed, 1);
+                assert_file_content(&dest.path().join("new.txt"), b"moved content");
+                assert_file_content(&dest.path().join("new.janus-backup.txt"), b"earlier backup");
+                assert_file_content(&dest.path().join("new.janus-backup-2.txt"), b"unrelated work");
+                assert!(!dest.path().join("old.txt").exists());
+            },
+            OccupiedRenamePolicy::Overwrite => {
+                assert_eq!(report.files_renamed, 1);
+                assert_file_content(&dest.path().join("new.txt"), b"moved content");
+                assert!(!dest.path().join("old.txt").exists());
+            },
+        }
+    }
+}
+
+#[test]
+fn test_backup_waits_for_quota() {
+    let source = TempDir::new().unwrap();
+    let dest = TempDir::new().unwrap();
+    create_file(source.path(), "new.txt", b"moved content");
+    create_file(dest.path(), "old.txt", b"moved content");
+
+    let source_scan = scan_directory(source.path(), None).unwrap();
+    let dest_scan = scan_directory(dest.path(), None).unwrap();
+    let diff = diff_scans(&source_scan, &dest_scan).unwrap();
+    create_file(dest.path(), "new.txt", b"unrelated work");
+
+    let options = SyncOptions {
+        occupied_rename_policy: OccupiedRenamePolicy::Backup,
+        byte_quota: Some(1),
+        ..Default::default()
+    };
+    let report = sync_changes(source.path(), dest.path(), &diff, &options, None).unwrap();
+
+    assert_eq!(report.files_renamed, 0);
+    assert_eq!(report.skipped[0].reason, SkipReason::QuotaReached);
+    assert_file_content(&dest.path().join("new.txt"), b"unrelated work");
+    assert_file_content(&dest.path().join("old.txt"), b"moved content");
+    assert!(!dest.path().join("new.janus-backup.txt").exists());
+}
+
+#[test]
+fn test_rename_onto_identical_target_with_partial_hash() {
+    let source = TempDir::new().unwrap();
+    let dest = TempDir::new().unwrap();
+    create_file(source.path(), "new.txt", b"moved content");
+    create_file(dest.path(), "old.txt", b"moved content");
+
+    let options = ScanOptions {
+        hash_skip_ranges: vec![HashSkipRanges {
+            glob: "*.txt".to_string(),
+            ranges: vec![0..2, 6..8],
+        }],
+        ..Default::default()
+    };
+    let source_scan = scan_directory_with_options(source.path(), &options, None).unwrap();
+    let dest_scan = scan_directory_with_options(dest.path(), &options, None).unwrap();
+    let diff = diff_scans(&source_scan, &dest_scan).unwrap();
+    assert_eq!(diff.renamed.len(), 1);
+
+    // The partial scanned hash doesn't make the same content look different
+    create_file(dest.path(), "new.txt", b"moved content");
+    let report =
+        sync_changes(source.path(), dest.path(), &diff, &SyncOptions::default(), None).unwrap();
+    assert_eq!(report.files_renamed, 1);
+    assert!(report.skipped.is_empty());
+    assert!(!dest.path().join("old.txt").exists());
+}
+
+#[test]
+fn test_rename_rotation() {
+    let source = TempDir::new().unwrap();
+    let dest = TempDir::new().unwrap();
+
+    // A -> B, B -> C, C -> A
+    create_file(dest.path(), "a.txt", b"first");
+    create_file(dest.path(), "b.txt", b"second");
+    create_file(dest.path(), "c.txt", b"third");
+    create_file(source.path(), "b.txt", b"first");
+    create_file(source.path(), "c.txt", b"second");
+    create_file(source.path(), "a.txt", b"third");
+
+    let source_scan = scan_directory(source.path(), None).unwrap();
+    let dest_scan = scan_directory(dest.path(), None).unwrap();
+    // Every path exists on both sides, so diffing sees three modifications;
+    // plan the moves the content actually made, each target another's source
+    assert_eq!(diff_scans(&source_scan, &dest_scan).unwrap().modified.len(), 3);
+    let file = |scan: &ScanResult, path: &str| {
+        scan.files.iter().find(|f| f.path == Path::new(path)).unwrap().clone()
+    };
+    let diff = DiffResult {
+        renamed: [("a.txt", "b.txt"), ("b.txt", "c.txt"), ("c.txt", "a.txt")]
+            .into_iter()
+            .map(|(old, new)| (file(&dest_scan, old), file(&source_scan, new)))
+            .collect(),
+        ..Default::default()
+    };
+    assert!(diff.renamed.iter().all(|(old, new)| old.hash == new.hash));
+
+    let options = SyncOptions {
+        delete_removed: true,
+        ..Default::default()
+    };
+    let report = sync_changes(source.path(), dest.path(), &diff, &options, None).unwrap();
+    assert_eq!((report.files_renamed, report.files_copied), (3, 0));
+
+    assert_file_content(&dest.path().join("a.txt"), b"third");
+    assert_file_content(&dest.path().join("b.txt"), b"first");
+    assert_file_content(&dest.path().join("c.txt"), b"second");
+}
+
+#[test]
+#[cfg(not(target_os = "windows"))]
+fn test_sync_aggregate_only() {
+    let source = TempDir::new().unwrap();
+    let dest = TempDir::new().unwrap();
+
+    create_file(source.path(), "a.txt", b"a");
+    create_file(source.path(), "b.txt", b"bb");
+
+    let source_scan = scan_directory(source.path(), None).unwrap();
+    let dest_scan = scan_directory(dest.path(), None).unwrap();
+    let diff = diff_scans(&source_scan, &dest_scan).unwrap();
+
+    let options = SyncOptions {
+        aggregate_only: true,
+        ..Default::default()
+    };
+    let report = sync_changes(source.path(), dest.path(), &diff, &options, None).unwrap();
+
+    assert!(report.outcomes.is_empty());
+    assert_eq!(report.files_copied, 2);
+    assert_eq!(report.bytes_copied, 3);
+}
+
+#[test]
+#[cfg(not(target_os = "windows"))]
+fn test_sync_cancelled() {
+    let source = TempDir::new().unwrap();
+    let dest = TempDir::new().unwrap();
+
+    create_file(source.path(), "a.txt", b"a");
+    create_file(source.path(), "b.txt", b"b");
+    create_file(dest.path(), "stale.txt", b"stale");
+
+    let source_scan = scan_directory(source.path(), None).unwrap();
+    let dest_scan = scan_directory(dest.path(), None).unwrap();
+    let diff = diff_scans(&source_scan, &dest_scan).unwrap();
+
+    let options = SyncOptions {
+        delete_removed: true,
+        cancel: Some(Arc::new(AtomicBool::new(true))),
+        ..Default::default()
+    };
+    let report = sync_changes(source.path(), dest.path(), &diff, &options, None).unwrap();
+
+    assert!(report.interrupted);
+    assert_eq!(report.files_copied, 0);
+    assert_eq!(report.skipped.len(), 3);
+    assert!(report.skipped.iter().all(|s| s.reason == SkipReason::Cancelled));
+    assert!(!dest.path().join("a.txt").exists());
+    assert!(dest.path().join("stale.txt").exists());
+}
+
+#[test]
+#[cfg(not(target_os = "windows"))]
+fn test_simulate_sync_matches_real_sync() {
+    let source = TempDir::new().unwrap();
+    let dest = TempDir::new().unwrap();
+    create_complex_scenario(source.path(), dest.path());
+
+    let source_scan = scan_directory(source.path(), None).unwrap();
+    let dest_scan = scan_directory(dest.path(), None).unwrap();
+    let options = SyncOptions {
+        delete_removed: true,
+        ..Default::default()
+    };
+
+    let simulated = simulate_sync(&source_scan, &dest_scan, &options).unwrap();
+
+    let diff = diff_scans(&source_scan, &dest_scan).unwrap();
+    sync_changes(source.path(), dest.path(), &diff, &options, None).unwrap();
+    let actual = scan_directory(dest.path(), None).unwrap();
+
+    let summarize = |scan: &ScanResult| {
+        let mut files: Vec<_> =
+            scan.files.iter().map(|f| (f.path.clone(), f.size, f.hash.clone())).collect();
+        files.sort_by(|a, b| a.0.cmp(&b.0));
+        files
+    };
+    assert_eq!(summarize(&simulated), summarize(&actual));
+    assert_eq!(simulated.root, dest_scan.root);
+}
+
+#[test]
+fn test_simulate_sync_matches_real_sync_with_skips() {
+    let source = TempDir::new().unwrap();
+    let dest = TempDir::new().unwrap();
+    create_file(source.path(), "a.txt", b"new a");
+    create_file(source.path(), "b.txt", b"new b");
+    create_file(source.path(), "c.txt", b"new c");
+    create_file(source.path(), "app.lock", b"source lock");
+    create_file(dest.path(), "app.lock", b"dest lock");
+    create_file(dest.path(), "logs/run.log", b"dest only");
+    create_file(dest.path(), "stale.txt", b"dest only");
+
+    let source_scan = scan_directory(source.path(), None).unwrap();
+    let dest_scan = scan_directory(dest.path(), None).unwrap();
+    let options = SyncOptions {
+        delete_removed: true,
+        protect: vec!["*.lock".to_string()],
+        keep: vec!["logs/**".to_string()],
+        limit: Some(2),
+        ..Default::default()
+    };
+
+    let simulated = simulate_sync(&source_scan, &dest_scan, &options).unwrap();
+
+    let diff = diff_scans(&source_scan, &dest_scan).unwrap();
+    sync_changes(source.path(), dest.path(), &diff, &options, None).unwrap();
+    let actual = scan_directory(dest.path(), None).unwrap();
+
+    let summarize = |scan: &ScanResult| {
+        let mut files: Vec<_> =
+            scan.files.iter().map(|f| (f.path.clone(), f.size, f.hash.clone())).collect();
+        files.sort_by(|a, b| a.0.cmp(&b.0));
+        files
+    };
+    assert_eq!(summarize(&simulated), summarize(&actual));
+    assert_eq!(actual.files.len(), 4, "two copies, the lock file, and the kept log");
+}
+
+#[test]
+fn test_scan_skips_vcs_directories() {
+    let temp_dir = TempDir::new().unwrap();
+    create_file(temp_dir.path(), "src/main.rs", b"fn main() {}");
+    create_file(temp_dir.path(), ".git/HEAD", b"ref: refs/heads/main");
+    create_file(temp_dir.path(), ".git/objects/ab/cdef", b"object");
+    create_file(temp_dir.path(), ".hg/store/data", b"hg");
+
+    let scan = scan_directory(temp_dir.path(), None).unwrap();
+    let paths: Vec<_> = scan.files.iter().map(|f| f.path.clone()).collect();
+    assert_eq!(paths, vec![PathBuf::from("src/main.rs")]);
+
+    let options = ScanOptions { include_vcs: true, ..Default::default() };
+    let scan = scan_directory_with_options(temp_dir.path(), &options, None).unwrap();
+    assert_eq!(scan.files.len(), 4);
+    assert!(scan.files.iter().any(|f| f.path == Path::new(".git/HEAD")));
+}
+
+#[test]
+fn test_collision_check() {
+    let source = TempDir::new().unwrap();
+    let dest = TempDir::new().unwrap();
+    create_file(source.path(), "same_path.txt", b"source bytes");
+    create_file(dest.path(), "same_path.txt", b"dest bytes!!");
+    create_file(source.path(), "moved.txt", b"real content");
+    create_file(dest.path(), "original.txt", b"fake content");
+
+    let source_scan = scan_directory(source.path(), None).unwrap();
+    let mut dest_scan = scan_directory(dest.path(), None).unwrap();
+
+    // Simulate collisions: give each dest file the hash of a different source file
+    for file in &mut dest_scan.files {
+        let twin = if file.path == Path::new("same_path.txt") {
+            "same_path.txt"
+        } else {
+            "moved.txt"
+        };
+        file.hash = source_scan
+            .files
+            .iter()
+            .find(|f| f.path == Path::new(twin))
+            .unwrap()
+            .hash
+            .clone();
+    }
+
+    let diff = diff_scans(&source_scan, &dest_scan).unwrap();
+    assert!(diff.modified.is_empty(), "hash equality alone hides the change");
+    assert_eq!(diff.renamed.len(), 1);
+
+    let options = DiffOptions {
+        collision_check: true,
+        ..Default::default()
+    };
+    let diff = diff_scans_with_options(&source_scan, &dest_scan, &options).unwrap();
+    assert_eq!(diff.modified.len(), 1);
+    assert_eq!(diff.modified[0].path, PathBuf::from("same_path.txt"));
+    assert!(diff.renamed.is_empty());
+    assert_eq!(diff.added.len(), 1);
+    assert_eq!(diff.removed.len(), 1);
+}
+
+#[test]
+fn test_scan_walk_builder_hook() {
+    let temp_dir = TempDir::new().unwrap();
+    create_file(temp_dir.path(), ".janusignore", b"*.tmp\n");
+    create_file(temp_dir.path(), "keep.txt", b"keep");
+    create_file(temp_dir.path(), "scratch.tmp", b"skip");
+
+    let scan = scan_directory(temp_dir.path(), None).unwrap();
+    assert_eq!(scan.files.len(), 3);
+
+    let options = ScanOptions::default().with_walk_builder(|builder| {
+        builder.add_custom_ignore_filename(".janusignore");
+    });
+    let scan = scan_directory_with_options(temp_dir.path(), &options, None).unwrap();
+    let mut paths: Vec<_> = scan.files.iter().map(|f| f.path.clone()).collect();
+    paths.sort();
+    assert_eq!(paths, vec![PathBuf::from(".janusignore"), PathBuf::from("keep.txt")]);
+}
+
+#[test]
+#[cfg(unix)]
+fn test_metadata_only_changes() {
+    use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+    let source = TempDir::new().unwrap();
+    let dest = TempDir::new().unwrap();
+    let src_file = create_file(source.path(), "script.sh", b"#!/bin/sh\n");
+    let dst_file = create_file(dest.path(), "script.sh", b"#!/bin/sh\n");
+    fs::set_permissions(&src_file, fs::Permissions::from_mode(0o755)).unwrap();
+    fs::set_permissions(&dst_file, fs::Permissions::from_mode(0o600)).unwrap();
+
+    // Changing ownership needs root; otherwise only the mode drifts
+    let is_root = unsafe { libc::geteuid() } == 0;
+    if is_root {
+        std::os::unix::fs::chown(&dst_file, Some(1234), Some(1234)).unwrap();
+    }
+    let inode_before = fs::metadata(&dst_file).unwrap().ino();
+
+    let source_scan = scan_directory(source.path(), None).unwrap();
+    let dest_scan = scan_directory(dest.path(), None).unwrap();
+
+    let diff = diff_scans(&source_scan, &dest_scan).unwrap();
+    assert!(diff.metadata_changed.is_empty(), "metadata comparison is opt-in");
+
+    let options = DiffOptions {
+        compare_metadata: true,
+        ..Default::default()
+    };
+    let diff = diff_scans_with_options(&source_scan, &dest_scan, &options).unwrap();
+    assert!(diff.modified.is_empty());
+    assert_eq!(diff.metadata_changed.len(), 1);
+
+    let report =
+        sync_changes(source.path(), dest.path(), &diff, &SyncOptions::default(), None).unwrap();
+    assert_eq!(report.files_copied, 0);
+    assert_eq!(report.files_metadata_updated, 1);
+
+    let src_meta = fs::metadata(&src_file).unwrap();
+    let dst_meta = fs::metadata(&dst_file).unwrap();
+    assert_eq!(dst_meta.ino(), inode_before, "content was not recopied");
+    assert_eq!(dst_meta.permissions().mode() & 0o777, 0o755);
+    assert_eq!((dst_meta.uid(), dst_meta.gid()), (src_meta.uid(), src_meta.gid()));
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn test_case_conflict_policy() {
+    let source = TempDir::new().unwrap();
+    create_file(source.path(), "Foo.txt", b"upper");
+    create_file(source.path(), "foo.txt", b"lower");
+    let source_scan = scan_directory(source.path(), None).unwrap();
+
+    let sync_with = |policy: CaseConflictPolicy, insensitive: Option<bool>| {
+        let dest = TempDir::new().unwrap();
+        let dest_scan = scan_directory(dest.path(), None).unwrap();
+        let diff = diff_scans(&source_scan, &dest_scan).unwrap();
+        let options = SyncOptions {
+            case_conflict_policy: policy,
+            dest_case_insensitive: insensitive,
+            ..Default::default()
+        };
+        let result = sync_changes(source.path(), dest.path(), &diff, &options, None);
+        (dest, result)
+    };
+
+    // A case-sensitive destination (detected at runtime) takes both files
+    let (dest, report) = sync_with(CaseConflictPolicy::Error, None);
+    assert_eq!(report.unwrap().files_copied, 2);
+    assert_file_content(&dest.path().join("foo.txt"), b"lower");
+
+    // Forcing a case-insensitive destination triggers the policy
+    let (dest, result) = sync_with(CaseConflictPolicy::Error, Some(true));
+    assert!(result.unwrap_err().to_string().contains("foo.txt"));
+    assert_eq!(fs::read_dir(dest.path()).unwrap().count(), 0, "nothing written on error");
+
+    let (dest, report) = sync_with(CaseConflictPolicy::SkipSecond, Some(true));
+    let report = report.unwrap();
+    assert_eq!(report.files_copied, 1);
+    assert_eq!(report.skipped[0].path, PathBuf::from("foo.txt"));
+    assert_eq!(report.skipped[0].reason, SkipReason::CaseConflict);
+    assert_file_content(&dest.path().join("Foo.txt"), b"upper");
+
+    let (dest, report) = sync_with(CaseConflictPolicy::Rename, Some(true));
+    assert_eq!(report.unwrap().files_copied, 2);
+    assert_file_content(&dest.path().join("Foo.txt"), b"upper");
+    assert_file_content(&dest.path().join("foo (1).txt"), b"lower");
+}
+
+#[test]
+fn test_scan_with_disabled_progress() {
+    let temp_dir = TempDir::new().unwrap();
+    create_file(temp_dir.path(), "a.txt", b"aaa");
+    create_file(temp_dir.path(), "nested/b.txt", b"bbbb");
+
+    let reporter = ProgressReporter::disabled();
+    let scan = scan_directory(temp_dir.path(), Some(&reporter)).unwrap();
+    assert_eq!(scan.files.len(), 2);
+    assert_eq!(scan.total_size(), 7);
+}
+
+#[test]
+#[cfg(unix)]
+fn test_minimize_writes_skips_matching_metadata() {
+    use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+    let source = TempDir::new().unwrap();
+    let dest = TempDir::new().unwrap();
+    let src_file = create_file(source.path(), "data.bin", b"identical");
+    let dst_file = create_file(dest.path(), "data.bin", b"identical");
+    fs::set_permissions(&src_file, fs::Permissions::from_mode(0o640)).unwrap();
+    fs::set_permissions(&dst_file, fs::Permissions::from_mode(0o600)).unwrap();
+
+    let source_scan = scan_directory(source.path(), None).unwrap();
+    let dest_scan = scan_directory(dest.path(), None).unwrap();
+    let options = DiffOptions {
+        compare_metadata: true,
+        ..Default::default()
+    };
+    let diff = diff_scans_with_options(&source_scan, &dest_scan, &options).unwrap();
+    assert_eq!(diff.metadata_changed.len(), 1);
+
+    // The destination catches up after the scan, so the planned update is stale
+    fs::set_permissions(&dst_file, fs::Permissions::from_mode(0o640)).unwrap();
+    thread::sleep(Duration::from_millis(20));
+    let ctime = |path: &Path| {
+        let meta = fs::metadata(path).unwrap();
+        (meta.ctime(), meta.ctime_nsec())
+    };
+    let before = ctime(&dst_file);
+
+    let options = SyncOptions {
+        minimize_writes: true,
+        ..Default::default()
+    };
+    let report = sync_changes(source.path(), dest.path(), &diff, &options, None).unwrap();
+    assert_eq!(report.files_metadata_updated, 0);
+    assert_eq!(report.skipped[0].reason, SkipReason::UpToDate);
+    assert_eq!(ctime(&dst_file), before, "no write syscalls touched the file");
+
+    // Without the option the stale update is written anyway
+    let report =
+        sync_changes(source.path(), dest.path(), &diff, &SyncOptions::default(), None).unwrap();
+    assert_eq!(report.files_metadata_updated, 1);
+    assert_ne!(ctime(&dst_file), before);
+}
+
+#[test]
+fn test_reconcile() {
+    let source = TempDir::new().unwrap();
+    let dest = TempDir::new().unwrap();
+    create_complex_scenario(source.path(), dest.path());
+
+    let options = ReconcileOptions {
+        sync: SyncOptions {
+            delete_removed: true,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let report = reconcile(source.path(), dest.path(), &options).unwrap();
+    assert_eq!(report.files_copied, 2);
+    assert_eq!(report.files_renamed, 1);
+    assert_eq!(report.files_deleted, 1);
+
+    let plan = plan_reconcile(source.path(), dest.path(), &options).unwrap();
+    assert!(!plan.has_changes(true), "destination matches the source");
+    for file in &plan.source.files {
+        assert_file_content(
+            &dest.path().join(&file.path),
+            &fs::read(source.path().join(&file.path)).unwrap(),
+        );
+    }
+}
+
+#[test]
+fn test_trusted_dest_manifest() {
+    let source = TempDir::new().unwrap();
+    let dest = TempDir::new().unwrap();
+    let state = TempDir::new().unwrap();
+    create_complex_scenario(source.path(), dest.path());
+
+    let manifest = state.path().join("dest.json");
+    let mut options = ReconcileOptions {
+        sync: SyncOptions {
+            delete_removed: true,
+            ..Default::default()
+        },
+        trusted_dest_manifest: Some(manifest.clone()),
+        dest_sample_rate: 0.0,
+        ..Default::default()
+    };
+    let plan = plan_reconcile(source.path(), dest.path(), &options).unwrap();
+    assert!(!plan.dest_from_manifest, "no manifest on the first run");
+    reconcile(source.path(), dest.path(), &options).unwrap();
+    assert!(manifest.exists());
+
+    // With sampling off, a change behind Janus's back goes unnoticed while the manifest is trusted
+    let victim = &plan.source.files[0].path;
+    fs::remove_file(dest.path().join(victim)).unwrap();
+
+    let plan = plan_reconcile(source.path(), dest.path(), &options).unwrap();
+    assert!(plan.dest_from_manifest);
+    assert!(!plan.has_changes(true), "destination was not re-read");
+
+    options.verify_dest = true;
+    let plan = plan_reconcile(source.path(), dest.path(), &options).unwrap();
+    assert!(!plan.dest_from_manifest);
+    assert!(plan.diff.added.iter().any(|f| &f.path == victim));
+}
+
+#[test]
+fn test_dest_manifest_drift_sampling() {
+    let source = TempDir::new().unwrap();
+    let dest = TempDir::new().unwrap();
+    let state = TempDir::new().unwrap();
+    create_complex_scenario(source.path(), dest.path());
+
+    let mut options = ReconcileOptions {
+        trusted_dest_manifest: Some(state.path().join("dest.json")),
+        dest_sample_rate: 1.0,
+        ..Default::default()
+    };
+    reconcile(source.path(), dest.path(), &options).unwrap();
+
+    let plan = plan_reconcile(source.path(), dest.path(), &options).unwrap();
+    assert!(plan.dest_from_manifest);
+    assert!(plan.dest_drift.is_empty(), "freshly written manifest matches disk");
+
+    // Modify a destination file out-of-band
+    let victim = plan.dest.files[0].path.clone();
+    fs::write(dest.path().join(&victim), b"changed by someone else").unwrap();
+    assert_eq!(sample_manifest_drift(&plan.dest, 1.0), vec![victim.clone()]);
+
+    options.drift_policy = DriftPolicy::Warn;
+    let plan = plan_reconcile(source.path(), dest.path(), &options).unwrap();
+    assert!(plan.dest_from_manifest);
+    assert_eq!(plan.dest_drift, vec![victim.clone()]);
+
+    options.drift_policy = DriftPolicy::Rescan;
+    let plan = plan_reconcile(source.path(), dest.path(), &options).unwrap();
+    assert!(!plan.dest_from_manifest, "drift forces a real scan");
+    assert!(plan.diff.modified.iter().any(|f| f.path == victim));
+}
+
+#[test]
+#[cfg(unix)]
+fn test_drain_fifos() {
+    use std::ffi::CString;
+    use std::io::Write;
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::fs::PermissionsExt;
+
+    let source = TempDir::new().unwrap();
+    let dest = TempDir::new().unwrap();
+    create_file(source.path(), "regular.txt", b"regular");
+
+    let fifo = source.path().join("pipe");
+    let c_path = CString::new(fifo.as_os_str().as_bytes()).unwrap();
+    assert_eq!(unsafe { libc::mkfifo(c_path.as_ptr(), 0o600) }, 0);
+
+    // FIFOs are skipped by default
+    let scan = scan_directory(source.path(), None).unwrap();
+    assert!(scan.files.iter().all(|f| f.path != Path::new("pipe")));
+
+    // Opening for write blocks until the scan opens the pipe for reading
+    let writer = thread::spawn(move || {
+        let mut pipe = fs::OpenOptions::new().write(true).open(fifo).unwrap();
+        pipe.write_all(b"streamed through a pipe").unwrap();
+    });
+
+    let options = ReconcileOptions {
+        scan: ScanOptions { drain_fifos: true, ..Default::default() },
+        ..Default::default()
+    };
+    let report = reconcile(source.path(), dest.path(), &options).unwrap();
+    writer.join().unwrap();
+
+    assert_eq!(report.files_copied, 2);
+    let drained = dest.path().join("pipe");
+    assert!(fs::symlink_metadata(&drained).unwrap().is_file());
+    assert_file_content(&drained, b"streamed through a pipe");
+
+    // The spool is private to the scan and removed with it, even unsynced
+    let fifo = source.path().join("pipe");
+    let writer = thread::spawn(move || {
+        let mut pipe = fs::OpenOptions::new().write(true).open(fifo).unwrap();
+        pipe.write_all(b"never synced").unwrap();
+    });
+    let scan = scan_directory_with_options(source.path(), &options.scan, None).unwrap();
+    writer.join().unwrap();
+    let spool = scan.fifo_spool.as_ref().unwrap().path().to_path_buf();
+    assert_eq!(fs::metadata(&spool).unwrap().permissions().mode() & 0o777, 0o700);
+    assert!(spool.join(hash_bytes(b"never synced").to_string()).is_file());
+    drop(scan);
+    assert!(!spool.exists());
+}
+
+#[test]
+#[cfg(unix)]
+fn test_scan_resumes_from_checkpoint() {
+    use std::ffi::CString;
+    use std::io::Write;
+    use std::os::unix::ffi::OsStrExt;
+
+    let source = TempDir::new().unwrap();
+    let state = TempDir::new().unwrap();
+    let checkpoint = state.path().join("scan.checkpoint");
+    let first = create_file(source.path(), "a.txt", b"first");
+    create_file(source.path(), "b.txt", b"second");
+    let mtime = fs::metadata(&first).unwrap().modified().unwrap();
+
+    // The scan hashes in path order and stalls on the pipe after both files
+    let fifo = source.path().join("zz-pipe");
+    let c_path = CString::new(fifo.as_os_str().as_bytes()).unwrap();
+    assert_eq!(unsafe { libc::mkfifo(c_path.as_ptr(), 0o600) }, 0);
+    let options = ScanOptions {
+        scan_order: ScanOrder::PathSorted,
+        drain_fifos: true,
+        scan_checkpoint: Some(checkpoint.clone()),
+        checkpoint_interval: Some(Duration::from_millis(10)),
+        ..Default::default()
+    };
+    let stalled = {
+        let (root, options) = (source.path().to_path_buf(), options.clone());
+        thread::spawn(move || scan_directory_with_options(&root, &options, None).unwrap())
+    };
+
+    // Keep the checkpoint as a crash at this point would leave it
+    let deadline = std::time::Instant::now() + Duration::from_secs(10);
+    let saved = loop {
+        if let Ok(saved) = ScanResult::load_from_file(&checkpoint) {
+            if saved.files.len() == 2 {
+                break fs::read(&checkpoint).unwrap();
+            }
+        }
+        assert!(std::time::Instant::now() < deadline, "checkpoint was never saved");
+        thread::sleep(Duration::from_millis(5));
+    };
+    fs::OpenOptions::new()
+        .write(true)
+        .open(&fifo)
+        .unwrap()
+        .write_all(b"pipe")
+        .unwrap();
+    stalled.join().unwrap();
+    assert!(!checkpoint.exists(), "a completed scan removes its checkpoint");
+    fs::write(&checkpoint, saved).unwrap();
+    fs::remove_file(&fifo).unwrap();
+
+    // Same size and mtime, new content: only a re-read would notice
+    fs::write(&first, b"FIRST").unwrap();
+    set_file_mtime(&first, mtime).unwrap();
+    create_file(source.path(), "c.txt", b"third");
+
+    let options = ScanOptions { drain_fifos: false, ..options };
+    let resumed = scan_directory_with_options(source.path(), &options, None).unwrap();
+    let hash_of =
+        |name: &str| resumed.files.iter().find(|f| f.path == Path::new(name)).unwrap().hash.clone();
+    assert_eq!(resumed.files.len(), 3);
+    assert_eq!(hash_of("a.txt"), hash_bytes(b"first"));
+    assert_eq!(hash_of("b.txt"), hash_bytes(b"second"));
+    assert_eq!(hash_of("c.txt"), hash_bytes(b"third"));
+    assert!(!checkpoint.exists());
+}
+
+#[test]
+fn test_bidirectional_keep_both() {
+    let left = TempDir::new().unwrap();
+    let right = TempDir::new().unwrap();
+    create_file(left.path(), "notes.txt", b"edited on the laptop");
+    create_file(right.path(), "notes.txt", b"edited on the desktop");
+    create_file(left.path(), "left_only.txt", b"left");
+    create_file(right.path(), "right_only.txt", b"right");
+
+    let options = BidirectionalOptions::default().with_conflict_resolver(|_, _| {
+        Resolution::KeepBoth { suffix: "conflict-desktop".to_string() }
+    });
+    let report = sync_bidirectional(left.path(), right.path(), &options).unwrap();
+
+    assert_eq!(report.conflicts.len(), 1);
+    assert_eq!(report.conflicts[0].path, PathBuf::from("notes.txt"));
+    for side in [left.path(), right.path()] {
+        assert_file_content(&side.join("notes.txt"), b"edited on the laptop");
+        assert_file_content(&side.join("notes.conflict-desktop.txt"), b"edited on the desktop");
+        assert_file_content(&side.join("left_only.txt"), b"left");
+        assert_file_content(&side.join("right_only.txt"), b"right");
+    }
+
+    // Both sides now agree, so a second run finds nothing to resolve
+    let report = sync_bidirectional(left.path(), right.path(), &options).unwrap();
+    assert!(report.conflicts.is_empty());
+    assert_eq!(report.left.files_copied + report.right.files_copied, 0);
+}
+
+#[test]
+fn test_bidirectional_with_deferred_hashes() {
+    let left = TempDir::new().unwrap();
+    let right = TempDir::new().unwrap();
+    create_file(left.path(), "same_size.txt", b"aaaa");
+    create_file(right.path(), "same_size.txt", b"bbbb");
+    create_file(left.path(), "identical.txt", b"same");
+    create_file(right.path(), "identical.txt", b"same");
+
+    let options = BidirectionalOptions {
+        scan: ScanOptions {
+            hash_policy: HashPolicy::OnConflict,
+            ..Default::default()
+        },
+        ..Default::default()
+    }
+    .with_conflict_resolver(|_, _| Resolution::TakeLeft);
+    let report = sync_bidirectional(left.path(), right.path(), &options).unwrap();
+
+    assert_eq!(report.conflicts.len(), 1);
+    assert_eq!(report.conflicts[0].path, PathBuf::from("same_size.txt"));
+    assert_file_content(&right.path().join("same_size.txt"), b"aaaa");
+    assert_eq!(report.left.files_copied + report.right.files_copied, 1);
+}
+
+#[test]
+fn test_bidirectional_clock_skew() {
+    // Left was edited at `edited`. The right copy was written 10s earlier,
+    // but the right clock runs 30s fast so it was stamped 20s later.
+    let edited = SystemTime::now() - Duration::from_secs(3_600);
+    let sync_with_skew = |clock_skew: Duration| {
+        let left = TempDir::new().unwrap();
+        let right = TempDir::new().unwrap();
+        let stamp = |path: PathBuf, mtime: SystemTime| {
+            fs::File::options().write(true).open(path).unwrap().set_modified(mtime).unwrap();
+        };
+        stamp(create_file(left.path(), "doc.txt", b"fresh edit"), edited);
+        let stale = create_file(right.path(), "doc.txt", b"stale copy");
+        stamp(stale, edited + Duration::from_secs(20));
+
+        let options = BidirectionalOptions { clock_skew, ..Default::default() };
+        let report = sync_bidirectional(left.path(), right.path(), &options).unwrap();
+        assert_eq!(report.conflicts.len(), 1);
+        (
+            report.conflicts[0].resolution.clone(),
+            fs::read(right.path().join("doc.txt")).unwrap(),
+        )
+    };
+
+    assert_eq!(sync_with_skew(Duration::ZERO), (Resolution::TakeRight, b"stale copy".to_vec()));
+    let adjusted = sync_with_skew(Duration::from_secs(30));
+    assert_eq!(adjusted, (Resolution::TakeLeft, b"fresh edit".to_vec()));
+}
+
+#[test]
+fn test_expected_tree_hash() {
+    let source = TempDir::new().unwrap();
+    let dest = TempDir::new().unwrap();
+    create_file(source.path(), "app/main.js", b"console.log('hi')");
+    create_file(source.path(), "index.html", b"<html></html>");
+    let expected = scan_directory(source.path(), None).unwrap().tree_hash().unwrap();
+
+    let options = ReconcileOptions {
+        expected_tree_hash: Some(expected.clone()),
+        ..Default::default()
+    };
+    reconcile(source.path(), dest.path(), &options).unwrap();
+    assert_eq!(scan_directory(dest.path(), None).unwrap().tree_hash().unwrap(), expected);
+
+    // A stray destination file survives a sync without --delete
+    create_file(dest.path(), "stray.txt", b"left over");
+    let err = reconcile(source.path(), dest.path(), &options).unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<SyncError>(),
+        Some(SyncError::TreeHashMismatch { expected: e, .. }) if *e == expected
+    ));
+    assert!(err.to_string().contains("does not match the expected"));
+}
+
+#[test]
+fn test_hash_skip_ranges() {
+    let source = TempDir::new().unwrap();
+    let dest = TempDir::new().unwrap();
+    // Identical apart from an embedded build timestamp and build ID
+    create_file(source.path(), "build/app.bin", b"HDR:20240101;id=a1;payload");
+    create_file(dest.path(), "build/app.bin", b"HDR:20240315;id=b7;payload");
+    create_file(source.path(), "notes.txt", b"HDR:20240101;payload");
+    create_file(dest.path(), "notes.txt", b"HDR:20240315;payload");
+
+    let options = ScanOptions {
+        hash_skip_ranges: vec![HashSkipRanges {
+            glob: "**/*.bin".to_string(),
+            ranges: vec![4..12, 16..18],
+        }],
+        ..Default::default()
+    };
+    let source_scan = scan_directory_with_options(source.path(), &options, None).unwrap();
+    let dest_scan = scan_directory_with_options(dest.path(), &options, None).unwrap();
+    let hash_of = |scan: &ScanResult, path: &str| {
+        scan.files.iter().find(|f| f.path == Path::new(path)).unwrap().hash.clone()
+    };
+    assert_eq!(hash_of(&source_scan, "build/app.bin"), hash_of(&dest_scan, "build/app.bin"));
+    assert_ne!(hash_of(&source_scan, "notes.txt"), hash_of(&dest_scan, "notes.txt"));
+
+    let diff = diff_scans(&source_scan, &dest_scan).unwrap();
+    let modified: Vec<&Path> = diff.modified.iter().map(|f| f.path.as_path()).collect();
+    assert_eq!(modified, vec![Path::new("notes.txt")]);
+}
+
+#[test]
+#[cfg(unix)]
+fn test_content_only_skips_metadata() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let source = TempDir::new().unwrap();
+    let dest = TempDir::new().unwrap();
+    let src_file = create_file(source.path(), "script.sh", b"#!/bin/sh\necho hi\n");
+    fs::set_permissions(&src_file, fs::Permissions::from_mode(0o741)).unwrap();
+    let old_mtime = SystemTime::now() - Duration::from_secs(30 * 24 * 3600);
+    fs::File::options()
+        .write(true)
+        .open(&src_file)
+        .unwrap()
+        .set_modified(old_mtime)
+        .unwrap();
+
+    let source_scan = scan_directory(source.path(), None).unwrap();
+    let dest_scan = scan_directory(dest.path(), None).unwrap();
+    let diff = diff_scans(&source_scan, &dest_scan).unwrap();
+
+    for strategy in [OverwriteStrategy::InPlace, OverwriteStrategy::TempThenRename] {
+        let options = SyncOptions {
+            content_only: true,
+            overwrite_strategy: strategy,
+            ..Default::default()
+        };
+        sync_changes(source.path(), dest.path(), &diff, &options, None).unwrap();
+
+        let dst_file = dest.path().join("script.sh");
+        assert_file_content(&dst_file, b"#!/bin/sh\necho hi\n");
+        let meta = fs::metadata(&dst_file).unwrap();
+        assert_ne!(meta.permissions().mode() & 0o777, 0o741, "mode was not copied");
+        assert!(meta.modified().unwrap() > old_mtime + Duration::from_secs(3600), "fresh mtime");
+        fs::remove_file(&dst_file).unwrap();
+    }
+}
+
+#[test]
+fn test_relative_root_manifest_rebase() {
+    // A path relative to the working directory (the package root under cargo)
+    fs::create_dir_all("target").unwrap();
+    let original = TempDir::new_in("target").unwrap();
+    let cwd = std::env::current_dir().unwrap();
+    let relative = original.path().strip_prefix(&cwd).unwrap();
+    create_file(relative, "docs/readme.md", b"hello");
+    create_file(relative, "data.csv", b"1,2,3");
+
+    let scan = scan_directory(&Path::new(".").join(relative), None).unwrap();
+    assert_eq!(scan.root, cwd.join(relative));
+
+    let state = TempDir::new().unwrap();
+    let manifest = state.path().join("manifest.json");
+    scan.save_to_file(&manifest).unwrap();
+
+    // The same tree restored at another location
+    let elsewhere = TempDir::new().unwrap();
+    create_file(elsewhere.path(), "docs/readme.md", b"hello");
+    create_file(elsewhere.path(), "data.csv", b"1,2,3");
+
+    let mut loaded = ScanResult::load_from_file(&manifest).unwrap();
+    assert_eq!(loaded.root, scan.root);
+    loaded.rebase_root(elsewhere.path());
+    assert_eq!(loaded.root, elsewhere.path());
+
+    let rescanned = scan_directory(elsewhere.path(), None).unwrap();
+    assert_eq!(loaded.tree_hash().unwrap(), rescanned.tree_hash().unwrap());
+    for file in &loaded.files {
+        assert!(loaded.root.join(&file.path).is_file());
+    }
+}
+
+#[test]
+#[cfg(unix)]
+fn test_cache_detects_replaced_file() {
+    let source = TempDir::new().unwrap();
+    let file = create_file(source.path(), "config.toml", b"mode = 'safe'");
+    let cache = Arc::new(Mutex::new(HashCache::new(CacheConfig::default())));
+    let options = ScanOptions {
+        hash_cache: Some(Arc::clone(&cache)),
+        ..Default::default()
+    };
+    let before = scan_directory_with_options(source.path(), &options, None).unwrap();
+
+    // Replace the file atomically, carefully keeping its size and mtime
+    let mtime = fs::metadata(&file).unwrap().modified().unwrap();
+    let replacement = create_file(source.path(), "config.toml.new", b"mode = 'fast'");
+    set_file_mtime(&replacement, mtime).unwrap();
+    fs::rename(&replacement, &file).unwrap();
+    let metadata = fs::metadata(&file).unwrap();
+    assert_eq!((metadata.len(), metadata.modified().unwrap()), (before.files[0].size, mtime));
+
+    let after = scan_directory_with_options(source.path(), &options, None).unwrap();
+    assert_ne!(after.files[0].inode, before.files[0].inode);
+    assert_eq!(after.files[0].hash, hash_bytes(b"mode = 'fast'"), "cache entry was not reused");
+    let diff = diff_scans(&after, &before).unwrap();
+    assert_eq!(diff.modified.len(), 1);
+}
+
+#[test]
+fn test_sync_byte_quota() {
+    let source = TempDir::new().unwrap();
+    let dest = TempDir::new().unwrap();
+    for name in ["a.bin", "b.bin", "c.bin"] {
+        create_file(source.path(), name, &[7u8; 400]);
+    }
+
+    let source_scan = scan_directory(source.path(), None).unwrap();
+    let dest_scan = scan_directory(dest.path(), None).unwrap();
+    let diff = diff_scans(&source_scan, &dest_scan).unwrap();
+
+    // Room for two of the three files
+    let options = SyncOptions {
+        byte_quota: Some(1000),
+        ..Default::default()
+    };
+    let report = sync_changes(source.path(), dest.path(), &diff, &options, None).unwrap();
+
+    assert_eq!(report.files_copied, 2);
+    assert_eq!(report.bytes_copied, 800);
+    assert_eq!(report.skipped.len(), 1);
+    assert_eq!(report.skipped[0].reason, SkipReason::QuotaReached);
+    assert!(!dest.path().join(&report.skipped[0].path).exists());
+
+    // The deferred file goes through on the next run
+    let dest_scan = scan_directory(dest.path(), None).unwrap();
+    let diff = diff_scans(&source_scan, &dest_scan).unwrap();
+    let report = sync_changes(source.path(), dest.path(), &diff, &options, None).unwrap();
+    assert_eq!(report.files_copied, 1);
+    assert!(report.skipped.is_empty());
+}
+
+#[test]
+fn test_sync_newest_first_with_limit() {
+    let source = TempDir::new().unwrap();
+    let dest = TempDir::new().unwrap();
+
+    for (name, secs) in [("a.txt", 1000), ("b.txt", 4000), ("c.txt", 2000), ("d.txt", 3000)] {
+        let path = create_file(source.path(), name, name.as_bytes());
+        set_file_mtime(&path, UNIX_EPOCH + Duration::from_secs(secs)).unwrap();
+    }
+    // The newest change is a rename, which competes for the same limit
+    let moved = create_file(source.path(), "moved.txt", b"moved content");
+    set_file_mtime(&moved, UNIX_EPOCH + Duration::from_secs(5000)).unwrap();
+    create_file(dest.path(), "old.txt", b"moved content");
+
+    let source_scan = scan_directory(source.path(), None).unwrap();
+    let dest_scan = scan_directory(dest.path(), None).unwrap();
+    let diff = diff_scans(&source_scan, &dest_scan).unwrap();
+
+    let options = SyncOptions {
+        transfer_order: TransferOrder::NewestFirst,
+        limit: Some(2),
+        ..Default::default()
+    };
+    let report = sync_changes(source.path(), dest.path(), &diff, &options, None).unwrap();
+
+    assert_eq!((report.files_renamed, report.files_copied), (1, 1));
+    assert!(dest.path().join("moved.txt").exists());
+    assert!(dest.path().join("b.txt").exists());
+    let mut deferred: Vec<_> = report.skipped.iter().map(|s| s.path.clone()).collect();
+    deferred.sort();
+    assert_eq!(deferred, ["a.txt", "c.txt", "d.txt"].map(PathBuf::from));
+    assert!(report.skipped.iter().all(|s| s.reason == SkipReason::LimitReached));
+    for name in ["a.txt", "c.txt", "d.txt"] {
+        assert!(!dest.path().join(name).exists());
+    }
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn test_verify_after_copy_bypassing_cache() {
+    let source = TempDir::new().unwrap();
+    let dest = TempDir::new().unwrap();
+    create_file(source.path(), "a.txt", b"first");
+    create_file(source.path(), "nested/b.txt", b"second");
+
+    let source_scan = scan_directory(source.path(), None).unwrap();
+    let dest_scan = scan_directory(dest.path(), None).unwrap();
+    let mut diff = diff_scans(&source_scan, &dest_scan).unwrap();
+
+    let options = SyncOptions {
+        verify_after_copy: true,
+        verify_bypass_cache: true,
+        ..Default::default()
+    };
+    assert!(options.validate().is_ok());
+    let report = sync_changes(source.path(), dest.path(), &diff, &options, None).unwrap();
+    assert_eq!(report.files_copied, 2);
+
+    // A copy that doesn't hash to the planned content fails verification
+    diff.added[0].hash = hash_bytes(b"not what was copied");
+    let err = sync_changes(source.path(), dest.path(), &diff, &options, None).unwrap_err();
+    assert!(err.to_string().contains("does not match the source"), "{err:#}");
+}
+
+#[test]
+fn test_replicate_empty_parents() {
+    let source = TempDir::new().unwrap();
+    create_file(source.path(), ".ignore", b"*.log\nbuild/\n");
+    create_file(source.path(), "deep/nested/dir/kept.txt", b"kept");
+    create_file(source.path(), "logs/app/debug.log", b"filtered out");
+    create_file(source.path(), "build/out.bin", b"skipped directory");
+    create_file(source.path(), ".nobackup-dir/.nobackup", b"");
+    fs::create_dir(source.path().join("empty")).unwrap();
+    let scan = ScanOptions {
+        exclude_if_present: vec![".nobackup".to_string()],
+        ..Default::default()
+    };
+
+    // By default only the parents of synced files are created
+    let dest = TempDir::new().unwrap();
+    let options = ReconcileOptions { scan: scan.clone(), ..Default::default() };
+    reconcile(source.path(), dest.path(), &options).unwrap();
+    assert!(dest.path().join("deep/nested/dir/kept.txt").exists());
+    assert!(!dest.path().join("logs").exists());
+    assert!(!dest.path().join("empty").exists());
+
+    let dest = TempDir::new().unwrap();
+    let options = ReconcileOptions {
+        scan,
+        sync: SyncOptions {
+            replicate_empty_parents: true,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    reconcile(source.path(), dest.path(), &options).unwrap();
+    assert!(dest.path().join("deep/nested/dir/kept.txt").exists());
+    assert!(dest.path().join("logs/app").is_dir());
+    assert!(!dest.path().join("logs/app/debug.log").exists());
+    assert!(dest.path().join("empty").is_dir());
+    // Directories the walk skipped stay out
+    assert!(!dest.path().join("build").exists());
+    assert!(!dest.path().join(".nobackup-dir").exists());
+}
+
+#[test]
+#[cfg(unix)]
+fn test_sync_over_transport() {
+    use janus::transport::{serve, sync_to_transport, PipeTransport};
+    use std::os::unix::net::UnixStream;
+
+    let source = TempDir::new().unwrap();
+    let dest = TempDir::new().unwrap();
+    create_file(source.path(), "a.txt", b"alpha");
+    create_file(source.path(), "nested/b.txt", b"beta");
+    create_file(source.path(), ".ignore", b"*.log\n");
+    create_file(dest.path(), "stale.txt", b"old");
+    // Files the source scan filters out aren't the sync's to delete
+    create_file(dest.path(), ".git/HEAD", b"ref: refs/heads/main");
+    create_file(dest.path(), "debug.log", b"kept");
+
+    let (client, server) = UnixStream::pair().unwrap();
+    let dest_root = dest.path().to_path_buf();
+    let server = thread::spawn(move || serve(&dest_root, server.try_clone().unwrap(), server));
+    let mut transport = PipeTransport::new(client.try_clone().unwrap(), client);
+
+    let options = ScanOptions::default();
+    let scan = scan_directory(source.path(), None).unwrap();
+    let report = sync_to_transport(&scan, &options, &mut transport, true).unwrap();
+    assert_eq!((report.files_copied, report.files_deleted, report.bytes_copied), (3, 1, 15));
+    let diff = diff_scans(&scan, &scan_directory(dest.path(), None).unwrap()).unwrap();
+    assert!(diff.added.is_empty() && diff.modified.is_empty() && diff.removed.is_empty());
+    assert!(!dest.path().join("stale.txt").exists());
+    assert!(dest.path().join(".git/HEAD").exists());
+    assert!(dest.path().join("debug.log").exists());
+
+    // Only changed files are sent again
+    create_file(source.path(), "a.txt", b"alpha, edited");
+    let scan = scan_directory(source.path(), None).unwrap();
+    let report = sync_to_transport(&scan, &options, &mut transport, true).unwrap();
+    assert_eq!((report.files_copied, report.files_deleted, report.bytes_copied), (1, 0, 13));
+    assert_eq!(fs::read(dest.path().join("a.txt")).unwrap(), b"alpha, edited");
+
+    drop(transport);
+    server.join().unwrap().unwrap();
+}
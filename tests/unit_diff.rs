@@ -0,0 +1,220 @@
+//! Unit tests for diff options
+
+use janus::core::{
+    detect_clock_skew, diff_scans_with_options, DiffOptions, FileMeta, NfForm, ScanResult,
+};
+use janus::hash::hash_bytes;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Helper to create a FileMeta with a fixed mtime (seconds since the epoch)
+fn make_file_meta(path: &str, content: &[u8], mtime_secs: u64) -> FileMeta {
+    FileMeta {
+        path: PathBuf::from(path),
+        size: content.len() as u64,
+        mtime: UNIX_EPOCH + Duration::from_secs(mtime_secs),
+        hash: hash_bytes(content),
+        permissions: None,
+        owner: None,
+        metadata_hash: None,
+        inode: None,
+        ctime: None,
+        atime: None,
+        btime: None,
+        hash_deferred: false,
+    }
+}
+
+/// Helper to create a ScanResult from a list of files
+fn make_scan(files: Vec<FileMeta>) -> ScanResult {
+    ScanResult {
+        root: PathBuf::from("/test"),
+        files,
+        scan_time: SystemTime::now(),
+        warnings: Vec::new(),
+        dirs: Vec::new(),
+        traversed_dirs: Vec::new(),
+        fifo_spool: None,
+    }
+}
+
+#[test]
+fn test_newer_wins_with_clock_skew() {
+    // Source edited at t=1000. The destination copy was written at t=990, but
+    // the destination clock runs 30s fast so it was stamped t=1020.
+    let source = make_scan(vec![make_file_meta("doc.txt", b"fresh edit", 1000)]);
+    let dest = make_scan(vec![make_file_meta("doc.txt", b"stale copy", 1020)]);
+
+    let naive = DiffOptions { newer_wins: true, ..Default::default() };
+    let diff = diff_scans_with_options(&source, &dest, &naive).unwrap();
+    assert!(diff.modified.is_empty(), "Unadjusted comparison keeps the stale copy");
+    assert_eq!(diff.kept_newer.len(), 1);
+
+    let adjusted = DiffOptions {
+        newer_wins: true,
+        clock_skew: Duration::from_secs(30),
+        ..Default::default()
+    };
+    let diff = diff_scans_with_options(&source, &dest, &adjusted).unwrap();
+    assert_eq!(diff.modified.len(), 1, "Skew-adjusted comparison picks the source");
+    assert!(diff.kept_newer.is_empty());
+}
+
+#[test]
+fn test_newer_wins_keeps_genuinely_newer_dest() {
+    let source = make_scan(vec![make_file_meta("doc.txt", b"old", 1000)]);
+    let dest = make_scan(vec![make_file_meta("doc.txt", b"edited on dest", 2000)]);
+
+    let options = DiffOptions {
+        newer_wins: true,
+        clock_skew: Duration::from_secs(30),
+        ..Default::default()
+    };
+    let diff = diff_scans_with_options(&source, &dest, &options).unwrap();
+    assert!(diff.modified.is_empty());
+    assert_eq!(diff.kept_newer[0].path, PathBuf::from("doc.txt"));
+}
+
+#[test]
+fn test_detect_clock_skew() {
+    let names = ["a", "b", "c", "d", "e", "f"];
+    let source = make_scan(names.iter().map(|n| make_file_meta(n, b"x", 1000)).collect());
+
+    // Every destination file is ~60s ahead
+    let dest = make_scan(
+        names
+            .iter()
+            .enumerate()
+            .map(|(i, n)| make_file_meta(n, b"x", 1060 + (i as u64 % 2)))
+            .collect(),
+    );
+    let skew = detect_clock_skew(&source, &dest).unwrap();
+    assert!(skew >= Duration::from_secs(60) && skew <= Duration::from_secs(61));
+
+    // Mixed offsets are not a constant skew
+    let mut mixed = dest.clone();
+    mixed.files[0].mtime = UNIX_EPOCH + Duration::from_secs(1030);
+    assert_eq!(detect_clock_skew(&source, &mixed), None);
+
+    // Files copied with their mtimes don't hide the skew of the rest
+    let mut partly_copied = dest.clone();
+    partly_copied.files[0].mtime = UNIX_EPOCH + Duration::from_secs(1000);
+    let skew = detect_clock_skew(&source, &partly_copied).unwrap();
+    assert!(skew >= Duration::from_secs(60) && skew <= Duration::from_secs(61));
+
+    // Too few samples to say anything
+    let few = make_scan(dest.files[..2].to_vec());
+    assert_eq!(detect_clock_skew(&source, &few), None);
+}
+
+#[test]
+fn test_unicode_normalization() {
+    let nfc = "caf\u{e9}.txt";
+    let nfd = "cafe\u{301}.txt";
+    let source = make_scan(vec![make_file_meta(nfc, b"menu", 1000)]);
+    let dest = make_scan(vec![make_file_meta(nfd, b"menu", 1000)]);
+
+    // Byte-wise the names differ, so the file looks renamed
+    let diff = diff_scans_with_options(&source, &dest, &DiffOptions::default()).unwrap();
+    assert_eq!(diff.renamed.len(), 1);
+
+    for form in [NfForm::Nfc, NfForm::Nfd] {
+        let options = DiffOptions {
+            unicode_normalization: Some(form),
+            ..Default::default()
+        };
+        let diff = diff_scans_with_options(&source, &dest, &options).unwrap();
+        assert!(diff.added.is_empty() && diff.removed.is_empty() && diff.renamed.is_empty());
+        assert!(diff.modified.is_empty());
+    }
+
+    // Modified files keep the source's byte form
+    let dest = make_scan(vec![make_file_meta(nfd, b"old menu", 1000)]);
+    let options = DiffOptions {
+        unicode_normalization: Some(NfForm::Nfc),
+        ..Default::default()
+    };
+    let diff = diff_scans_with_options(&source, &dest, &options).unwrap();
+    assert_eq!(diff.modified.len(), 1);
+    assert_eq!(diff.modified[0].path, PathBuf::from(nfc));
+    assert!(diff.removed.is_empty());
+}
+
+#[test]
+fn test_detect_renames_off_reports_add_and_remove() {
+    let source = make_scan(vec![make_file_meta("new/report.pdf", b"report", 1000)]);
+    let dest = make_scan(vec![make_file_meta("old/report.pdf", b"report", 1000)]);
+
+    let diff = diff_scans_with_options(&source, &dest, &DiffOptions::default()).unwrap();
+    assert_eq!(diff.renamed.len(), 1);
+
+    let options = DiffOptions {
+        detect_renames: false,
+        ..Default::default()
+    };
+    let diff = diff_scans_with_options(&source, &dest, &options).unwrap();
+    assert!(diff.renamed.is_empty());
+    assert_eq!(diff.added[0].path, PathBuf::from("new/report.pdf"));
+    assert_eq!(diff.removed[0].path, PathBuf::from("old/report.pdf"));
+}
+
+#[test]
+fn test_structure_only_ignores_content() {
+    let source = make_scan(vec![
+        make_file_meta("src/main.rs", b"fn main() {}", 1000),
+        make_file_meta("README.md", b"# Template", 1000),
+        make_file_meta("moved.txt", b"same", 1000),
+    ]);
+    let dest = make_scan(vec![
+        make_file_meta("src/main.rs", b"fn main() { run() }", 2000),
+        make_file_meta("README.md", b"# My project", 3000),
+        make_file_meta("elsewhere.txt", b"same", 1000),
+    ]);
+
+    let options = DiffOptions {
+        structure_only: true,
+        ..Default::default()
+    };
+    let diff = diff_scans_with_options(&source, &dest, &options).unwrap();
+    assert!(diff.modified.is_empty());
+    assert!(diff.renamed.is_empty());
+    assert_eq!(diff.added.len(), 1);
+    assert_eq!(diff.added[0].path, PathBuf::from("moved.txt"));
+    assert_eq!(diff.removed.len(), 1);
+    assert_eq!(diff.removed[0].path, PathBuf::from("elsewhere.txt"));
+
+    // With the layouts matching, there are no differences at all
+    let diff = diff_scans_with_options(
+        &make_scan(source.files[..2].to_vec()),
+        &make_scan(dest.files[..2].to_vec()),
+        &options,
+    )
+    .unwrap();
+    assert!(diff.added.is_empty() && diff.removed.is_empty() && diff.modified.is_empty());
+}
+
+#[test]
+fn test_rename_min_size() {
+    let source = make_scan(vec![
+        make_file_meta("b/.keep", b"", 1000),
+        make_file_meta("b/stub.toml", b"[a]", 1000),
+        make_file_meta("b/data.bin", b"0123456789abcdef", 1000),
+    ]);
+    let dest = make_scan(vec![
+        make_file_meta("a/.keep", b"", 1000),
+        make_file_meta("a/stub.toml", b"[a]", 1000),
+        make_file_meta("a/data.bin", b"0123456789abcdef", 1000),
+    ]);
+
+    let diff = diff_scans_with_options(&source, &dest, &DiffOptions::default()).unwrap();
+    assert_eq!(diff.renamed.len(), 3);
+
+    let options = DiffOptions { rename_min_size: 8, ..Default::default() };
+    let diff = diff_scans_with_options(&source, &dest, &options).unwrap();
+    assert_eq!(diff.renamed.len(), 1);
+    assert_eq!(diff.renamed[0].1.path, PathBuf::from("b/data.bin"));
+    let mut added: Vec<_> = diff.added.iter().map(|f| f.path.clone()).collect();
+    added.sort();
+    assert_eq!(added, [PathBuf::from("b/.keep"), PathBuf::from("b/stub.toml")]);
+    assert_eq!(diff.removed.len(), 2);
+}
@@ -271,3 +271,18 @@ fn test_hasher_reuse() {
     // Can't use hasher after finalize (it's consumed)
     // This test just ensures the API works as expected
 }
+
+#[test]
+fn test_hasher_reset() {
+    let mut hasher = Hasher::new();
+    hasher.update(b"leftover state from a previous file");
+    hasher.reset();
+    hasher.update(b"test");
+    let reset_hash = hasher.finalize_reset();
+
+    assert_eq!(reset_hash, hash_bytes(b"test"), "Reset hasher should match a fresh one");
+
+    // finalize_reset leaves the hasher ready for the next file
+    hasher.update(b"next file");
+    assert_eq!(hasher.finalize(), hash_bytes(b"next file"));
+}
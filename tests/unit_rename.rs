@@ -13,6 +13,13 @@ fn make_file_meta(path: &str, content: &[u8]) -> FileMeta {
         mtime: SystemTime::now(),
         hash: hash_bytes(content),
         permissions: None,
+        owner: None,
+        metadata_hash: None,
+        inode: None,
+        ctime: None,
+        atime: None,
+        btime: None,
+        hash_deferred: false,
     }
 }
 
@@ -22,6 +29,10 @@ fn make_scan(files: Vec<FileMeta>) -> ScanResult {
         root: PathBuf::from("/test"),
         files,
         scan_time: SystemTime::now(),
+        warnings: Vec::new(),
+        dirs: Vec::new(),
+        traversed_dirs: Vec::new(),
+        fifo_spool: None,
     }
 }
 
@@ -0,0 +1,232 @@
+//! Unit tests for scan result helpers
+
+use janus::core::{
+    diff_scans, scan_directory_with_options, DuplicatePathPolicy, FileMeta, ManifestFormat,
+    ScanOptions, ScanResult, SyncError, MANIFEST_FORMAT_VERSION,
+};
+use janus::hash::hash_bytes;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+/// Helper to create a FileMeta with a given size (content is irrelevant here)
+fn make_file_meta(path: &str, size: u64) -> FileMeta {
+    FileMeta {
+        path: PathBuf::from(path),
+        size,
+        mtime: SystemTime::now(),
+        hash: hash_bytes(path.as_bytes()),
+        permissions: None,
+        owner: None,
+        metadata_hash: None,
+        inode: None,
+        ctime: None,
+        atime: None,
+        btime: None,
+        hash_deferred: false,
+    }
+}
+
+/// Helper to create a ScanResult from a list of files
+fn make_scan(files: Vec<FileMeta>) -> ScanResult {
+    ScanResult {
+        root: PathBuf::from("/test"),
+        files,
+        scan_time: SystemTime::now(),
+        warnings: Vec::new(),
+        dirs: Vec::new(),
+        traversed_dirs: Vec::new(),
+        fifo_spool: None,
+    }
+}
+
+#[test]
+fn test_histogram_buckets() {
+    let scan = make_scan(vec![
+        make_file_meta("a.txt", 10),
+        make_file_meta("b.txt", 1023),
+        make_file_meta("c.jpg", 1024),
+        make_file_meta("d.JPG", 500 * 1024),
+        make_file_meta("video.mp4", 50 * 1024 * 1024),
+        make_file_meta("disk.img", 200 * 1024 * 1024),
+        make_file_meta("Makefile", 100),
+    ]);
+
+    let stats = scan.histogram();
+
+    let counts: Vec<usize> = stats.size_buckets.iter().map(|b| b.count).collect();
+    assert_eq!(counts, vec![3, 2, 1, 1]);
+    assert_eq!(stats.size_buckets[0].total_size, 10 + 1023 + 100);
+
+    // Largest extension first, case-insensitive grouping
+    assert_eq!(stats.top_extensions[0].label, "img");
+    let jpg = stats.top_extensions.iter().find(|b| b.label == "jpg").unwrap();
+    assert_eq!(jpg.count, 2);
+    assert_eq!(jpg.total_size, 1024 + 500 * 1024);
+    assert!(stats.top_extensions.iter().any(|b| b.label == "(none)"));
+}
+
+#[test]
+fn test_histogram_empty_scan() {
+    let stats = make_scan(Vec::new()).histogram();
+
+    assert!(stats.size_buckets.iter().all(|b| b.count == 0));
+    assert!(stats.top_extensions.is_empty());
+}
+
+#[test]
+fn test_front_coded_manifest_round_trip() {
+    let dir = tempfile::TempDir::new().unwrap();
+    let deep = "projects/janus/assets/textures/environment/outdoor/forest/summer";
+    let mut files = vec![make_file_meta("README.md", 10), make_file_meta("ünïcode/é.txt", 3)];
+    for i in 0..200 {
+        files.push(make_file_meta(&format!("{deep}/tile_{i:03}.png"), i));
+    }
+    files.push(make_file_meta(&format!("{deep}/tile_199/nested.png"), 1));
+    let scan = make_scan(files);
+
+    let plain = dir.path().join("plain.json");
+    let coded = dir.path().join("coded.json");
+    scan.save_to_file_as(&plain, ManifestFormat::Plain).unwrap();
+    scan.save_to_file_as(&coded, ManifestFormat::FrontCoded).unwrap();
+
+    let loaded = ScanResult::load_from_file(&coded).unwrap();
+    assert_eq!(loaded.root, scan.root);
+    let mut expected: Vec<_> = scan.files.iter().map(|f| (&f.path, f.size, &f.hash)).collect();
+    expected.sort_by(|a, b| a.0.cmp(b.0));
+    let actual: Vec<_> = loaded.files.iter().map(|f| (&f.path, f.size, &f.hash)).collect();
+    assert_eq!(actual, expected);
+
+    let plain_size = std::fs::metadata(&plain).unwrap().len();
+    let coded_size = std::fs::metadata(&coded).unwrap().len();
+    assert!(
+        coded_size * 2 < plain_size,
+        "front-coded manifest ({coded_size} bytes) should be under half of plain ({plain_size})"
+    );
+}
+
+#[test]
+fn test_manifest_paths_are_portable() {
+    let dir = tempfile::TempDir::new().unwrap();
+    let native = PathBuf::from("photos").join("2024").join("summer").join("beach.jpg");
+    let mut file = make_file_meta("placeholder", 5);
+    file.path = native.clone();
+    let scan = make_scan(vec![file]);
+
+    // Saved with `/` whatever the platform's separator
+    for format in [ManifestFormat::Plain, ManifestFormat::FrontCoded] {
+        let path = dir.path().join("manifest.json");
+        scan.save_to_file_as(&path, format).unwrap();
+        let json = std::fs::read_to_string(&path).unwrap();
+        assert!(json.contains("photos/2024/summer/beach.jpg"));
+        assert!(!json.contains('\\'), "native separators leaked into {json}");
+        assert_eq!(ScanResult::load_from_file(&path).unwrap().files[0].path, native);
+    }
+
+    // A manifest written elsewhere loads with native separators
+    let path = dir.path().join("foreign.json");
+    let hash = serde_json::to_string(&hash_bytes(b"beach")).unwrap();
+    let foreign = format!(
+        r#"{{"root": "/backup", "scan_time": 0, "files": [
+            {{"path": "photos/2024/summer/beach.jpg", "size": 5, "mtime": 0, "hash": {hash}}}
+        ]}}"#
+    );
+    std::fs::write(&path, foreign).unwrap();
+    let loaded = ScanResult::load_from_file(&path).unwrap();
+    assert_eq!(loaded.files[0].path, native);
+    assert_eq!(loaded.files[0].path.components().count(), 4);
+}
+
+#[test]
+fn test_tree_hash_uses_portable_paths() {
+    let mut file = make_file_meta("placeholder", 5);
+    file.path = PathBuf::from("photos").join("beach.jpg");
+    let scan = make_scan(vec![file.clone()]);
+
+    // The same bytes on every platform: `/`-separated path, NUL, content hash
+    let mut expected = b"photos/beach.jpg\0".to_vec();
+    expected.extend_from_slice(file.hash.as_bytes());
+    assert_eq!(scan.tree_hash().unwrap(), hash_bytes(&expected));
+
+    // A deferred placeholder can't stand in for the content
+    file.hash_deferred = true;
+    assert!(make_scan(vec![file]).tree_hash().is_err());
+}
+
+#[test]
+fn test_manifest_rejects_unknown_version() {
+    let dir = tempfile::TempDir::new().unwrap();
+    let path = dir.path().join("future.json");
+    let future = MANIFEST_FORMAT_VERSION + 1;
+    std::fs::write(&path, format!(r#"{{"format_version": {future}, "files": []}}"#)).unwrap();
+
+    let err = ScanResult::load_from_file(&path).unwrap_err();
+    assert!(err.to_string().contains("unsupported manifest format version"));
+}
+
+#[test]
+fn test_merge_shard_scans() {
+    let mut first = make_scan(vec![make_file_meta("a/1.txt", 1), make_file_meta("a/2.txt", 2)]);
+    let mut second = make_scan(vec![make_file_meta("b/1.txt", 3)]);
+    first.scan_time = SystemTime::UNIX_EPOCH + Duration::from_secs(2_000);
+    second.scan_time = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000);
+
+    let merged = first.clone().merge([second], DuplicatePathPolicy::Error).unwrap();
+    let mut paths: Vec<_> = merged.files.iter().map(|f| f.path.to_str().unwrap()).collect();
+    paths.sort_unstable();
+    assert_eq!(paths, ["a/1.txt", "a/2.txt", "b/1.txt"]);
+    assert_eq!(merged.scan_time, first.scan_time);
+    assert_eq!(merged.total_size(), 6);
+
+    // Overlapping shards
+    let overlap = make_scan(vec![make_file_meta("a/2.txt", 20)]);
+    let err = first.clone().merge([overlap.clone()], DuplicatePathPolicy::Error).unwrap_err();
+    assert!(err.to_string().contains("a/2.txt"));
+    let duplicate = err.downcast_ref::<SyncError>().and_then(SyncError::path);
+    assert_eq!(duplicate, Some(Path::new("a/2.txt")));
+    let merged = first.clone().merge([overlap], DuplicatePathPolicy::LastWins).unwrap();
+    assert_eq!(merged.files.len(), 2);
+    assert_eq!(merged.total_size(), 21);
+
+    // Shards of a different tree
+    let mut elsewhere = make_scan(vec![make_file_meta("c.txt", 1)]);
+    elsewhere.rebase_root("/other");
+    assert!(first.merge([elsewhere], DuplicatePathPolicy::LastWins).is_err());
+}
+
+#[test]
+fn test_dir_hashes_match_for_unchanged_subtrees() {
+    let source = tempfile::TempDir::new().unwrap();
+    let dest = tempfile::TempDir::new().unwrap();
+    for root in [source.path(), dest.path()] {
+        std::fs::create_dir_all(root.join("docs/guides")).unwrap();
+        std::fs::create_dir_all(root.join("src")).unwrap();
+        std::fs::write(root.join("docs/guides/intro.md"), b"intro").unwrap();
+        std::fs::write(root.join("docs/index.md"), b"index").unwrap();
+        std::fs::write(root.join("src/lib.rs"), b"fn old() {}").unwrap();
+    }
+    std::fs::write(source.path().join("src/lib.rs"), b"fn new() {}").unwrap();
+
+    let options = ScanOptions { dir_hashes: true, ..Default::default() };
+    let source_scan = scan_directory_with_options(source.path(), &options, None).unwrap();
+    let dest_scan = scan_directory_with_options(dest.path(), &options, None).unwrap();
+    let dir_hash = |scan: &ScanResult, path: &str| {
+        scan.dirs
+            .iter()
+            .find(|dir| dir.path == std::path::Path::new(path))
+            .unwrap()
+            .hash
+            .clone()
+    };
+
+    assert_eq!(source_scan.dirs.len(), 4);
+    assert_eq!(dir_hash(&source_scan, "docs"), dir_hash(&dest_scan, "docs"));
+    assert_eq!(dir_hash(&source_scan, "docs/guides"), dir_hash(&dest_scan, "docs/guides"));
+    assert_ne!(dir_hash(&source_scan, "src"), dir_hash(&dest_scan, "src"));
+    assert_ne!(dir_hash(&source_scan, ""), dir_hash(&dest_scan, ""));
+
+    // Skipping the unchanged subtree finds the same change
+    let diff = diff_scans(&source_scan, &dest_scan).unwrap();
+    assert_eq!(diff.modified.len(), 1);
+    assert_eq!(diff.modified[0].path, PathBuf::from("src/lib.rs"));
+    assert!(diff.added.is_empty() && diff.removed.is_empty() && diff.renamed.is_empty());
+}